@@ -184,6 +184,22 @@ def cmd_objdump(input: Path, output: Path) -> Cmd:
     )
 
 
+def cmd_nm(input: Path, output: Path) -> Cmd:
+    return Cmd(
+        inputs={input},
+        output=output,
+        cmd=[
+            "nm",
+            "--defined-only",
+            "--print-size",
+            "--numeric-sort",
+            "--demangle",
+            input,
+        ],
+        stdout_file=output,
+    )
+
+
 def cmd_readelf(input: Path, output: Path) -> Cmd:
     return Cmd(
         inputs={input}, output=output, cmd=["readelf", "-e", input], stdout_file=output
@@ -450,6 +466,14 @@ def step_produce_dumps(root_dir) -> Set[Step]:
     }
 
 
+def step_kernel_symbols(root_dir) -> Step:
+    """Symbol table for the initrd, format documented in `d7abi::ksyms`."""
+    return Step(
+        requires={step_link_kernel},
+        cmd=cmd_nm(root_dir / "build/kernel_orig.elf", root_dir / "build/ksyms.txt"),
+    )
+
+
 def step_image_size(root_dir) -> Tuple[Step]:
     return (
         Step(
@@ -510,9 +534,8 @@ def step_create_disk(root_dir) -> Tuple[Union[Step, Set[Step]]]:
     )
 
 
-def step_create_filesystem(root_dir) -> Step:
-    disk_img = root_dir / "build/disk.img"
-
+def initrd_filelist(root_dir, overrides={}) -> List[str]:
+    """Files from `initrd_files.txt`, with the paths in `overrides` replaced."""
     with open(root_dir / "build_config/initrd_files.txt") as f:
         filelist = []
         for line in f:
@@ -520,7 +543,13 @@ def step_create_filesystem(root_dir) -> Step:
             if line:
                 assert line.count("=") == 1, f"Invalid line {line !r}"
                 l, r = line.split("=")
-                filelist.append(f"{l.strip()}={r.strip()}")
+                l, r = l.strip(), r.strip()
+                filelist.append(f"{l}={overrides.get(l, r)}")
+    return filelist
+
+
+def create_filesystem(root_dir, filelist: List[str]) -> Step:
+    disk_img = root_dir / "build/disk.img"
 
     return Step(
         requires={
@@ -529,6 +558,7 @@ def step_create_filesystem(root_dir) -> Step:
             step_image_size,
             step_process_common,
             step_kernel_modules,
+            step_kernel_symbols,
         },
         cmd=lambda c: Cmd(
             cmd=[
@@ -541,6 +571,21 @@ def step_create_filesystem(root_dir) -> Step:
     )
 
 
+def step_create_filesystem(root_dir) -> Step:
+    return create_filesystem(root_dir, initrd_filelist(root_dir))
+
+
+def step_create_bench_filesystem(root_dir) -> Step:
+    """Starts the benchmark module instead of the normal services."""
+    return create_filesystem(
+        root_dir,
+        initrd_filelist(
+            root_dir,
+            {"startup_services.json": "build_config/files/startup_services_bench.json"},
+        ),
+    )
+
+
 def step_all(root_dir) -> Step:
     return Step(
         requires={step_create_filesystem},  # , step_produce_dumps
@@ -548,6 +593,13 @@ def step_all(root_dir) -> Step:
     )
 
 
+def step_bench(root_dir) -> Step:
+    return Step(
+        requires={step_create_bench_filesystem},
+        cmd=Cmd(cmd=["echo", "done"]),
+    )
+
+
 def init(cfg):
     # A disk of 0x2000 0x200-byte sectors, 4 * 2**20 bytes, four mebibytes
     DISK_SIZE_SECTORS = 0x2000
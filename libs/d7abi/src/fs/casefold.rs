@@ -0,0 +1,94 @@
+//! Case-insensitive name comparison for filesystems like FAT32.
+//!
+//! Uses simple Unicode case folding (one character to one character),
+//! covering ASCII and Latin-1. Other characters are compared as-is.
+
+use alloc::prelude::v1::*;
+use serde::{Deserialize, Serialize};
+
+/// Name lookup semantics, declared by a filesystem driver per branch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CaseSensitivity {
+    /// Names are compared byte-by-byte
+    Exact,
+    /// Names are compared after case folding
+    Insensitive,
+}
+impl Default for CaseSensitivity {
+    fn default() -> Self {
+        Self::Exact
+    }
+}
+impl CaseSensitivity {
+    /// Compare two names using these semantics
+    pub fn eq(self, a: &str, b: &str) -> bool {
+        match self {
+            Self::Exact => a == b,
+            Self::Insensitive => eq_ignore_case(a, b),
+        }
+    }
+
+    /// Lookup key for a name, e.g. for use in a HashMap.
+    /// Names are equal iff their keys are equal.
+    pub fn key(self, name: &str) -> String {
+        match self {
+            Self::Exact => name.to_owned(),
+            Self::Insensitive => fold(name),
+        }
+    }
+}
+
+/// Simple case folding of a single character
+pub fn fold_char(c: char) -> char {
+    match c {
+        'A'..='Z' => ((c as u8) + 0x20) as char,
+        // Latin-1 uppercase letters, except the multiplication sign
+        '\u{c0}'..='\u{de}' if c != '\u{d7}' => char::from_u32((c as u32) + 0x20).unwrap(),
+        // Micro sign folds to the Greek small letter mu
+        '\u{b5}' => '\u{3bc}',
+        // Uppercase of `ÿ` is outside Latin-1
+        '\u{178}' => '\u{ff}',
+        _ => c,
+    }
+}
+
+/// Case-folded copy of a string
+pub fn fold(s: &str) -> String {
+    s.chars().map(fold_char).collect()
+}
+
+/// Case-insensitive comparison, without allocating
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.chars().map(fold_char).eq(b.chars().map(fold_char))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ascii() {
+        assert!(eq_ignore_case("README.TXT", "readme.txt"));
+        assert!(!eq_ignore_case("readme.txt", "readme.tx"));
+        assert_eq!(fold("Hello_World-1"), "hello_world-1");
+    }
+
+    #[test]
+    fn test_latin1() {
+        assert!(eq_ignore_case("ÄÖÅ", "äöå"));
+        assert!(eq_ignore_case("ÞÉ", "þé"));
+        assert_eq!(fold_char('×'), '×');
+        assert_eq!(fold_char('÷'), '÷');
+        assert_eq!(fold_char('ß'), 'ß');
+        assert_eq!(fold_char('Ÿ'), 'ÿ');
+        assert!(eq_ignore_case("µ", "μ"));
+    }
+
+    #[test]
+    fn test_case_sensitivity() {
+        assert!(!CaseSensitivity::Exact.eq("A", "a"));
+        assert!(CaseSensitivity::Insensitive.eq("A", "a"));
+        assert_eq!(CaseSensitivity::Insensitive.key("ÄB"), "äb");
+        assert_eq!(CaseSensitivity::Exact.key("ÄB"), "ÄB");
+    }
+}
@@ -1,3 +1,4 @@
-use serde::{Deserialize, Serialize};
-
-pub mod protocol;
+use serde::{Deserialize, Serialize};
+
+pub mod casefold;
+pub mod protocol;
@@ -0,0 +1,200 @@
+//! Shared input event ring, mapped writable into a process at `PROCESS_INPUT_RING`
+//! by the `input_ring` system call.
+//!
+//! The kernel appends input events to the ring directly from its interrupt
+//! handlers, so reading them doesn't need a system call per event. After
+//! appending, the kernel increments the doorbell word and wakes its futex
+//! waiters, so an idle reader blocks with `futex_wait` on the doorbell.
+//!
+//! Events are the raw PS/2 bytes of the keyboard. `SOURCE_MOUSE` is reserved
+//! for PS/2 mouse bytes, which the kernel doesn't receive yet.
+//!
+//! # Layout
+//! The ring is `INPUT_RING_SIZE` bytes. It starts with a header of
+//! `INPUT_RING_HEADER_SIZE` bytes: `head`, `tail` and `dropped` as `u64`, and
+//! `doorbell` as `u32`, followed by an array of `InputEvent`s.
+//! `head` is the total number of events written by the kernel, and `tail` the
+//! total number of events consumed by the process. Event `n` is stored at
+//! index `n % capacity`. `dropped` counts the events lost while the ring was full.
+//!
+//! There is a single writer for each position: the kernel only advances
+//! `head`, `dropped` and `doorbell`, and the process only advances `tail`.
+//! The kernel must not trust the values written by the process.
+
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Size of the ring, one large page
+pub const INPUT_RING_SIZE: usize = 0x20_0000;
+
+/// Header size in bytes, aligned so that the events start on a cache line
+pub const INPUT_RING_HEADER_SIZE: usize = 64;
+
+/// Offset of the doorbell word from the start of the ring, for `futex_wait`
+pub const DOORBELL_OFFSET: u64 = 24;
+
+/// Byte from the PS/2 keyboard
+pub const SOURCE_KEYBOARD: u32 = 1;
+/// Byte from the PS/2 mouse
+pub const SOURCE_MOUSE: u32 = 2;
+
+#[repr(C)]
+struct Header {
+    head: AtomicU64,
+    tail: AtomicU64,
+    dropped: AtomicU64,
+    doorbell: AtomicU32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    /// TSC value when the kernel received the event, see `time_page`
+    pub tsc: u64,
+    /// One of the `SOURCE_*` constants
+    pub source: u32,
+    pub value: u32,
+}
+
+pub struct InputRing {
+    header: *const Header,
+    events: *mut InputEvent,
+    capacity: u64,
+}
+impl InputRing {
+    /// # Safety
+    /// `ptr` must point to a zero-initialized or previously used ring of
+    /// `size` bytes, aligned to 8 bytes, which must stay valid while the
+    /// returned value is used. `size` must have room for at least one event
+    /// after `INPUT_RING_HEADER_SIZE`.
+    pub unsafe fn from_raw(ptr: *mut u8, size: usize) -> Self {
+        assert!(size >= INPUT_RING_HEADER_SIZE + size_of::<InputEvent>());
+        let capacity = (size - INPUT_RING_HEADER_SIZE) / size_of::<InputEvent>();
+        Self {
+            header: ptr as *const Header,
+            events: ptr.add(INPUT_RING_HEADER_SIZE) as *mut InputEvent,
+            capacity: capacity as u64,
+        }
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// The futex word incremented after each write
+    pub fn doorbell(&self) -> &AtomicU32 {
+        &self.header().doorbell
+    }
+
+    /// Number of events dropped because the ring was full
+    pub fn dropped(&self) -> u64 {
+        self.header().dropped.load(Ordering::Relaxed)
+    }
+
+    /// Writer side: append an event, and ring the doorbell.
+    /// Returns `false` if the event was dropped, because the ring is full
+    /// or the reader has corrupted the header.
+    pub fn push(&self, event: InputEvent) -> bool {
+        let head = self.header().head.load(Ordering::Relaxed);
+        let tail = self.header().tail.load(Ordering::Acquire);
+        // The reader can also overwrite `head`, so it cannot be incremented blindly
+        let next_head = match head.checked_add(1) {
+            Some(next) if tail <= head && head - tail < self.capacity => next,
+            _ => {
+                self.header().dropped.fetch_add(1, Ordering::Relaxed);
+                return false;
+            },
+        };
+        let index = head % self.capacity;
+        unsafe { self.events.add(index as usize).write_volatile(event) };
+        self.header().head.store(next_head, Ordering::Release);
+        self.header().doorbell.fetch_add(1, Ordering::Release);
+        true
+    }
+
+    /// Reader side: take the oldest unread event
+    pub fn pop(&self) -> Option<InputEvent> {
+        let head = self.header().head.load(Ordering::Acquire);
+        let tail = self.header().tail.load(Ordering::Relaxed);
+        if tail >= head {
+            return None;
+        }
+        let index = tail % self.capacity;
+        let event = unsafe { self.events.add(index as usize).read_volatile() };
+        self.header().tail.store(tail + 1, Ordering::Release);
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(value: u32) -> InputEvent {
+        InputEvent {
+            tsc: value as u64,
+            source: SOURCE_KEYBOARD,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_push_and_pop() {
+        let mut buffer = [0u64; (INPUT_RING_HEADER_SIZE + 2 * 16) / 8];
+        let size = buffer.len() * 8;
+        let ring = unsafe { InputRing::from_raw(buffer.as_mut_ptr() as *mut u8, size) };
+        assert_eq!(ring.capacity(), 2);
+        let doorbell = ring.doorbell() as *const AtomicU32 as usize;
+        assert_eq!((doorbell - buffer.as_ptr() as usize) as u64, DOORBELL_OFFSET);
+
+        assert!(ring.push(event(1)));
+        assert!(ring.push(event(2)));
+        assert!(!ring.push(event(3)));
+        assert_eq!(ring.dropped(), 1);
+        assert_eq!(ring.doorbell().load(Ordering::Relaxed), 2);
+
+        assert_eq!(ring.pop(), Some(event(1)));
+        assert!(ring.push(event(4)));
+        assert_eq!(ring.pop(), Some(event(2)));
+        assert_eq!(ring.pop(), Some(event(4)));
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn test_corrupted_header() {
+        let mut buffer = [0u64; (INPUT_RING_HEADER_SIZE + 2 * 16) / 8];
+        buffer[1] = 100; // tail
+        let size = buffer.len() * 8;
+        let ring = unsafe { InputRing::from_raw(buffer.as_mut_ptr() as *mut u8, size) };
+        assert!(!ring.push(event(1)));
+        assert_eq!(ring.dropped(), 1);
+    }
+
+    #[test]
+    fn test_corrupted_head() {
+        let mut buffer = [0u64; (INPUT_RING_HEADER_SIZE + 2 * 16) / 8];
+        let size = buffer.len() * 8;
+        let ring = unsafe { InputRing::from_raw(buffer.as_mut_ptr() as *mut u8, size) };
+
+        // Head at the maximum, with the reader caught up
+        unsafe {
+            (*ring.header).head.store(u64::MAX, Ordering::Relaxed);
+            (*ring.header).tail.store(u64::MAX, Ordering::Relaxed);
+        }
+        assert!(!ring.push(event(1)));
+        assert_eq!(ring.dropped(), 1);
+
+        // More unread events than fit in the ring
+        unsafe {
+            (*ring.header).head.store(10, Ordering::Relaxed);
+            (*ring.header).tail.store(0, Ordering::Relaxed);
+        }
+        assert!(!ring.push(event(2)));
+        assert_eq!(ring.dropped(), 2);
+        assert_eq!(ring.doorbell().load(Ordering::Relaxed), 0);
+    }
+}
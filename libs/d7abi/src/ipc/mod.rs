@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 use alloc::prelude::v1::*;
 
 pub mod protocol;
+pub mod rpc;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 pub struct SubscriptionId(u64);
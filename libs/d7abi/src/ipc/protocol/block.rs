@@ -0,0 +1,55 @@
+//! Block device protocol, used between filesystems and disk drivers
+
+use alloc::prelude::v1::*;
+use serde::{Deserialize, Serialize};
+
+/// Request to a block device driver.
+/// Sent to topic `<driver>/<drive_index>/io`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    /// First sector
+    pub lba: u64,
+    /// Number of sectors
+    pub sectors: u64,
+    /// Operation
+    pub operation: Operation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Operation {
+    /// Read sectors
+    Read,
+    /// Write sectors, data must be exactly `sectors * sector_size` bytes.
+    /// Writes are considered background writeback, and are
+    /// scheduled after pending reads.
+    Write(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    /// Data of the read sectors
+    Read(Vec<u8>),
+    /// Write complete
+    Write,
+    /// Request was out of range or otherwise invalid
+    Invalid,
+}
+
+/// I/O scheduler counters of a block device driver
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Stats {
+    /// Requests received from clients
+    pub requests: u64,
+    /// Requests merged into an adjacent queued request
+    pub merged: u64,
+    /// Operations actually issued to the device
+    pub dispatched: u64,
+    /// Sectors read from the device
+    pub sectors_read: u64,
+    /// Sectors written to the device
+    pub sectors_written: u64,
+    /// Sectors read ahead for sequential readers
+    pub sectors_prefetched: u64,
+    /// Requested sectors served from the readahead cache
+    pub prefetch_hits: u64,
+}
@@ -0,0 +1,46 @@
+//! Job control for the virtual consoles of `consoled`.
+//!
+//! Each console has a foreground process, which receives the input lines
+//! and the control key signals of the console. These are messages, not
+//! `process_signal` signals: the foreground process decides how to react,
+//! e.g. a shell terminates or pauses its current job.
+
+use alloc::prelude::v1::*;
+use serde::{Deserialize, Serialize};
+
+use crate::process::ProcessId;
+
+/// Reliable topic of `consoled` for `ConsoleControl` requests,
+/// accepted only from processes with at least `Privilege::Driver`
+pub const CONTROL_TOPIC: &str = "console/control";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ConsoleControl {
+    /// Transfer the console to another process. The previous foreground
+    /// process stops receiving input. `None` leaves the console without one.
+    SetForeground {
+        console: u8,
+        pid: Option<ProcessId>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConsoleSignal {
+    /// Ctrl+C
+    Interrupt,
+    /// Ctrl+Z
+    Suspend,
+}
+
+/// Published to `input_topic` of the foreground process
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ConsoleInput {
+    /// A line entered by the user, without the line feed
+    Line(String),
+    Signal(ConsoleSignal),
+}
+
+/// Unreliable topic where the foreground process of a console receives `ConsoleInput`
+pub fn input_topic(console: u8, pid: ProcessId) -> String {
+    format!("console/{}/input/{}", console, pid)
+}
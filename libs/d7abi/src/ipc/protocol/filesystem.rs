@@ -0,0 +1,78 @@
+//! Filesystem protocol, served by filesystem drivers.
+//!
+//! Each mounted filesystem is a server at `mnt/<mount>`, e.g. `mnt/ata0p1`
+//! for the first partition of the first ATA drive. The names of the mounted
+//! filesystems are published as a retained `Vec<String>` to `MOUNTS_TOPIC`.
+//! Paths are relative to the root of the filesystem, with `/` as separator.
+
+use alloc::prelude::v1::*;
+use serde::{Deserialize, Serialize};
+
+pub const MOUNTS_TOPIC: &str = "mnt/mounts";
+
+/// Largest read or write done by a single request.
+/// Longer requests are shortened, and the reply tells how much was done.
+pub const MAX_TRANSFER_BYTES: u64 = 0x1_0000;
+
+/// Topic of the server of a mounted filesystem
+pub fn mount_topic(mount: &str) -> String {
+    format!("mnt/{}", mount)
+}
+
+crate::rpc! {
+    /// Unmount a filesystem, or all partitions of a drive if the name is a
+    /// drive, e.g. `ata0`. Replies with the unmounted names. Clients waiting
+    /// for a reply from an unmounted filesystem fail with `ipc_delivery_no_target`.
+    /// Requires `Privilege::Driver`.
+    pub struct Unmount: "mnt/unmount", String => Vec<String>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Entries of a directory
+    List(String),
+    /// Part of a file, shorter at the end of the file
+    Read { path: String, offset: u64, len: u64 },
+    /// Write to a file at offset, extending it if needed.
+    /// The file is created if it doesn't exist, but its directory must.
+    /// At most `MAX_TRANSFER_BYTES` from the start of `data` are written.
+    Write {
+        path: String,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// Remove a file, or an empty directory
+    Remove(String),
+    /// Move a file or a directory within the filesystem. An existing file
+    /// at `to` is replaced, so a file can be updated by writing a temporary
+    /// file and renaming it over the old one.
+    Rename { from: String, to: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
+    /// Zero for directories
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    List(Vec<DirEntry>),
+    Read(Vec<u8>),
+    /// Number of bytes written
+    Write(u64),
+    Removed,
+    Renamed,
+    NotFound,
+    /// A file was given where a directory is expected, or vice versa
+    WrongKind,
+    /// The name cannot be created on this filesystem
+    InvalidName,
+    NoSpace,
+    /// The device failed, or the filesystem is corrupted
+    IoError,
+    /// The directory to remove has entries
+    NotEmpty,
+}
@@ -0,0 +1,25 @@
+//! Random access to initrd files with the `initrd/read_at` kernel service.
+//!
+//! `initrd/read` replies with the whole file, which is wasteful when only
+//! a part of a large file is needed.
+
+use alloc::prelude::v1::*;
+use serde::{Deserialize, Serialize};
+
+/// Largest `len` accepted by `initrd/read_at`
+pub const READ_AT_MAX_BYTES: u64 = 0x1_0000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadAt {
+    pub path: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadAtReply {
+    /// Size of the whole file
+    pub size: u64,
+    /// Shorter than requested at the end of the file, and empty past it
+    pub data: Vec<u8>,
+}
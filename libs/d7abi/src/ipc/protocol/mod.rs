@@ -1,12 +1,67 @@
+use alloc::prelude::v1::*;
 use serde::{Deserialize, Serialize};
 
 use crate::process::{ProcessId, ProcessResult};
+use crate::ShutdownAction;
 
+pub mod block;
+pub mod console;
+pub mod filesystem;
+pub mod initrd;
 pub mod keyboard;
+pub mod netd;
 pub mod service;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProcessTerminated {
     pub pid: ProcessId,
     pub result: ProcessResult,
-}
\ No newline at end of file
+}
+
+/// Published to `system/shutdown` when a shutdown starts.
+/// Processes should flush their state and exit within the timeout,
+/// after which the remaining processes are terminated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownStarted {
+    pub action: ShutdownAction,
+    pub timeout_ns: u64,
+}
+
+/// Free physical memory level, see `MemoryPressure`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MemoryPressureLevel {
+    Normal,
+    Low,
+    Critical,
+}
+
+/// Published as a retained message to `memory/pressure` when the level changes.
+/// Processes holding caches should release memory when the level rises,
+/// before allocations start failing. No retained message means `Normal`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryPressure {
+    pub level: MemoryPressureLevel,
+    pub free_bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// Published as a retained message to `memory/kernel_heap` when the usage of
+/// the kernel heap crosses `KERNEL_HEAP_LOW_PERCENT` or `KERNEL_HEAP_CRITICAL_PERCENT`.
+/// The kernel heap never shrinks, so a rising level means a kernel memory leak,
+/// or a heap too small for the workload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KernelHeap {
+    pub level: MemoryPressureLevel,
+    pub used_bytes: u64,
+    pub size_bytes: u64,
+}
+
+/// Request to the `debug/ipc_trace` kernel service, which logs the IPC
+/// operations on topics starting with the traced prefixes to the kernel log.
+/// Answered with the traced prefixes after the request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum IpcTraceRequest {
+    List,
+    Enable(String),
+    Disable(String),
+}
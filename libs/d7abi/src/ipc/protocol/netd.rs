@@ -0,0 +1,104 @@
+//! Network daemon control protocol
+
+use alloc::prelude::v1::*;
+use serde::{Deserialize, Serialize};
+
+/// Token bucket transmit rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Sustained rate
+    pub bytes_per_sec: u64,
+    /// Max amount sent at once after being idle
+    pub burst_bytes: u64,
+}
+
+crate::rpc! {
+    /// Transmit rate limits and counters of netd
+    pub struct Qos: "netd/qos", QosRequest => QosStats;
+}
+
+/// Request to `netd/qos`. Always answered with `QosStats`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum QosRequest {
+    /// Only read the statistics
+    Stats,
+    /// Limit for sockets without an explicit limit, `None` for unlimited
+    SetDefaultLimit(Option<RateLimit>),
+    /// Limit for a single socket, `None` to use the default limit
+    SetSocketLimit(u64, Option<RateLimit>),
+}
+
+/// Transmit queue counters
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct QosStats {
+    /// Frames sent from the control queue (ARP, etc.)
+    pub sent_control: u64,
+    /// Frames sent from the bulk (socket data) queue
+    pub sent_bulk: u64,
+    /// Frames dropped because a queue was full
+    pub dropped: u64,
+    /// Bulk frames held back by rate limiting, each counted once
+    pub throttled: u64,
+}
+
+crate::rpc! {
+    /// Interface and protocol counters of netd, e.g. for a netstat tool
+    pub struct Stats: "netd/stats", () => NetStats;
+}
+
+/// Counters since netd started. Transmit queue drops and rate limiting
+/// are counted separately in `QosStats`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct NetStats {
+    pub interface: InterfaceStats,
+    pub protocols: ProtocolStats,
+}
+
+/// Counters of the network interface
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct InterfaceStats {
+    pub rx_frames: u64,
+    pub rx_bytes: u64,
+    /// Received frames that were too short for their headers, and were ignored
+    pub rx_errors: u64,
+    pub tx_frames: u64,
+    pub tx_bytes: u64,
+}
+
+/// Received frames and packets by protocol
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ProtocolStats {
+    pub arp: u64,
+    pub ipv4: u64,
+    /// Frames with other ethertypes
+    pub other_ethertype: u64,
+    pub icmp: u64,
+    pub tcp: u64,
+    pub udp: u64,
+    /// IPv4 packets with other protocols
+    pub other_ip: u64,
+}
+
+crate::rpc! {
+    /// Address configuration of netd, from DHCP or the fallback defaults
+    pub struct Config: "netd/config", () => NetConfig;
+}
+
+/// Current address configuration. Addresses are in network byte order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetConfig {
+    pub ip: [u8; 4],
+    pub netmask: [u8; 4],
+    /// Router for addresses outside of the local network
+    pub gateway: [u8; 4],
+    pub dns: Vec<[u8; 4]>,
+    /// `None` until a DHCP server has acknowledged the address
+    pub lease: Option<DhcpLease>,
+}
+
+/// Lease of the address from a DHCP server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DhcpLease {
+    pub server: [u8; 4],
+    pub lease_seconds: u32,
+}
@@ -2,6 +2,8 @@ use alloc::prelude::v1::*;
 use core::fmt;
 use serde::{Deserialize, Serialize};
 
+use crate::process::ProcessResult;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[serde(transparent)]
 pub struct ServiceName(pub String);
@@ -19,3 +21,15 @@ pub struct Registration {
     #[serde(default)]
     pub oneshot: bool,
 }
+
+/// Reply of the `boot/init` kernel service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InitStatus {
+    /// Initrd file started as the first process
+    pub started: String,
+    /// Programs tried before it, and why they could not be started
+    pub failed: Vec<(String, String)>,
+    /// Results of the earlier first processes, which have been restarted
+    #[serde(default)]
+    pub terminated: Vec<ProcessResult>,
+}
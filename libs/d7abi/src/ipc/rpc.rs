@@ -0,0 +1,42 @@
+//! Typed request-reply interfaces.
+//!
+//! An `Rpc` ties the topic of a server to its request and response types.
+//! When it is declared once here, and used on both sides with
+//! `libd7::ipc::Server::rpc` and `libd7::ipc::call`, the server and its clients
+//! cannot disagree on the message types, a mistake that would otherwise only
+//! show up as a deserialization failure at runtime.
+//!
+//! No request ids are needed: every request carries its own reply topic,
+//! and is a single reliable message, which the kernel either delivers whole
+//! or fails to deliver. A process serves multiple interfaces by passing
+//! their servers to `select!`.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+pub trait Rpc {
+    /// Exact topic of the server
+    const TOPIC: &'static str;
+    type Request: Serialize + DeserializeOwned;
+    type Response: Serialize + DeserializeOwned;
+}
+
+/// Declares a unit struct implementing `Rpc`:
+/// ```ignore
+/// rpc! {
+///     /// Quality of service control of netd
+///     pub struct Qos: "netd/qos", QosRequest => QosStats;
+/// }
+/// ```
+#[macro_export]
+macro_rules! rpc {
+    ($(#[$meta:meta])* $vis:vis struct $name:ident: $topic:literal, $rq:ty => $rs:ty;) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy)]
+        $vis struct $name;
+        impl $crate::ipc::rpc::Rpc for $name {
+            const TOPIC: &'static str = $topic;
+            type Request = $rq;
+            type Response = $rs;
+        }
+    };
+}
@@ -0,0 +1,126 @@
+//! Kernel symbol table, for symbolizing kernel addresses in userspace.
+//!
+//! The build extracts the symbols of the unstripped kernel with
+//! `nm --defined-only --print-size --numeric-sort --demangle`
+//! and stores them in the initrd as `ksyms`, readable with `initrd/read`.
+//!
+//! # Format
+//! UTF-8 text, one symbol per line, ordered by address:
+//! `<address> [<size>] <type> <name>`, where address and size are hexadecimal
+//! and type is the `nm` symbol type character. Size is omitted for symbols
+//! without one. Names may contain spaces.
+
+use alloc::prelude::v1::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub address: u64,
+    /// Zero if unknown
+    pub size: u64,
+    /// Symbol type character from `nm`, e.g. `T` for code
+    pub kind: char,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    /// Sorted by address
+    symbols: Vec<Symbol>,
+}
+impl SymbolTable {
+    /// Parse the `ksyms` file. Invalid lines are skipped.
+    pub fn parse(text: &str) -> Self {
+        let mut symbols: Vec<Symbol> = text.lines().filter_map(parse_line).collect();
+        symbols.sort_by_key(|s| s.address);
+        Self { symbols }
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Symbol containing the address, and the offset from its start.
+    /// Symbols without a size are assumed to extend to the next symbol.
+    pub fn lookup(&self, address: u64) -> Option<(&Symbol, u64)> {
+        let index = match self.symbols.binary_search_by_key(&address, |s| s.address) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let symbol = &self.symbols[index];
+        let offset = address - symbol.address;
+        if symbol.size == 0 || offset < symbol.size {
+            Some((symbol, offset))
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<Symbol> {
+    let mut parts = line.splitn(2, ' ');
+    let address = u64::from_str_radix(parts.next()?, 16).ok()?;
+    let rest = parts.next()?;
+
+    let (size, rest) = match rest.find(' ') {
+        Some(1) => (0, rest),
+        Some(i) => (u64::from_str_radix(&rest[..i], 16).ok()?, &rest[i + 1..]),
+        None => return None,
+    };
+
+    let mut chars = rest.chars();
+    let kind = chars.next()?;
+    if chars.next() != Some(' ') {
+        return None;
+    }
+    let name = chars.as_str();
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(Symbol {
+        address,
+        size,
+        kind,
+        name: name.to_owned(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+0000000000100000 T _start
+0000000000100010 0000000000000020 T d7os::rust_main
+0000000000100030 0000000000000008 t <d7os::Foo as core::fmt::Debug>::fmt
+invalid line
+0000000000200000 B KERNEL_END
+";
+
+    #[test]
+    fn test_parse() {
+        let table = SymbolTable::parse(EXAMPLE);
+        assert_eq!(table.symbols().len(), 4);
+        assert_eq!(table.symbols()[0].size, 0);
+        assert_eq!(table.symbols()[1].name, "d7os::rust_main");
+        assert_eq!(table.symbols()[2].kind, 't');
+        assert_eq!(
+            table.symbols()[2].name,
+            "<d7os::Foo as core::fmt::Debug>::fmt"
+        );
+    }
+
+    #[test]
+    fn test_lookup() {
+        let table = SymbolTable::parse(EXAMPLE);
+        assert_eq!(table.lookup(0xfffff), None);
+        let (s, offset) = table.lookup(0x100004).unwrap();
+        assert_eq!((s.name.as_str(), offset), ("_start", 4));
+        let (s, offset) = table.lookup(0x10002f).unwrap();
+        assert_eq!((s.name.as_str(), offset), ("d7os::rust_main", 0x1f));
+        assert!(table.lookup(0x100038).is_none());
+        let (s, _) = table.lookup(0x300000).unwrap();
+        assert_eq!(s.name, "KERNEL_END");
+    }
+}
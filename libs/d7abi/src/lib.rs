@@ -5,7 +5,7 @@
 #![deny(unused_assignments)]
 #![deny(clippy::missing_safety_doc)]
 // no_std
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 // Unstable features
 #![feature(const_fn)]
 #![feature(integer_atomics)]
@@ -19,8 +19,15 @@ mod kernel_constants;
 mod syscall;
 
 pub mod fs;
+pub mod input_ring;
 pub mod ipc;
+pub mod ksyms;
+pub mod output_ring;
 pub mod process;
+pub mod time_page;
 
-pub use self::kernel_constants::PROCESS_DYNAMIC_MEMORY;
+pub use self::kernel_constants::{
+    PROCESS_DYNAMIC_MEMORY, PROCESS_INPUT_RING, PROCESS_OUTPUT_RING, PROCESS_SHARED_MEMORY,
+    PROCESS_TIME_PAGE,
+};
 pub use self::syscall::*;
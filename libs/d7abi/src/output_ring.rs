@@ -0,0 +1,132 @@
+//! Shared output ring, mapped writable into a process at `PROCESS_OUTPUT_RING`
+//! by the `debug_output_ring` system call.
+//!
+//! The process appends text to the ring, and the kernel drains it on scheduler
+//! ticks and when the process terminates, printing complete lines like
+//! `debug_print` does. Printing then doesn't need a system call, unless the
+//! ring is full.
+//!
+//! # Layout
+//! The ring is `OUTPUT_RING_SIZE` bytes. It starts with a header of
+//! `OUTPUT_RING_HEADER_SIZE` bytes: `head` and `tail` as `u64`, followed by
+//! the data area.
+//! `head` is the total number of bytes written by the process, and `tail` the
+//! total number of bytes consumed by the kernel. Byte `n` of the stream is
+//! stored at `n % capacity` of the data area.
+//!
+//! There is a single writer for each position: the process only advances
+//! `head`, and the kernel only advances `tail`. The kernel must not trust the
+//! values written by the process.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Size of the ring, one large page
+pub const OUTPUT_RING_SIZE: usize = 0x20_0000;
+
+/// Header size in bytes, aligned so that the data area starts on a cache line
+pub const OUTPUT_RING_HEADER_SIZE: usize = 64;
+
+#[repr(C)]
+struct Header {
+    head: AtomicU64,
+    tail: AtomicU64,
+}
+
+pub struct OutputRing {
+    header: *const Header,
+    data: *mut u8,
+    capacity: u64,
+}
+impl OutputRing {
+    /// # Safety
+    /// `ptr` must point to a zero-initialized or previously used ring of
+    /// `size` bytes, which must stay valid while the returned value is used.
+    /// `size` must be larger than `OUTPUT_RING_HEADER_SIZE`.
+    pub unsafe fn from_raw(ptr: *mut u8, size: usize) -> Self {
+        assert!(size > OUTPUT_RING_HEADER_SIZE);
+        Self {
+            header: ptr as *const Header,
+            data: ptr.add(OUTPUT_RING_HEADER_SIZE),
+            capacity: (size - OUTPUT_RING_HEADER_SIZE) as u64,
+        }
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*self.header }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Writer side: append as much of `bytes` as fits.
+    /// Returns the number of bytes written.
+    pub fn write(&self, bytes: &[u8]) -> usize {
+        let head = self.header().head.load(Ordering::Relaxed);
+        let tail = self.header().tail.load(Ordering::Acquire);
+        let free = self.capacity - (head - tail);
+        let count = (bytes.len() as u64).min(free);
+        for (i, &b) in bytes[..count as usize].iter().enumerate() {
+            let offset = (head + i as u64) % self.capacity;
+            unsafe { self.data.add(offset as usize).write_volatile(b) };
+        }
+        self.header().head.store(head + count, Ordering::Release);
+        count as usize
+    }
+
+    /// Reader side: the unread bytes, in stream order.
+    /// Returns `None` if the writer has corrupted the header.
+    pub fn unread(&self) -> Option<impl Iterator<Item = u8> + '_> {
+        let head = self.header().head.load(Ordering::Acquire);
+        let tail = self.header().tail.load(Ordering::Relaxed);
+        if head < tail || head - tail > self.capacity {
+            return None;
+        }
+        Some((tail..head).map(move |n| unsafe {
+            self.data.add((n % self.capacity) as usize).read_volatile()
+        }))
+    }
+
+    /// Reader side: mark `count` unread bytes as consumed
+    pub fn consume(&self, count: u64) {
+        self.header().tail.fetch_add(count, Ordering::Release);
+    }
+
+    /// Reader side: drop all unread bytes, e.g. after corruption
+    pub fn reset(&self) {
+        let head = self.header().head.load(Ordering::Acquire);
+        self.header().tail.store(head, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_and_consume() {
+        let mut buffer = [0u64; (OUTPUT_RING_HEADER_SIZE + 8) / 8];
+        let ring = unsafe { OutputRing::from_raw(buffer.as_mut_ptr() as *mut u8, 72) };
+        assert_eq!(ring.capacity(), 8);
+
+        assert_eq!(ring.write(b"hello"), 5);
+        assert_eq!(ring.write(b"world"), 3);
+        let unread: Vec<u8> = ring.unread().unwrap().collect();
+        assert_eq!(unread, b"hellowor");
+
+        ring.consume(6);
+        assert_eq!(ring.write(b"ld!"), 3);
+        let unread: Vec<u8> = ring.unread().unwrap().collect();
+        assert_eq!(unread, b"orld!");
+    }
+
+    #[test]
+    fn test_corrupted_header() {
+        let mut buffer = [0u64; (OUTPUT_RING_HEADER_SIZE + 8) / 8];
+        buffer[0] = 100; // head
+        let ring = unsafe { OutputRing::from_raw(buffer.as_mut_ptr() as *mut u8, 72) };
+        assert!(ring.unread().is_none());
+        ring.reset();
+        assert_eq!(ring.unread().unwrap().count(), 0);
+    }
+}
@@ -1,6 +1,8 @@
+use alloc::prelude::v1::*;
 use core::num::NonZeroU64;
 use core::fmt;
 use core::u64;
+use num_enum::TryFromPrimitive;
 use serde::{Serialize, Deserialize};
 use x86_64::structures::idt::InterruptStackFrameValue;
 use x86_64::VirtAddr;
@@ -8,6 +10,10 @@ use x86_64::structures::idt::PageFaultErrorCode;
 
 /// ProcessId is stores as `NonZeroU64`, so that `Option<ProcessId>`
 /// still has uses only `size_of<Processid>` bytes
+///
+/// Process ids are allocated sequentially and never reused: `next` panics
+/// instead of wrapping around. Stale ids stored in long-lived maps can
+/// therefore never refer to a different process, so no generation is needed.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 #[serde(transparent)]
 pub struct ProcessId(NonZeroU64);
@@ -39,6 +45,111 @@ impl fmt::Display for ProcessId {
     }
 }
 
+/// Privilege level of a process, assigned on exec.
+/// A process can only spawn processes with at most its own privilege level.
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    TryFromPrimitive,
+    Deserialize,
+    Serialize,
+)]
+#[repr(u64)]
+pub enum Privilege {
+    /// Normal processes
+    User = 0,
+    /// Hardware access: physical memory mappings, DMA, IRQs and kernel log
+    Driver = 1,
+    /// The service daemon, can spawn processes of any privilege level
+    Full = 2,
+}
+impl Default for Privilege {
+    fn default() -> Self {
+        Self::User
+    }
+}
+
+/// Event posted by the kernel to the events topic of a process,
+/// see `events_topic`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum ProcessEvent {
+    /// A process spawned by this process was terminated
+    ChildTerminated(ProcessId, ProcessResult),
+    /// A timer set using `sched_timer` expired, with the token it was given
+    TimerFired(u64),
+    /// A signal sent with `process_signal`, once it is not masked
+    Signal(Signal),
+}
+
+/// Asynchronous notification sent to a process with `process_signal`.
+/// `Kill` terminates the process, and the other signals are posted to
+/// its events topic as `ProcessEvent::Signal`, interrupting a blocking
+/// system call. The process decides how to react to them.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, Hash, TryFromPrimitive, Deserialize, Serialize,
+)]
+#[repr(u64)]
+pub enum Signal {
+    /// Terminates the process immediately, cannot be masked
+    Kill = 0,
+    /// Stop the current operation, e.g. Ctrl+C
+    Interrupt = 1,
+    /// Clean up and exit
+    Terminate = 2,
+    /// Meaning agreed between the processes
+    User = 3,
+}
+impl Signal {
+    /// Bit of the signal in masks, see `process_signal_mask`
+    pub const fn bit(self) -> u64 {
+        1 << (self as u64)
+    }
+}
+
+/// Topic for kernel-posted events of a process.
+/// Only the process itself can subscribe to it.
+pub fn events_topic(pid: ProcessId) -> String {
+    format!("process/events/{}", pid)
+}
+
+/// What a memory region of a process is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MemoryRegionKind {
+    /// Kernel code for process switches, shared by all processes
+    CommonCode,
+    /// Read-only clock data, see `time_page`
+    TimePage,
+    Stack,
+    /// Segment loaded from the executable
+    Executable,
+    /// Dynamic memory, resized with `mem_set_size`
+    Dynamic,
+    /// Physical memory mapped with `mmap_physical`
+    Physical,
+    /// Output ring mapped with `debug_output_ring`, see `output_ring`
+    OutputRing,
+    /// Input event ring mapped with `input_ring`, see `input_ring`
+    InputRing,
+    /// Shared memory object mapped with `shm_create` or `shm_map`
+    SharedMemory,
+}
+
+/// A mapped memory region of a process, returned by `process_memory_map`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MemoryRegion {
+    pub start: VirtAddr,
+    pub size_bytes: u64,
+    pub kind: MemoryRegionKind,
+    pub writable: bool,
+    pub executable: bool,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum ProcessResult {
     /// The process exited with a return code
@@ -63,4 +174,8 @@ pub enum Error {
     Pointer(VirtAddr),
     /// Owner process died
     ChainedTermination,
+    /// Killed by the process with `Signal::Kill`
+    Killed(ProcessId),
+    /// Still running when the system shut down
+    Shutdown,
 }
@@ -13,9 +13,23 @@ pub enum SyscallNumber {
     get_pid = 0x01,
     debug_print = 0x02,
     mem_set_size = 0x03,
+    debug_output_ring = 0x04,
+    input_ring = 0x05,
     exec = 0x30,
+    process_memory_map = 0x31,
+    thread_create = 0x32,
+    thread_set_fs_base = 0x33,
+    process_vm_read = 0x34,
+    process_vm_write = 0x35,
+    process_signal = 0x36,
+    process_signal_mask = 0x37,
     sched_yield = 0x50,
     sched_sleep_ns = 0x51,
+    sched_timer = 0x52,
+    sched_yield_to = 0x53,
+    futex_wait = 0x54,
+    futex_wake = 0x55,
+    sched_time_namespace = 0x56,
     ipc_subscribe = 0x70,
     ipc_unsubscribe = 0x71,
     ipc_publish = 0x72,
@@ -24,11 +38,19 @@ pub enum SyscallNumber {
     ipc_receive = 0x75,
     ipc_acknowledge = 0x76,
     ipc_select = 0x77,
+    ipc_transfer = 0x78,
+    ipc_publish_retained = 0x79,
+    ipc_restrict = 0x7a,
+    ipc_deliver_timeout = 0x7b,
     kernel_log_read = 0x80,
+    kernel_panic_action = 0x81,
+    kernel_shutdown = 0x82,
     irq_set_handler = 0x84,
     mmap_physical = 0x90,
     dma_allocate = 0x92,
     dma_free = 0x93,
+    shm_create = 0x94,
+    shm_map = 0x95,
 }
 
 #[derive(Debug, Copy, Clone, TryFromPrimitive, IntoPrimitive, Deserialize, Serialize)]
@@ -82,4 +104,77 @@ pub enum SyscallErrorCode {
     ptr_unaligned,
     /// Invalid or unsupported memory protection flags given to mmap
     mmap_invalid_protection_flags,
+    /// No process with the given id exists
+    process_not_found,
+    /// Privilege level of the process is too low for this operation
+    permission_denied,
+    /// Operation did not complete in the given time
+    timed_out,
+    /// Blocking operation was interrupted before it completed
+    interrupted,
+    /// A string argument, e.g. a topic name, is too long
+    name_too_long,
+    /// Reliable transfer failed: the receiving process terminated
+    /// before acknowledging the message
+    ipc_delivery_target_terminated,
+    /// Given buffer is too small for the result. Nothing was consumed.
+    buffer_too_small,
+    /// Invalid argument value
+    invalid_argument,
+    /// Operation is not supported or not implemented
+    not_supported,
+    /// Per-process resource quota would be exceeded
+    quota_exceeded,
+    /// Not enough (contiguous) memory available
+    out_of_memory,
+    /// Address range is not mapped, or not writable, in the target process
+    bad_address,
+    /// A named object, e.g. shared memory, already exists
+    already_exists,
+    /// No named object, e.g. shared memory, exists with the given name
+    not_found,
+    /// Image given to `exec` is not a valid or supported executable
+    invalid_executable,
+}
+impl SyscallErrorCode {
+    /// Closest POSIX `errno` value, for porting code that expects one.
+    /// The mapping is stable, but not one-to-one.
+    pub fn errno(self) -> i32 {
+        use SyscallErrorCode::*;
+        match self {
+            unknown => 5,                                  // EIO
+            empty_list_argument | invalid_argument => 22,  // EINVAL
+            would_block => 11,                             // EAGAIN
+            fs_node_exists => 17,                          // EEXIST
+            fs_node_path_blocked | fs_node_not_leaf => 20, // ENOTDIR
+            fs_node_not_found => 2,                        // ENOENT
+            fs_node_is_leaf => 21,                         // EISDIR
+            fs_unknown_control_function => 25,             // ENOTTY
+            fs_operation_not_supported | not_supported => 95, // ENOTSUP
+            fs_file_destroyed => 32,                       // EPIPE
+            fs_node_not_process | process_not_found => 3,  // ESRCH
+            ipc_invalid_topic => 22,                       // EINVAL
+            ipc_filter_exclusion => 98,                    // EADDRINUSE
+            ipc_delivery_no_target => 111,                 // ECONNREFUSED
+            ipc_delivery_target_full => 105,               // ENOBUFS
+            ipc_delivery_target_nack => 104,               // ECONNRESET
+            ipc_delivery_target_terminated => 32,          // EPIPE
+            ipc_unsubscribed => 9,                         // EBADF
+            ipc_re_acknowledge => 114,                     // EALREADY
+            ipc_permission_error | permission_denied => 1, // EPERM
+            invalid_utf8 => 84,                            // EILSEQ
+            ptr_unaligned => 14,                           // EFAULT
+            mmap_invalid_protection_flags => 22,           // EINVAL
+            timed_out => 110,                              // ETIMEDOUT
+            interrupted => 4,                              // EINTR
+            name_too_long => 36,                           // ENAMETOOLONG
+            buffer_too_small => 90,                        // EMSGSIZE
+            quota_exceeded => 122,                         // EDQUOT
+            out_of_memory => 12,                           // ENOMEM
+            bad_address => 14,                             // EFAULT
+            already_exists => 17,                          // EEXIST
+            not_found => 2,                                // ENOENT
+            invalid_executable => 8,                       // ENOEXEC
+        }
+    }
 }
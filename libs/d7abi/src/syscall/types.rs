@@ -1,4 +1,5 @@
 use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
 
 bitflags! {
     pub struct MemoryProtectionFlags: u8 {
@@ -7,3 +8,96 @@ bitflags! {
         const EXECUTE   = (1 << 2);
     }
 }
+
+bitflags! {
+    /// Flags for `exec`
+    pub struct ExecFlags: u64 {
+        /// Hide system-wide information, e.g. the process list, from the new
+        /// process and everything it spawns
+        const RESTRICTED_VIEW = (1 << 0);
+    }
+}
+
+/// Longest reboot delay of `PanicAction::Reboot`, one year.
+/// The kernel doesn't accept longer timer deadlines.
+pub const PANIC_REBOOT_DELAY_MAX_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+/// Action taken by the kernel on panic, set with `kernel_panic_action`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicAction {
+    /// Stop the system, leaving the screen visible for debugging
+    Halt,
+    /// Reboot after the given delay, e.g. for unattended test machines
+    Reboot { after_seconds: u64 },
+}
+impl PanicAction {
+    /// Encode as system call arguments
+    pub fn to_args(self) -> (u64, u64) {
+        match self {
+            Self::Halt => (0, 0),
+            Self::Reboot { after_seconds } => (1, after_seconds),
+        }
+    }
+
+    /// Decode from system call arguments.
+    /// Delays over `PANIC_REBOOT_DELAY_MAX_SECONDS` are rejected.
+    pub fn from_args(action: u64, value: u64) -> Option<Self> {
+        match action {
+            0 => Some(Self::Halt),
+            1 if value <= PANIC_REBOOT_DELAY_MAX_SECONDS => Some(Self::Reboot {
+                after_seconds: value,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Final step of a shutdown started with `kernel_shutdown`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ShutdownAction {
+    PowerOff,
+    Reboot,
+}
+impl ShutdownAction {
+    /// Encode as a system call argument
+    pub fn to_arg(self) -> u64 {
+        match self {
+            Self::PowerOff => 0,
+            Self::Reboot => 1,
+        }
+    }
+
+    /// Decode from a system call argument
+    pub fn from_arg(action: u64) -> Option<Self> {
+        match action {
+            0 => Some(Self::PowerOff),
+            1 => Some(Self::Reboot),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_panic_action_args() {
+        for action in &[
+            PanicAction::Halt,
+            PanicAction::Reboot { after_seconds: 0 },
+            PanicAction::Reboot {
+                after_seconds: PANIC_REBOOT_DELAY_MAX_SECONDS,
+            },
+        ] {
+            let (a, v) = action.to_args();
+            assert_eq!(PanicAction::from_args(a, v), Some(*action));
+        }
+        assert_eq!(
+            PanicAction::from_args(1, PANIC_REBOOT_DELAY_MAX_SECONDS + 1),
+            None
+        );
+        assert_eq!(PanicAction::from_args(1, u64::MAX), None);
+        assert_eq!(PanicAction::from_args(2, 0), None);
+    }
+}
@@ -0,0 +1,108 @@
+//! Clock data page, mapped read-only into every process at `PROCESS_TIME_PAGE`.
+//!
+//! The kernel refreshes the page on every scheduler tick on the BSP.
+//! Processes read it without a system call, and extrapolate from the
+//! base values using their own TSC reading:
+//!
+//! `ns = base_sec * 10^9 + base_nsec + ((tsc - tsc_base) * tsc_mult) >> tsc_shift`
+//!
+//! Monotonic time starts from zero at boot, so it is also the uptime.
+//! Processes in a time namespace, see `sched_time_namespace`, have a page
+//! of their own, whose values describe the virtualized clock.
+//!
+//! The page also contains a random boot id, chosen by the kernel on boot,
+//! which processes can include in logs to tell different boots apart.
+//!
+//! The page is protected by a sequence counter: it is odd while the kernel
+//! is writing to the page, and a reader must retry if the value changed
+//! during the read.
+//!
+//! # Layout (`repr(C)`, all fields little-endian)
+//!
+//! Offset | Size | Field
+//! -------|------|-------
+//! 0x00   | 4    | version
+//! 0x04   | 4    | sequence
+//! 0x08   | 8    | tsc_base
+//! 0x10   | 8    | base_sec
+//! 0x18   | 8    | base_nsec
+//! 0x20   | 8    | tsc_mult
+//! 0x28   | 8    | tsc_shift
+//! 0x30   | 16   | boot_id
+
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+use d7time::Duration;
+
+/// Incremented on every incompatible layout change
+pub const TIME_PAGE_VERSION: u32 = 1;
+
+/// Fraction bits of the fixed-point clock scale of `sched_time_namespace`,
+/// so that `1 << TIME_SCALE_SHIFT` is the speed of the real clock
+pub const TIME_SCALE_SHIFT: u64 = 32;
+
+#[repr(C)]
+pub struct TimePage {
+    /// Layout version, `TIME_PAGE_VERSION`, or zero if the page is not ready
+    pub version: AtomicU32,
+    /// Sequence counter, odd while an update is in progress
+    pub sequence: AtomicU32,
+    /// TSC value at the time of the last update
+    pub tsc_base: AtomicU64,
+    /// Monotonic time at `tsc_base`, whole seconds
+    pub base_sec: AtomicU64,
+    /// Monotonic time at `tsc_base`, nanoseconds part
+    pub base_nsec: AtomicU64,
+    /// TSC ticks to nanoseconds multiplier
+    pub tsc_mult: AtomicU64,
+    /// TSC ticks to nanoseconds shift
+    pub tsc_shift: AtomicU64,
+    /// Random id of the current boot, low half first. Written before
+    /// `version`, and never changed after that.
+    pub boot_id: [AtomicU64; 2],
+}
+impl TimePage {
+    /// Random id of the current boot
+    pub fn boot_id(&self) -> u128 {
+        let low = self.boot_id[0].load(Ordering::Relaxed) as u128;
+        let high = self.boot_id[1].load(Ordering::Relaxed) as u128;
+        (high << 64) | low
+    }
+
+    /// Consistent snapshot of the base values:
+    /// `(tsc_base, base, tsc_mult, tsc_shift)`
+    fn snapshot(&self) -> (u64, Duration, u64, u64) {
+        loop {
+            let seq = self.sequence.load(Ordering::Acquire);
+            if seq % 2 == 1 {
+                core::sync::atomic::spin_loop_hint();
+                continue;
+            }
+
+            let tsc_base = self.tsc_base.load(Ordering::Relaxed);
+            let sec = self.base_sec.load(Ordering::Relaxed);
+            let nsec = self.base_nsec.load(Ordering::Relaxed);
+            let mult = self.tsc_mult.load(Ordering::Relaxed);
+            let shift = self.tsc_shift.load(Ordering::Relaxed);
+
+            fence(Ordering::Acquire);
+            if self.sequence.load(Ordering::Relaxed) == seq {
+                return (tsc_base, Duration::new(sec, nsec as u32), mult, shift);
+            }
+        }
+    }
+
+    /// Monotonic time corresponding to the given TSC value.
+    /// Panics if the layout version doesn't match.
+    pub fn time_at(&self, tsc: u64) -> Duration {
+        assert_eq!(
+            self.version.load(Ordering::Acquire),
+            TIME_PAGE_VERSION,
+            "Time page version mismatch"
+        );
+
+        let (tsc_base, base, mult, shift) = self.snapshot();
+        let delta_ticks = tsc.saturating_sub(tsc_base) as u128;
+        let delta_ns = (delta_ticks * (mult as u128)) >> shift;
+        base + Duration::from_nanos(delta_ns as u64)
+    }
+}
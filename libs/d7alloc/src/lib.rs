@@ -8,16 +8,12 @@
 #![deny(unused_assignments)]
 #![no_std]
 
-extern crate spin;
-
 use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
 use core::ptr::NonNull;
 
 pub const HEAP_START: u64 = 0x4000_0000; // At 1 GiB
 pub const HEAP_SIZE: u64 = 0x640_0000; // 100 MiB heap
 
-use spin::Mutex;
-
 /// Align downwards. Returns the greatest x with alignment `align`
 /// so that x <= addr. The alignment must be a power of 2.
 pub fn align_down(addr: u64, align: u64) -> u64 {
@@ -54,6 +50,16 @@ impl BumpAllocator {
             next: AtomicU64::new(heap_start),
         }
     }
+
+    /// Bytes allocated so far. Freed memory is never reused,
+    /// so this is also the high watermark.
+    pub fn used_bytes(&self) -> u64 {
+        self.next.load(Ordering::Relaxed) - self.heap_start
+    }
+
+    pub fn size_bytes(&self) -> u64 {
+        self.heap_end - self.heap_start
+    }
 }
 
 unsafe impl<'a> Allocator for &'a BumpAllocator {
@@ -94,28 +100,34 @@ unsafe impl<'a> Allocator for &'a BumpAllocator {
     }
 }
 
+/// The bump allocator only updates an atomic, so it needs no lock,
+/// and the usage can be read e.g. from the scheduler tick.
 pub struct GlobAlloc {
-    alloc: Mutex<BumpAllocator>,
+    alloc: BumpAllocator,
 }
 impl GlobAlloc {
     pub const fn new(alloc: BumpAllocator) -> Self {
-        Self {
-            alloc: Mutex::new(alloc),
-        }
+        Self { alloc }
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.alloc.used_bytes()
+    }
+
+    pub fn size_bytes(&self) -> u64 {
+        self.alloc.size_bytes()
     }
 }
 unsafe impl GlobalAlloc for GlobAlloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let alloc = self.alloc.lock();
-        (&*alloc)
+        (&self.alloc)
             .allocate(layout)
             .expect("Could not allocate")
             .as_mut_ptr()
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let alloc = self.alloc.lock();
-        (&*alloc).deallocate(
+        (&self.alloc).deallocate(
             NonNull::new(ptr as *mut _).expect("Cannot deallocate null pointer"),
             layout,
         );
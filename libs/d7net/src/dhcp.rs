@@ -0,0 +1,302 @@
+//! https://en.wikipedia.org/wiki/Dynamic_Host_Configuration_Protocol
+//!
+//! Only the fields and options needed by a client are supported.
+//! Messages come from the network, so parsing returns `None` on invalid data.
+
+use alloc::prelude::v1::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{Ipv4Addr, MacAddr};
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Fixed-size fields before the magic cookie
+const FIXED_LEN: usize = 236;
+const FLAG_BROADCAST: u16 = 0x8000;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETER_REQUEST_LIST: u8 = 55;
+const OPTION_END: u8 = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+    Inform = 8,
+}
+impl MessageType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            1 => Self::Discover,
+            2 => Self::Offer,
+            3 => Self::Request,
+            4 => Self::Decline,
+            5 => Self::Ack,
+            6 => Self::Nak,
+            7 => Self::Release,
+            8 => Self::Inform,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Options {
+    pub message_type: Option<MessageType>,
+    pub subnet_mask: Option<Ipv4Addr>,
+    /// The first router, if any
+    pub router: Option<Ipv4Addr>,
+    pub dns: Vec<Ipv4Addr>,
+    pub requested_ip: Option<Ipv4Addr>,
+    pub lease_seconds: Option<u32>,
+    pub server_id: Option<Ipv4Addr>,
+    /// Options the client asks the server to send
+    pub parameter_request_list: Vec<u8>,
+}
+impl Options {
+    fn from_bytes(mut input: &[u8]) -> Option<Self> {
+        let mut result = Self::default();
+        let addr = |data: &[u8]| {
+            if data.len() >= 4 {
+                Some(Ipv4Addr::from_bytes(&data[..4]))
+            } else {
+                None
+            }
+        };
+        while let Some(&code) = input.first() {
+            match code {
+                OPTION_PAD => {
+                    input = &input[1..];
+                    continue;
+                },
+                OPTION_END => break,
+                _ => {},
+            }
+            let len = *input.get(1)? as usize;
+            let data = input.get(2..2 + len)?;
+            match code {
+                OPTION_SUBNET_MASK => result.subnet_mask = addr(data),
+                OPTION_ROUTER => result.router = addr(data),
+                OPTION_DNS => {
+                    result.dns = data.chunks_exact(4).map(Ipv4Addr::from_bytes).collect();
+                },
+                OPTION_REQUESTED_IP => result.requested_ip = addr(data),
+                OPTION_LEASE_TIME if len == 4 => {
+                    result.lease_seconds = Some(u32::from_be_bytes([
+                        data[0], data[1], data[2], data[3],
+                    ]));
+                },
+                OPTION_MESSAGE_TYPE if len == 1 => {
+                    result.message_type = MessageType::from_u8(data[0]);
+                },
+                OPTION_SERVER_ID => result.server_id = addr(data),
+                OPTION_PARAMETER_REQUEST_LIST => result.parameter_request_list = data.to_vec(),
+                _ => {},
+            }
+            input = &input[2 + len..];
+        }
+        Some(result)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        let mut option = |code: u8, data: &[u8]| {
+            result.push(code);
+            result.push(data.len() as u8);
+            result.extend(data);
+        };
+        if let Some(message_type) = self.message_type {
+            option(OPTION_MESSAGE_TYPE, &[message_type as u8]);
+        }
+        if let Some(mask) = self.subnet_mask {
+            option(OPTION_SUBNET_MASK, &mask.0);
+        }
+        if let Some(router) = self.router {
+            option(OPTION_ROUTER, &router.0);
+        }
+        if !self.dns.is_empty() {
+            let data: Vec<u8> = self.dns.iter().flat_map(|a| a.0.iter().copied()).collect();
+            option(OPTION_DNS, &data);
+        }
+        if let Some(ip) = self.requested_ip {
+            option(OPTION_REQUESTED_IP, &ip.0);
+        }
+        if let Some(seconds) = self.lease_seconds {
+            option(OPTION_LEASE_TIME, &seconds.to_be_bytes());
+        }
+        if let Some(server) = self.server_id {
+            option(OPTION_SERVER_ID, &server.0);
+        }
+        if !self.parameter_request_list.is_empty() {
+            option(OPTION_PARAMETER_REQUEST_LIST, &self.parameter_request_list);
+        }
+        result.push(OPTION_END);
+        // Return
+        result
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Message {
+    /// Sent by a server, instead of a client
+    pub is_reply: bool,
+    /// Transaction id, chosen by the client
+    pub xid: u32,
+    /// Ask the server to broadcast the reply
+    pub broadcast: bool,
+    /// `ciaddr`, address of a configured client
+    pub client_ip: Ipv4Addr,
+    /// `yiaddr`, address offered to the client
+    pub your_ip: Ipv4Addr,
+    /// `siaddr`, next server for booting
+    pub server_ip: Ipv4Addr,
+    pub client_mac: MacAddr,
+    pub options: Options,
+}
+impl Message {
+    /// Options requested by the client messages
+    const REQUESTED: [u8; 4] = [
+        OPTION_SUBNET_MASK,
+        OPTION_ROUTER,
+        OPTION_DNS,
+        OPTION_LEASE_TIME,
+    ];
+
+    fn client_message(xid: u32, mac: MacAddr, options: Options) -> Self {
+        Self {
+            is_reply: false,
+            xid,
+            broadcast: true,
+            client_ip: Ipv4Addr::ZERO,
+            your_ip: Ipv4Addr::ZERO,
+            server_ip: Ipv4Addr::ZERO,
+            client_mac: mac,
+            options,
+        }
+    }
+
+    pub fn discover(xid: u32, mac: MacAddr) -> Self {
+        Self::client_message(xid, mac, Options {
+            message_type: Some(MessageType::Discover),
+            parameter_request_list: Self::REQUESTED.to_vec(),
+            ..Options::default()
+        })
+    }
+
+    /// Request the address offered by a server
+    pub fn request(xid: u32, mac: MacAddr, ip: Ipv4Addr, server: Ipv4Addr) -> Self {
+        Self::client_message(xid, mac, Options {
+            message_type: Some(MessageType::Request),
+            requested_ip: Some(ip),
+            server_id: Some(server),
+            parameter_request_list: Self::REQUESTED.to_vec(),
+            ..Options::default()
+        })
+    }
+
+    pub fn from_bytes(input: &[u8]) -> Option<Self> {
+        if input.len() < FIXED_LEN + MAGIC_COOKIE.len()
+            || input[FIXED_LEN..FIXED_LEN + 4] != MAGIC_COOKIE
+            || input[1] != 1
+            || input[2] != 6
+        {
+            return None;
+        }
+        Some(Self {
+            is_reply: input[0] == OP_REPLY,
+            xid: u32::from_be_bytes([input[4], input[5], input[6], input[7]]),
+            broadcast: u16::from_be_bytes([input[10], input[11]]) & FLAG_BROADCAST != 0,
+            client_ip: Ipv4Addr::from_bytes(&input[12..16]),
+            your_ip: Ipv4Addr::from_bytes(&input[16..20]),
+            server_ip: Ipv4Addr::from_bytes(&input[20..24]),
+            client_mac: MacAddr::from_bytes(&input[28..34]),
+            options: Options::from_bytes(&input[FIXED_LEN + 4..])?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        result.push(if self.is_reply { OP_REPLY } else { OP_REQUEST });
+        // Ethernet, MAC address length, hops
+        result.extend(&[1, 6, 0]);
+        result.extend(&self.xid.to_be_bytes());
+        // Seconds elapsed
+        result.extend(&[0, 0]);
+        let flags = if self.broadcast { FLAG_BROADCAST } else { 0 };
+        result.extend(&flags.to_be_bytes());
+        result.extend(&self.client_ip.0);
+        result.extend(&self.your_ip.0);
+        result.extend(&self.server_ip.0);
+        // Relay agent address
+        result.extend(&Ipv4Addr::ZERO.0);
+        // Client hardware address, padded to 16 bytes
+        result.extend(&self.client_mac.0);
+        result.extend(&[0; 10]);
+        // Server name and boot file name
+        result.extend(&[0; 64 + 128][..]);
+        result.extend(&MAGIC_COOKIE);
+        result.extend(self.options.to_bytes());
+        // Return
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mac = MacAddr::from_bytes(&[1, 2, 3, 4, 5, 6]);
+        let message = Message::request(
+            0x1234_5678,
+            mac,
+            Ipv4Addr([10, 0, 2, 15]),
+            Ipv4Addr([10, 0, 2, 2]),
+        );
+        let bytes = message.to_bytes();
+        assert_eq!(&bytes[4..8], &[0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(Message::from_bytes(&bytes), Some(message));
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let mut ack = Message::discover(1, MacAddr::ZERO);
+        ack.is_reply = true;
+        ack.your_ip = Ipv4Addr([10, 0, 2, 15]);
+        ack.options = Options {
+            message_type: Some(MessageType::Ack),
+            subnet_mask: Some(Ipv4Addr([255, 255, 255, 0])),
+            router: Some(Ipv4Addr([10, 0, 2, 2])),
+            dns: vec![Ipv4Addr([10, 0, 2, 3]), Ipv4Addr([1, 1, 1, 1])],
+            lease_seconds: Some(86400),
+            server_id: Some(Ipv4Addr([10, 0, 2, 2])),
+            ..Options::default()
+        };
+        let mut bytes = ack.to_bytes();
+        // Padding before the end is skipped
+        bytes.insert(FIXED_LEN + 4, OPTION_PAD);
+        assert_eq!(Message::from_bytes(&bytes), Some(ack));
+
+        // Truncated options
+        let end = bytes.len() - 3;
+        assert_eq!(Message::from_bytes(&bytes[..end]), None);
+        assert_eq!(Message::from_bytes(&bytes[..100]), None);
+    }
+}
@@ -0,0 +1,254 @@
+//! Minimal HTTP/1.1 client protocol support, for fetching binaries.
+//! Only `GET` requests and `Content-Length` delimited or
+//! connection-close delimited responses are supported.
+//!
+//! This is only the protocol part. Nothing fetches with it yet, as the
+//! fetch service needs TCP, which `netd` doesn't implement.
+
+use alloc::prelude::v1::*;
+use core::str;
+
+/// Parsed `http://host[:port]/path` URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+impl Url {
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rfind(':') {
+            Some(i) => (&authority[..i], authority[i + 1..].parse().ok()?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(Self {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+
+    /// `GET` request for this url
+    pub fn get_request(&self) -> Vec<u8> {
+        format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: d7\r\n\r\n",
+            self.path, self.host
+        )
+        .into_bytes()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Status line or headers malformed
+    Malformed,
+    /// Header section too large
+    HeadersTooLarge,
+    /// Server uses transfer encoding that is not supported
+    UnsupportedEncoding,
+    /// More body data than `Content-Length` specified
+    TooMuchData,
+    /// Connection closed before `Content-Length` bytes were received
+    Truncated,
+}
+
+/// Limit for the status line and headers combined
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseHeader {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub content_length: Option<u64>,
+}
+impl ResponseHeader {
+    fn parse(input: &[u8]) -> Result<Self, Error> {
+        let text = str::from_utf8(input).map_err(|_| Error::Malformed)?;
+        let mut lines = text.split("\r\n");
+
+        let status_line = lines.next().ok_or(Error::Malformed)?;
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().ok_or(Error::Malformed)?;
+        if !version.starts_with("HTTP/1.") {
+            return Err(Error::Malformed);
+        }
+        let status: u16 = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or(Error::Malformed)?;
+
+        let mut headers = Vec::new();
+        let mut content_length = None;
+        for line in lines.filter(|l| !l.is_empty()) {
+            let i = line.find(':').ok_or(Error::Malformed)?;
+            let name = line[..i].trim().to_owned();
+            let value = line[i + 1..].trim().to_owned();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.parse().map_err(|_| Error::Malformed)?);
+            } else if name.eq_ignore_ascii_case("transfer-encoding")
+                && !value.eq_ignore_ascii_case("identity")
+            {
+                return Err(Error::UnsupportedEncoding);
+            }
+            headers.push((name, value));
+        }
+
+        Ok(Self {
+            status,
+            headers,
+            content_length,
+        })
+    }
+}
+
+/// Incremental response reader, fed with data as it arrives from the connection
+#[derive(Debug, Clone)]
+pub struct ResponseReader {
+    buffer: Vec<u8>,
+    header: Option<ResponseHeader>,
+}
+impl ResponseReader {
+    pub fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            header: None,
+        }
+    }
+
+    pub fn header(&self) -> Option<&ResponseHeader> {
+        self.header.as_ref()
+    }
+
+    /// Progress as `(received_body_bytes, total_bytes_if_known)`
+    pub fn progress(&self) -> (u64, Option<u64>) {
+        match &self.header {
+            Some(h) => (self.buffer.len() as u64, h.content_length),
+            None => (0, None),
+        }
+    }
+
+    /// True when the whole body has been received.
+    /// Responses without `Content-Length` are complete only at connection close.
+    pub fn is_complete(&self) -> bool {
+        match &self.header {
+            Some(ResponseHeader {
+                content_length: Some(len),
+                ..
+            }) => self.buffer.len() as u64 == *len,
+            _ => false,
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.buffer.extend_from_slice(data);
+
+        if self.header.is_none() {
+            let end = self.buffer.windows(4).position(|w| w == b"\r\n\r\n");
+            match end {
+                Some(i) => {
+                    self.header = Some(ResponseHeader::parse(&self.buffer[..i])?);
+                    self.buffer.drain(..i + 4);
+                },
+                None if self.buffer.len() > MAX_HEADER_SIZE => {
+                    return Err(Error::HeadersTooLarge);
+                },
+                None => return Ok(()),
+            }
+        }
+
+        if let Some(len) = self.header.as_ref().unwrap().content_length {
+            if self.buffer.len() as u64 > len {
+                return Err(Error::TooMuchData);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Connection closed, returns header and body
+    pub fn finish(self) -> Result<(ResponseHeader, Vec<u8>), Error> {
+        let header = self.header.ok_or(Error::Truncated)?;
+        if let Some(len) = header.content_length {
+            if self.buffer.len() as u64 != len {
+                return Err(Error::Truncated);
+            }
+        }
+        Ok((header, self.buffer))
+    }
+}
+
+/// CRC-32 (IEEE 802.3), used to verify fetched binaries
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        assert_eq!(
+            Url::parse("http://10.0.2.2:8000/bin/examplebin"),
+            Some(Url {
+                host: "10.0.2.2".to_owned(),
+                port: 8000,
+                path: "/bin/examplebin".to_owned(),
+            })
+        );
+        assert_eq!(Url::parse("http://host").unwrap().path, "/");
+        assert_eq!(Url::parse("http://host").unwrap().port, 80);
+        assert_eq!(Url::parse("https://host/"), None);
+        assert_eq!(Url::parse("http://:80/"), None);
+    }
+
+    #[test]
+    fn test_response() {
+        let mut r = ResponseReader::new();
+        r.feed(b"HTTP/1.1 200 OK\r\nContent-Le").unwrap();
+        assert!(r.header().is_none());
+        r.feed(b"ngth: 5\r\n\r\nab").unwrap();
+        assert_eq!(r.header().unwrap().status, 200);
+        assert_eq!(r.progress(), (2, Some(5)));
+        assert!(!r.is_complete());
+        r.feed(b"cde").unwrap();
+        assert!(r.is_complete());
+        let (_, body) = r.finish().unwrap();
+        assert_eq!(body, b"abcde");
+    }
+
+    #[test]
+    fn test_response_errors() {
+        let mut r = ResponseReader::new();
+        assert_eq!(
+            r.feed(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"),
+            Err(Error::UnsupportedEncoding)
+        );
+
+        let mut r = ResponseReader::new();
+        r.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nab").unwrap();
+        assert_eq!(r.finish(), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,81 @@
+//! https://en.wikipedia.org/wiki/Internet_Control_Message_Protocol
+
+use alloc::prelude::v1::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ipv4::checksum;
+
+pub const TYPE_ECHO_REPLY: u8 = 0;
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Packet {
+    pub type_: u8,
+    pub code: u8,
+    /// Contents depend on the type, e.g. identifier and sequence number of echo
+    pub rest_of_header: [u8; 4],
+    pub payload: Vec<u8>,
+}
+impl Packet {
+    /// Checksum is not verified
+    pub fn from_bytes(input: &[u8]) -> Self {
+        let mut rest_of_header = [0; 4];
+        rest_of_header.copy_from_slice(&input[4..8]);
+        Self {
+            type_: input[0],
+            code: input[1],
+            rest_of_header,
+            payload: input[8..].to_vec(),
+        }
+    }
+
+    /// The checksum is computed
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut result = Vec::new();
+        result.push(self.type_);
+        result.push(self.code);
+        // Checksum, computed below
+        result.extend(&[0, 0]);
+        result.extend(&self.rest_of_header);
+        result.extend(&self.payload);
+        let checksum = checksum(&result);
+        result[2..4].copy_from_slice(&checksum.to_be_bytes());
+        // Return
+        result
+    }
+
+    pub fn is_echo_request(&self) -> bool {
+        self.type_ == TYPE_ECHO_REQUEST && self.code == 0
+    }
+
+    /// Echo reply with the same identifier, sequence number and data
+    pub fn to_echo_reply(mut self) -> Self {
+        assert!(self.is_echo_request());
+        self.type_ = TYPE_ECHO_REPLY;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_echo_reply() {
+        let request = Packet {
+            type_: TYPE_ECHO_REQUEST,
+            code: 0,
+            rest_of_header: [0, 1, 0, 7],
+            payload: vec![1, 2, 3],
+        };
+        let bytes = request.clone().to_bytes();
+        assert_eq!(checksum(&bytes), 0);
+        let parsed = Packet::from_bytes(&bytes);
+        assert_eq!(parsed, request);
+
+        let reply = parsed.to_echo_reply().to_bytes();
+        assert_eq!(reply[0], TYPE_ECHO_REPLY);
+        assert_eq!(checksum(&reply), 0);
+        assert_eq!(&reply[4..], &bytes[4..]);
+    }
+}
@@ -8,6 +8,12 @@ use crate::Ipv4Addr;
 
 pub use crate::ip_protocol::IpProtocol;
 
+/// Flags field value of an unfragmented packet
+pub const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+
+/// Time to live of sent packets
+pub const DEFAULT_TTL: u8 = 64;
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Packet {
     pub header: Header,
@@ -22,10 +28,13 @@ impl Packet {
         }
     }
 
-    /// https://en.wikipedia.org/wiki/Address_Resolution_Protocol#Packet_structure
+    /// The payload length of the header is set from the payload
     pub fn to_bytes(self) -> Vec<u8> {
-        let mut result = Vec::new();
-        todo!();
+        let mut header = self.header;
+        header.payload_len = self.payload.len() as u16;
+        let mut result = header.to_bytes();
+        result.extend(&self.payload);
+        // Return
         result
     }
 }
@@ -64,9 +73,85 @@ impl Header {
         }
     }
 
+    /// Header of an unfragmented packet
+    pub fn new(
+        protocol: IpProtocol, src_ip: Ipv4Addr, dst_ip: Ipv4Addr, payload_len: u16,
+    ) -> Self {
+        Self {
+            dscp_and_ecn: 0,
+            payload_len,
+            identification: 0,
+            flags_and_frament: FLAG_DONT_FRAGMENT,
+            ttl: DEFAULT_TTL,
+            protocol,
+            src_ip,
+            dst_ip,
+        }
+    }
+
+    /// The header checksum is computed
     pub fn to_bytes(self) -> Vec<u8> {
         let mut result = Vec::new();
-        todo!();
+        // Version 4, IHL 5
+        result.push(0x45);
+        result.push(self.dscp_and_ecn);
+        result.extend(&u16::to_be_bytes(self.payload_len + 20));
+        result.extend(&u16::to_be_bytes(self.identification));
+        result.extend(&u16::to_be_bytes(self.flags_and_frament));
+        result.push(self.ttl);
+        result.push(self.protocol as u8);
+        // Checksum, computed below
+        result.extend(&[0, 0]);
+        result.extend(&self.src_ip.0);
+        result.extend(&self.dst_ip.0);
+        let checksum = checksum(&result);
+        result[10..12].copy_from_slice(&checksum.to_be_bytes());
+        // Return
         result
     }
 }
+
+/// Internet checksum, used by IPv4 headers, ICMP, UDP and TCP.
+/// Checksummed data including a valid checksum gives zero.
+/// https://en.wikipedia.org/wiki/Internet_checksum
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in data.chunks(2) {
+        let high = chunk[0];
+        let low = chunk.get(1).copied().unwrap_or(0);
+        sum += u16::from_be_bytes([high, low]) as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = Header::new(
+            IpProtocol::UDP,
+            Ipv4Addr([10, 0, 2, 15]),
+            Ipv4Addr([10, 0, 2, 2]),
+            12,
+        );
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), 20);
+        assert_eq!(checksum(&bytes), 0);
+        assert_eq!(Header::from_bytes(&bytes), header);
+    }
+
+    #[test]
+    fn test_checksum() {
+        // Example from https://en.wikipedia.org/wiki/IPv4_header_checksum
+        let example: Vec<u8> = vec![
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(checksum(&example), 0xb861);
+    }
+}
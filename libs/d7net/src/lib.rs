@@ -16,9 +16,15 @@ mod ip_protocol;
 mod mac;
 
 pub mod arp;
+pub mod dhcp;
 pub mod ethernet;
+pub mod http;
+pub mod icmp;
 pub mod ipv4;
+pub mod pcap;
 pub mod tcp;
+pub mod tftp;
+pub mod udp;
 
 pub use self::ethertype::EtherType;
 pub use self::ip_addr::*;
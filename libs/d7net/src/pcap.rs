@@ -0,0 +1,77 @@
+//! Packet capture file format, readable with standard tools like Wireshark.
+//! https://wiki.wireshark.org/Development/LibpcapFileFormat
+
+use alloc::prelude::v1::*;
+use core::time::Duration;
+
+/// Link type for ethernet frames
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Frames are truncated to this many bytes by default
+pub const DEFAULT_SNAPLEN: u32 = 0xffff;
+
+/// File header, written once before the records
+pub fn global_header(snaplen: u32) -> Vec<u8> {
+    let mut result = Vec::new();
+    result.extend(&0xa1b2_c3d4u32.to_le_bytes()); // Magic, microsecond timestamps
+    result.extend(&2u16.to_le_bytes()); // Version major
+    result.extend(&4u16.to_le_bytes()); // Version minor
+    result.extend(&0i32.to_le_bytes()); // Timezone correction
+    result.extend(&0u32.to_le_bytes()); // Timestamp accuracy
+    result.extend(&snaplen.to_le_bytes());
+    result.extend(&LINKTYPE_ETHERNET.to_le_bytes());
+    // Return
+    result
+}
+
+/// Captured frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Capture time, relative to any fixed point
+    pub timestamp: Duration,
+    /// Frame length on the wire
+    pub original_len: u32,
+    /// Frame contents, possibly truncated
+    pub data: Vec<u8>,
+}
+impl Record {
+    pub fn new(timestamp: Duration, frame: &[u8], snaplen: u32) -> Self {
+        let len = frame.len().min(snaplen as usize);
+        Self {
+            timestamp,
+            original_len: frame.len() as u32,
+            data: frame[..len].to_vec(),
+        }
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut result = Vec::new();
+        result.extend(&(self.timestamp.as_secs() as u32).to_le_bytes());
+        result.extend(&self.timestamp.subsec_micros().to_le_bytes());
+        result.extend(&(self.data.len() as u32).to_le_bytes());
+        result.extend(&self.original_len.to_le_bytes());
+        result.extend(&self.data);
+        // Return
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_global_header() {
+        let header = global_header(DEFAULT_SNAPLEN);
+        assert_eq!(header.len(), 24);
+        assert_eq!(&header[..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+    }
+
+    #[test]
+    fn test_record() {
+        let record = Record::new(Duration::new(3, 2_000), &[1, 2, 3, 4], 2);
+        assert_eq!(record.to_bytes(), vec![
+            3, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 4, 0, 0, 0, 1, 2
+        ]);
+    }
+}
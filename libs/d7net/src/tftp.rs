@@ -0,0 +1,245 @@
+//! TFTP client protocol (RFC 1350), read requests only.
+//! Used to download binaries from the TFTP server provided by QEMU user networking.
+
+use alloc::prelude::v1::*;
+use core::str;
+
+/// Well-known server port for read requests
+pub const SERVER_PORT: u16 = 69;
+
+/// Data block size, a shorter block ends the transfer
+pub const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    ReadRequest { filename: String },
+    Data { block: u16, data: Vec<u8> },
+    Ack { block: u16 },
+    Error { code: u16, message: String },
+}
+impl Packet {
+    /// Returns None on malformed or unsupported packets
+    pub fn from_bytes(input: &[u8]) -> Option<Self> {
+        if input.len() < 4 {
+            return None;
+        }
+        let opcode = u16::from_be_bytes([input[0], input[1]]);
+        let arg = u16::from_be_bytes([input[2], input[3]]);
+        match opcode {
+            1 => {
+                let mut fields = input[2..].split(|b| *b == 0);
+                let filename = str::from_utf8(fields.next()?).ok()?.to_owned();
+                Some(Self::ReadRequest { filename })
+            },
+            3 if input.len() <= 4 + BLOCK_SIZE => Some(Self::Data {
+                block: arg,
+                data: input[4..].to_vec(),
+            }),
+            4 => Some(Self::Ack { block: arg }),
+            5 => {
+                let message = input[4..].split(|b| *b == 0).next()?;
+                Some(Self::Error {
+                    code: arg,
+                    message: str::from_utf8(message).ok()?.to_owned(),
+                })
+            },
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut result = Vec::new();
+        match self {
+            Self::ReadRequest { filename } => {
+                result.extend(&1u16.to_be_bytes());
+                result.extend(filename.as_bytes());
+                result.push(0);
+                result.extend(b"octet");
+                result.push(0);
+            },
+            Self::Data { block, data } => {
+                result.extend(&3u16.to_be_bytes());
+                result.extend(&block.to_be_bytes());
+                result.extend(&data);
+            },
+            Self::Ack { block } => {
+                result.extend(&4u16.to_be_bytes());
+                result.extend(&block.to_be_bytes());
+            },
+            Self::Error { code, message } => {
+                result.extend(&5u16.to_be_bytes());
+                result.extend(&code.to_be_bytes());
+                result.extend(message.as_bytes());
+                result.push(0);
+            },
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Server sent an error packet
+    Server { code: u16, message: String },
+    /// Unexpected or malformed packet
+    Protocol,
+}
+
+/// What the caller should do after a packet has been processed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Send this packet to the server transfer port
+    Send(Vec<u8>),
+    /// Send this final acknowledgement, the transfer is complete
+    Done(Vec<u8>),
+    /// Ignore the packet, e.g. a duplicate data block
+    Ignore,
+}
+
+/// Read transfer state
+#[derive(Debug, Clone)]
+pub struct Client {
+    filename: String,
+    /// Server transfer port, selected by the server on the first data packet
+    server_port: Option<u16>,
+    /// Last block received
+    block: u16,
+    data: Vec<u8>,
+}
+impl Client {
+    /// Returns the client and the read request to send to `SERVER_PORT`
+    pub fn new(filename: &str) -> (Self, Vec<u8>) {
+        let request = Packet::ReadRequest {
+            filename: filename.to_owned(),
+        };
+        let client = Self {
+            filename: filename.to_owned(),
+            server_port: None,
+            block: 0,
+            data: Vec::new(),
+        };
+        (client, request.to_bytes())
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn server_port(&self) -> Option<u16> {
+        self.server_port
+    }
+
+    /// Bytes received so far
+    pub fn received(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Packet to resend on timeout
+    pub fn retransmit(&self) -> Vec<u8> {
+        if self.block == 0 {
+            Packet::ReadRequest {
+                filename: self.filename.clone(),
+            }
+            .to_bytes()
+        } else {
+            Packet::Ack { block: self.block }.to_bytes()
+        }
+    }
+
+    /// Process a packet from port `src_port`
+    pub fn on_packet(&mut self, src_port: u16, input: &[u8]) -> Result<Action, Error> {
+        if let Some(port) = self.server_port {
+            if port != src_port {
+                // Packet from another transfer, RFC 1350 section 4
+                return Ok(Action::Ignore);
+            }
+        }
+
+        match Packet::from_bytes(input).ok_or(Error::Protocol)? {
+            Packet::Data { block, data } => {
+                self.server_port = Some(src_port);
+                if block == self.block {
+                    // Duplicate, our ack was lost
+                    return Ok(Action::Send(Packet::Ack { block }.to_bytes()));
+                }
+                if block != self.block.wrapping_add(1) {
+                    return Ok(Action::Ignore);
+                }
+                self.block = block;
+                let last = data.len() < BLOCK_SIZE;
+                self.data.extend(data);
+                let ack = Packet::Ack { block }.to_bytes();
+                Ok(if last { Action::Done(ack) } else { Action::Send(ack) })
+            },
+            Packet::Error { code, message } => Err(Error::Server { code, message }),
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    /// Downloaded file contents, call after `Action::Done`
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_packet_roundtrip() {
+        for packet in vec![
+            Packet::ReadRequest {
+                filename: "examplebin".to_owned(),
+            },
+            Packet::Data {
+                block: 3,
+                data: vec![1, 2, 3],
+            },
+            Packet::Ack { block: 7 },
+            Packet::Error {
+                code: 1,
+                message: "File not found".to_owned(),
+            },
+        ] {
+            assert_eq!(Packet::from_bytes(&packet.clone().to_bytes()), Some(packet));
+        }
+    }
+
+    #[test]
+    fn test_transfer() {
+        let (mut client, rrq) = Client::new("initrd");
+        assert_eq!(&rrq[..2], &[0, 1]);
+
+        let block1 = Packet::Data {
+            block: 1,
+            data: vec![0xaa; BLOCK_SIZE],
+        };
+        let ack1 = Packet::Ack { block: 1 }.to_bytes();
+        assert_eq!(
+            client.on_packet(1234, &block1.clone().to_bytes()),
+            Ok(Action::Send(ack1.clone()))
+        );
+        // Duplicate is acknowledged again
+        assert_eq!(
+            client.on_packet(1234, &block1.to_bytes()),
+            Ok(Action::Send(ack1))
+        );
+        // Other ports are ignored
+        let other = Packet::Data {
+            block: 2,
+            data: vec![],
+        };
+        assert_eq!(client.on_packet(999, &other.to_bytes()), Ok(Action::Ignore));
+
+        let block2 = Packet::Data {
+            block: 2,
+            data: vec![0xbb; 3],
+        };
+        assert_eq!(
+            client.on_packet(1234, &block2.to_bytes()),
+            Ok(Action::Done(Packet::Ack { block: 2 }.to_bytes()))
+        );
+        assert_eq!(client.into_data().len(), BLOCK_SIZE + 3);
+    }
+}
@@ -0,0 +1,111 @@
+//! https://en.wikipedia.org/wiki/User_Datagram_Protocol#UDP_datagram_structure
+//!
+//! Datagrams come from the network, so parsing returns `None` on invalid data.
+
+use alloc::prelude::v1::*;
+use serde::{Deserialize, Serialize};
+
+pub const HEADER_LEN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Datagram {
+    pub header: Header,
+    pub payload: Vec<u8>,
+}
+impl Datagram {
+    /// Bytes after `header.length` are ignored
+    pub fn from_bytes(input: &[u8]) -> Option<Self> {
+        let header = Header::from_bytes(input)?;
+        let length = header.length as usize;
+        if length < HEADER_LEN {
+            return None;
+        }
+        Some(Self {
+            header,
+            payload: input.get(HEADER_LEN..length)?.to_vec(),
+        })
+    }
+
+    /// Checksum is left zero, which is allowed with IPv4
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut result = Vec::new();
+        result.extend(&self.header.to_bytes());
+        result.extend(&self.payload);
+        // Return
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Header {
+    pub src_port: u16,
+    pub dst_port: u16,
+    /// Length of header and payload
+    pub length: u16,
+    pub checksum: u16,
+}
+impl Header {
+    pub fn from_bytes(input: &[u8]) -> Option<Self> {
+        if input.len() < HEADER_LEN {
+            return None;
+        }
+        Some(Self {
+            src_port: u16::from_be_bytes([input[0], input[1]]),
+            dst_port: u16::from_be_bytes([input[2], input[3]]),
+            length: u16::from_be_bytes([input[4], input[5]]),
+            checksum: u16::from_be_bytes([input[6], input[7]]),
+        })
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut result = Vec::new();
+        result.extend(&u16::to_be_bytes(self.src_port));
+        result.extend(&u16::to_be_bytes(self.dst_port));
+        result.extend(&u16::to_be_bytes(self.length));
+        result.extend(&u16::to_be_bytes(self.checksum));
+        // Return
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_datagram() {
+        let example: Vec<u8> = vec![0x04, 0x00, 0x00, 0x45, 0x00, 0x0b, 0x00, 0x00, 1, 2, 3];
+
+        let datagram = Datagram::from_bytes(&example).unwrap();
+        assert_eq!(datagram, Datagram {
+            header: Header {
+                src_port: 1024,
+                dst_port: 69,
+                length: 11,
+                checksum: 0,
+            },
+            payload: vec![1, 2, 3],
+        });
+
+        assert_eq!(datagram.to_bytes(), example);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        // Shorter than the header
+        assert_eq!(Header::from_bytes(&[0x04, 0x00, 0x00]), None);
+        assert_eq!(Datagram::from_bytes(&[0x04, 0x00, 0x00, 0x45, 0x00]), None);
+
+        // Length field smaller than the header
+        let short_length = [0x04, 0x00, 0x00, 0x45, 0x00, 0x07, 0x00, 0x00, 1];
+        assert_eq!(Datagram::from_bytes(&short_length), None);
+
+        // Truncated payload
+        let truncated = [0x04, 0x00, 0x00, 0x45, 0x00, 0x0b, 0x00, 0x00, 1, 2];
+        assert_eq!(Datagram::from_bytes(&truncated), None);
+
+        // Trailing bytes, e.g. Ethernet padding, are not part of the payload
+        let padded = [0x04, 0x00, 0x00, 0x45, 0x00, 0x09, 0x00, 0x00, 1, 0, 0];
+        assert_eq!(Datagram::from_bytes(&padded).unwrap().payload, vec![1]);
+    }
+}
@@ -101,4 +101,9 @@ impl Device {
     pub unsafe fn enable_bus_mastering(&self) {
         self.write(0x04, self.read(0x04) | (1 << 2));
     }
+
+    /// Allows accessing memory-mapped BARs, if the firmware hasn't done so
+    pub unsafe fn enable_memory_space(&self) {
+        self.write(0x04, self.read(0x04) | (1 << 1));
+    }
 }
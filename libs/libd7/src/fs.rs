@@ -0,0 +1,155 @@
+//! Files on mounted filesystems, see `d7abi::ipc::protocol::filesystem`.
+//!
+//! Each read and write is a request to the filesystem server, and blocks
+//! until the server replies. Requests longer than `MAX_TRANSFER_BYTES`
+//! are shortened by the server, so `read` and `write` return the number
+//! of bytes done, like their POSIX counterparts.
+
+use alloc::prelude::v1::*;
+
+use d7abi::ipc::protocol::filesystem::{mount_topic, Request, Response};
+pub use d7abi::ipc::protocol::filesystem::{DirEntry, MAX_TRANSFER_BYTES};
+
+use crate::ipc;
+use crate::syscall::SyscallErrorCode;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Error {
+    /// The request could not be delivered, e.g. the filesystem is not mounted
+    Ipc(SyscallErrorCode),
+    NotFound,
+    /// A file was given where a directory is expected, or vice versa
+    WrongKind,
+    /// The name cannot be created on this filesystem
+    InvalidName,
+    NoSpace,
+    /// The directory to remove has entries
+    NotEmpty,
+    /// The device failed, or the filesystem is corrupted
+    Io,
+    /// The server replied with a response to a different request
+    Protocol,
+}
+impl From<SyscallErrorCode> for Error {
+    fn from(error: SyscallErrorCode) -> Self {
+        Self::Ipc(error)
+    }
+}
+
+/// A file with a cursor. Nothing is opened on the server, so the file is
+/// created by the first write, and it is not an error if it doesn't exist yet.
+#[derive(Debug)]
+pub struct File {
+    topic: String,
+    path: String,
+    cursor: u64,
+}
+impl File {
+    /// File at `path` on the filesystem mounted as `mount`, e.g. `ata0p1`
+    pub fn new(mount: &str, path: &str) -> Self {
+        Self {
+            topic: mount_topic(mount),
+            path: path.to_owned(),
+            cursor: 0,
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.cursor
+    }
+
+    /// Moves the cursor. It can be past the end of the file,
+    /// in which case a write fills the gap with zeroes.
+    pub fn set_position(&mut self, position: u64) {
+        self.cursor = position;
+    }
+
+    /// Reads from the cursor and advances it.
+    /// Returns the number of bytes read, zero at the end of the file.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let response = request(&self.topic, Request::Read {
+            path: self.path.clone(),
+            offset: self.cursor,
+            len: buffer.len() as u64,
+        })?;
+        match response {
+            Response::Read(data) if data.len() <= buffer.len() => {
+                buffer[..data.len()].copy_from_slice(&data);
+                self.cursor += data.len() as u64;
+                Ok(data.len())
+            },
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    /// Writes at the cursor and advances it.
+    /// Returns the number of bytes written, which can be less than `data.len()`.
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let len = data.len().min(MAX_TRANSFER_BYTES as usize);
+        let response = request(&self.topic, Request::Write {
+            path: self.path.clone(),
+            offset: self.cursor,
+            data: data[..len].to_vec(),
+        })?;
+        match response {
+            Response::Write(written) if written <= len as u64 => {
+                self.cursor += written;
+                Ok(written as usize)
+            },
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    /// Writes all of `data`, in as many requests as needed
+    pub fn write_all(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        while !data.is_empty() {
+            let written = self.write(data)?;
+            if written == 0 {
+                return Err(Error::NoSpace);
+            }
+            data = &data[written..];
+        }
+        Ok(())
+    }
+}
+
+/// Entries of a directory on the filesystem mounted as `mount`
+pub fn list(mount: &str, path: &str) -> Result<Vec<DirEntry>, Error> {
+    match request(&mount_topic(mount), Request::List(path.to_owned()))? {
+        Response::List(entries) => Ok(entries),
+        _ => Err(Error::Protocol),
+    }
+}
+
+/// Removes a file, or an empty directory, on the filesystem mounted as `mount`
+pub fn remove(mount: &str, path: &str) -> Result<(), Error> {
+    match request(&mount_topic(mount), Request::Remove(path.to_owned()))? {
+        Response::Removed => Ok(()),
+        _ => Err(Error::Protocol),
+    }
+}
+
+/// Moves a file or a directory on the filesystem mounted as `mount`,
+/// replacing an existing file at `to`
+pub fn rename(mount: &str, from: &str, to: &str) -> Result<(), Error> {
+    match request(&mount_topic(mount), Request::Rename {
+        from: from.to_owned(),
+        to: to.to_owned(),
+    })? {
+        Response::Renamed => Ok(()),
+        _ => Err(Error::Protocol),
+    }
+}
+
+/// Sends a request, and converts error responses to errors
+fn request(topic: &str, request: Request) -> Result<Response, Error> {
+    match ipc::request(topic, request)? {
+        Response::NotFound => Err(Error::NotFound),
+        Response::WrongKind => Err(Error::WrongKind),
+        Response::InvalidName => Err(Error::InvalidName),
+        Response::NoSpace => Err(Error::NoSpace),
+        Response::NotEmpty => Err(Error::NotEmpty),
+        Response::IoError => Err(Error::Io),
+        other => Ok(other),
+    }
+}
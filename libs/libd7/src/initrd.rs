@@ -0,0 +1,81 @@
+//! Initrd files with a cursor, read in parts with the `initrd/read_at` service.
+//!
+//! Use `ipc::request("initrd/read", path)` to read a whole file at once.
+
+use alloc::prelude::v1::*;
+
+use d7abi::ipc::protocol::initrd::{ReadAt, ReadAtReply};
+pub use d7abi::ipc::protocol::initrd::READ_AT_MAX_BYTES;
+
+use crate::ipc;
+use crate::syscall::{SyscallErrorCode, SyscallResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+/// A read-only initrd file
+#[derive(Debug)]
+pub struct File {
+    path: String,
+    size: u64,
+    cursor: u64,
+}
+impl File {
+    pub fn open(path: &str) -> SyscallResult<Self> {
+        let reply = read_at(path, 0, 0)?;
+        Ok(Self {
+            path: path.to_owned(),
+            size: reply.size,
+            cursor: 0,
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Reads from the cursor and advances it.
+    /// Returns the number of bytes read, zero at the end of the file.
+    pub fn read(&mut self, buffer: &mut [u8]) -> SyscallResult<usize> {
+        if self.cursor >= self.size || buffer.is_empty() {
+            return Ok(0);
+        }
+        let len = (buffer.len() as u64).min(READ_AT_MAX_BYTES);
+        let reply = read_at(&self.path, self.cursor, len)?;
+        buffer[..reply.data.len()].copy_from_slice(&reply.data);
+        self.cursor += reply.data.len() as u64;
+        Ok(reply.data.len())
+    }
+
+    /// Moves the cursor and returns the new position. The cursor can be
+    /// past the end of the file, but not before the start of it.
+    pub fn seek(&mut self, pos: SeekFrom) -> SyscallResult<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => offset_by(self.size, delta),
+            SeekFrom::Current(delta) => offset_by(self.cursor, delta),
+        };
+        self.cursor = target.ok_or(SyscallErrorCode::invalid_argument)?;
+        Ok(self.cursor)
+    }
+}
+
+fn offset_by(base: u64, delta: i64) -> Option<u64> {
+    if delta < 0 {
+        base.checked_sub(delta.wrapping_neg() as u64)
+    } else {
+        base.checked_add(delta as u64)
+    }
+}
+
+fn read_at(path: &str, offset: u64, len: u64) -> SyscallResult<ReadAtReply> {
+    ipc::request("initrd/read_at", ReadAt {
+        path: path.to_owned(),
+        offset,
+        len,
+    })
+}
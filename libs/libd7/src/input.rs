@@ -0,0 +1,50 @@
+//! Keyboard input events from the shared input ring, see `d7abi::input_ring`.
+//!
+//! Reading the ring doesn't need a system call unless it's empty, so this
+//! is faster than subscribing to `irq/keyboard` for programs like games.
+
+use core::sync::atomic::Ordering;
+
+pub use d7abi::input_ring::{InputEvent, SOURCE_KEYBOARD, SOURCE_MOUSE};
+use d7abi::input_ring::{InputRing, INPUT_RING_SIZE};
+
+use crate::syscall::{self, SyscallErrorCode, SyscallResult};
+
+pub struct InputReader {
+    ring: InputRing,
+}
+impl InputReader {
+    /// Maps the input ring. Requires the `Driver` privilege level.
+    /// Only the events received after the first call are in the ring.
+    pub fn open() -> SyscallResult<Self> {
+        let addr = syscall::input_ring()?;
+        Ok(Self {
+            ring: unsafe { InputRing::from_raw(addr.as_mut_ptr(), INPUT_RING_SIZE) },
+        })
+    }
+
+    /// Takes the next event, if any
+    pub fn try_next(&self) -> Option<InputEvent> {
+        self.ring.pop()
+    }
+
+    /// Blocks until an event is available, and takes it
+    pub fn next(&self) -> SyscallResult<InputEvent> {
+        loop {
+            let doorbell = self.ring.doorbell().load(Ordering::Acquire);
+            if let Some(event) = self.ring.pop() {
+                return Ok(event);
+            }
+            match syscall::futex_wait(self.ring.doorbell(), doorbell) {
+                // Woken, or the doorbell was rung after reading it
+                Ok(()) | Err(SyscallErrorCode::would_block) => {},
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Number of events dropped because they were not read in time
+    pub fn dropped(&self) -> u64 {
+        self.ring.dropped()
+    }
+}
@@ -8,6 +8,19 @@ pub fn publish<T: Serialize>(topic: &str, message: &T) -> SyscallResult<()> {
     syscall::ipc_publish(topic, &data)
 }
 
+/// Send an unreliable message, and keep it as the latest value of the topic.
+/// New subscribers receive the latest value immediately, which suits status
+/// topics like link state. Use `clear_retained` to remove the value.
+pub fn publish_retained<T: Serialize>(topic: &str, message: &T) -> SyscallResult<()> {
+    let data = pinecone::to_vec(message).unwrap();
+    syscall::ipc_publish_retained(topic, &data)
+}
+
+/// Remove the retained value of a topic
+pub fn clear_retained(topic: &str) -> SyscallResult<()> {
+    syscall::ipc_publish_retained(topic, &[])
+}
+
 /// Send a reliable message to a topic, and wait until receiver acknowledges it
 pub fn deliver<T: Serialize>(topic: &str, message: &T) -> SyscallResult<()> {
     let data = pinecone::to_vec(message).unwrap();
@@ -17,7 +17,9 @@ use core::marker::PhantomData;
 use core::sync::atomic::{AtomicU64, Ordering};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
+use d7abi::ipc::rpc::Rpc;
 use d7abi::ipc::*;
+use d7abi::process::Privilege;
 
 use crate::syscall::{self, SyscallResult};
 
@@ -45,6 +47,16 @@ impl<RQ: Serialize + DeserializeOwned, RS: Serialize + DeserializeOwned> Server<
         Ok(Self::new(ReliableSubscription::prefix(filter)?))
     }
 
+    /// Server for the topic of an `Rpc` interface
+    pub fn rpc<R: Rpc<Request = RQ, Response = RS>>() -> SyscallResult<Self> {
+        Self::exact(R::TOPIC)
+    }
+
+    /// Only accept requests from processes with at least this privilege
+    pub fn restrict(&self, privilege: Privilege) -> SyscallResult<()> {
+        self.sub.restrict(privilege)
+    }
+
     /// Handle one request
     pub fn handle<F>(&self, f: F) -> SyscallResult<()>
     where F: FnOnce(RQ) -> SyscallResult<RS> {
@@ -90,3 +102,8 @@ pub fn request<RQ: Serialize, RS: DeserializeOwned>(topic: &str, message: RQ) ->
     ack_ctx.ack()?;
     Ok(data)
 }
+
+/// Request to the server of an `Rpc` interface
+pub fn call<R: Rpc>(message: R::Request) -> SyscallResult<R::Response> {
+    request(R::TOPIC, message)
+}
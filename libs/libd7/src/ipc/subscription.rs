@@ -3,6 +3,7 @@ use core::marker::PhantomData;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use d7abi::ipc::*;
+use d7abi::process::{Privilege, ProcessId};
 
 use super::InternalSubscription;
 
@@ -32,6 +33,24 @@ impl<T: DeserializeOwned> UnreliableSubscription<T> {
         })
     }
 
+    /// Use a subscription transferred from another process
+    pub fn from_transferred(id: SubscriptionId) -> Self {
+        Self {
+            id,
+            msg_type: PhantomData,
+        }
+    }
+
+    /// Give this subscription to another process.
+    /// Returns the id that the receiving process must use.
+    /// On failure the subscription is dropped, i.e. unsubscribed.
+    pub fn transfer(self, target: ProcessId) -> SyscallResult<SubscriptionId> {
+        let id = self.id;
+        syscall::ipc_transfer(id, target)?;
+        core::mem::forget(self);
+        Ok(id)
+    }
+
     /// Receive, data only
     pub fn receive(&self) -> SyscallResult<T> {
         Ok(self.receive_topic()?.0)
@@ -78,6 +97,29 @@ impl<T: DeserializeOwned> ReliableSubscription<T> {
         })
     }
 
+    /// Use a subscription transferred from another process
+    pub fn from_transferred(id: SubscriptionId) -> Self {
+        Self {
+            id,
+            msg_type: PhantomData,
+        }
+    }
+
+    /// Give this subscription to another process.
+    /// Returns the id that the receiving process must use.
+    /// On failure the subscription is dropped, i.e. unsubscribed.
+    pub fn transfer(self, target: ProcessId) -> SyscallResult<SubscriptionId> {
+        let id = self.id;
+        syscall::ipc_transfer(id, target)?;
+        core::mem::forget(self);
+        Ok(id)
+    }
+
+    /// Only accept messages from processes with at least this privilege
+    pub fn restrict(&self, privilege: Privilege) -> SyscallResult<()> {
+        syscall::ipc_restrict(self.id, privilege)
+    }
+
     /// Receive, data only
     pub fn receive(&self) -> SyscallResult<(AcknowledgeContext, T)> {
         let (ack_ctx, data, _topic) = self.receive_topic()?;
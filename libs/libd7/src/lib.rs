@@ -24,11 +24,16 @@ mod allocator;
 
 // pub mod attachment;
 // pub mod console;
+pub mod fs;
+pub mod initrd;
+pub mod input;
 pub mod ipc;
 pub mod net;
+pub mod output;
 pub mod process;
 pub mod service;
 pub mod syscall;
+pub mod time;
 
 use core::alloc::Layout;
 use core::panic::PanicInfo;
@@ -89,7 +94,7 @@ fn out_of_memory(_: Layout) -> ! {
 #[macro_export]
 macro_rules! println {
     ($($arg:tt)*) => ({
-        $crate::syscall::debug_print(&format!("{}", &format_args!($($arg)*)));
+        $crate::output::print_line(&format!("{}", &format_args!($($arg)*)));
     });
 }
 
@@ -0,0 +1,136 @@
+//! Berkeley-style socket interface.
+//!
+//! Sockets are operated by `netd`, and these calls are translated to
+//! requests to its `netd/socket` endpoint. This allows porting code written
+//! against the usual `socket`/`bind`/`connect`/`send`/`recv` interface.
+
+use alloc::prelude::v1::*;
+use serde::{Deserialize, Serialize};
+
+use d7net::SocketAddr;
+
+use crate::ipc;
+use crate::syscall::SyscallResult;
+
+/// Socket identifier, unique within `netd`
+pub type SocketId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SocketType {
+    /// TCP
+    Stream,
+    /// UDP
+    Datagram,
+}
+
+/// Request to `netd/socket`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    Socket(SocketType),
+    Bind(SocketId, SocketAddr),
+    Connect(SocketId, SocketAddr),
+    Listen(SocketId, u32),
+    Accept(SocketId),
+    Send(SocketId, Vec<u8>),
+    Recv(SocketId, u64),
+    Close(SocketId),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    Socket(SocketId),
+    Ok,
+    Accepted(SocketId, SocketAddr),
+    Sent(u64),
+    Received(Vec<u8>),
+    Error(Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Error {
+    /// No such socket
+    InvalidSocket,
+    /// Operation not valid in the current socket state
+    InvalidState,
+    /// Address already bound by another socket
+    AddressInUse,
+    /// Operation not supported for this socket type, or not implemented yet
+    NotSupported,
+    /// No data or connection available right now
+    WouldBlock,
+    /// Datagram doesn't fit into a single frame
+    MessageTooLong,
+}
+
+fn request(request: Request) -> SyscallResult<Result<Response, Error>> {
+    match ipc::request("netd/socket", request)? {
+        Response::Error(e) => Ok(Err(e)),
+        other => Ok(Ok(other)),
+    }
+}
+
+fn unexpected(response: Response) -> ! {
+    panic!("Unexpected response from netd: {:?}", response)
+}
+
+fn expect_ok(response: Result<Response, Error>) -> Result<(), Error> {
+    match response? {
+        Response::Ok => Ok(()),
+        other => unexpected(other),
+    }
+}
+
+pub fn socket(type_: SocketType) -> SyscallResult<Result<SocketId, Error>> {
+    Ok(match request(Request::Socket(type_))? {
+        Ok(Response::Socket(id)) => Ok(id),
+        Ok(other) => unexpected(other),
+        Err(e) => Err(e),
+    })
+}
+
+/// Use `port = 0` to auto-assign a free port
+pub fn bind(socket: SocketId, addr: SocketAddr) -> SyscallResult<Result<(), Error>> {
+    Ok(expect_ok(request(Request::Bind(socket, addr))?))
+}
+
+pub fn connect(socket: SocketId, addr: SocketAddr) -> SyscallResult<Result<(), Error>> {
+    Ok(expect_ok(request(Request::Connect(socket, addr))?))
+}
+
+pub fn listen(socket: SocketId, backlog: u32) -> SyscallResult<Result<(), Error>> {
+    Ok(expect_ok(request(Request::Listen(socket, backlog))?))
+}
+
+pub fn accept(socket: SocketId) -> SyscallResult<Result<(SocketId, SocketAddr), Error>> {
+    Ok(match request(Request::Accept(socket))? {
+        Ok(Response::Accepted(id, addr)) => Ok((id, addr)),
+        Ok(other) => unexpected(other),
+        Err(e) => Err(e),
+    })
+}
+
+/// Returns number of bytes sent
+pub fn send(socket: SocketId, data: &[u8]) -> SyscallResult<Result<usize, Error>> {
+    Ok(match request(Request::Send(socket, data.to_vec()))? {
+        Ok(Response::Sent(count)) => Ok(count as usize),
+        Ok(other) => unexpected(other),
+        Err(e) => Err(e),
+    })
+}
+
+/// Returns number of bytes received into `buffer`
+pub fn recv(socket: SocketId, buffer: &mut [u8]) -> SyscallResult<Result<usize, Error>> {
+    Ok(match request(Request::Recv(socket, buffer.len() as u64))? {
+        Ok(Response::Received(data)) => {
+            assert!(data.len() <= buffer.len(), "netd returned too much data");
+            buffer[..data.len()].copy_from_slice(&data);
+            Ok(data.len())
+        },
+        Ok(other) => unexpected(other),
+        Err(e) => Err(e),
+    })
+}
+
+pub fn close(socket: SocketId) -> SyscallResult<Result<(), Error>> {
+    Ok(expect_ok(request(Request::Close(socket))?))
+}
@@ -1,31 +1,73 @@
-use d7net::{tcp, IpAddr, SocketAddr};
+use d7net::SocketAddr;
 
-use crate::{pinecone, syscall::SyscallResult};
+use crate::syscall::SyscallResult;
 
-use super::create_socket;
+use super::socket::{self, Error, SocketId, SocketType};
+
+/// Max number of pending connections
+const LISTEN_BACKLOG: u32 = 16;
 
 /// A TCP socket
 pub struct Socket {
-    // file: File,
+    id: SocketId,
 }
 impl Socket {
     /// Bind to given host and port.
     /// Use `port = 0` to auto-assign a free port.
-    pub fn bind(addr: SocketAddr) -> SyscallResult<Self> {
-        todo!()
+    pub fn bind(addr: SocketAddr) -> SyscallResult<Result<Self, Error>> {
+        let id = match socket::socket(SocketType::Stream)? {
+            Ok(id) => id,
+            Err(e) => return Ok(Err(e)),
+        };
+        let socket = Self { id };
+        if let Err(e) = socket::bind(id, addr)? {
+            return Ok(Err(e));
+        }
+        if let Err(e) = socket::listen(id, LISTEN_BACKLOG)? {
+            return Ok(Err(e));
+        }
+        Ok(Ok(socket))
     }
 
     /// Accept a new incoming connection.
     ///
-    /// This function will block the calling thread until a new TCP connection is established.
     /// When established, the corresponding Stream and the remote peer's address will be returned.
-    pub fn accept(&self, addr: SocketAddr) -> SyscallResult<(Stream, SocketAddr)> {
-        // let x = self.file.read();
-        todo!();
+    pub fn accept(&self) -> SyscallResult<Result<(Stream, SocketAddr), Error>> {
+        Ok(socket::accept(self.id)?.map(|(id, addr)| (Stream { id }, addr)))
+    }
+}
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let _ = socket::close(self.id);
     }
 }
 
 pub struct Stream {
-    // file: File,
+    id: SocketId,
+}
+impl Stream {
+    pub fn connect(addr: SocketAddr) -> SyscallResult<Result<Self, Error>> {
+        let id = match socket::socket(SocketType::Stream)? {
+            Ok(id) => id,
+            Err(e) => return Ok(Err(e)),
+        };
+        let stream = Self { id };
+        if let Err(e) = socket::connect(id, addr)? {
+            return Ok(Err(e));
+        }
+        Ok(Ok(stream))
+    }
+
+    pub fn send(&self, data: &[u8]) -> SyscallResult<Result<usize, Error>> {
+        socket::send(self.id, data)
+    }
+
+    pub fn recv(&self, buffer: &mut [u8]) -> SyscallResult<Result<usize, Error>> {
+        socket::recv(self.id, buffer)
+    }
+}
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let _ = socket::close(self.id);
+    }
 }
-impl Stream {}
@@ -0,0 +1,43 @@
+//! Message types of the TLS termination service, see `docs/tls.md`.
+//! The service itself is not implemented yet, as it needs TCP in `netd`.
+
+use alloc::prelude::v1::*;
+use serde::{Deserialize, Serialize};
+
+use d7net::SocketAddr;
+
+pub type SessionId = u64;
+
+/// Request to `tlsd/open`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    Open {
+        remote: SocketAddr,
+        /// Name the server certificate must be valid for
+        server_name: String,
+        /// Topic prefix for the plaintext data topics
+        topic_prefix: String,
+    },
+    Close(SessionId),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    Opened(SessionId),
+    Closed,
+    Error(Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Error {
+    /// TCP connection could not be established
+    ConnectionFailed,
+    /// Handshake failed, e.g. no common cipher suite
+    HandshakeFailed,
+    /// Certificate chain invalid or not valid for `server_name`
+    CertificateInvalid,
+    /// Topic prefix invalid or already in use
+    InvalidTopicPrefix,
+    /// No such session
+    InvalidSession,
+}
@@ -0,0 +1,44 @@
+//! Process output, printed to the kernel log.
+//!
+//! By default every line is printed with the `debug_print` system call.
+//! After `enable_ring`, lines are appended to the shared output ring instead,
+//! and the kernel prints them on its next scheduler tick.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use d7abi::output_ring::{OutputRing, OUTPUT_RING_SIZE};
+
+use crate::syscall::{self, SyscallResult};
+
+static RING_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Use the shared output ring for `println!`.
+/// Useful for programs that print a lot.
+pub fn enable_ring() -> SyscallResult<()> {
+    syscall::debug_output_ring()?;
+    RING_ENABLED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Print a line, used by `println!`
+pub fn print_line(line: &str) {
+    if !RING_ENABLED.load(Ordering::SeqCst) {
+        syscall::debug_print(line);
+        return;
+    }
+
+    let ring = unsafe {
+        OutputRing::from_raw(d7abi::PROCESS_OUTPUT_RING.as_mut_ptr(), OUTPUT_RING_SIZE)
+    };
+    for part in &[line.as_bytes(), &b"\n"[..]] {
+        let mut rest = *part;
+        loop {
+            rest = &rest[ring.write(rest)..];
+            if rest.is_empty() {
+                break;
+            }
+            // Ring full, let the kernel drain it
+            syscall::sched_yield();
+        }
+    }
+}
@@ -1,9 +1,12 @@
 use alloc::prelude::v1::*;
 
-pub use d7abi::process::{ProcessId, ProcessResult};
+pub use d7abi::process::{
+    MemoryRegion, MemoryRegionKind, Privilege, ProcessEvent, ProcessId, ProcessResult,
+};
 
 use crate::ipc;
-use crate::syscall::{self, SyscallResult};
+use crate::syscall::{self, ExecFlags, SyscallResult};
+use crate::time::Duration;
 
 /// A safe wrapper for a process
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -11,9 +14,28 @@ pub struct Process {
     pid: ProcessId,
 }
 impl Process {
+    /// Spawn a process with `Privilege::User`
     pub fn spawn(path: &str) -> SyscallResult<Self> {
+        Self::spawn_with_privilege(path, Privilege::User)
+    }
+
+    pub fn spawn_with_privilege(path: &str, privilege: Privilege) -> SyscallResult<Self> {
         let image: Vec<u8> = ipc::request("initrd/read", path)?;
-        let pid = syscall::exec(&image)?;
+        Self::spawn_image(&image, privilege)
+    }
+
+    /// Spawn a process from an ELF image in memory, e.g. one received over
+    /// the network. Fails with `invalid_executable` if the image is invalid.
+    pub fn spawn_image(image: &[u8], privilege: Privilege) -> SyscallResult<Self> {
+        Self::spawn_image_with_flags(image, privilege, ExecFlags::empty())
+    }
+
+    /// Spawn a process from an ELF image, e.g. an untrusted one with
+    /// `ExecFlags::RESTRICTED_VIEW`
+    pub fn spawn_image_with_flags(
+        image: &[u8], privilege: Privilege, flags: ExecFlags,
+    ) -> SyscallResult<Self> {
+        let pid = syscall::exec(image, privilege, flags)?;
         Ok(Process { pid })
     }
 
@@ -21,3 +43,36 @@ impl Process {
         self.pid
     }
 }
+
+/// Subscribe to the kernel-posted events of this process, e.g. terminated
+/// child processes and expired timers. The subscription can be used with
+/// `select!` alongside other subscriptions, so that a single event loop
+/// can handle everything.
+pub fn events() -> SyscallResult<ipc::UnreliableSubscription<ProcessEvent>> {
+    let topic = d7abi::process::events_topic(syscall::get_pid());
+    ipc::UnreliableSubscription::exact(&topic)
+}
+
+/// Posts `ProcessEvent::TimerFired(token)` to `events()` after `after` has passed
+pub fn set_timer(after: Duration, token: u64) -> SyscallResult<()> {
+    syscall::sched_timer(after.as_nanos() as u64, token)
+}
+
+/// Memory regions of a process, or of this process if `pid` is `None`.
+/// Inspecting other processes requires `Privilege::Driver`.
+pub fn memory_map(pid: Option<ProcessId>) -> SyscallResult<Vec<MemoryRegion>> {
+    let pid = pid.map(|p| p.as_u64()).unwrap_or(0);
+    let mut buffer = vec![0u8; 0x400];
+    loop {
+        match syscall::process_memory_map(pid, &mut buffer) {
+            Ok(count) => {
+                return Ok(pinecone::from_bytes(&buffer[..count]).expect("Invalid memory map"));
+            },
+            Err(syscall::SyscallErrorCode::buffer_too_small) => {
+                let new_len = buffer.len() * 2;
+                buffer.resize(new_len, 0);
+            },
+            Err(error) => return Err(error),
+        }
+    }
+}
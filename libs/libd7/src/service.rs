@@ -14,7 +14,15 @@ pub fn register(name: &str, oneshot: bool) {
 }
 
 pub fn wait_for_one(name: &str) {
-    let mut hs = HashSet::new();
-    hs.insert(ServiceName(name.to_owned()));
+    wait_for_any(&[name]);
+}
+
+/// Waits until at least one of the services is running,
+/// e.g. one of the drivers for a device class
+pub fn wait_for_any(names: &[&str]) {
+    let hs: HashSet<_> = names
+        .iter()
+        .map(|name| ServiceName((*name).to_owned()))
+        .collect();
     crate::ipc::deliver("serviced/waitfor/any", &hs).unwrap();
 }
@@ -1,14 +1,15 @@
 use core::convert::TryFrom;
 use core::hint::unreachable_unchecked;
+use core::sync::atomic::AtomicU32;
 use x86_64::{PhysAddr, VirtAddr};
 
 use d7abi::{
     ipc::{AcknowledgeId, SubscriptionId},
-    process::ProcessId,
+    process::{Privilege, ProcessId, Signal},
     SyscallNumber,
 };
 
-pub use d7abi::{MemoryProtectionFlags, SyscallErrorCode};
+pub use d7abi::{ExecFlags, MemoryProtectionFlags, PanicAction, ShutdownAction, SyscallErrorCode};
 
 macro_rules! syscall {
     ($n:expr; $a0:expr, $a1:expr, $a2:expr, $a3:expr) => {
@@ -79,28 +80,105 @@ pub unsafe fn mem_set_size(new_size_bytes: u64) -> SyscallResult<u64> {
     syscall!(SyscallNumber::mem_set_size; new_size_bytes)
 }
 
-/// Start a new process from an ELF image
-pub fn exec(image: &[u8]) -> SyscallResult<ProcessId> {
+/// Start a new process from an ELF image.
+/// The privilege level must not be higher than that of the calling process.
+pub fn exec(image: &[u8], privilege: Privilege, flags: ExecFlags) -> SyscallResult<ProcessId> {
     let len = image.len() as u64;
     let slice = image.as_ptr() as u64;
 
     unsafe {
         Ok(ProcessId::from_u64(
-            syscall!(SyscallNumber::exec; len, slice)?,
+            syscall!(SyscallNumber::exec; len, slice, privilege as u64, flags.bits())?,
         ))
     }
 }
 
+/// Creates a thread sharing the address space of this process, and returns its pid.
+/// The thread starts at `entry` with `arg` as its argument, and ends with `exit`.
+/// The caller receives `ProcessEvent::ChildTerminated` when the thread ends.
+///
+/// # Safety
+/// `stack_top` must be the 16-byte aligned end of writable memory,
+/// which is used only by the new thread until it ends.
+pub unsafe fn thread_create(
+    entry: extern "C" fn(u64) -> !, stack_top: VirtAddr, arg: u64,
+) -> SyscallResult<ProcessId> {
+    Ok(ProcessId::from_u64(syscall!(
+        SyscallNumber::thread_create;
+        entry as usize as u64,
+        stack_top.as_u64(),
+        arg
+    )?))
+}
+
+/// Sets the FS base of the calling thread, replacing the thread-local storage
+/// block set up by the kernel, e.g. for a runtime managing its own blocks.
+///
+/// # Safety
+/// Thread-local variables are accessed relative to the FS base.
+pub unsafe fn thread_set_fs_base(fs_base: VirtAddr) -> SyscallResult<()> {
+    syscall!(SyscallNumber::thread_set_fs_base; fs_base.as_u64()).map(|_| ())
+}
+
+/// Maps the output ring of this process, see `d7abi::output_ring`.
+/// Returns its address. Calling this again returns the same ring.
+pub fn debug_output_ring() -> SyscallResult<VirtAddr> {
+    unsafe { Ok(VirtAddr::new(syscall!(SyscallNumber::debug_output_ring)?)) }
+}
+
+/// Maps the shared input event ring, see `d7abi::input_ring`.
+/// Requires the `Driver` privilege level.
+pub fn input_ring() -> SyscallResult<VirtAddr> {
+    unsafe { Ok(VirtAddr::new(syscall!(SyscallNumber::input_ring)?)) }
+}
+
 /// This system call never fails, and does not return anything
 pub fn sched_yield() {
     let _ = unsafe { syscall!(SyscallNumber::sched_yield) };
 }
 
+/// Donates the rest of the current time slice to the target process.
+/// Returns false, without yielding, if the target is not runnable.
+pub fn sched_yield_to(target: ProcessId) -> SyscallResult<bool> {
+    unsafe { syscall!(SyscallNumber::sched_yield_to; target.as_u64()).map(|v| v != 0) }
+}
+
+/// Blocks until `futex_wake` is called for the word, if it contains `expected`.
+/// Fails with `would_block` if it doesn't. The word must be 4-byte aligned.
+pub fn futex_wait(word: &AtomicU32, expected: u32) -> SyscallResult<()> {
+    let addr = word as *const AtomicU32 as u64;
+    unsafe { syscall!(SyscallNumber::futex_wait; addr, expected as u64).map(|_| ()) }
+}
+
+/// Wakes all threads waiting on the word in `futex_wait`.
+/// Returns false if there were none.
+pub fn futex_wake(word: &AtomicU32) -> SyscallResult<bool> {
+    let addr = word as *const AtomicU32 as u64;
+    unsafe { syscall!(SyscallNumber::futex_wake; addr).map(|v| v != 0) }
+}
+
 /// Max sleep time is 2**64 ns, about 584 years.
 pub fn sched_sleep_ns(ns: u64) -> SyscallResult<()> {
     unsafe { syscall!(SyscallNumber::sched_sleep_ns; ns).map(|_| ()) }
 }
 
+/// Posts `ProcessEvent::TimerFired(token)` to the events topic
+/// of this process after the given time. Does not block.
+/// Fails with `not_supported` if called on other cores than the BSP.
+pub fn sched_timer(ns: u64, token: u64) -> SyscallResult<()> {
+    unsafe { syscall!(SyscallNumber::sched_timer; ns, token).map(|_| ()) }
+}
+
+/// Gives a process, its threads and its future children a virtualized clock,
+/// which reads `offset_ns` now and advances `scale` times as fast as the real
+/// clock, for testing. `scale` is fixed point, with `TIME_SCALE_SHIFT` fraction
+/// bits. Zero pid means the calling process. Requires `Privilege::Full`.
+/// Fails with `invalid_argument` if `scale` is zero or above 65536 times the
+/// real speed, or if `offset_ns` is over 100 years.
+pub fn sched_time_namespace(pid: u64, offset_ns: u64, scale: u64) -> SyscallResult<()> {
+    unsafe { syscall!(SyscallNumber::sched_time_namespace; pid, offset_ns, scale).map(|_| ()) }
+}
+
 /// Subscribes to message by a filter. If exact is false, filter is used as a prefix.
 pub fn ipc_subscribe(filter: &str, exact: bool, reliable: bool) -> SyscallResult<SubscriptionId> {
     let len = filter.len() as u64;
@@ -140,6 +218,20 @@ pub fn ipc_publish(topic: &str, data: &[u8]) -> SyscallResult<()> {
     }
 }
 
+/// Publish unreliable message, and retain it for new subscribers (asynchronous)
+pub fn ipc_publish_retained(topic: &str, data: &[u8]) -> SyscallResult<()> {
+    let len = topic.len() as u64;
+    let slice = topic.as_ptr() as u64;
+    unsafe {
+        syscall!(
+            SyscallNumber::ipc_publish_retained;
+            len, slice,
+            data.len() as u64, data.as_ptr() as u64
+        )
+        .map(|_| ())
+    }
+}
+
 /// Deliver reliable message (blocking)
 pub fn ipc_deliver(topic: &str, data: &[u8]) -> SyscallResult<()> {
     let len = topic.len() as u64;
@@ -154,6 +246,13 @@ pub fn ipc_deliver(topic: &str, data: &[u8]) -> SyscallResult<()> {
     }
 }
 
+/// Set a timeout for the `ipc_deliver` calls of this thread, zero for none.
+/// Timed out deliveries fail with `timed_out`, but the receiver may still
+/// handle the message.
+pub fn ipc_deliver_timeout(timeout_ns: u64) -> SyscallResult<()> {
+    unsafe { syscall!(SyscallNumber::ipc_deliver_timeout; timeout_ns).map(|_| ()) }
+}
+
 /// Deliver a reply to a reliable message
 pub fn ipc_deliver_reply(topic: &str, data: &[u8]) -> SyscallResult<()> {
     let len = topic.len() as u64;
@@ -211,6 +310,52 @@ pub fn ipc_select(sub_ids: &[SubscriptionId], nonblocking: bool) -> SyscallResul
     }
 }
 
+/// Like blocking `ipc_select`, but fails with `timed_out`
+/// if no message is available within `timeout_ns` nanoseconds
+pub fn ipc_select_timeout(
+    sub_ids: &[SubscriptionId], timeout_ns: u64,
+) -> SyscallResult<SubscriptionId> {
+    if sub_ids.is_empty() {
+        panic!("Cannot ipc_select from an empty list");
+    }
+
+    unsafe {
+        Ok(SubscriptionId::from_u64(syscall!(
+            SyscallNumber::ipc_select;
+            sub_ids.len() as u64,
+            sub_ids.as_ptr() as u64,
+            0,
+            timeout_ns.max(1)
+        )?))
+    }
+}
+
+/// Give a subscription to another process.
+/// The subscription id stays the same, and it can be sent to the new owner.
+pub fn ipc_transfer(sub_id: SubscriptionId, target: ProcessId) -> SyscallResult<()> {
+    unsafe {
+        syscall!(
+            SyscallNumber::ipc_transfer;
+            sub_id.as_u64(),
+            target.as_u64()
+        )
+        .map(|_| ())
+    }
+}
+
+/// Only accept deliveries to a subscription from processes with at least
+/// the given privilege. `Privilege::User` removes the restriction.
+pub fn ipc_restrict(sub_id: SubscriptionId, privilege: Privilege) -> SyscallResult<()> {
+    unsafe {
+        syscall!(
+            SyscallNumber::ipc_restrict;
+            sub_id.as_u64(),
+            privilege as u64
+        )
+        .map(|_| ())
+    }
+}
+
 /// Read (and clear) kernel log buffer. Nonblocking.
 pub fn kernel_log_read(buffer: &mut [u8]) -> SyscallResult<usize> {
     if buffer.is_empty() {
@@ -226,6 +371,75 @@ pub fn kernel_log_read(buffer: &mut [u8]) -> SyscallResult<usize> {
     }
 }
 
+/// Writes the serialized memory map of a process to the buffer.
+/// Zero pid means the calling process. Returns the number of bytes written.
+pub fn process_memory_map(pid: u64, buffer: &mut [u8]) -> SyscallResult<usize> {
+    unsafe {
+        Ok(syscall!(
+            SyscallNumber::process_memory_map;
+            pid,
+            buffer.len() as u64,
+            buffer.as_mut_ptr() as u64
+        )? as usize)
+    }
+}
+
+/// Copies memory of another process, starting from `remote`, to the buffer.
+/// Requires `Privilege::Driver`. Fails with `bad_address` unless
+/// the whole range is mapped in the target process.
+pub fn process_vm_read(pid: ProcessId, remote: VirtAddr, buffer: &mut [u8]) -> SyscallResult<()> {
+    unsafe {
+        syscall!(
+            SyscallNumber::process_vm_read;
+            pid.as_u64(),
+            remote.as_u64(),
+            buffer.len() as u64,
+            buffer.as_mut_ptr() as u64
+        )
+        .map(|_| ())
+    }
+}
+
+/// Copies data to the memory of another process, starting from `remote`.
+/// Requires `Privilege::Full`. Fails with `bad_address` unless
+/// the whole range is mapped and writable in the target process.
+pub fn process_vm_write(pid: ProcessId, remote: VirtAddr, data: &[u8]) -> SyscallResult<()> {
+    unsafe {
+        syscall!(
+            SyscallNumber::process_vm_write;
+            pid.as_u64(),
+            remote.as_u64(),
+            data.len() as u64,
+            data.as_ptr() as u64
+        )
+        .map(|_| ())
+    }
+}
+
+/// Sends a signal to this process or one of its children, or to any
+/// process with `Privilege::Full`. Kill cannot be sent to the caller itself.
+pub fn process_signal(pid: ProcessId, signal: Signal) -> SyscallResult<()> {
+    unsafe { syscall!(SyscallNumber::process_signal; pid.as_u64(), signal as u64).map(|_| ()) }
+}
+
+/// Holds back the signals in the mask, built from `Signal::bit`, until they
+/// are unmasked. Kill cannot be masked. Returns the previous mask.
+pub fn process_signal_mask(mask: u64) -> SyscallResult<u64> {
+    unsafe { syscall!(SyscallNumber::process_signal_mask; mask) }
+}
+
+/// Sets the action taken on kernel panic. Requires `Privilege::Full`.
+pub fn kernel_panic_action(action: PanicAction) -> SyscallResult<()> {
+    let (action, value) = action.to_args();
+    unsafe { syscall!(SyscallNumber::kernel_panic_action; action, value).map(|_| ()) }
+}
+
+/// Starts an orderly shutdown, see `d7abi::ipc::protocol::ShutdownStarted`.
+/// Requires `Privilege::Full`. Returns false if a shutdown was already in progress.
+pub fn kernel_shutdown(action: ShutdownAction) -> SyscallResult<bool> {
+    unsafe { Ok(syscall!(SyscallNumber::kernel_shutdown; action.to_arg())? != 0) }
+}
+
 /// Assigns code to be ran on interrupt handler.
 /// Code must be an executable sequence of instructions,
 /// modifies no registers except `rax`, that will be sent
@@ -293,3 +507,41 @@ pub unsafe fn dma_free(phys_addr: PhysAddr, len: u64) -> SyscallResult<()> {
     )?;
     Ok(())
 }
+
+/// Creates a named shared memory object of `len` bytes, rounded up to pages
+/// and zeroed, and maps it. Fails with `already_exists` if the name is taken.
+/// Other processes need at least `privilege` to map it. Requires `Privilege::Driver`.
+pub fn shm_create(name: &str, len: u64, privilege: Privilege) -> SyscallResult<*mut u8> {
+    if len == 0 {
+        panic!("Cannot shm_create an empty region");
+    }
+
+    unsafe {
+        Ok(syscall!(
+            SyscallNumber::shm_create;
+            name.len() as u64,
+            name.as_ptr() as u64,
+            len,
+            privilege as u64
+        )? as *mut u8)
+    }
+}
+
+/// Maps the first `len` bytes of a shared memory object created with `shm_create`.
+/// Fails with `not_found` if there is no such object, with `invalid_argument`
+/// if it is smaller than `len`, and with `permission_denied` if the process
+/// is not allowed to map it.
+pub fn shm_map(name: &str, len: u64) -> SyscallResult<*mut u8> {
+    if len == 0 {
+        panic!("Cannot shm_map an empty region");
+    }
+
+    unsafe {
+        Ok(syscall!(
+            SyscallNumber::shm_map;
+            name.len() as u64,
+            name.as_ptr() as u64,
+            len
+        )? as *mut u8)
+    }
+}
@@ -0,0 +1,49 @@
+//! Fast time access through the kernel-provided time page.
+//!
+//! See `d7abi::time_page` for the layout.
+
+use d7abi::time_page::TimePage;
+use d7abi::PROCESS_TIME_PAGE;
+
+pub use d7abi::time_page::{TIME_PAGE_VERSION, TIME_SCALE_SHIFT};
+pub use core::time::Duration;
+
+/// Read the time stamp counter
+#[inline]
+fn read_tsc() -> u64 {
+    let rdx: u64;
+    let rax: u64;
+    unsafe {
+        asm!(
+            "rdtscp", // Serializing read
+            out("rdx") rdx,
+            out("rax") rax,
+            out("rcx") _,
+            options(nomem, nostack)
+        )
+    }
+
+    (rdx << 32) | (rax & 0xffff_ffff)
+}
+
+fn time_page() -> &'static TimePage {
+    unsafe { &*PROCESS_TIME_PAGE.as_ptr::<TimePage>() }
+}
+
+/// Monotonic time, as a duration from an unspecified fixed point.
+/// Uses the same clock as the `time/monotonic` kernel service,
+/// but doesn't require a system call.
+pub fn now() -> Duration {
+    time_page().time_at(read_tsc())
+}
+
+/// Time since boot. This is the monotonic clock, so in a time namespace
+/// it is the virtualized uptime.
+pub fn uptime() -> Duration {
+    now()
+}
+
+/// Random id of the current boot, for correlating logs across reboots
+pub fn boot_id() -> u128 {
+    time_page().boot_id()
+}
@@ -0,0 +1,147 @@
+//! Micro-benchmarks, run by the `bench` build target.
+//!
+//! Every benchmark prints one JSON object on its own log line, and the
+//! system is powered off when all of them have completed. The objects
+//! start with `{"benchmark":`, so they can be extracted from the serial log.
+//!
+//! There are no pipes or filesystem calls in this system, so the
+//! nearest equivalents are measured instead: IPC publish throughput and
+//! kernel service request latency.
+
+#![no_std]
+#![feature(alloc_prelude)]
+#![deny(unused_must_use)]
+
+#[macro_use]
+extern crate alloc;
+
+#[macro_use]
+extern crate libd7;
+
+use alloc::prelude::v1::*;
+use core::sync::atomic::{AtomicU32, Ordering};
+use serde::Serialize;
+
+use libd7::syscall::{self, ShutdownAction};
+use libd7::time::{self, Duration};
+use libd7::{ipc, VirtAddr};
+
+const THREAD_STACK_SIZE: usize = 0x1_0000;
+const IPC_MESSAGE_SIZE: usize = 0x1000;
+
+#[derive(Serialize)]
+struct Measurement {
+    benchmark: &'static str,
+    iterations: u64,
+    total_ns: u64,
+    ns_per_iteration: u64,
+    /// Only for throughput benchmarks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes_per_second: Option<u64>,
+}
+
+fn run<F: FnMut()>(benchmark: &'static str, iterations: u64, bytes: u64, mut f: F) {
+    let start = time::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let total = time::now() - start;
+    report(benchmark, iterations, bytes, total);
+}
+
+fn report(benchmark: &'static str, iterations: u64, bytes: u64, total: Duration) {
+    let total_ns = (total.as_nanos() as u64).max(1);
+    let result = Measurement {
+        benchmark,
+        iterations,
+        total_ns,
+        ns_per_iteration: total_ns / iterations,
+        bytes_per_second: if bytes == 0 {
+            None
+        } else {
+            Some(((bytes * iterations) as u128 * 1_000_000_000 / total_ns as u128) as u64)
+        },
+    };
+    println!("{}", serde_json::to_string(&result).unwrap());
+}
+
+/// Minimal system call
+fn syscall_round_trip() {
+    run("syscall_round_trip", 100_000, 0, || {
+        syscall::get_pid();
+    });
+}
+
+/// Yield with no other process to switch to
+fn sched_yield() {
+    run("sched_yield", 10_000, 0, syscall::sched_yield);
+}
+
+/// Set to 1 by the main thread and back to 0 by the partner thread
+static PING: AtomicU32 = AtomicU32::new(0);
+
+extern "C" fn ping_partner(_: u64) -> ! {
+    loop {
+        while PING.load(Ordering::SeqCst) == 0 {
+            // Fails when the value has already changed
+            let _ = syscall::futex_wait(&PING, 0);
+        }
+        PING.store(0, Ordering::SeqCst);
+        syscall::futex_wake(&PING).unwrap();
+    }
+}
+
+/// Futex ping-pong between two threads, i.e. two context switches per iteration
+fn context_switch() {
+    let stack: &'static mut [u8] = Box::leak(vec![0u8; THREAD_STACK_SIZE].into_boxed_slice());
+    let stack_end = stack.as_ptr() as u64 + stack.len() as u64;
+    let stack_top = VirtAddr::new(stack_end).align_down(16u64);
+    unsafe { syscall::thread_create(ping_partner, stack_top, 0) }.unwrap();
+
+    run("context_switch", 10_000, 0, || {
+        PING.store(1, Ordering::SeqCst);
+        syscall::futex_wake(&PING).unwrap();
+        while PING.load(Ordering::SeqCst) == 1 {
+            let _ = syscall::futex_wait(&PING, 1);
+        }
+    });
+}
+
+/// Publish messages to a topic this process is subscribed to, and receive them
+fn ipc_throughput() {
+    let sub = syscall::ipc_subscribe("benchmark/ipc", true, false).unwrap();
+    let message = vec![0xd7u8; IPC_MESSAGE_SIZE];
+    let mut buffer = vec![0u8; 2 * IPC_MESSAGE_SIZE];
+
+    run("ipc_throughput", 10_000, IPC_MESSAGE_SIZE as u64, || {
+        syscall::ipc_publish("benchmark/ipc", &message).unwrap();
+        let count = syscall::ipc_receive(sub, &mut buffer).unwrap();
+        assert_eq!(count, IPC_MESSAGE_SIZE);
+    });
+
+    syscall::ipc_unsubscribe(sub).unwrap();
+}
+
+/// Request-reply round trip to a kernel service
+fn service_request() {
+    run("service_request", 1_000, 0, || {
+        let _: Duration = ipc::request("time/monotonic", ()).unwrap();
+    });
+}
+
+#[no_mangle]
+fn main() -> u64 {
+    println!("Benchmarks starting");
+
+    syscall_round_trip();
+    sched_yield();
+    context_switch();
+    ipc_throughput();
+    service_request();
+
+    println!("Benchmarks done");
+    syscall::kernel_shutdown(ShutdownAction::PowerOff).unwrap();
+    loop {
+        syscall::sched_sleep_ns(1_000_000_000).unwrap();
+    }
+}
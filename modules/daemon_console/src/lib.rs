@@ -4,6 +4,10 @@
 //! Has normal tty-consoles in 1-9 and kerenl log in 0.
 //! The active console can be switched with `ctrl-alt-number`.
 //!
+//! Each tty-console has a foreground process, set with `ConsoleControl` by drivers,
+//! which receives the entered lines and the Ctrl+C and Ctrl+Z signals.
+//! See `d7abi::ipc::protocol::console`.
+//!
 //! TODO: color support
 
 #![no_std]
@@ -22,10 +26,14 @@ use hashbrown::HashSet;
 
 use libd7::{
     ipc::{self, protocol::keyboard::KeyboardEvent, InternalSubscription, SubscriptionId},
-    process::ProcessId,
+    process::{Privilege, ProcessId},
     select, syscall,
 };
 
+use libd7::ipc::protocol::console::{
+    input_topic, ConsoleControl, ConsoleInput, ConsoleSignal, CONTROL_TOPIC,
+};
+
 mod keyboard;
 mod vga;
 mod virtual_console;
@@ -36,12 +44,22 @@ use self::virtual_console::VirtualConsole;
 struct Console {
     device: VirtualConsole,
     sub_print: ipc::ReliableSubscription<String>,
+    /// Receives the input of this console
+    foreground: Option<ProcessId>,
 }
 impl Console {
     pub fn new(name: &str) -> Self {
         Self {
             device: VirtualConsole::new(),
             sub_print: ipc::ReliableSubscription::exact(&format!("console/{}", name)).unwrap(),
+            foreground: None,
+        }
+    }
+
+    /// Input is dropped if there is no foreground process
+    pub fn send_input(&self, index: usize, input: ConsoleInput) {
+        if let Some(pid) = self.foreground {
+            ipc::publish(&input_topic(index as u8, pid), &input).unwrap();
         }
     }
 
@@ -76,6 +94,9 @@ fn main() -> ! {
     consoles[0].device.render(&mut vga_buffer);
 
     let kbd_sub = ipc::UnreliableSubscription::<KeyboardEvent>::exact("keyboard/event").unwrap();
+    let control_sub = ipc::ReliableSubscription::<ConsoleControl>::exact(CONTROL_TOPIC).unwrap();
+    // The foreground process receives the input, so user processes cannot change it
+    control_sub.restrict(Privilege::Driver).unwrap();
     let c_sub_ids: Vec<SubscriptionId> = consoles.iter().map(|c| c.sub_print.sub_id()).collect();
 
     // Inform the serviced that we are up
@@ -99,19 +120,44 @@ fn main() -> ! {
 
                 let mut mods_ctrl = HashSet::new();
                 mods_ctrl.insert(d7keymap::KeySymbol::new("LeftCtrl"));
+                let mut signal = None;
                 if let self::keyboard::EventAction::Unmatched(k, mods) = &action {
                     if mods == &mods_ctrl {
                         if let Ok(number) = k.as_str().parse::<usize>() {
                             active_index = number;
                         }
+                        signal = match k.as_str() {
+                            "C" => Some(ConsoleSignal::Interrupt),
+                            "Z" => Some(ConsoleSignal::Suspend),
+                            _ => None,
+                        };
                     }
                 }
 
                 if active_index != 0 {
-                    consoles[active_index].device.input.keyboard_event(action);
+                    let console = &mut consoles[active_index];
+                    if let Some(signal) = signal {
+                        console.send_input(active_index, ConsoleInput::Signal(signal));
+                    } else if let Some(line) = console.device.input.keyboard_event(action) {
+                        console.device.output.write_str(line.as_bytes());
+                        console.device.output.new_line();
+                        console.send_input(active_index, ConsoleInput::Line(line));
+                    }
                 }
 
                 consoles[active_index].device.render(&mut vga_buffer);
+            },
+            one(control_sub) => {
+                match control_sub.ack_receive().unwrap() {
+                    ConsoleControl::SetForeground { console, pid } => {
+                        let index = console as usize;
+                        if index == 0 || index >= consoles.len() {
+                            println!("SetForeground: invalid console {}", console);
+                        } else {
+                            consoles[index].foreground = pid;
+                        }
+                    },
+                }
             }
         }
     }
@@ -86,7 +86,8 @@ impl Input {
         }
     }
 
-    pub fn keyboard_event(&mut self, action: EventAction) {
+    /// Returns the input line when Enter is pressed
+    pub fn keyboard_event(&mut self, action: EventAction) -> Option<String> {
         use unicode_normalization::UnicodeNormalization;
         use unicode_segmentation::UnicodeSegmentation;
 
@@ -107,7 +108,8 @@ impl Input {
             },
             EventAction::Unmatched(symbol, modifiers) => match symbol.as_str() {
                 "Enter" if modifiers.is_empty() => {
-                    self.input_buffer.push('\n');
+                    self.dead_key_buffer.clear();
+                    return Some(core::mem::take(&mut self.input_buffer));
                 }
                 "Backspace" if modifiers.is_empty() => {
                     self.dead_key_buffer.clear();
@@ -120,6 +122,7 @@ impl Input {
             },
             EventAction::Ignore | EventAction::NoSuchSymbol => {}
         }
+        None
     }
 }
 
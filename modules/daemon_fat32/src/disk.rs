@@ -0,0 +1,120 @@
+//! Sector access to a drive or a partition through the block device protocol
+
+use alloc::prelude::v1::*;
+
+use libd7::d7abi::ipc::protocol::block;
+use libd7::ipc;
+
+pub const SECTOR_SIZE: usize = 0x200;
+
+/// Largest request accepted by the ATA PIO driver
+const MAX_SECTORS_PER_REQUEST: u64 = 0xff;
+
+/// Partition types of FAT32 (CHS and LBA addressing)
+const MBR_TYPES_FAT32: [u8; 2] = [0x0b, 0x0c];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoError;
+
+#[derive(Debug, Clone)]
+pub struct Disk {
+    topic: String,
+    start_lba: u64,
+    sectors: u64,
+}
+impl Disk {
+    /// Whole drive of the ATA PIO driver
+    pub fn ata_drive(index: u64) -> Self {
+        Self {
+            topic: format!("ata_pio/{}/io", index),
+            start_lba: 0,
+            sectors: u64::MAX,
+        }
+    }
+
+    /// Part of this disk, in sectors
+    fn partition(&self, start_lba: u64, sectors: u64) -> Self {
+        Self {
+            topic: self.topic.clone(),
+            start_lba: self.start_lba + start_lba,
+            sectors,
+        }
+    }
+
+    fn check_range(&self, lba: u64, count: u64) -> Result<(), IoError> {
+        if lba.checked_add(count).map_or(false, |end| end <= self.sectors) {
+            Ok(())
+        } else {
+            Err(IoError)
+        }
+    }
+
+    pub fn read(&self, lba: u64, count: u64) -> Result<Vec<u8>, IoError> {
+        self.check_range(lba, count)?;
+        let mut result = Vec::with_capacity(count as usize * SECTOR_SIZE);
+        let mut done = 0;
+        while done < count {
+            let sectors = (count - done).min(MAX_SECTORS_PER_REQUEST);
+            let response = ipc::request(&self.topic, block::Request {
+                lba: self.start_lba + lba + done,
+                sectors,
+                operation: block::Operation::Read,
+            })
+            .map_err(|_| IoError)?;
+            match response {
+                block::Response::Read(data) => result.extend(data),
+                _ => return Err(IoError),
+            }
+            done += sectors;
+        }
+        Ok(result)
+    }
+
+    /// Data must be a multiple of the sector size
+    pub fn write(&self, lba: u64, data: &[u8]) -> Result<(), IoError> {
+        assert!(data.len() % SECTOR_SIZE == 0);
+        let count = (data.len() / SECTOR_SIZE) as u64;
+        self.check_range(lba, count)?;
+        let mut done = 0;
+        while done < count {
+            let sectors = (count - done).min(MAX_SECTORS_PER_REQUEST);
+            let start = done as usize * SECTOR_SIZE;
+            let end = start + sectors as usize * SECTOR_SIZE;
+            let response = ipc::request(&self.topic, block::Request {
+                lba: self.start_lba + lba + done,
+                sectors,
+                operation: block::Operation::Write(data[start..end].to_vec()),
+            })
+            .map_err(|_| IoError)?;
+            match response {
+                block::Response::Write => {},
+                _ => return Err(IoError),
+            }
+            done += sectors;
+        }
+        Ok(())
+    }
+
+    /// FAT32 partitions of the MBR partition table, numbered from 1.
+    /// A drive formatted without a partition table is returned as partition 0.
+    pub fn fat32_partitions(&self) -> Result<Vec<(usize, Disk)>, IoError> {
+        let sector = self.read(0, 1)?;
+        if sector[510..512] != [0x55, 0xaa] {
+            return Ok(Vec::new());
+        }
+        if crate::fat32::is_boot_sector(&sector) {
+            return Ok(vec![(0, self.clone())]);
+        }
+
+        let mut result = Vec::new();
+        for index in 0..4 {
+            let entry = &sector[0x1be + index * 16..0x1be + (index + 1) * 16];
+            let start = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]);
+            let count = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]);
+            if MBR_TYPES_FAT32.contains(&entry[4]) && start != 0 && count != 0 {
+                result.push((index + 1, self.partition(start as u64, count as u64)));
+            }
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,799 @@
+//! FAT32 filesystem: https://wiki.osdev.org/FAT
+//!
+//! Long file names are read, but new files get 8.3 names only, so names
+//! that don't fit are rejected. Names are compared case-insensitively, and
+//! both the long name and the 8.3 alias of an entry can be used in paths.
+//! Timestamps are not maintained, and directories cannot be created yet,
+//! but files and directories can be renamed, and removed when empty.
+
+use alloc::prelude::v1::*;
+use core::char;
+
+use libd7::d7abi::fs::casefold;
+use libd7::d7abi::ipc::protocol::filesystem::{DirEntry, Request, Response, MAX_TRANSFER_BYTES};
+
+use crate::disk::{Disk, IoError, SECTOR_SIZE};
+
+const CLUSTER_MASK: u32 = 0x0fff_ffff;
+const END_OF_CHAIN: u32 = 0x0fff_ffff;
+const DIR_ENTRY_SIZE: usize = 32;
+const FSINFO_SIGNATURE: u32 = 0x4161_5252;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+/// Flags in the reserved byte of the entry, used by Windows NT and Linux
+const LOWERCASE_BASE: u8 = 0x08;
+const LOWERCASE_EXT: u8 = 0x10;
+
+/// Allowed in 8.3 names in addition to uppercase letters and digits
+const SHORT_NAME_SYMBOLS: &[u8] = b"!#$%&'()-@^_`{}~";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Io,
+    NotFound,
+    WrongKind,
+    InvalidName,
+    NoSpace,
+    NotEmpty,
+}
+impl From<IoError> for Error {
+    fn from(_: IoError) -> Self {
+        Self::Io
+    }
+}
+
+fn u16_le(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_le(bytes: &[u8], offset: usize) -> u32 {
+    let mut value = [0; 4];
+    value.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(value)
+}
+
+pub fn is_boot_sector(sector: &[u8]) -> bool {
+    &sector[82..90] == b"FAT32   " && u16_le(sector, 11) as usize == SECTOR_SIZE
+}
+
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty())
+}
+
+/// Checksum of the 8.3 name, stored in the long name entries
+fn short_name_checksum(short: &[u8]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// Byte ranges of the name characters in a long name entry
+static LONG_NAME_RANGES: [(usize, usize); 3] = [(1, 11), (14, 26), (28, 32)];
+
+fn long_name_chars(raw: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    LONG_NAME_RANGES
+        .iter()
+        .flat_map(move |&(start, end)| raw[start..end].chunks(2))
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+}
+
+/// Long name from its entries, in the on-disk order (last part first).
+/// `None` if there are none, or if they belong to another short name.
+fn long_name(parts: &[(u8, Vec<u16>)], checksum: u8) -> Option<String> {
+    if parts.is_empty() || parts.iter().any(|(sum, _)| *sum != checksum) {
+        return None;
+    }
+    let units = parts
+        .iter()
+        .rev()
+        .flat_map(|(_, chars)| chars.iter().copied())
+        .take_while(|c| *c != 0x0000 && *c != 0xffff);
+    Some(
+        char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect(),
+    )
+}
+
+/// Name of an 8.3 entry. Bytes above ASCII are read as Latin-1.
+fn short_name(raw: &[u8]) -> String {
+    let part = |bytes: &[u8], lowercase: bool| -> String {
+        let mut bytes = bytes.to_vec();
+        if bytes.first() == Some(&0x05) {
+            bytes[0] = 0xe5;
+        }
+        let text: String = bytes.iter().map(|b| *b as char).collect();
+        let text = text.trim_end_matches(' ');
+        if lowercase {
+            text.to_ascii_lowercase()
+        } else {
+            text.to_owned()
+        }
+    };
+    let base = part(&raw[0..8], raw[12] & LOWERCASE_BASE != 0);
+    let ext = part(&raw[8..11], raw[12] & LOWERCASE_EXT != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, ext)
+    }
+}
+
+/// 8.3 name bytes and lowercase flags, if the name can be stored as one
+fn to_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+
+    let mut result = [b' '; 11];
+    let mut flags = 0;
+    for (part, offset, flag) in [(base, 0, LOWERCASE_BASE), (ext, 8, LOWERCASE_EXT)].iter() {
+        let has_upper = part.bytes().any(|b| b.is_ascii_uppercase());
+        let has_lower = part.bytes().any(|b| b.is_ascii_lowercase());
+        if has_upper && has_lower {
+            // Mixed case requires a long name
+            return None;
+        }
+        if has_lower {
+            flags |= flag;
+        }
+        for (i, byte) in part.bytes().enumerate() {
+            if !(byte.is_ascii_alphanumeric() || SHORT_NAME_SYMBOLS.contains(&byte)) {
+                return None;
+            }
+            result[offset + i] = byte.to_ascii_uppercase();
+        }
+    }
+    Some((result, flags))
+}
+
+/// Directory entry and the location of its 8.3 entry on disk
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    short_name: String,
+    attr: u8,
+    first_cluster: u32,
+    size: u32,
+    lba: u64,
+    offset: usize,
+    /// Locations of the long name entries before it
+    long_name_slots: Vec<(u64, usize)>,
+}
+impl Entry {
+    fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        casefold::eq_ignore_case(&self.name, name)
+            || casefold::eq_ignore_case(&self.short_name, name)
+    }
+}
+
+pub struct Fat32 {
+    disk: Disk,
+    sectors_per_cluster: u64,
+    fat_start: u64,
+    fat_sectors: u64,
+    fat_count: u64,
+    data_start: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+    /// Cleared once the free cluster count in it has been invalidated
+    fsinfo_sector: Option<u64>,
+    /// Where the search for a free cluster starts
+    next_free: u32,
+    /// Most recently used FAT sector
+    fat_cache: Option<(u64, Vec<u8>)>,
+}
+impl Fat32 {
+    pub fn mount(disk: Disk) -> Result<Self, Error> {
+        let boot = disk.read(0, 1)?;
+        if !is_boot_sector(&boot) {
+            return Err(Error::Io);
+        }
+
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved_sectors = u16_le(&boot, 14) as u64;
+        let fat_count = boot[16] as u64;
+        let total_sectors = u32_le(&boot, 32) as u64;
+        let fat_sectors = u32_le(&boot, 36) as u64;
+        let root_cluster = u32_le(&boot, 44);
+        let fsinfo_sector = u16_le(&boot, 48) as u64;
+
+        if !sectors_per_cluster.is_power_of_two() || fat_count == 0 || fat_sectors == 0 {
+            return Err(Error::Io);
+        }
+        let data_start = reserved_sectors + fat_count * fat_sectors;
+        if total_sectors <= data_start {
+            return Err(Error::Io);
+        }
+        let entries_per_fat = fat_sectors * (SECTOR_SIZE as u64 / 4);
+        let cluster_count = ((total_sectors - data_start) / sectors_per_cluster)
+            .min(entries_per_fat - 2)
+            .min(CLUSTER_MASK as u64 - 0x10) as u32;
+
+        let fs = Self {
+            disk,
+            sectors_per_cluster,
+            fat_start: reserved_sectors,
+            fat_sectors,
+            fat_count,
+            data_start,
+            cluster_count,
+            root_cluster,
+            fsinfo_sector: if fsinfo_sector != 0 && fsinfo_sector < reserved_sectors {
+                Some(fsinfo_sector)
+            } else {
+                None
+            },
+            next_free: 2,
+            fat_cache: None,
+        };
+        if !fs.is_valid_cluster(root_cluster) {
+            return Err(Error::Io);
+        }
+        Ok(fs)
+    }
+
+    pub fn handle(&mut self, request: Request) -> Response {
+        let result = match request {
+            Request::List(path) => self.list(&path).map(Response::List),
+            Request::Read { path, offset, len } => self
+                .read(&path, offset, len.min(MAX_TRANSFER_BYTES))
+                .map(Response::Read),
+            Request::Write { path, offset, data } => {
+                let len = data.len().min(MAX_TRANSFER_BYTES as usize);
+                self.write(&path, offset, &data[..len]).map(|()| Response::Write(len as u64))
+            },
+            Request::Remove(path) => self.remove(&path).map(|()| Response::Removed),
+            Request::Rename { from, to } => self.rename(&from, &to).map(|()| Response::Renamed),
+        };
+        result.unwrap_or_else(|error| match error {
+            Error::Io => {
+                // The cached sector may not match the disk after a failed write
+                self.fat_cache = None;
+                Response::IoError
+            },
+            Error::NotFound => Response::NotFound,
+            Error::WrongKind => Response::WrongKind,
+            Error::InvalidName => Response::InvalidName,
+            Error::NoSpace => Response::NoSpace,
+            Error::NotEmpty => Response::NotEmpty,
+        })
+    }
+
+    fn cluster_bytes(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    fn cluster_lba(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster as u64 - 2) * self.sectors_per_cluster
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2
+    }
+
+    fn read_cluster(&self, cluster: u32) -> Result<Vec<u8>, Error> {
+        Ok(self
+            .disk
+            .read(self.cluster_lba(cluster), self.sectors_per_cluster)?)
+    }
+
+    /// Sector index in the FAT and byte offset in it
+    fn fat_location(cluster: u32) -> (u64, usize) {
+        let byte = cluster as usize * 4;
+        ((byte / SECTOR_SIZE) as u64, byte % SECTOR_SIZE)
+    }
+
+    fn fat_sector(&mut self, index: u64) -> Result<&mut Vec<u8>, Error> {
+        if self.fat_cache.as_ref().map(|(i, _)| *i) != Some(index) {
+            let data = self.disk.read(self.fat_start + index, 1)?;
+            self.fat_cache = Some((index, data));
+        }
+        Ok(&mut self.fat_cache.as_mut().unwrap().1)
+    }
+
+    fn fat_get(&mut self, cluster: u32) -> Result<u32, Error> {
+        let (index, offset) = Self::fat_location(cluster);
+        Ok(u32_le(self.fat_sector(index)?, offset) & CLUSTER_MASK)
+    }
+
+    /// Updates all copies of the FAT. The upper bits of entries are reserved.
+    fn fat_set(&mut self, cluster: u32, value: u32) -> Result<(), Error> {
+        let (index, offset) = Self::fat_location(cluster);
+        let sector = self.fat_sector(index)?;
+        let old = u32_le(sector, offset);
+        let new = (old & !CLUSTER_MASK) | (value & CLUSTER_MASK);
+        sector[offset..offset + 4].copy_from_slice(&new.to_le_bytes());
+        let sector = sector.clone();
+        for copy in 0..self.fat_count {
+            let lba = self.fat_start + copy * self.fat_sectors + index;
+            self.disk.write(lba, &sector)?;
+        }
+        Ok(())
+    }
+
+    /// Clusters of a file or a directory, empty for an empty file
+    fn chain(&mut self, first_cluster: u32) -> Result<Vec<u32>, Error> {
+        let mut result = Vec::new();
+        let mut cluster = first_cluster;
+        while self.is_valid_cluster(cluster) {
+            if result.len() >= self.cluster_count as usize {
+                // Loop in the chain
+                return Err(Error::Io);
+            }
+            result.push(cluster);
+            cluster = self.fat_get(cluster)?;
+        }
+        Ok(result)
+    }
+
+    /// Allocates a zeroed cluster, and links it after `previous` if given
+    fn allocate_cluster(&mut self, previous: Option<u32>) -> Result<u32, Error> {
+        for i in 0..self.cluster_count {
+            let cluster = 2 + (self.next_free - 2 + i) % self.cluster_count;
+            if self.fat_get(cluster)? == 0 {
+                let zeroes = vec![0; self.cluster_bytes()];
+                self.disk.write(self.cluster_lba(cluster), &zeroes)?;
+                self.fat_set(cluster, END_OF_CHAIN)?;
+                if let Some(previous) = previous {
+                    self.fat_set(previous, cluster)?;
+                }
+                self.next_free = 2 + (cluster - 1) % self.cluster_count;
+                self.invalidate_fsinfo()?;
+                return Ok(cluster);
+            }
+        }
+        Err(Error::NoSpace)
+    }
+
+    /// The free cluster count in FSInfo is only a hint,
+    /// so it is marked unknown instead of being maintained
+    fn invalidate_fsinfo(&mut self) -> Result<(), Error> {
+        if let Some(lba) = self.fsinfo_sector.take() {
+            let mut sector = self.disk.read(lba, 1)?;
+            if u32_le(&sector, 0) == FSINFO_SIGNATURE {
+                sector[488..496].copy_from_slice(&[0xff; 8]);
+                self.disk.write(lba, &sector)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_dir(&mut self, first_cluster: u32) -> Result<Vec<Entry>, Error> {
+        let mut entries = Vec::new();
+        let mut long_parts: Vec<(u8, Vec<u16>)> = Vec::new();
+        let mut long_slots: Vec<(u64, usize)> = Vec::new();
+        for cluster in self.chain(first_cluster)? {
+            let data = self.read_cluster(cluster)?;
+            for (i, raw) in data.chunks(DIR_ENTRY_SIZE).enumerate() {
+                match raw[0] {
+                    0x00 => return Ok(entries),
+                    0xe5 => {
+                        long_parts.clear();
+                        long_slots.clear();
+                        continue;
+                    },
+                    _ => {},
+                }
+                let byte = i * DIR_ENTRY_SIZE;
+                let slot = (
+                    self.cluster_lba(cluster) + (byte / SECTOR_SIZE) as u64,
+                    byte % SECTOR_SIZE,
+                );
+                let attr = raw[11];
+                if attr & 0x3f == ATTR_LONG_NAME {
+                    if raw[0] & 0x40 != 0 {
+                        long_parts.clear();
+                        long_slots.clear();
+                    }
+                    long_parts.push((raw[13], long_name_chars(raw).collect()));
+                    long_slots.push(slot);
+                    continue;
+                }
+
+                let short = short_name(raw);
+                let name = long_name(&long_parts, short_name_checksum(&raw[0..11]));
+                long_parts.clear();
+                let long_name_slots = core::mem::replace(&mut long_slots, Vec::new());
+                if attr & ATTR_VOLUME_ID != 0 || raw[0] == b'.' {
+                    continue;
+                }
+                entries.push(Entry {
+                    // Long name entries belong to this entry only if the checksum matches
+                    long_name_slots: if name.is_some() { long_name_slots } else { Vec::new() },
+                    name: name.unwrap_or_else(|| short.clone()),
+                    short_name: short,
+                    attr,
+                    first_cluster: ((u16_le(raw, 20) as u32) << 16) | u16_le(raw, 26) as u32,
+                    size: u32_le(raw, 28),
+                    lba: slot.0,
+                    offset: slot.1,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Entry at the path, `None` for the root directory
+    fn lookup(&mut self, path: &str) -> Result<Option<Entry>, Error> {
+        let mut current: Option<Entry> = None;
+        for name in components(path) {
+            let dir = match &current {
+                None => self.root_cluster,
+                Some(entry) if entry.is_dir() => entry.first_cluster,
+                Some(_) => return Err(Error::WrongKind),
+            };
+            let entry = self.read_dir(dir)?.into_iter().find(|e| e.matches(name));
+            current = Some(entry.ok_or(Error::NotFound)?);
+        }
+        Ok(current)
+    }
+
+    fn lookup_dir(&mut self, path: &str) -> Result<u32, Error> {
+        match self.lookup(path)? {
+            None => Ok(self.root_cluster),
+            Some(entry) if entry.is_dir() => Ok(entry.first_cluster),
+            Some(_) => Err(Error::WrongKind),
+        }
+    }
+
+    fn lookup_file(&mut self, path: &str) -> Result<Entry, Error> {
+        match self.lookup(path)? {
+            Some(entry) if !entry.is_dir() => Ok(entry),
+            _ => Err(Error::WrongKind),
+        }
+    }
+
+    fn list(&mut self, path: &str) -> Result<Vec<DirEntry>, Error> {
+        let cluster = self.lookup_dir(path)?;
+        Ok(self
+            .read_dir(cluster)?
+            .into_iter()
+            .map(|entry| DirEntry {
+                is_dir: entry.is_dir(),
+                size: if entry.is_dir() { 0 } else { entry.size as u64 },
+                name: entry.name,
+            })
+            .collect())
+    }
+
+    fn read(&mut self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>, Error> {
+        let entry = self.lookup_file(path)?;
+        let size = entry.size as u64;
+        let end = offset.saturating_add(len).min(size);
+        let chain = self.chain(entry.first_cluster)?;
+        let cluster_bytes = self.cluster_bytes() as u64;
+
+        let mut result = Vec::new();
+        let mut pos = offset;
+        while pos < end {
+            let cluster = *chain.get((pos / cluster_bytes) as usize).ok_or(Error::Io)?;
+            let data = self.read_cluster(cluster)?;
+            let from = (pos % cluster_bytes) as usize;
+            let to = (from as u64 + (end - pos)).min(cluster_bytes) as usize;
+            result.extend_from_slice(&data[from..to]);
+            pos += (to - from) as u64;
+        }
+        Ok(result)
+    }
+
+    fn write(&mut self, path: &str, offset: u64, data: &[u8]) -> Result<(), Error> {
+        let entry = match self.lookup_file(path) {
+            Err(Error::NotFound) => self.create(path)?,
+            other => other?,
+        };
+        let end = match offset.checked_add(data.len() as u64) {
+            Some(end) if end <= u32::MAX as u64 => end,
+            _ => return Err(Error::NoSpace),
+        };
+
+        let cluster_bytes = self.cluster_bytes() as u64;
+        let mut chain = self.chain(entry.first_cluster)?;
+        let allocated_end = chain.len() as u64 * cluster_bytes;
+        while (chain.len() as u64) * cluster_bytes < end {
+            let cluster = self.allocate_cluster(chain.last().copied())?;
+            chain.push(cluster);
+        }
+
+        // New clusters are zeroed, but the last old one can contain stale data
+        let old_size = entry.size as u64;
+        let gap_end = offset.min(allocated_end);
+        if old_size < gap_end {
+            let zeroes = vec![0; (gap_end - old_size) as usize];
+            self.write_range(&chain, old_size, &zeroes)?;
+        }
+        self.write_range(&chain, offset, data)?;
+
+        let first_cluster = chain.first().copied().unwrap_or(0);
+        if first_cluster != entry.first_cluster || end > old_size {
+            self.update_entry(&entry, first_cluster, end.max(old_size) as u32)?;
+        }
+        Ok(())
+    }
+
+    /// Writes to the clusters of a file, reading only the partially written sectors
+    fn write_range(&mut self, chain: &[u32], offset: u64, data: &[u8]) -> Result<(), Error> {
+        let cluster_bytes = self.cluster_bytes() as u64;
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done as u64;
+            let cluster = chain[(pos / cluster_bytes) as usize];
+            let from = (pos % cluster_bytes) as usize;
+            let count = (cluster_bytes as usize - from).min(data.len() - done);
+
+            let first_sector = from / SECTOR_SIZE;
+            let sectors = (from + count - 1) / SECTOR_SIZE - first_sector + 1;
+            let lba = self.cluster_lba(cluster) + first_sector as u64;
+            let mut buffer = if from % SECTOR_SIZE == 0 && count % SECTOR_SIZE == 0 {
+                vec![0; count]
+            } else {
+                self.disk.read(lba, sectors as u64)?
+            };
+            let start = from - first_sector * SECTOR_SIZE;
+            buffer[start..start + count].copy_from_slice(&data[done..done + count]);
+            self.disk.write(lba, &buffer)?;
+            done += count;
+        }
+        Ok(())
+    }
+
+    fn update_entry(&mut self, entry: &Entry, first_cluster: u32, size: u32) -> Result<(), Error> {
+        let mut sector = self.disk.read(entry.lba, 1)?;
+        let raw = &mut sector[entry.offset..entry.offset + DIR_ENTRY_SIZE];
+        raw[20..22].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+        raw[26..28].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+        raw[28..32].copy_from_slice(&size.to_le_bytes());
+        self.disk.write(entry.lba, &sector)?;
+        Ok(())
+    }
+
+    /// Creates an empty file in an existing directory
+    fn create(&mut self, path: &str) -> Result<Entry, Error> {
+        let parts: Vec<&str> = components(path).collect();
+        let (name, parent) = parts.split_last().ok_or(Error::WrongKind)?;
+        let dir = self.lookup_dir(&parent.join("/"))?;
+        let (short, flags) = to_short_name(name).ok_or(Error::InvalidName)?;
+
+        let (lba, offset) = self.free_dir_slot(dir)?;
+        let mut sector = self.disk.read(lba, 1)?;
+        let raw = &mut sector[offset..offset + DIR_ENTRY_SIZE];
+        for byte in raw.iter_mut() {
+            *byte = 0;
+        }
+        raw[0..11].copy_from_slice(&short);
+        raw[11] = ATTR_ARCHIVE;
+        raw[12] = flags;
+        let short_name = short_name(raw);
+        self.disk.write(lba, &sector)?;
+
+        Ok(Entry {
+            name: short_name.clone(),
+            short_name,
+            attr: ATTR_ARCHIVE,
+            first_cluster: 0,
+            size: 0,
+            lba,
+            offset,
+            long_name_slots: Vec::new(),
+        })
+    }
+
+    /// Removes a file or an empty directory. The entry is marked unused
+    /// before its clusters are freed, so that an interrupted removal
+    /// only leaks clusters, and cannot leave an entry pointing to free ones.
+    fn remove(&mut self, path: &str) -> Result<(), Error> {
+        // The root directory cannot be removed
+        let entry = self.lookup(path)?.ok_or(Error::WrongKind)?;
+        if entry.is_dir() && !self.read_dir(entry.first_cluster)?.is_empty() {
+            return Err(Error::NotEmpty);
+        }
+        let chain = self.chain(entry.first_cluster)?;
+        self.mark_unused(&entry)?;
+        self.free_chain(&chain)
+    }
+
+    /// Marks the 8.3 entry and the long name entries of an entry unused
+    fn mark_unused(&mut self, entry: &Entry) -> Result<(), Error> {
+        let slots = entry.long_name_slots.iter().chain(Some(&(entry.lba, entry.offset)));
+        for &(lba, offset) in slots {
+            let mut sector = self.disk.read(lba, 1)?;
+            sector[offset] = 0xe5;
+            self.disk.write(lba, &sector)?;
+        }
+        Ok(())
+    }
+
+    fn free_chain(&mut self, chain: &[u32]) -> Result<(), Error> {
+        for &cluster in chain {
+            self.fat_set(cluster, 0)?;
+        }
+        self.invalidate_fsinfo()
+    }
+
+    /// Moves a file or a directory, replacing an existing file at `to`.
+    /// The new entry is written before the old one is removed, so an
+    /// interrupted rename can leave both, but never loses the file.
+    fn rename(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        // The root directory cannot be moved, or replaced
+        let entry = self.lookup(from)?.ok_or(Error::WrongKind)?;
+        let parts: Vec<&str> = components(to).collect();
+        let (name, parent) = parts.split_last().ok_or(Error::WrongKind)?;
+        let dir = self.lookup_dir(&parent.join("/"))?;
+
+        // A directory cannot be moved into itself
+        if entry.is_dir() {
+            for depth in 1..=parent.len() {
+                let ancestor = self.lookup(&parent[..depth].join("/"))?;
+                if ancestor.map_or(false, |a| a.first_cluster == entry.first_cluster) {
+                    return Err(Error::InvalidName);
+                }
+            }
+        }
+
+        let replaced = match self.lookup(to) {
+            Ok(Some(existing)) if existing.lba == entry.lba && existing.offset == entry.offset => {
+                return Ok(());
+            },
+            Ok(Some(existing)) if existing.is_dir() || entry.is_dir() => {
+                return Err(Error::WrongKind);
+            },
+            Ok(existing) => existing,
+            Err(Error::NotFound) => None,
+            Err(error) => return Err(error),
+        };
+
+        let (lba, offset) = match &replaced {
+            Some(existing) => (existing.lba, existing.offset),
+            None => {
+                let (short, flags) = to_short_name(name).ok_or(Error::InvalidName)?;
+                let (lba, offset) = self.free_dir_slot(dir)?;
+                let mut sector = self.disk.read(lba, 1)?;
+                let raw = &mut sector[offset..offset + DIR_ENTRY_SIZE];
+                raw[0..11].copy_from_slice(&short);
+                raw[12] = flags;
+                self.disk.write(lba, &sector)?;
+                (lba, offset)
+            },
+        };
+
+        // Copy everything except the name to the new entry
+        let old = self.disk.read(entry.lba, 1)?;
+        let old = &old[entry.offset..entry.offset + DIR_ENTRY_SIZE];
+        let mut sector = self.disk.read(lba, 1)?;
+        let raw = &mut sector[offset..offset + DIR_ENTRY_SIZE];
+        raw[11] = old[11];
+        raw[13..].copy_from_slice(&old[13..]);
+        self.disk.write(lba, &sector)?;
+
+        self.mark_unused(&entry)?;
+        if let Some(replaced) = replaced {
+            let chain = self.chain(replaced.first_cluster)?;
+            self.free_chain(&chain)?;
+        }
+
+        // The `..` entry of a moved directory points to its parent, zero for the root
+        if entry.is_dir() {
+            let parent_cluster = if dir == self.root_cluster { 0 } else { dir };
+            let lba = self.cluster_lba(entry.first_cluster);
+            let mut sector = self.disk.read(lba, 1)?;
+            let raw = &mut sector[DIR_ENTRY_SIZE..2 * DIR_ENTRY_SIZE];
+            if &raw[0..2] == b".." {
+                raw[20..22].copy_from_slice(&((parent_cluster >> 16) as u16).to_le_bytes());
+                raw[26..28].copy_from_slice(&(parent_cluster as u16).to_le_bytes());
+                self.disk.write(lba, &sector)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Location of an unused entry in the directory, which is extended if needed
+    fn free_dir_slot(&mut self, dir: u32) -> Result<(u64, usize), Error> {
+        let chain = self.chain(dir)?;
+        for &cluster in &chain {
+            let data = self.read_cluster(cluster)?;
+            let slot = data
+                .chunks(DIR_ENTRY_SIZE)
+                .position(|raw| raw[0] == 0x00 || raw[0] == 0xe5);
+            if let Some(index) = slot {
+                let byte = index * DIR_ENTRY_SIZE;
+                let lba = self.cluster_lba(cluster) + (byte / SECTOR_SIZE) as u64;
+                return Ok((lba, byte % SECTOR_SIZE));
+            }
+        }
+        let cluster = self.allocate_cluster(chain.last().copied())?;
+        Ok((self.cluster_lba(cluster), 0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn units(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    /// Long name entry characters, terminated and padded as on disk
+    fn entry_chars(s: &str) -> Vec<u16> {
+        let mut chars = units(s);
+        if chars.len() < 13 {
+            chars.push(0x0000);
+        }
+        chars.resize(13, 0xffff);
+        chars
+    }
+
+    #[test]
+    fn test_to_short_name() {
+        assert_eq!(to_short_name("README.TXT"), Some((*b"README  TXT", 0)));
+        assert_eq!(to_short_name("FOO"), Some((*b"FOO        ", 0)));
+        assert_eq!(
+            to_short_name("readme.txt"),
+            Some((*b"README  TXT", LOWERCASE_BASE | LOWERCASE_EXT))
+        );
+        assert_eq!(to_short_name("KERNEL.elf"), Some((*b"KERNEL  ELF", LOWERCASE_EXT)));
+        assert_eq!(to_short_name("A-1_~{}.$$$"), Some((*b"A-1_~{} $$$", 0)));
+    }
+
+    #[test]
+    fn test_to_short_name_rejected() {
+        assert_eq!(to_short_name(""), None);
+        assert_eq!(to_short_name(".txt"), None);
+        assert_eq!(to_short_name("LONGNAME1.TXT"), None);
+        assert_eq!(to_short_name("FILE.TEXT"), None);
+        assert_eq!(to_short_name("ReadMe.txt"), None);
+        assert_eq!(to_short_name("A B.TXT"), None);
+        assert_eq!(to_short_name("A+B.TXT"), None);
+        assert_eq!(to_short_name("ÄÖ.TXT"), None);
+    }
+
+    #[test]
+    fn test_short_name_round_trip() {
+        for name in &["README.TXT", "readme.txt", "KERNEL.elf", "FOO", "bar"] {
+            let (raw, flags) = to_short_name(name).unwrap();
+            let mut entry = [0u8; DIR_ENTRY_SIZE];
+            entry[..11].copy_from_slice(&raw);
+            entry[12] = flags;
+            assert_eq!(short_name(&entry), *name);
+        }
+    }
+
+    #[test]
+    fn test_short_name_checksum() {
+        assert_eq!(short_name_checksum(b"README  TXT"), 0x73);
+        assert_eq!(short_name_checksum(b"FOO        "), 0x88);
+        assert_ne!(short_name_checksum(b"README  TXT"), short_name_checksum(b"README  TX "));
+    }
+
+    #[test]
+    fn test_long_name() {
+        let sum = short_name_checksum(b"AVERYL~1TXT");
+        // Stored last part first, and only the last part is terminated
+        let parts = vec![(sum, entry_chars("ile_name.txt")), (sum, units("A very long f"))];
+        assert_eq!(long_name(&parts, sum).as_deref(), Some("A very long file_name.txt"));
+
+        let parts = vec![(sum, entry_chars("ÄÖ.txt"))];
+        assert_eq!(long_name(&parts, sum).as_deref(), Some("ÄÖ.txt"));
+    }
+
+    #[test]
+    fn test_long_name_mismatch() {
+        let sum = short_name_checksum(b"AVERYL~1TXT");
+        assert_eq!(long_name(&[], sum), None);
+        let parts = vec![(sum, entry_chars("ile_name.txt")), (sum ^ 1, units("A very long f"))];
+        assert_eq!(long_name(&parts, sum), None);
+        assert_eq!(long_name(&parts[..1], sum ^ 1), None);
+    }
+}
@@ -0,0 +1,136 @@
+//! FAT32 filesystem driver.
+//!
+//! Mounts the FAT32 partitions of the ATA drives, and serves each of them
+//! with the filesystem protocol at `mnt/ata<drive>p<partition>`, or at
+//! `mnt/ata<drive>` for a drive formatted without a partition table.
+//! See `d7abi::ipc::protocol::filesystem`.
+//!
+//! Filesystems are unmounted with the `Unmount` request, which is accepted
+//! only from processes with at least `Privilege::Driver`, after which the
+//! drive can be used by other processes. Writes are not buffered, so there
+//! is nothing to flush.
+
+#![no_std]
+#![feature(alloc_prelude)]
+#![deny(unused_must_use)]
+
+#[macro_use]
+extern crate alloc;
+
+#[macro_use]
+extern crate libd7;
+
+use alloc::prelude::v1::*;
+use libd7::{
+    d7abi::ipc::protocol::{
+        block,
+        filesystem::{mount_topic, Request, Response, Unmount, MOUNTS_TOPIC},
+    },
+    ipc::{self, InternalSubscription},
+    process::Privilege,
+    select,
+};
+
+mod disk;
+mod fat32;
+
+use self::disk::Disk;
+use self::fat32::Fat32;
+
+struct Mount {
+    name: String,
+    fs: Fat32,
+    server: ipc::Server<Request, Response>,
+}
+
+/// Mount all FAT32 partitions of a drive
+fn mount_drive(drive: u64, mounts: &mut Vec<Mount>) {
+    let partitions = match Disk::ata_drive(drive).fat32_partitions() {
+        Ok(partitions) => partitions,
+        Err(_) => {
+            println!("Cannot read the partition table of drive {}", drive);
+            return;
+        },
+    };
+
+    for (index, partition) in partitions {
+        let name = if index == 0 {
+            format!("ata{}", drive)
+        } else {
+            format!("ata{}p{}", drive, index)
+        };
+        match Fat32::mount(partition) {
+            Ok(fs) => {
+                println!("Mounted {}", name);
+                mounts.push(Mount {
+                    server: ipc::Server::exact(&mount_topic(&name)).unwrap(),
+                    name,
+                    fs,
+                });
+            },
+            Err(error) => println!("Cannot mount {}: {:?}", name, error),
+        }
+    }
+}
+
+#[no_mangle]
+fn main() -> u64 {
+    println!("FAT32 driver starting");
+
+    // The ATA driver has statistics for each of its drives
+    let mut mounts = Vec::new();
+    let mut drive = 0;
+    while let Ok(Some(_)) = ipc::request::<_, Option<block::Stats>>("ata_pio/stats", drive) {
+        mount_drive(drive, &mut mounts);
+        drive += 1;
+    }
+
+    publish_mounts(&mounts);
+
+    if mounts.is_empty() {
+        println!("No FAT32 filesystems found");
+        libd7::service::register("fat32d", true);
+        return 0;
+    }
+    libd7::service::register("fat32d", false);
+
+    // Unmounting interrupts the other clients of the filesystem
+    let unmount = ipc::Server::rpc::<Unmount>().unwrap();
+    unmount.restrict(Privilege::Driver).unwrap();
+
+    loop {
+        let subs: Vec<_> = mounts.iter().map(|m| m.server.sub_id()).collect();
+        select! {
+            any(subs) -> sub_id => {
+                let mount = mounts.iter_mut().find(|m| m.server.sub_id() == sub_id).unwrap();
+                let Mount { server, fs, .. } = mount;
+                server.handle(|request| Ok(fs.handle(request))).unwrap();
+            },
+            one(unmount) => unmount.handle(|name| {
+                Ok(unmount_matching(&name, &mut mounts))
+            }).unwrap()
+        }
+    }
+}
+
+fn publish_mounts(mounts: &[Mount]) {
+    let names: Vec<String> = mounts.iter().map(|m| m.name.clone()).collect();
+    ipc::publish_retained(MOUNTS_TOPIC, &names).unwrap();
+}
+
+/// Unmount a filesystem, or all partitions of a drive.
+/// Dropping the server unsubscribes it, which fails the pending requests.
+fn unmount_matching(name: &str, mounts: &mut Vec<Mount>) -> Vec<String> {
+    let partition_prefix = format!("{}p", name);
+    let (removed, kept): (Vec<Mount>, Vec<Mount>) = mounts
+        .drain(..)
+        .partition(|m: &Mount| m.name == name || m.name.starts_with(&partition_prefix));
+    *mounts = kept;
+
+    let names: Vec<String> = removed.into_iter().map(|m| m.name).collect();
+    for name in &names {
+        println!("Unmounted {}", name);
+    }
+    publish_mounts(mounts);
+    names
+}
@@ -0,0 +1,110 @@
+//! DHCP client, configures the address of netd at startup.
+//!
+//! The client broadcasts a discover, requests the first address offered,
+//! and is bound when the server acknowledges it. A negative acknowledgement
+//! restarts the discovery with a new transaction id.
+
+use alloc::prelude::v1::*;
+
+use libd7::net::d7net::{
+    dhcp::{Message, MessageType},
+    Ipv4Addr, MacAddr,
+};
+
+/// Address configuration acknowledged by a DHCP server
+#[derive(Debug, Clone)]
+pub struct Lease {
+    pub ip: Ipv4Addr,
+    pub netmask: Option<Ipv4Addr>,
+    pub router: Option<Ipv4Addr>,
+    pub dns: Vec<Ipv4Addr>,
+    pub server: Ipv4Addr,
+    /// `u32::MAX` for an infinite lease
+    pub lease_seconds: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Discover sent, waiting for offers
+    Selecting,
+    /// Offered address requested, waiting for an acknowledgement
+    Requesting { ip: Ipv4Addr, server: Ipv4Addr },
+    Bound,
+}
+
+/// What to do after a message from a server
+#[derive(Debug)]
+pub enum Action {
+    /// The message was not for us, or nothing is needed
+    None,
+    /// Broadcast this message
+    Send(Message),
+    /// Configure the interface
+    Bound(Lease),
+}
+
+#[derive(Debug)]
+pub struct Client {
+    mac: MacAddr,
+    xid: u32,
+    state: State,
+}
+impl Client {
+    pub fn new(mac: MacAddr) -> Self {
+        Self {
+            mac,
+            // Differs between boots, so stale replies are not accepted
+            xid: libd7::time::boot_id() as u32,
+            state: State::Selecting,
+        }
+    }
+
+    /// No address has been acknowledged yet
+    pub fn is_configuring(&self) -> bool {
+        self.state != State::Bound
+    }
+
+    /// Starts a new discovery, returning the message to broadcast.
+    /// TODO: retransmit when no offer arrives, and renew the lease
+    /// before it expires
+    pub fn discover(&mut self) -> Message {
+        self.state = State::Selecting;
+        Message::discover(self.xid, self.mac)
+    }
+
+    pub fn on_message(&mut self, message: Message) -> Action {
+        if !message.is_reply || message.xid != self.xid || message.client_mac != self.mac {
+            return Action::None;
+        }
+        let options = &message.options;
+        match (self.state, options.message_type) {
+            (State::Selecting, Some(MessageType::Offer)) => {
+                let server = match options.server_id {
+                    Some(server) => server,
+                    None => return Action::None,
+                };
+                let ip = message.your_ip;
+                self.state = State::Requesting { ip, server };
+                Action::Send(Message::request(self.xid, self.mac, ip, server))
+            },
+            (State::Requesting { ip, server }, Some(MessageType::Ack))
+                if options.server_id.map_or(true, |s| s == server) =>
+            {
+                self.state = State::Bound;
+                Action::Bound(Lease {
+                    ip: if message.your_ip != Ipv4Addr::ZERO { message.your_ip } else { ip },
+                    netmask: options.subnet_mask,
+                    router: options.router,
+                    dns: options.dns.clone(),
+                    server,
+                    lease_seconds: options.lease_seconds.unwrap_or(u32::MAX),
+                })
+            },
+            (State::Requesting { .. }, Some(MessageType::Nak)) => {
+                self.xid = self.xid.wrapping_add(1);
+                Action::Send(self.discover())
+            },
+            _ => Action::None,
+        }
+    }
+}
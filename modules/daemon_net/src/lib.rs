@@ -13,18 +13,53 @@ extern crate alloc;
 extern crate libd7;
 
 use alloc::prelude::v1::*;
+use core::convert::TryFrom;
 use hashbrown::{HashMap, HashSet};
 use serde::{Deserialize, Serialize};
 
 use libd7::{
+    d7abi::ipc::protocol::netd::{
+        Config, DhcpLease, NetConfig, NetStats, Qos, QosRequest, QosStats, Stats,
+    },
     ipc::{self, SubscriptionId},
     net::d7net::*,
+    net::socket as socket_api,
     pinecone,
     process::{Process, ProcessId},
     select, service, syscall,
     syscall::{SyscallErrorCode, SyscallResult},
 };
 
+mod dhcp;
+mod qos;
+mod socket;
+
+use libd7::net::d7net::dhcp as dhcp_message;
+
+use self::dhcp::Action as DhcpAction;
+use self::qos::{Priority, TxQueue};
+use self::socket::{Outgoing, Sockets};
+
+/// Max time to sleep while waiting for rate limited frames,
+/// before checking for new events again
+const MAX_THROTTLE_SLEEP_NS: u64 = 10_000_000;
+
+/// Shorter frames and packets are counted as receive errors
+const ETHERNET_HEADER_LEN: usize = 14;
+const IPV4_HEADER_LEN: usize = 20;
+const ICMP_HEADER_LEN: usize = 8;
+
+/// Used until a DHCP server has configured the interface,
+/// matching the QEMU user network
+const DEFAULT_IP: Ipv4Addr = Ipv4Addr([10, 0, 2, 15]);
+const DEFAULT_NETMASK: Ipv4Addr = Ipv4Addr([255, 255, 255, 0]);
+const DEFAULT_GATEWAY: Ipv4Addr = Ipv4Addr([10, 0, 2, 2]);
+
+const BROADCAST_IP: Ipv4Addr = Ipv4Addr([255, 255, 255, 255]);
+
+/// Packets waiting for ARP resolution at most, later ones are dropped
+const ARP_PENDING_LIMIT: usize = 32;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct Driver {
     name: String,
@@ -34,27 +69,275 @@ pub struct Driver {
 struct NetState {
     pub mac: MacAddr,
     pub ip: Ipv4Addr,
-    pub sockets: HashMap<u64, SocketAddr>,
-    pub next_socket: u64,
+    pub netmask: Ipv4Addr,
+    /// Router for addresses outside of the local network
+    pub gateway: Ipv4Addr,
+    pub dns: Vec<Ipv4Addr>,
+    pub dhcp: dhcp::Client,
+    /// `None` while using the default configuration
+    pub lease: Option<dhcp::Lease>,
+    pub sockets: Sockets,
+    pub tx: TxQueue,
+    pub stats: NetStats,
+    /// MAC addresses learned from ARP packets
+    pub arp_cache: HashMap<Ipv4Addr, MacAddr>,
+    /// IPv4 packets waiting for the MAC address of their next hop
+    pub arp_pending: Vec<(Ipv4Addr, Priority, Vec<u8>)>,
 }
 impl NetState {
     pub fn new(mac: MacAddr) -> Self {
         Self {
             mac,
-            ip: Ipv4Addr([10, 0, 2, 15]), // Use fixed IP until DHCP is implemented
-            sockets: HashMap::new(),
-            next_socket: 1,
+            ip: DEFAULT_IP,
+            netmask: DEFAULT_NETMASK,
+            gateway: DEFAULT_GATEWAY,
+            dns: Vec::new(),
+            dhcp: dhcp::Client::new(mac),
+            lease: None,
+            sockets: Sockets::new(),
+            tx: TxQueue::new(),
+            stats: NetStats::default(),
+            arp_cache: HashMap::new(),
+            arp_pending: Vec::new(),
+        }
+    }
+
+    fn send_frame(
+        &mut self, priority: Priority, dst_mac: MacAddr, ethertype: EtherType, payload: Vec<u8>,
+    ) {
+        let frame = ethernet::Frame {
+            header: ethernet::FrameHeader {
+                dst_mac,
+                src_mac: self.mac,
+                ethertype,
+            },
+            payload,
+        };
+        self.tx.enqueue(priority, frame.to_bytes());
+    }
+
+    /// Address the packet is sent to on the local network
+    fn next_hop(&self, dst: Ipv4Addr) -> Ipv4Addr {
+        let mask = self.netmask.0;
+        let local = (0..4).all(|i| dst.0[i] & mask[i] == self.ip.0[i] & mask[i]);
+        if local {
+            dst
+        } else {
+            self.gateway
+        }
+    }
+
+    /// Broadcasts a DHCP message. The interface may not have an address
+    /// yet, so the packet is sent from 0.0.0.0 without ARP resolution.
+    pub fn send_dhcp(&mut self, message: dhcp_message::Message) {
+        let payload = message.to_bytes();
+        let datagram = udp::Datagram {
+            header: udp::Header {
+                src_port: dhcp_message::CLIENT_PORT,
+                dst_port: dhcp_message::SERVER_PORT,
+                length: (udp::HEADER_LEN + payload.len()) as u16,
+                checksum: 0,
+            },
+            payload,
+        };
+        let payload = datagram.to_bytes();
+        let header = ipv4::Header::new(
+            IpProtocol::UDP,
+            Ipv4Addr::ZERO,
+            BROADCAST_IP,
+            payload.len() as u16,
+        );
+        let packet = ipv4::Packet { header, payload }.to_bytes();
+        self.send_frame(Priority::Control, MacAddr::BROADCAST, EtherType::Ipv4, packet);
+    }
+
+    fn on_dhcp(&mut self, payload: &[u8]) {
+        let message = match dhcp_message::Message::from_bytes(payload) {
+            Some(message) => message,
+            None => {
+                self.stats.interface.rx_errors += 1;
+                return;
+            },
+        };
+        match self.dhcp.on_message(message) {
+            DhcpAction::None => {},
+            DhcpAction::Send(reply) => self.send_dhcp(reply),
+            DhcpAction::Bound(lease) => {
+                println!("DHCP: Bound to {:?}", lease.ip);
+                self.ip = lease.ip;
+                self.netmask = lease.netmask.unwrap_or(DEFAULT_NETMASK);
+                self.gateway = lease.router.unwrap_or(DEFAULT_GATEWAY);
+                self.dns = lease.dns.clone();
+                self.lease = Some(lease);
+            },
+        }
+    }
+
+    pub fn config(&self) -> NetConfig {
+        NetConfig {
+            ip: self.ip.0,
+            netmask: self.netmask.0,
+            gateway: self.gateway.0,
+            dns: self.dns.iter().map(|addr| addr.0).collect(),
+            lease: self.lease.as_ref().map(|lease| DhcpLease {
+                server: lease.server.0,
+                lease_seconds: lease.lease_seconds,
+            }),
+        }
+    }
+
+    /// Sends an IPv4 packet, or queues it until the MAC address
+    /// of the next hop has been resolved with ARP
+    pub fn send_ipv4(
+        &mut self, priority: Priority, dst: Ipv4Addr, protocol: IpProtocol, payload: Vec<u8>,
+    ) {
+        let header = ipv4::Header::new(protocol, self.ip, dst, payload.len() as u16);
+        let packet = ipv4::Packet { header, payload }.to_bytes();
+        let hop = self.next_hop(dst);
+        if let Some(mac) = self.arp_cache.get(&hop).copied() {
+            self.send_frame(priority, mac, EtherType::Ipv4, packet);
+            return;
+        }
+
+        if !self.arp_pending.iter().any(|(ip, _, _)| *ip == hop) {
+            let request = arp::Packet {
+                ptype: EtherType::Ipv4,
+                operation: arp::Operation::Request,
+                sender_hw: self.mac,
+                sender_ip: self.ip,
+                target_hw: MacAddr::ZERO,
+                target_ip: hop,
+            };
+            self.send_frame(
+                Priority::Control,
+                MacAddr::BROADCAST,
+                EtherType::ARP,
+                request.to_bytes(),
+            );
+        }
+        if self.arp_pending.len() < ARP_PENDING_LIMIT {
+            self.arp_pending.push((hop, priority, packet));
+        } else {
+            println!("ARP: Too many pending packets, dropping");
+        }
+    }
+
+    /// Sends the datagrams of the sockets
+    pub fn send_outgoing(&mut self) {
+        for outgoing in self.sockets.take_outgoing() {
+            let Outgoing {
+                socket,
+                src_port,
+                dst,
+                dst_port,
+                payload,
+            } = outgoing;
+            let datagram = udp::Datagram {
+                header: udp::Header {
+                    src_port,
+                    dst_port,
+                    length: (udp::HEADER_LEN + payload.len()) as u16,
+                    checksum: 0,
+                },
+                payload,
+            };
+            self.send_ipv4(
+                Priority::Bulk { socket },
+                dst,
+                IpProtocol::UDP,
+                datagram.to_bytes(),
+            );
+        }
+    }
+
+    fn on_arp(&mut self, src_mac: MacAddr, arp_packet: arp::Packet) {
+        if arp_packet.sender_ip != Ipv4Addr::ZERO {
+            let ip = arp_packet.sender_ip;
+            self.arp_cache.insert(ip, arp_packet.sender_hw);
+
+            // Send the packets waiting for this address
+            let (ready, waiting): (Vec<_>, Vec<_>) = self
+                .arp_pending
+                .drain(..)
+                .partition(|(hop, _, _)| *hop == ip);
+            self.arp_pending = waiting;
+            for (_, priority, packet) in ready {
+                self.send_frame(priority, arp_packet.sender_hw, EtherType::Ipv4, packet);
+            }
+        }
+
+        // Reply to ARP requests
+        if arp_packet.is_request() && arp_packet.target_ip == self.ip {
+            println!("ARP: Replying");
+            let reply = arp_packet.to_reply(self.mac, self.ip).to_bytes();
+            self.send_frame(Priority::Control, src_mac, EtherType::ARP, reply);
         }
     }
 
-    pub fn create_socket(&mut self, addr: SocketAddr) -> u64 {
-        let id = self.next_socket;
-        self.next_socket += 1;
-        self.sockets.insert(id, addr);
-        id
+    fn on_ipv4(&mut self, ip_packet: ipv4::Packet) {
+        let header = ip_packet.header;
+        // Servers may send DHCP replies to the offered address
+        let for_us = header.dst_ip == self.ip || header.dst_ip == BROADCAST_IP;
+        if !for_us && !self.dhcp.is_configuring() {
+            return;
+        }
+
+        match header.protocol {
+            IpProtocol::ICMP => {
+                self.stats.protocols.icmp += 1;
+                if ip_packet.payload.len() < ICMP_HEADER_LEN {
+                    self.stats.interface.rx_errors += 1;
+                    return;
+                }
+                let icmp_packet = icmp::Packet::from_bytes(&ip_packet.payload);
+                if icmp_packet.is_echo_request() {
+                    let reply = icmp_packet.to_echo_reply().to_bytes();
+                    self.send_ipv4(Priority::Control, header.src_ip, IpProtocol::ICMP, reply);
+                }
+            },
+            IpProtocol::TCP => {
+                // TODO: TCP connections
+                self.stats.protocols.tcp += 1;
+            },
+            IpProtocol::UDP => {
+                self.stats.protocols.udp += 1;
+                let datagram = match udp::Datagram::from_bytes(&ip_packet.payload) {
+                    Some(datagram) => datagram,
+                    None => {
+                        self.stats.interface.rx_errors += 1;
+                        return;
+                    },
+                };
+                if datagram.header.src_port == dhcp_message::SERVER_PORT
+                    && datagram.header.dst_port == dhcp_message::CLIENT_PORT
+                {
+                    self.on_dhcp(&datagram.payload);
+                    return;
+                }
+                let src = SocketAddr {
+                    host: IpAddr::V4(header.src_ip),
+                    port: datagram.header.src_port,
+                };
+                let dst_port = datagram.header.dst_port;
+                if !self
+                    .sockets
+                    .on_udp(src, header.dst_ip, dst_port, datagram.payload)
+                {
+                    println!("UDP: No socket for port {}", dst_port);
+                }
+            },
+            _ => self.stats.protocols.other_ip += 1,
+        }
     }
 
     pub fn on_event(&mut self, packet: &[u8]) {
+        self.stats.interface.rx_frames += 1;
+        self.stats.interface.rx_bytes += packet.len() as u64;
+        if packet.len() < ETHERNET_HEADER_LEN {
+            self.stats.interface.rx_errors += 1;
+            return;
+        }
+
         let frame = ethernet::Frame::from_bytes(&packet);
 
         println!(
@@ -64,43 +347,93 @@ impl NetState {
 
         match frame.header.ethertype {
             EtherType::ARP => {
-                // Reply to ARP packets
+                self.stats.protocols.arp += 1;
                 let arp_packet = arp::Packet::from_bytes(&frame.payload);
-                if arp_packet.is_request() && arp_packet.target_ip == self.ip {
-                    println!("ARP: Replying");
-
-                    let reply = (ethernet::Frame {
-                        header: ethernet::FrameHeader {
-                            dst_mac: frame.header.src_mac,
-                            src_mac: self.mac,
-                            ethertype: EtherType::ARP,
-                        },
-                        payload: arp_packet.to_reply(self.mac, self.ip).to_bytes(),
-                    })
-                    .to_bytes();
-
-                    ipc::deliver("nic/send", &reply).unwrap();
-                }
+                self.on_arp(frame.header.src_mac, arp_packet);
             }
             EtherType::Ipv4 => {
-                let ip_packet = ipv4::Packet::from_bytes(&frame.payload);
-                println!("{:?}", ip_packet);
-
-                match ip_packet.header.protocol {
-                    IpProtocol::TCP => {
-                        let tcp_packet = tcp::Segment::from_bytes(&ip_packet.payload);
-                        println!("{:?}", tcp_packet);
-                    }
-                    _ => {}
+                self.stats.protocols.ipv4 += 1;
+                if frame.payload.len() < IPV4_HEADER_LEN {
+                    self.stats.interface.rx_errors += 1;
+                    return;
+                }
+
+                // The parser panics on options, unknown protocols and truncated packets
+                let p = &frame.payload;
+                let total_len = u16::from_be_bytes([p[2], p[3]]) as usize;
+                if p[0] != 0x45 || total_len < IPV4_HEADER_LEN || total_len > p.len() {
+                    self.stats.interface.rx_errors += 1;
+                    return;
                 }
+                if IpProtocol::try_from(p[9]).is_err() {
+                    self.stats.protocols.other_ip += 1;
+                    return;
+                }
+
+                let ip_packet = ipv4::Packet::from_bytes(&frame.payload);
+                println!("{:?}", ip_packet.header);
+                self.on_ipv4(ip_packet);
+            }
+            _ => self.stats.protocols.other_ethertype += 1,
+        }
+    }
+
+    /// Send queued frames to the NIC driver.
+    /// Returns the time to wait if rate limited frames remain.
+    pub fn flush_tx(&mut self) -> Option<u64> {
+        if self.tx.is_empty() {
+            return None;
+        }
 
-                panic!("IP!!!");
+        let now: core::time::Duration = ipc::request("time/monotonic", ()).unwrap();
+        let now = now.as_nanos() as u64;
+        loop {
+            match self.tx.dequeue(now) {
+                Ok(Some(frame)) => {
+                    self.stats.interface.tx_frames += 1;
+                    self.stats.interface.tx_bytes += frame.len() as u64;
+                    ipc::deliver("nic/send", &frame).unwrap();
+                }
+                Ok(None) => return None,
+                Err(wait) => return Some(wait),
             }
-            _ => {}
         }
     }
 }
 
+fn on_received(received: &ipc::ReliableSubscription<Vec<u8>>, net_state: &mut NetState) {
+    let packet = received.ack_receive().unwrap();
+    net_state.on_event(&packet);
+}
+
+fn on_socket(
+    server: &ipc::Server<socket_api::Request, socket_api::Response>, net_state: &mut NetState,
+) {
+    server
+        .handle(|request| {
+            let (response, closed) = net_state.sockets.handle(request);
+            if let Some(id) = closed {
+                net_state.tx.remove_socket(id);
+            }
+            net_state.send_outgoing();
+            Ok(response)
+        })
+        .unwrap();
+}
+
+fn on_qos(qos: &ipc::Server<QosRequest, QosStats>, net_state: &mut NetState) {
+    qos.handle(|request| Ok(net_state.tx.configure(request)))
+        .unwrap();
+}
+
+fn on_stats(stats: &ipc::Server<(), NetStats>, net_state: &mut NetState) {
+    stats.handle(|()| Ok(net_state.stats)).unwrap();
+}
+
+fn on_config(config: &ipc::Server<(), NetConfig>, net_state: &mut NetState) {
+    config.handle(|()| Ok(net_state.config())).unwrap();
+}
+
 // fn handle_attachment(a: &mut attachment::BufferedAttachment, net_state: &mut NetState) {
 //     let r = match a.next_request() {
 //         Some(v) => v,
@@ -175,9 +508,9 @@ fn main() -> ! {
     println!("Network daemon starting");
 
     // Wait until a driver is available
-    service::wait_for_one("driver_rtl8139");
+    service::wait_for_any(&["driver_rtl8139", "driver_e1000", "driver_virtio_net"]);
 
-    let mac_addr: MacAddr = match ipc::request("nic/rtl8139/mac", &()) {
+    let mac_addr: MacAddr = match ipc::request("nic/mac", &()) {
         Ok(mac) => mac,
         Err(SyscallErrorCode::ipc_delivery_no_target) => {
             panic!("No NIC drivers available");
@@ -186,21 +519,43 @@ fn main() -> ! {
     };
 
     let mut net_state = NetState::new(mac_addr);
+    let discover = net_state.dhcp.discover();
+    net_state.send_dhcp(discover);
 
     // Subscribe to messages
-    let a: ipc::Server<SocketAddr, u64> = ipc::Server::exact("netd/newsocket").unwrap();
+    let socket: ipc::Server<socket_api::Request, socket_api::Response> =
+        ipc::Server::exact("netd/socket").unwrap();
     let received = ipc::ReliableSubscription::<Vec<u8>>::exact("netd/received").unwrap();
+    let qos = ipc::Server::rpc::<Qos>().unwrap();
+    let stats = ipc::Server::rpc::<Stats>().unwrap();
+    let config = ipc::Server::rpc::<Config>().unwrap();
 
     // Announce that we are running
     libd7::service::register("netd", false);
 
     loop {
+        if let Some(wait_ns) = net_state.flush_tx() {
+            // Rate limited frames are waiting, so only poll for new events
+            syscall::sched_sleep_ns(wait_ns.min(MAX_THROTTLE_SLEEP_NS)).unwrap();
+            select! {
+                one(received) => on_received(&received, &mut net_state),
+                one(socket) => on_socket(&socket, &mut net_state),
+                one(qos) => on_qos(&qos, &mut net_state),
+                one(stats) => on_stats(&stats, &mut net_state),
+                one(config) => on_config(&config, &mut net_state),
+                would_block => {},
+                error -> e => panic!("ERROR {:?}", e)
+            };
+            continue;
+        }
+
         println!("--> select!");
         select! {
-            one(received) => {
-                let packet = received.ack_receive().unwrap();
-                net_state.on_event(&packet);
-            },
+            one(received) => on_received(&received, &mut net_state),
+            one(socket) => on_socket(&socket, &mut net_state),
+            one(qos) => on_qos(&qos, &mut net_state),
+            one(stats) => on_stats(&stats, &mut net_state),
+            one(config) => on_config(&config, &mut net_state),
             // one(a.inner.fd) => handle_attachment(&mut a, &mut net_state),
             error -> e => panic!("ERROR {:?}", e)
         };
@@ -0,0 +1,191 @@
+//! Transmit queueing in front of the NIC driver.
+//!
+//! Frames are placed in two priority levels: control frames (ARP, etc.)
+//! are always sent first, and bulk frames from sockets are sent in order,
+//! subject to per-socket token bucket rate limits.
+
+use alloc::collections::VecDeque;
+use alloc::prelude::v1::*;
+use hashbrown::HashMap;
+
+use libd7::d7abi::ipc::protocol::netd::{QosRequest, QosStats, RateLimit};
+
+/// Max frames queued per priority level
+const QUEUE_LIMIT: usize = 128;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Control,
+    Bulk { socket: u64 },
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: u64,
+    /// Last refill time, in nanoseconds
+    updated: u64,
+}
+impl TokenBucket {
+    fn new(limit: RateLimit, now: u64) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst_bytes,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.updated);
+        let new_tokens = (elapsed as u128 * self.limit.bytes_per_sec as u128) / NS_PER_SEC as u128;
+        if new_tokens > 0 {
+            self.tokens = (self.tokens as u128 + new_tokens).min(self.limit.burst_bytes as u128) as u64;
+            self.updated = now;
+        }
+    }
+
+    /// Nanoseconds until `amount` tokens are available.
+    /// Frames larger than the burst size are allowed with a full bucket.
+    fn wait_ns(&self, amount: u64) -> u64 {
+        let needed = amount.min(self.limit.burst_bytes);
+        if self.tokens >= needed || self.limit.bytes_per_sec == 0 {
+            0
+        } else {
+            ((needed - self.tokens) * NS_PER_SEC + self.limit.bytes_per_sec - 1)
+                / self.limit.bytes_per_sec
+        }
+    }
+
+    fn take(&mut self, amount: u64) {
+        self.tokens = self.tokens.saturating_sub(amount);
+    }
+}
+
+#[derive(Debug)]
+struct BulkFrame {
+    socket: u64,
+    frame: Vec<u8>,
+    /// Already counted in `QosStats::throttled`
+    throttled: bool,
+}
+
+pub struct TxQueue {
+    control: VecDeque<Vec<u8>>,
+    bulk: VecDeque<BulkFrame>,
+    default_limit: Option<RateLimit>,
+    socket_limits: HashMap<u64, RateLimit>,
+    buckets: HashMap<u64, TokenBucket>,
+    stats: QosStats,
+}
+impl TxQueue {
+    pub fn new() -> Self {
+        Self {
+            control: VecDeque::new(),
+            bulk: VecDeque::new(),
+            default_limit: None,
+            socket_limits: HashMap::new(),
+            buckets: HashMap::new(),
+            stats: QosStats::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.control.is_empty() && self.bulk.is_empty()
+    }
+
+    pub fn configure(&mut self, request: QosRequest) -> QosStats {
+        match request {
+            QosRequest::Stats => {},
+            QosRequest::SetDefaultLimit(limit) => {
+                self.default_limit = limit;
+                self.buckets.clear();
+            },
+            QosRequest::SetSocketLimit(socket, limit) => {
+                if let Some(limit) = limit {
+                    self.socket_limits.insert(socket, limit);
+                } else {
+                    self.socket_limits.remove(&socket);
+                }
+                self.buckets.remove(&socket);
+            },
+        }
+        self.stats
+    }
+
+    /// Forget the state of a closed socket
+    pub fn remove_socket(&mut self, socket: u64) {
+        self.socket_limits.remove(&socket);
+        self.buckets.remove(&socket);
+    }
+
+    pub fn enqueue(&mut self, priority: Priority, frame: Vec<u8>) {
+        let full = match priority {
+            Priority::Control => self.control.len() >= QUEUE_LIMIT,
+            Priority::Bulk { .. } => self.bulk.len() >= QUEUE_LIMIT,
+        };
+        if full {
+            self.stats.dropped += 1;
+            return;
+        }
+
+        match priority {
+            Priority::Control => self.control.push_back(frame),
+            Priority::Bulk { socket } => self.bulk.push_back(BulkFrame {
+                socket,
+                frame,
+                throttled: false,
+            }),
+        }
+    }
+
+    /// Next frame to send at time `now` (ns).
+    /// If only rate limited frames are queued, returns the time to wait instead.
+    pub fn dequeue(&mut self, now: u64) -> Result<Option<Vec<u8>>, u64> {
+        if let Some(frame) = self.control.pop_front() {
+            self.stats.sent_control += 1;
+            return Ok(Some(frame));
+        }
+
+        // Frames of a rate limited socket must not block other sockets,
+        // but the order of frames within a socket is preserved
+        let mut blocked: Vec<u64> = Vec::new();
+        let mut min_wait: Option<u64> = None;
+        for i in 0..self.bulk.len() {
+            let socket = self.bulk[i].socket;
+            if blocked.contains(&socket) {
+                continue;
+            }
+            let len = self.bulk[i].frame.len() as u64;
+
+            let limit = self.socket_limits.get(&socket).copied().or(self.default_limit);
+            if let Some(limit) = limit {
+                let bucket = self
+                    .buckets
+                    .entry(socket)
+                    .or_insert_with(|| TokenBucket::new(limit, now));
+                bucket.refill(now);
+                let wait = bucket.wait_ns(len);
+                if wait > 0 {
+                    if !self.bulk[i].throttled {
+                        self.bulk[i].throttled = true;
+                        self.stats.throttled += 1;
+                    }
+                    blocked.push(socket);
+                    min_wait = Some(min_wait.map_or(wait, |w| w.min(wait)));
+                    continue;
+                }
+                bucket.take(len);
+            }
+
+            self.stats.sent_bulk += 1;
+            return Ok(self.bulk.remove(i).map(|bulk| bulk.frame));
+        }
+
+        match min_wait {
+            Some(wait) => Err(wait),
+            None => Ok(None),
+        }
+    }
+}
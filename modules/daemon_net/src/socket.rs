@@ -0,0 +1,222 @@
+//! Socket state, operated through the `netd/socket` endpoint
+
+use alloc::collections::VecDeque;
+use alloc::prelude::v1::*;
+use hashbrown::HashMap;
+
+use libd7::net::d7net::{IpAddr, Ipv4Addr, SocketAddr};
+use libd7::net::socket::{Error, Request, Response, SocketId, SocketType};
+
+/// Received datagrams queued for a socket at most, later ones are dropped
+const RECEIVE_QUEUE_LIMIT: usize = 64;
+
+/// Largest UDP payload that fits into an Ethernet frame without fragmentation
+pub const MAX_DATAGRAM_PAYLOAD: usize = 1500 - 20 - 8;
+
+/// Ports given to sockets bound to port 0, or that send without binding first
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+#[derive(Debug, Clone)]
+struct Socket {
+    type_: SocketType,
+    local: Option<SocketAddr>,
+    remote: Option<SocketAddr>,
+    /// Payloads of received datagrams
+    received: VecDeque<Vec<u8>>,
+}
+
+/// Datagram sent by a socket, to be routed by `NetState`
+#[derive(Debug, Clone)]
+pub struct Outgoing {
+    pub socket: SocketId,
+    pub src_port: u16,
+    pub dst: Ipv4Addr,
+    pub dst_port: u16,
+    pub payload: Vec<u8>,
+}
+
+pub struct Sockets {
+    sockets: HashMap<SocketId, Socket>,
+    next_id: SocketId,
+    next_ephemeral_port: u16,
+    /// Sent datagrams, see `take_outgoing`
+    outgoing: Vec<Outgoing>,
+}
+impl Sockets {
+    pub fn new() -> Self {
+        Self {
+            sockets: HashMap::new(),
+            next_id: 1,
+            next_ephemeral_port: EPHEMERAL_PORT_START,
+            outgoing: Vec::new(),
+        }
+    }
+
+    fn get_mut(&mut self, id: SocketId) -> Result<&mut Socket, Error> {
+        self.sockets.get_mut(&id).ok_or(Error::InvalidSocket)
+    }
+
+    fn address_in_use(&self, type_: SocketType, addr: &SocketAddr) -> bool {
+        self.sockets.values().any(|s| {
+            s.type_ == type_
+                && s.local
+                    .as_ref()
+                    .map(|l| l.port == addr.port && l.host == addr.host)
+                    .unwrap_or(false)
+        })
+    }
+
+    /// Free ephemeral address on `host`
+    fn ephemeral_addr(&mut self, type_: SocketType, host: IpAddr) -> Result<SocketAddr, Error> {
+        for _ in EPHEMERAL_PORT_START..=u16::MAX {
+            let port = self.next_ephemeral_port;
+            self.next_ephemeral_port = port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START);
+            let addr = SocketAddr { host, port };
+            if !self.address_in_use(type_, &addr) {
+                return Ok(addr);
+            }
+        }
+        Err(Error::AddressInUse)
+    }
+
+    /// Local port of a socket, binding it to a free ephemeral port if needed
+    fn local_port(&mut self, id: SocketId) -> Result<u16, Error> {
+        let socket = self.get_mut(id)?;
+        if let Some(local) = &socket.local {
+            return Ok(local.port);
+        }
+        let type_ = socket.type_;
+        let addr = self.ephemeral_addr(type_, IpAddr::V4(Ipv4Addr::ZERO))?;
+        let port = addr.port;
+        self.get_mut(id)?.local = Some(addr);
+        Ok(port)
+    }
+
+    /// Datagrams sent since the previous call
+    pub fn take_outgoing(&mut self) -> Vec<Outgoing> {
+        core::mem::replace(&mut self.outgoing, Vec::new())
+    }
+
+    /// Queues a received UDP datagram for the socket bound to its destination.
+    /// Returns false if there is no such socket.
+    pub fn on_udp(
+        &mut self, src: SocketAddr, dst: Ipv4Addr, dst_port: u16, payload: Vec<u8>,
+    ) -> bool {
+        let bound_to = |local: &SocketAddr| {
+            local.port == dst_port
+                && (local.host == IpAddr::V4(Ipv4Addr::ZERO) || local.host == IpAddr::V4(dst))
+        };
+        let socket = self.sockets.values_mut().find(|s| {
+            s.type_ == SocketType::Datagram && s.local.as_ref().map(bound_to).unwrap_or(false)
+        });
+        match socket {
+            Some(socket) => {
+                // Connected sockets only receive from their peer
+                if let Some(remote) = &socket.remote {
+                    if remote.host != src.host || remote.port != src.port {
+                        return false;
+                    }
+                }
+                if socket.received.len() < RECEIVE_QUEUE_LIMIT {
+                    socket.received.push_back(payload);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Returns the response, and the id of a closed socket, if any
+    pub fn handle(&mut self, request: Request) -> (Response, Option<SocketId>) {
+        let mut closed = None;
+        let response = self
+            .handle_inner(request, &mut closed)
+            .unwrap_or_else(Response::Error);
+        (response, closed)
+    }
+
+    fn handle_inner(
+        &mut self, request: Request, closed: &mut Option<SocketId>,
+    ) -> Result<Response, Error> {
+        match request {
+            // TODO: TCP connections. Until then, stream sockets cannot be
+            // created, and listening and accepting are not supported.
+            Request::Socket(SocketType::Stream) => Err(Error::NotSupported),
+            Request::Socket(type_) => {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.sockets.insert(id, Socket {
+                    type_,
+                    local: None,
+                    remote: None,
+                    received: VecDeque::new(),
+                });
+                Ok(Response::Socket(id))
+            },
+            Request::Bind(id, addr) => {
+                let socket = self.get_mut(id)?;
+                if socket.local.is_some() {
+                    return Err(Error::InvalidState);
+                }
+                let type_ = socket.type_;
+                let addr = if addr.port == 0 {
+                    self.ephemeral_addr(type_, addr.host)?
+                } else if self.address_in_use(type_, &addr) {
+                    return Err(Error::AddressInUse);
+                } else {
+                    addr
+                };
+                self.get_mut(id)?.local = Some(addr);
+                Ok(Response::Ok)
+            },
+            Request::Connect(id, addr) => {
+                self.get_mut(id)?.remote = Some(addr);
+                Ok(Response::Ok)
+            },
+            Request::Listen(id, _) | Request::Accept(id) => {
+                self.get_mut(id)?;
+                Err(Error::NotSupported)
+            },
+            Request::Send(id, data) => {
+                let socket = self.get_mut(id)?;
+                let remote = socket.remote.clone().ok_or(Error::InvalidState)?;
+                let dst = match remote.host {
+                    IpAddr::V4(ip) => ip,
+                    IpAddr::V6(_) => return Err(Error::NotSupported),
+                };
+                if data.len() > MAX_DATAGRAM_PAYLOAD {
+                    return Err(Error::MessageTooLong);
+                }
+                let src_port = self.local_port(id)?;
+                let len = data.len() as u64;
+                self.outgoing.push(Outgoing {
+                    socket: id,
+                    src_port,
+                    dst,
+                    dst_port: remote.port,
+                    payload: data,
+                });
+                Ok(Response::Sent(len))
+            },
+            Request::Recv(id, max) => {
+                let socket = self.get_mut(id)?;
+                if socket.local.is_none() && socket.remote.is_none() {
+                    return Err(Error::InvalidState);
+                }
+                match socket.received.pop_front() {
+                    // Like with POSIX, the rest of a datagram that doesn't fit is lost
+                    Some(mut data) => {
+                        data.truncate(max as usize);
+                        Ok(Response::Received(data))
+                    },
+                    None => Err(Error::WouldBlock),
+                }
+            },
+            Request::Close(id) => {
+                self.sockets.remove(&id).ok_or(Error::InvalidSocket)?;
+                *closed = Some(id);
+                Ok(Response::Ok)
+            },
+        }
+    }
+}
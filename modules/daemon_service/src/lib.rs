@@ -22,7 +22,7 @@ use serde::{Deserialize, Serialize};
 use libd7::{
     d7abi::{
         ipc::protocol::{service::*, ProcessTerminated},
-        process::ProcessResult,
+        process::{Privilege, ProcessResult},
     },
     ipc::{self, AcknowledgeContext, SubscriptionId},
     pinecone,
@@ -44,6 +44,9 @@ struct ServiceDefinition {
     from_initrd: bool,
     /// Absolute path to the executable
     executable: String,
+    /// Privilege level of the process
+    #[serde(default)]
+    privilege: Privilege,
 }
 
 #[derive(Debug)]
@@ -102,7 +105,7 @@ impl Services {
             def.from_initrd,
             "Non-initrd executables are not supported yet"
         );
-        let process = Process::spawn(&def.executable).unwrap();
+        let process = Process::spawn_with_privilege(&def.executable, def.privilege).unwrap();
         self.managed
             .insert(process.pid(), (process, def.name.clone()));
     }
@@ -19,6 +19,9 @@ const PORT_DRIVESELECT: u16 = 0x1F6;
 const PORT_COMMAND: u16 = 0x1F7;
 const PORT_DEV_CTRL: u16 = 0x3F6;
 
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_BSY: u8 = 1 << 7;
+
 fn sleep_ms(ms: u64) {
     sched_sleep_ns(ms * 1_000_00).unwrap()
 }
@@ -109,6 +112,21 @@ impl AtaPio {
         while !Self::is_ready() {}
     }
 
+    /// Polls ATA controller until the drive is no longer busy.
+    /// Returns the final status.
+    unsafe fn wait_not_busy() -> u8 {
+        // 400ns delay, so that the status reflects the last command
+        for _ in 0..4 {
+            let _ = Self::read_status();
+        }
+        loop {
+            let status = Self::read_status();
+            if status & STATUS_BSY == 0 {
+                return status;
+            }
+        }
+    }
+
     /// Reads identification of the currently selected drive
     unsafe fn identify(drive: usize) -> Option<DriveProperties> {
         // https://wiki.osdev.org/ATA_PIO_Mode#IDENTIFY_command
@@ -194,9 +212,8 @@ impl AtaPio {
         })
     }
 
-    pub unsafe fn read_lba(&self, drive: usize, lba: u64, sectors: u8) -> Vec<u8> {
-        // https://wiki.osdev.org/ATA_read/write_sectors#Read_in_LBA_mode
-
+    /// Selects drive and sends sector count and address
+    unsafe fn setup_lba(drive: usize, lba: u64, sectors: u8) {
         assert!(sectors > 0);
         assert!(drive <= 1);
         assert!(lba < (1 << 28), "LBA64 not supported by the driver yet");
@@ -224,6 +241,12 @@ impl AtaPio {
         // Send bits 16-23 of LBA
         let mut port = UnsafePort::<u8>::new(PORT_LBA2);
         port.write(((lba & 0xFF0000) >> 0x10) as u8);
+    }
+
+    pub unsafe fn read_lba(&self, drive: usize, lba: u64, sectors: u8) -> Vec<u8> {
+        // https://wiki.osdev.org/ATA_read/write_sectors#Read_in_LBA_mode
+
+        Self::setup_lba(drive, lba, sectors);
 
         // Send command
         Self::send_command(0x20); // Read with retry
@@ -245,6 +268,44 @@ impl AtaPio {
         result
     }
 
+    pub unsafe fn write_lba(&self, drive: usize, lba: u64, data: &[u8]) {
+        // https://wiki.osdev.org/ATA_PIO_Mode#28_bit_PIO
+
+        assert!(data.len() % SECTOR_SIZE == 0, "Partial sector write");
+        let sectors = data.len() / SECTOR_SIZE;
+        assert!(sectors <= (u8::MAX as usize));
+
+        Self::setup_lba(drive, lba, sectors as u8);
+
+        // Send command
+        Self::send_command(0x30); // Write with retry
+
+        let mut data_port = UnsafePort::<u16>::new(PORT_DATA);
+        for sector in data.chunks_exact(SECTOR_SIZE) {
+            Self::wait_ready();
+            for word in sector.chunks_exact(2) {
+                data_port.write((word[0] as u16) | ((word[1] as u16) << 0x8));
+            }
+        }
+
+        self.flush_cache(drive);
+    }
+
+    /// Writes the volatile write cache of the drive to the disk,
+    /// returning when the drive has completed the flush
+    pub unsafe fn flush_cache(&self, drive: usize) {
+        // https://wiki.osdev.org/ATA_PIO_Mode#Cache_Flush
+        assert!(drive <= 1);
+        let mut port = UnsafePort::<u8>::new(PORT_DRIVESELECT);
+        port.write(if drive == 0 { 0xe0 } else { 0xf0 });
+
+        Self::send_command(0xE7); // Flush cache
+        let status = Self::wait_not_busy();
+        if status & STATUS_ERR != 0 {
+            println!("Cache flush failed on drive {}", drive);
+        }
+    }
+
     /// Capacity in sectors
     pub fn drive_count(&self) -> usize {
         self.drives.len()
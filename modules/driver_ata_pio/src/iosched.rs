@@ -0,0 +1,172 @@
+//! I/O scheduler between block device clients and the drive.
+//!
+//! * Requests to adjacent sectors are merged into a single device operation
+//! * Reads are prioritized over (background) writes
+//! * At most `depth` device operations are in flight per drive
+
+use alloc::collections::VecDeque;
+use alloc::prelude::v1::*;
+
+use libd7::d7abi::ipc::protocol::block::Stats;
+
+/// ATA PIO can transfer at most this many sectors with one command
+pub const MAX_SECTORS_PER_OP: u64 = 0xff;
+
+/// Writes are dispatched even if reads are pending
+/// after this many consecutive read dispatches
+const WRITE_STARVATION_LIMIT: u32 = 8;
+
+/// Identifies a client request, so that the results
+/// of merged operations can be split back to the clients
+pub type Tag = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+/// One (possibly merged) device operation
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub direction: Direction,
+    pub lba: u64,
+    pub sectors: u64,
+    /// Write data, empty for reads
+    pub data: Vec<u8>,
+    /// Original requests as `(tag, lba, sectors)`, in sector order
+    pub parts: Vec<(Tag, u64, u64)>,
+}
+impl Operation {
+    fn end(&self) -> u64 {
+        self.lba + self.sectors
+    }
+
+    /// Tries to merge other request into this one. Returns the request back on failure.
+    fn try_merge(&mut self, other: Operation) -> Result<(), Operation> {
+        if self.direction != other.direction
+            || self.sectors + other.sectors > MAX_SECTORS_PER_OP
+        {
+            return Err(other);
+        }
+
+        if self.end() == other.lba {
+            // Back merge
+            self.sectors += other.sectors;
+            self.data.extend(other.data);
+            self.parts.extend(other.parts);
+            Ok(())
+        } else if other.end() == self.lba {
+            // Front merge
+            self.lba = other.lba;
+            self.sectors += other.sectors;
+            let mut data = other.data;
+            data.extend(self.data.drain(..));
+            self.data = data;
+            let mut parts = other.parts;
+            parts.extend(self.parts.drain(..));
+            self.parts = parts;
+            Ok(())
+        } else {
+            Err(other)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct IoScheduler {
+    reads: VecDeque<Operation>,
+    writes: VecDeque<Operation>,
+    /// Max number of operations dispatched but not completed
+    depth: usize,
+    in_flight: usize,
+    /// Consecutive read dispatches while writes were waiting
+    reads_in_row: u32,
+    stats: Stats,
+}
+impl IoScheduler {
+    pub fn new(depth: usize) -> Self {
+        assert!(depth > 0, "Queue depth must be nonzero");
+        Self {
+            reads: VecDeque::new(),
+            writes: VecDeque::new(),
+            depth,
+            in_flight: 0,
+            reads_in_row: 0,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reads.is_empty() && self.writes.is_empty()
+    }
+
+    /// Queue a request, merging it with an adjacent queued request if possible
+    pub fn submit(&mut self, tag: Tag, direction: Direction, lba: u64, sectors: u64, data: Vec<u8>) {
+        self.stats.requests += 1;
+
+        let mut op = Operation {
+            direction,
+            lba,
+            sectors,
+            data,
+            parts: vec![(tag, lba, sectors)],
+        };
+
+        let queue = match direction {
+            Direction::Read => &mut self.reads,
+            Direction::Write => &mut self.writes,
+        };
+
+        for queued in queue.iter_mut() {
+            match queued.try_merge(op) {
+                Ok(()) => {
+                    self.stats.merged += 1;
+                    return;
+                },
+                Err(back) => op = back,
+            }
+        }
+
+        queue.push_back(op);
+    }
+
+    /// Next operation to issue to the device, if the queue depth allows it
+    pub fn dispatch(&mut self) -> Option<Operation> {
+        if self.in_flight >= self.depth {
+            return None;
+        }
+
+        let starving = !self.writes.is_empty() && self.reads_in_row >= WRITE_STARVATION_LIMIT;
+        let op = if starving {
+            self.reads_in_row = 0;
+            self.writes.pop_front()
+        } else if let Some(op) = self.reads.pop_front() {
+            if !self.writes.is_empty() {
+                self.reads_in_row += 1;
+            }
+            Some(op)
+        } else {
+            self.reads_in_row = 0;
+            self.writes.pop_front()
+        }?;
+
+        self.in_flight += 1;
+        self.stats.dispatched += 1;
+        match op.direction {
+            Direction::Read => self.stats.sectors_read += op.sectors,
+            Direction::Write => self.stats.sectors_written += op.sectors,
+        }
+        Some(op)
+    }
+
+    /// Mark a dispatched operation completed
+    pub fn complete(&mut self) {
+        assert!(self.in_flight > 0, "No operation in flight");
+        self.in_flight -= 1;
+    }
+}
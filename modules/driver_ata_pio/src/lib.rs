@@ -11,51 +11,219 @@ extern crate libd7;
 
 use alloc::prelude::v1::*;
 use hashbrown::HashMap;
-use libd7::{select, syscall};
+use libd7::{
+    d7abi::ipc::protocol::{block, ShutdownStarted},
+    ipc::{self, AcknowledgeContext},
+    select, syscall,
+};
 
 mod ata_pio;
+mod iosched;
+mod readahead;
+
+use self::iosched::{Direction, IoScheduler, Tag};
+use self::readahead::Readahead;
+
+/// PIO transfers are synchronous, so only one operation can be in flight
+const QUEUE_DEPTH: usize = 1;
+
+/// Block request and the topic to send the response to
+type IoRequest = (String, block::Request);
+
+/// Requests waiting for a response
+type Pending = HashMap<Tag, (AcknowledgeContext, String)>;
+
+struct Drive {
+    index: usize,
+    capacity_sectors: u64,
+    sub: ipc::ReliableSubscription<IoRequest>,
+    sched: IoScheduler,
+    readahead: Readahead,
+}
+impl Drive {
+    fn stats(&self) -> block::Stats {
+        let mut stats = self.sched.stats();
+        stats.sectors_prefetched = self.readahead.prefetched;
+        stats.prefetch_hits = self.readahead.hits;
+        stats
+    }
+}
+
+/// Validate a request and queue it
+fn receive(drive: &mut Drive, pending: &mut Pending, next_tag: &mut Tag) {
+    let (ack_ctx, (reply_to, request)) = drive.sub.receive().unwrap();
+
+    let valid_range = request.sectors != 0
+        && request.sectors <= iosched::MAX_SECTORS_PER_OP
+        && request
+            .lba
+            .checked_add(request.sectors)
+            .map_or(false, |end| end <= drive.capacity_sectors);
+
+    let (direction, data) = match request.operation {
+        block::Operation::Read => (Direction::Read, Vec::new()),
+        block::Operation::Write(data) => (Direction::Write, data),
+    };
+
+    let valid_data = match direction {
+        Direction::Read => true,
+        Direction::Write => data.len() as u64 == request.sectors * ata_pio::SECTOR_SIZE as u64,
+    };
+
+    if !(valid_range && valid_data) {
+        ipc::deliver_reply(&reply_to, &block::Response::Invalid).unwrap();
+        ack_ctx.ack().unwrap();
+        return;
+    }
+
+    match direction {
+        Direction::Read => {
+            if let Some(bytes) = drive.readahead.lookup(request.lba, request.sectors) {
+                drive.readahead.observe_read(request.lba, request.sectors);
+                ipc::deliver_reply(&reply_to, &block::Response::Read(bytes)).unwrap();
+                ack_ctx.ack().unwrap();
+                return;
+            }
+        },
+        Direction::Write => drive.readahead.invalidate(request.lba, request.sectors),
+    }
+
+    let tag = *next_tag;
+    *next_tag += 1;
+    pending.insert(tag, (ack_ctx, reply_to));
+    drive
+        .sched
+        .submit(tag, direction, request.lba, request.sectors, data);
+}
+
+/// Issue all operations the scheduler allows, and respond to the clients
+fn dispatch(controller: &ata_pio::AtaPio, drive: &mut Drive, pending: &mut Pending) {
+    while let Some(op) = drive.sched.dispatch() {
+        match op.direction {
+            Direction::Read => {
+                let bytes = unsafe { controller.read_lba(drive.index, op.lba, op.sectors as u8) };
+                for (tag, lba, sectors) in op.parts {
+                    drive.readahead.observe_read(lba, sectors);
+                    let start = ((lba - op.lba) as usize) * ata_pio::SECTOR_SIZE;
+                    let end = start + (sectors as usize) * ata_pio::SECTOR_SIZE;
+                    let (ack_ctx, reply_to) = pending.remove(&tag).unwrap();
+                    let response = block::Response::Read(bytes[start..end].to_vec());
+                    ipc::deliver_reply(&reply_to, &response).unwrap();
+                    ack_ctx.ack().unwrap();
+                }
+            },
+            Direction::Write => {
+                unsafe { controller.write_lba(drive.index, op.lba, &op.data) };
+                for (tag, _, _) in op.parts {
+                    let (ack_ctx, reply_to) = pending.remove(&tag).unwrap();
+                    ipc::deliver_reply(&reply_to, &block::Response::Write).unwrap();
+                    ack_ctx.ack().unwrap();
+                }
+            },
+        }
+        drive.sched.complete();
+    }
+}
+
+/// Read ahead for sequential readers while the drive is idle.
+/// Returns false if there was nothing to prefetch.
+fn prefetch(controller: &ata_pio::AtaPio, drive: &mut Drive) -> bool {
+    if !drive.sched.is_empty() {
+        return false;
+    }
+
+    if let Some((lba, sectors)) = drive.readahead.next_prefetch(drive.capacity_sectors) {
+        let bytes = unsafe { controller.read_lba(drive.index, lba, sectors as u8) };
+        drive.readahead.insert(lba, &bytes);
+        true
+    } else {
+        false
+    }
+}
 
 #[no_mangle]
 fn main() -> ! {
     syscall::debug_print("ata pio driver starting");
 
-    let controller = ata_pio::AtaPio::new();
+    let mut controller = ata_pio::AtaPio::new();
 
     let drive_count = controller.drive_count();
     assert!(drive_count > 0, "No drives found");
 
-    // let attachments: Vec<_> = (0..drive_count)
-    //     .map(|drive_index| Attachment::new_leaf(&format!("/dev/ata_pio_{}", drive_index)).unwrap())
-    //     .collect();
+    let mut drives: Vec<Drive> = (0..drive_count)
+        .map(|index| Drive {
+            index,
+            capacity_sectors: controller.capacity_sectors(index),
+            sub: ipc::ReliableSubscription::exact(&format!("ata_pio/{}/io", index)).unwrap(),
+            sched: IoScheduler::new(QUEUE_DEPTH),
+            readahead: Readahead::new(),
+        })
+        .collect();
+    let subs: Vec<_> = drives.iter().map(|d| d.sub.sub_id()).collect();
 
-    // let attachment_fds: Vec<_> = attachments.iter().map(|a| a.fd).collect();
+    let stats: ipc::Server<u64, Option<block::Stats>> = ipc::Server::exact("ata_pio/stats").unwrap();
+    let shutdown: ipc::UnreliableSubscription<ShutdownStarted> =
+        ipc::UnreliableSubscription::exact("system/shutdown").unwrap();
 
-    // let mut cursors: HashMap<Sender, u64> = HashMap::new();
+    let mut pending: Pending = HashMap::new();
+    let mut next_tag: Tag = 0;
+    let mut flush_all = false;
+
+    // Inform serviced that we are running
+    libd7::service::register("driver_ata_pio", false);
 
     loop {
-        // let index = select! {
-        //     any(attachment_fds) -> avail_fd => {
-        //         attachment_fds
-        //             .iter()
-        //             .position(|fd| *fd == avail_fd)
-        //             .unwrap()
-        //     }
-        // };
-
-        // let req = attachments[index].next_request().unwrap();
-
-        // let cur = *cursors.entry(req.sender).or_insert(0);
-
-        // match req.operation {
-        //     RequestFileOperation::Read(byte_count) => {
-        //         let sector_count = (byte_count / ata_pio::SECTOR_SIZE as u64) + 1;
-        //         assert!(sector_count <= (core::u8::MAX as u64));
-        //         let bytes = unsafe { controller.read_lba(index, cur, sector_count as u8) };
-        //         attachments[index]
-        //             .reply(req.response(ResponseFileOperation::Read(bytes)))
-        //             .unwrap();
-        //     }
-        //     other => unimplemented!("Unsupported request: {:?}", other),
-        // }
+        select! {
+            any(subs) -> sub_id => {
+                let index = subs.iter().position(|s| *s == sub_id).unwrap();
+                receive(&mut drives[index], &mut pending, &mut next_tag);
+            },
+            one(stats) => stats.handle(|index| {
+                Ok(drives.get(index as usize).map(|d| d.stats()))
+            }).unwrap(),
+            one(shutdown) => {
+                let _ = shutdown.receive().unwrap();
+                flush_all = true;
+            }
+        };
+
+        loop {
+            // Queue all immediately available requests before dispatching,
+            // so that requests to adjacent sectors can be merged
+            loop {
+                select! {
+                    any(subs) -> sub_id => {
+                        let index = subs.iter().position(|s| *s == sub_id).unwrap();
+                        receive(&mut drives[index], &mut pending, &mut next_tag);
+                    },
+                    would_block => break
+                };
+            }
+
+            for drive in drives.iter_mut() {
+                dispatch(&controller, drive, &mut pending);
+            }
+
+            // Prefetch one operation per drive at a time,
+            // so that new requests are not delayed by long readaheads
+            let mut prefetched = false;
+            for drive in drives.iter_mut() {
+                prefetched |= prefetch(&controller, drive);
+            }
+            if !prefetched {
+                break;
+            }
+        }
+
+        // Writes flush the cache themselves, but other writers may have
+        // used the drives. Requests are still served after this, so that
+        // filesystems can write their state during the shutdown.
+        if flush_all {
+            for drive in drives.iter() {
+                unsafe { controller.flush_cache(drive.index) };
+            }
+            println!("ata pio: caches flushed for shutdown");
+            flush_all = false;
+        }
     }
 }
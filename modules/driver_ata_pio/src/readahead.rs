@@ -0,0 +1,194 @@
+//! Sequential read detection and prefetching.
+//!
+//! Reads continuing where an earlier read ended are considered sequential.
+//! For such streams, the following sectors are read into a cache when the
+//! drive is otherwise idle. The prefetch window doubles on each sequential
+//! hit, up to `MAX_WINDOW_SECTORS`.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::prelude::v1::*;
+
+use crate::ata_pio::SECTOR_SIZE;
+use crate::iosched::MAX_SECTORS_PER_OP;
+
+/// Number of concurrent sequential streams tracked
+const MAX_STREAMS: usize = 8;
+
+const INITIAL_WINDOW_SECTORS: u64 = 8;
+const MAX_WINDOW_SECTORS: u64 = MAX_SECTORS_PER_OP;
+
+/// Max number of cached sectors
+const CACHE_LIMIT_SECTORS: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Stream {
+    /// Next sector the client is expected to read
+    next_lba: u64,
+    /// First sector not yet prefetched
+    prefetched_until: u64,
+    /// Current prefetch window size
+    window: u64,
+}
+
+#[derive(Debug)]
+pub struct Readahead {
+    /// Most recently used stream last
+    streams: VecDeque<Stream>,
+    /// Cached sectors by lba, in insertion order for eviction
+    cache: BTreeMap<u64, Vec<u8>>,
+    insertion_order: VecDeque<u64>,
+    /// Sectors read ahead from the device
+    pub prefetched: u64,
+    /// Prefetched sectors that were later read by a client
+    pub hits: u64,
+}
+impl Readahead {
+    pub fn new() -> Self {
+        Self {
+            streams: VecDeque::new(),
+            cache: BTreeMap::new(),
+            insertion_order: VecDeque::new(),
+            prefetched: 0,
+            hits: 0,
+        }
+    }
+
+    /// Returns the data if all requested sectors are cached
+    pub fn lookup(&mut self, lba: u64, sectors: u64) -> Option<Vec<u8>> {
+        if !(lba..lba + sectors).all(|s| self.cache.contains_key(&s)) {
+            return None;
+        }
+
+        let mut result = Vec::with_capacity((sectors as usize) * SECTOR_SIZE);
+        for s in lba..lba + sectors {
+            result.extend(&self.cache[&s]);
+        }
+        self.hits += sectors;
+        Some(result)
+    }
+
+    /// Update streams after a client read has been served
+    pub fn observe_read(&mut self, lba: u64, sectors: u64) {
+        let end = lba + sectors;
+        if let Some(i) = self.streams.iter().position(|s| s.next_lba == lba) {
+            let mut stream = self.streams.remove(i).unwrap();
+            stream.next_lba = end;
+            stream.prefetched_until = stream.prefetched_until.max(end);
+            stream.window = (stream.window * 2).min(MAX_WINDOW_SECTORS);
+            self.streams.push_back(stream);
+        } else {
+            if self.streams.len() >= MAX_STREAMS {
+                self.streams.pop_front();
+            }
+            self.streams.push_back(Stream {
+                next_lba: end,
+                prefetched_until: end,
+                window: INITIAL_WINDOW_SECTORS,
+            });
+        }
+    }
+
+    /// Drop cached copies of sectors that are being written
+    pub fn invalidate(&mut self, lba: u64, sectors: u64) {
+        for s in lba..lba + sectors {
+            self.cache.remove(&s);
+        }
+        // A sector inserted again later must not be evicted by its stale entry
+        self.insertion_order.retain(|s| !(lba..lba + sectors).contains(s));
+    }
+
+    /// Next range `(lba, sectors)` to prefetch, if any.
+    /// Only streams that have been continued at least once are prefetched.
+    pub fn next_prefetch(&mut self, capacity_sectors: u64) -> Option<(u64, u64)> {
+        for stream in self.streams.iter_mut().rev() {
+            if stream.window <= INITIAL_WINDOW_SECTORS {
+                continue;
+            }
+
+            let target = (stream.next_lba + stream.window).min(capacity_sectors);
+            if stream.prefetched_until < target {
+                let lba = stream.prefetched_until;
+                let sectors = (target - lba).min(MAX_SECTORS_PER_OP);
+                stream.prefetched_until = lba + sectors;
+                return Some((lba, sectors));
+            }
+        }
+        None
+    }
+
+    /// Store prefetched sectors
+    pub fn insert(&mut self, lba: u64, data: &[u8]) {
+        self.prefetched += (data.len() / SECTOR_SIZE) as u64;
+        for (i, sector) in data.chunks_exact(SECTOR_SIZE).enumerate() {
+            let s = lba + i as u64;
+            if self.cache.insert(s, sector.to_vec()).is_none() {
+                self.insertion_order.push_back(s);
+            }
+        }
+
+        while self.cache.len() > CACHE_LIMIT_SECTORS {
+            if let Some(oldest) = self.insertion_order.pop_front() {
+                self.cache.remove(&oldest);
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sectors(first: u8, count: usize) -> Vec<u8> {
+        (0..count).flat_map(|i| vec![first + i as u8; SECTOR_SIZE]).collect()
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut ra = Readahead::new();
+        ra.insert(10, &sectors(1, 4));
+        assert_eq!(ra.prefetched, 4);
+
+        assert_eq!(ra.lookup(11, 2), Some(sectors(2, 2)));
+        assert_eq!(ra.hits, 2);
+        assert_eq!(ra.lookup(12, 3), None);
+        assert_eq!(ra.lookup(9, 1), None);
+        assert_eq!(ra.hits, 2);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let mut ra = Readahead::new();
+        ra.insert(10, &sectors(1, 4));
+        ra.invalidate(11, 2);
+        assert_eq!(ra.lookup(10, 1), Some(sectors(1, 1)));
+        assert_eq!(ra.lookup(11, 1), None);
+        assert_eq!(ra.lookup(12, 1), None);
+        assert_eq!(ra.lookup(13, 1), Some(sectors(4, 1)));
+        assert_eq!(ra.insertion_order, vec![10, 13]);
+
+        ra.insert(11, &sectors(5, 1));
+        assert_eq!(ra.lookup(11, 1), Some(sectors(5, 1)));
+        assert_eq!(ra.insertion_order, vec![10, 13, 11]);
+    }
+
+    #[test]
+    fn test_eviction_order() {
+        let mut ra = Readahead::new();
+        for lba in 0..CACHE_LIMIT_SECTORS as u64 {
+            ra.insert(lba, &sectors(0, 1));
+        }
+
+        // Re-inserted after invalidation, so it's now the newest
+        ra.invalidate(0, 1);
+        ra.insert(0, &sectors(0, 1));
+
+        ra.insert(CACHE_LIMIT_SECTORS as u64, &sectors(0, 2));
+        assert_eq!(ra.cache.len(), CACHE_LIMIT_SECTORS);
+        assert!(ra.lookup(0, 1).is_some());
+        assert!(ra.lookup(1, 2).is_none());
+        assert!(ra.lookup(3, 1).is_some());
+        assert!(ra.lookup(CACHE_LIMIT_SECTORS as u64, 2).is_some());
+    }
+}
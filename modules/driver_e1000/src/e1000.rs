@@ -0,0 +1,389 @@
+//! https://wiki.osdev.org/Intel_Ethernet_i217
+//! Intel 8254x Software Developer's Manual, sections 3 and 13
+//!
+//! Uses the legacy descriptor format, which the 8254x (e1000) and
+//! the 82574 (e1000e) both support.
+
+use alloc::prelude::v1::*;
+use bitflags::bitflags;
+use core::ptr;
+
+use d7pci::Device;
+use libd7::net::d7net::MacAddr;
+use libd7::{syscall, PhysAddr, VirtAddr};
+
+use super::dma::DMARegion;
+
+/// Supported devices, as `pci_devices.json` shortnames and (vendor, id) pairs
+pub const DEVICES: &[(&str, (u16, u16))] = &[
+    ("e1000", (0x8086, 0x100e)),  // 82540EM, the QEMU default
+    ("e1000e", (0x8086, 0x10d3)), // 82574L
+];
+
+/// Where the register BAR is mapped, should be free
+const MMIO_VIRTUAL_ADDR: u64 = 0x11_0000_0000;
+/// Size of the register space
+const MMIO_SIZE: u64 = 0x2_0000;
+/// `mmap_physical` maps whole pages
+const PAGE_SIZE: u64 = 0x20_0000;
+
+/// Descriptor counts, the ring sizes must be multiples of 128 bytes
+const RX_DESC_COUNT: usize = 32;
+const TX_DESC_COUNT: usize = 8;
+const DESC_SIZE: usize = 16;
+
+/// Matches `rctl::BSIZE_2048`
+const BUFFER_SIZE: usize = 2048;
+/// Frame without the CRC, which the device adds
+const PACKET_SIZE_MAX: usize = 1514;
+
+mod reg {
+    pub const CTRL: u32 = 0x0000;
+    pub const STATUS: u32 = 0x0008;
+    pub const ICR: u32 = 0x00c0;
+    pub const IMS: u32 = 0x00d0;
+    pub const IMC: u32 = 0x00d8;
+    pub const RCTL: u32 = 0x0100;
+    pub const TCTL: u32 = 0x0400;
+    pub const TIPG: u32 = 0x0410;
+    pub const RDBAL: u32 = 0x2800;
+    pub const RDBAH: u32 = 0x2804;
+    pub const RDLEN: u32 = 0x2808;
+    pub const RDH: u32 = 0x2810;
+    pub const RDT: u32 = 0x2818;
+    pub const TDBAL: u32 = 0x3800;
+    pub const TDBAH: u32 = 0x3804;
+    pub const TDLEN: u32 = 0x3808;
+    pub const TDH: u32 = 0x3810;
+    pub const TDT: u32 = 0x3818;
+    /// Multicast table array, 128 entries
+    pub const MTA: u32 = 0x5200;
+    pub const RAL0: u32 = 0x5400;
+    pub const RAH0: u32 = 0x5404;
+}
+
+mod ctrl {
+    pub const ASDE: u32 = 1 << 5;
+    pub const SLU: u32 = 1 << 6;
+    pub const RST: u32 = 1 << 26;
+}
+
+mod status {
+    pub const LU: u32 = 1 << 1;
+}
+
+mod rctl {
+    pub const EN: u32 = 1 << 1;
+    /// Accept all multicast
+    pub const MPE: u32 = 1 << 4;
+    /// Accept broadcast
+    pub const BAM: u32 = 1 << 15;
+    pub const BSIZE_2048: u32 = 0;
+    /// Strip the CRC
+    pub const SECRC: u32 = 1 << 26;
+}
+
+mod tctl {
+    pub const EN: u32 = 1 << 1;
+    /// Pad short packets
+    pub const PSP: u32 = 1 << 3;
+    /// Collision threshold, the recommended value
+    pub const CT: u32 = 0x0f << 4;
+    /// Collision distance for full duplex
+    pub const COLD: u32 = 0x40 << 12;
+}
+
+/// Recommended inter packet gap for the copper interface
+const TIPG_VALUE: u32 = 10 | (8 << 10) | (6 << 20);
+
+/// Address valid bit of `RAH0`
+const RAH_AV: u32 = 1 << 31;
+
+bitflags! {
+    /// Interrupt cause flags
+    struct IntFlags: u32 {
+        const TXDW = 1 << 0;
+        const TXQE = 1 << 1;
+        const LSC = 1 << 2;
+        const RXDMT0 = 1 << 4;
+        const RXO = 1 << 6;
+        const RXT0 = 1 << 7;
+
+        const ENABLED = Self::LSC.bits
+                    | Self::RXDMT0.bits
+                    | Self::RXO.bits
+                    | Self::RXT0.bits;
+    }
+}
+
+mod desc_status {
+    /// Descriptor done
+    pub const DD: u8 = 1 << 0;
+    /// End of packet
+    pub const EOP: u8 = 1 << 1;
+}
+
+mod tx_cmd {
+    pub const EOP: u8 = 1 << 0;
+    /// Insert the CRC
+    pub const IFCS: u8 = 1 << 1;
+    /// Report status, so that `DD` is set when sent
+    pub const RS: u8 = 1 << 3;
+}
+
+/// Offsets within the legacy descriptors
+mod desc {
+    pub const ADDR: usize = 0;
+    pub const LENGTH: usize = 8;
+    pub const RX_STATUS: usize = 12;
+    pub const RX_ERRORS: usize = 13;
+    pub const TX_CMD: usize = 11;
+    pub const TX_STATUS: usize = 12;
+}
+
+struct Ring {
+    descriptors: DMARegion,
+    buffers: Vec<DMARegion>,
+    /// Next descriptor to check or fill
+    next: usize,
+}
+impl Ring {
+    fn new(count: usize) -> Self {
+        Self {
+            descriptors: DMARegion::allocate(count * DESC_SIZE),
+            buffers: (0..count)
+                .map(|_| DMARegion::allocate(BUFFER_SIZE))
+                .collect(),
+            next: 0,
+        }
+    }
+
+    fn field(&self, index: usize, offset: usize) -> *mut u8 {
+        unsafe {
+            self.descriptors
+                .virt
+                .as_mut_ptr::<u8>()
+                .add(index * DESC_SIZE + offset)
+        }
+    }
+
+    /// Clears the descriptors, and points them to the buffers
+    fn reset(&mut self) {
+        self.next = 0;
+        for (i, buffer) in self.buffers.iter().enumerate() {
+            unsafe {
+                ptr::write_bytes(self.field(i, 0), 0, DESC_SIZE);
+                ptr::write_volatile(self.field(i, desc::ADDR) as *mut u64, buffer.phys.as_u64());
+            }
+        }
+    }
+}
+
+pub struct E1000 {
+    pub irq: u8,
+    mmio: VirtAddr,
+    rx: Ring,
+    tx: Ring,
+    link_up: bool,
+}
+impl E1000 {
+    pub unsafe fn new(pci_device: Device) -> Self {
+        let ids = (pci_device.vendor, pci_device.id);
+        assert!(DEVICES.iter().any(|(_, d)| *d == ids), "Unsupported device");
+        // Ethernet controller
+        assert_eq!((pci_device.class.0, pci_device.class.1), (2, 0));
+
+        pci_device.enable_memory_space();
+        pci_device.enable_bus_mastering();
+
+        // BAR0 is a memory BAR, possibly 64-bit
+        let bar0 = pci_device.get_bar(0);
+        assert!(bar0 & 1 == 0, "Register BAR is not memory-mapped");
+        let mut phys = (bar0 & !0xf) as u64;
+        if (bar0 >> 1) & 0b11 == 0b10 {
+            phys |= (pci_device.get_bar(1) as u64) << 32;
+        }
+        let page = phys & !(PAGE_SIZE - 1);
+        syscall::mmap_physical(
+            PhysAddr::new(page),
+            VirtAddr::new(MMIO_VIRTUAL_ADDR),
+            phys - page + MMIO_SIZE,
+            syscall::MemoryProtectionFlags::READ | syscall::MemoryProtectionFlags::WRITE,
+        )
+        .unwrap();
+
+        let mut device = Self {
+            irq: pci_device
+                .get_interrupt_line()
+                .expect("Missing interrupt line"),
+            mmio: VirtAddr::new(MMIO_VIRTUAL_ADDR + (phys - page)),
+            rx: Ring::new(RX_DESC_COUNT),
+            tx: Ring::new(TX_DESC_COUNT),
+            link_up: false,
+        };
+        device.reset();
+        device
+    }
+
+    fn read(&self, register: u32) -> u32 {
+        unsafe { ptr::read_volatile((self.mmio + register as u64).as_ptr()) }
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        unsafe { ptr::write_volatile((self.mmio + register as u64).as_mut_ptr(), value) }
+    }
+
+    fn reset(&mut self) {
+        // Disable interrupts, and reset the device
+        self.write(reg::IMC, 0xffff_ffff);
+        let value = self.read(reg::CTRL);
+        self.write(reg::CTRL, value | ctrl::RST);
+        while self.read(reg::CTRL) & ctrl::RST != 0 {}
+        self.write(reg::IMC, 0xffff_ffff);
+        let _ = self.read(reg::ICR);
+
+        // Set link up, with automatic speed detection
+        let value = self.read(reg::CTRL);
+        self.write(reg::CTRL, value | ctrl::SLU | ctrl::ASDE);
+        self.link_up = self.read(reg::STATUS) & status::LU != 0;
+
+        // Accept all multicast with `MPE` instead of the hash table
+        for i in 0..128 {
+            self.write(reg::MTA + i * 4, 0);
+        }
+
+        // Receive ring, with all descriptors given to the device
+        self.rx.reset();
+        let rx_phys = self.rx.descriptors.phys.as_u64();
+        self.write(reg::RDBAL, rx_phys as u32);
+        self.write(reg::RDBAH, (rx_phys >> 32) as u32);
+        self.write(reg::RDLEN, (RX_DESC_COUNT * DESC_SIZE) as u32);
+        self.write(reg::RDH, 0);
+        self.write(reg::RDT, (RX_DESC_COUNT - 1) as u32);
+        self.write(reg::RCTL, rctl::EN | rctl::MPE | rctl::BAM | rctl::BSIZE_2048 | rctl::SECRC);
+
+        // Transmit ring, all descriptors marked as done so that they are free
+        self.tx.reset();
+        for i in 0..TX_DESC_COUNT {
+            unsafe { ptr::write_volatile(self.tx.field(i, desc::TX_STATUS), desc_status::DD) };
+        }
+        let tx_phys = self.tx.descriptors.phys.as_u64();
+        self.write(reg::TDBAL, tx_phys as u32);
+        self.write(reg::TDBAH, (tx_phys >> 32) as u32);
+        self.write(reg::TDLEN, (TX_DESC_COUNT * DESC_SIZE) as u32);
+        self.write(reg::TDH, 0);
+        self.write(reg::TDT, 0);
+        self.write(reg::TIPG, TIPG_VALUE);
+        self.write(reg::TCTL, tctl::EN | tctl::PSP | tctl::CT | tctl::COLD);
+
+        // Enable interrupts
+        self.write(reg::IMS, IntFlags::ENABLED.bits());
+    }
+
+    /// Takes the packets the device has written to the receive ring
+    fn receive(&mut self) -> Vec<Vec<u8>> {
+        let mut received_packets = Vec::new();
+        loop {
+            let i = self.rx.next;
+            let status = unsafe { ptr::read_volatile(self.rx.field(i, desc::RX_STATUS)) };
+            if status & desc_status::DD == 0 {
+                break;
+            }
+
+            let errors = unsafe { ptr::read_volatile(self.rx.field(i, desc::RX_ERRORS)) };
+            let length =
+                unsafe { ptr::read_volatile(self.rx.field(i, desc::LENGTH) as *const u16) };
+            if status & desc_status::EOP == 0 {
+                // Frames never span buffers, as long frames are not accepted
+                log::warn!("rx frame without end of packet");
+            } else if errors != 0 {
+                log::warn!("rx error {:#x}", errors);
+            } else {
+                let length = (length as usize).min(BUFFER_SIZE);
+                let mut packet = vec![0; length];
+                unsafe {
+                    let src = self.rx.buffers[i].virt.as_ptr::<u8>();
+                    ptr::copy_nonoverlapping(src, packet.as_mut_ptr(), length);
+                }
+                received_packets.push(packet);
+            }
+
+            // Give the descriptor back to the device
+            unsafe {
+                ptr::write_volatile(self.rx.field(i, desc::RX_STATUS), 0);
+            }
+            self.write(reg::RDT, i as u32);
+            self.rx.next = (i + 1) % RX_DESC_COUNT;
+        }
+        received_packets
+    }
+
+    /// Queues a frame for sending. The frame is dropped if all
+    /// transmit descriptors are still in use.
+    pub fn send(&mut self, packet: &[u8]) {
+        log::debug!(" send [length={}]", packet.len());
+
+        if packet.len() > PACKET_SIZE_MAX {
+            panic!("E1000: packet too large");
+        }
+
+        let i = self.tx.next;
+        let status = unsafe { ptr::read_volatile(self.tx.field(i, desc::TX_STATUS)) };
+        if status & desc_status::DD == 0 {
+            log::warn!("E1000: transmit ring full, dropping frame");
+            return;
+        }
+
+        unsafe {
+            let dst = self.tx.buffers[i].virt.as_mut_ptr::<u8>();
+            ptr::copy_nonoverlapping(packet.as_ptr(), dst, packet.len());
+            ptr::write_volatile(self.tx.field(i, desc::LENGTH) as *mut u16, packet.len() as u16);
+            ptr::write_volatile(
+                self.tx.field(i, desc::TX_CMD),
+                tx_cmd::EOP | tx_cmd::IFCS | tx_cmd::RS,
+            );
+            ptr::write_volatile(self.tx.field(i, desc::TX_STATUS), 0);
+        }
+        self.tx.next = (i + 1) % TX_DESC_COUNT;
+        self.write(reg::TDT, self.tx.next as u32);
+    }
+
+    /// Handles all pending interrupt causes. Reading `ICR` clears them.
+    /// Returns the received packets.
+    pub fn notify_irq(&mut self) -> Vec<Vec<u8>> {
+        let mut received_packets = Vec::new();
+        loop {
+            let cause = IntFlags::from_bits_truncate(self.read(reg::ICR));
+            if cause.is_empty() {
+                break;
+            }
+
+            log::info!("IRQ cause={:?}", cause);
+
+            if cause.contains(IntFlags::LSC) {
+                self.link_up = self.read(reg::STATUS) & status::LU != 0;
+                log::info!("link {}", if self.link_up { "up" } else { "down" });
+            }
+
+            if cause.contains(IntFlags::RXO) {
+                log::warn!("rx overrun");
+            }
+
+            if cause.intersects(IntFlags::RXT0 | IntFlags::RXDMT0 | IntFlags::RXO) {
+                received_packets.extend(self.receive());
+            }
+        }
+        received_packets
+    }
+
+    /// Loaded from the EEPROM by the device reset
+    pub fn mac_addr(&self) -> MacAddr {
+        let low = self.read(reg::RAL0);
+        let high = self.read(reg::RAH0);
+        assert!(high & RAH_AV != 0, "E1000: MAC address not loaded");
+        let mut result = [0; 6];
+        result[..4].copy_from_slice(&low.to_le_bytes());
+        result[4..].copy_from_slice(&high.to_le_bytes()[..2]);
+        MacAddr(result)
+    }
+}
@@ -0,0 +1,67 @@
+#![no_std]
+#![feature(alloc_prelude)]
+#![feature(allocator_api)]
+#![feature(no_more_cas)]
+#![deny(unused_must_use)]
+
+#[macro_use]
+extern crate alloc;
+
+#[macro_use]
+extern crate libd7;
+
+use alloc::prelude::v1::*;
+
+use libd7::net::d7net::MacAddr;
+use libd7::{ipc, select, syscall};
+
+mod dma;
+mod e1000;
+
+#[no_mangle]
+fn main() -> ! {
+    syscall::debug_print("E1000 driver starting");
+
+    // Make sure this is the only NIC driver running
+    libd7::service::register("exclude/nic", false);
+
+    // Get device info, the driver is started for any of the supported models
+    let pci_device = e1000::DEVICES
+        .iter()
+        .find_map(|(name, _)| {
+            let device: Option<d7pci::Device> = ipc::request("pci/device", name).unwrap();
+            device
+        })
+        .expect("PCI device resolution failed unexpectedly");
+
+    // Initialize the driver
+    let mut device = unsafe { e1000::E1000::new(pci_device) };
+
+    // Subscribe to hardware events
+    let irq = ipc::UnreliableSubscription::<u64>::exact(&format!("irq/{}", device.irq)).unwrap();
+
+    // Subscribe to client requests
+    let get_mac: ipc::Server<(), MacAddr> = ipc::Server::exact("nic/mac").unwrap();
+    let send = ipc::ReliableSubscription::<Vec<u8>>::exact("nic/send").unwrap();
+
+    // Inform serviced that we are running.
+    libd7::service::register("driver_e1000", false);
+
+    loop {
+        select! {
+            one(irq) => {
+                // The device reports the cause itself, so the value is not used
+                let _: u64 = irq.receive().unwrap();
+                for packet in device.notify_irq() {
+                    ipc::deliver("netd/received", &packet).unwrap();
+                }
+            },
+            one(get_mac) => get_mac.handle(|()| Ok(device.mac_addr())).unwrap(),
+            one(send) => {
+                let (ack_ctx, packet): (_, Vec<u8>) = send.receive().unwrap();
+                device.send(&packet);
+                ack_ctx.ack().unwrap();
+            }
+        }
+    }
+}
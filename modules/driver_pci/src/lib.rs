@@ -16,7 +16,11 @@ use alloc::prelude::v1::*;
 use hashbrown::HashMap;
 use serde::Deserialize;
 
-use libd7::{ipc, process::Process, syscall};
+use libd7::{
+    ipc,
+    process::{Privilege, Process},
+    syscall,
+};
 
 #[derive(Debug, Deserialize)]
 struct ConfigDevice {
@@ -60,7 +64,7 @@ fn main() -> ! {
                     driver.from_initrd,
                     "Non-initrd executables are not supported yet"
                 );
-                Process::spawn(&driver.executable).unwrap();
+                Process::spawn_with_privilege(&driver.executable, Privilege::Driver).unwrap();
             }
         } else {
             println!("Ignoring unknown PCI device {}", vendor_and_id);
@@ -0,0 +1,58 @@
+//! Packet capture of all sent and received frames.
+//! Records are kept in a bounded ring until read by the capturing process.
+
+use alloc::collections::VecDeque;
+use alloc::prelude::v1::*;
+
+use libd7::net::d7net::pcap;
+
+/// Max number of records kept before dropping
+const RING_CAPACITY: usize = 256;
+
+pub struct Capture {
+    enabled: bool,
+    ring: VecDeque<pcap::Record>,
+    /// Frames dropped because the ring was full
+    dropped: u64,
+}
+impl Capture {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            ring: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Enabling resets the ring and the drop counter
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.ring.clear();
+        self.dropped = 0;
+    }
+
+    pub fn frame(&mut self, frame: &[u8]) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.ring.len() >= RING_CAPACITY {
+            self.dropped += 1;
+            return;
+        }
+
+        // Read from the time page, as a request per frame would be too slow
+        let timestamp = libd7::time::now();
+        self.ring
+            .push_back(pcap::Record::new(timestamp, frame, pcap::DEFAULT_SNAPLEN));
+    }
+
+    /// Returns all captured records in pcap record format, and the drop counter
+    pub fn read(&mut self) -> (Vec<u8>, u64) {
+        let mut result = Vec::new();
+        for record in self.ring.drain(..) {
+            result.extend(record.to_bytes());
+        }
+        (result, self.dropped)
+    }
+}
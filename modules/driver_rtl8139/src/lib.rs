@@ -14,8 +14,13 @@ use alloc::prelude::v1::*;
 use hashbrown::HashMap;
 
 use libd7::net::d7net::MacAddr;
-use libd7::{ipc, process::ProcessId, select, syscall};
+use libd7::{
+    ipc,
+    process::{Privilege, ProcessId},
+    select, syscall,
+};
 
+mod capture;
 mod dma;
 mod rtl8139;
 
@@ -37,9 +42,19 @@ fn main() -> ! {
     let irq = ipc::UnreliableSubscription::<u64>::exact(&format!("irq/{}", device.irq)).unwrap();
 
     // Subscribe to client requests
-    let get_mac: ipc::Server<(), MacAddr> = ipc::Server::exact("nic/rtl8139/mac").unwrap();
+    let get_mac: ipc::Server<(), MacAddr> = ipc::Server::exact("nic/mac").unwrap();
     let send = ipc::ReliableSubscription::<Vec<u8>>::exact("nic/send").unwrap();
 
+    // Packet capture, records are in pcap format without the global header
+    let mut capture = capture::Capture::new();
+    let capture_enable: ipc::Server<bool, ()> =
+        ipc::Server::exact("nic/rtl8139/capture/enable").unwrap();
+    let capture_read: ipc::Server<(), (Vec<u8>, u64)> =
+        ipc::Server::exact("nic/rtl8139/capture/read").unwrap();
+    // Captured frames contain the traffic of all processes
+    capture_enable.restrict(Privilege::Driver).unwrap();
+    capture_read.restrict(Privilege::Driver).unwrap();
+
     // Inform serviced that we are running.
     libd7::service::register("driver_rtl8139", false);
 
@@ -50,12 +65,19 @@ fn main() -> ! {
                 println!("IRQ NOTIFY");
                 let received_packets = device.notify_irq(status as u16);
                 for packet in received_packets {
+                    capture.frame(&packet);
                     ipc::deliver("netd/received", &packet).unwrap();
                 }
             },
             one(get_mac) => get_mac.handle(|()| Ok(device.mac_addr())).unwrap(),
+            one(capture_enable) => capture_enable.handle(|enabled| {
+                capture.set_enabled(enabled);
+                Ok(())
+            }).unwrap(),
+            one(capture_read) => capture_read.handle(|()| Ok(capture.read())).unwrap(),
             one(send) => {
                 let (ack_ctx, packet): (_, Vec<u8>) = send.receive().unwrap();
+                capture.frame(&packet);
                 device.send(&packet);
                 ack_ctx.ack().unwrap();
             }
@@ -0,0 +1,63 @@
+#![no_std]
+#![feature(alloc_prelude)]
+#![feature(allocator_api)]
+#![feature(no_more_cas)]
+#![deny(unused_must_use)]
+
+#[macro_use]
+extern crate alloc;
+
+#[macro_use]
+extern crate libd7;
+
+use alloc::prelude::v1::*;
+
+use libd7::net::d7net::MacAddr;
+use libd7::{ipc, select, syscall};
+
+mod dma;
+mod virtio_net;
+mod virtq;
+
+#[no_mangle]
+fn main() -> ! {
+    syscall::debug_print("VirtIO-net driver starting");
+
+    // Make sure this is the only NIC driver running
+    libd7::service::register("exclude/nic", false);
+
+    // Get device info
+    let pci_device: Option<d7pci::Device> = ipc::request("pci/device", &"virtio-net").unwrap();
+    let pci_device = pci_device.expect("PCI device resolution failed unexpectedly");
+
+    // Initialize the driver
+    let mut device = unsafe { virtio_net::VirtioNet::new(pci_device) };
+
+    // Subscribe to hardware events
+    let irq = ipc::UnreliableSubscription::<u64>::exact(&format!("irq/{}", device.irq)).unwrap();
+
+    // Subscribe to client requests
+    let get_mac: ipc::Server<(), MacAddr> = ipc::Server::exact("nic/mac").unwrap();
+    let send = ipc::ReliableSubscription::<Vec<u8>>::exact("nic/send").unwrap();
+
+    // Inform serviced that we are running.
+    libd7::service::register("driver_virtio_net", false);
+
+    loop {
+        select! {
+            one(irq) => {
+                // The kernel doesn't acknowledge the interrupt, so the value is not used
+                let _: u64 = irq.receive().unwrap();
+                for packet in device.notify_irq() {
+                    ipc::deliver("netd/received", &packet).unwrap();
+                }
+            },
+            one(get_mac) => get_mac.handle(|()| Ok(device.mac_addr())).unwrap(),
+            one(send) => {
+                let (ack_ctx, packet): (_, Vec<u8>) = send.receive().unwrap();
+                device.send(&packet);
+                ack_ctx.ack().unwrap();
+            }
+        }
+    }
+}
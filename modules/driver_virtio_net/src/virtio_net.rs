@@ -0,0 +1,327 @@
+//! https://wiki.osdev.org/Virtio
+//! https://ozlabs.org/~rusty/virtio-spec/virtio-0.9.5.pdf, section 2 and appendix C
+//!
+//! Uses the legacy I/O port interface, which QEMU provides for the
+//! transitional device. Each packet is a chain of two descriptors, the
+//! virtio-net header and the frame, both placed in the same DMA buffer.
+
+use alloc::prelude::v1::*;
+use bitflags::bitflags;
+use core::ptr;
+use cpuio::UnsafePort;
+
+use d7pci::Device;
+use libd7::net::d7net::MacAddr;
+
+use super::dma::DMARegion;
+use super::virtq::{desc_flags, VirtQueue};
+
+/// Offsets of the legacy header registers from the I/O base
+mod reg {
+    pub const DEVICE_FEATURES: u16 = 0x00;
+    pub const GUEST_FEATURES: u16 = 0x04;
+    pub const QUEUE_ADDRESS: u16 = 0x08;
+    pub const QUEUE_SIZE: u16 = 0x0c;
+    pub const QUEUE_SELECT: u16 = 0x0e;
+    pub const QUEUE_NOTIFY: u16 = 0x10;
+    pub const DEVICE_STATUS: u16 = 0x12;
+    pub const ISR_STATUS: u16 = 0x13;
+    /// Device configuration, when MSI-X is not enabled
+    pub const MAC: u16 = 0x14;
+    pub const LINK_STATUS: u16 = 0x1a;
+}
+
+bitflags! {
+    struct DeviceStatus: u8 {
+        const ACKNOWLEDGE = 1 << 0;
+        const DRIVER = 1 << 1;
+        const DRIVER_OK = 1 << 2;
+        const FAILED = 1 << 7;
+    }
+}
+
+bitflags! {
+    /// Feature bits used by the driver, others are not negotiated
+    struct Features: u32 {
+        /// The device has a MAC address in the configuration
+        const MAC = 1 << 5;
+        /// The device reports the link status in the configuration
+        const STATUS = 1 << 16;
+    }
+}
+
+bitflags! {
+    /// Reading `ISR_STATUS` clears it, and acknowledges the interrupt
+    struct IsrStatus: u8 {
+        const QUEUE = 1 << 0;
+        const CONFIG = 1 << 1;
+    }
+}
+
+/// `LINK_STATUS` bit
+const LINK_UP: u16 = 1;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+
+/// Buffers per queue, each using two descriptors
+const RX_BUFFER_COUNT: u16 = 32;
+const TX_BUFFER_COUNT: u16 = 16;
+
+/// Legacy `virtio_net_hdr`, without `VIRTIO_NET_F_MRG_RXBUF`
+const HEADER_SIZE: usize = 10;
+const BUFFER_SIZE: usize = 2048;
+/// Frame without the CRC, which the device doesn't use
+const PACKET_SIZE_MAX: usize = 1514;
+
+struct Queue {
+    virtq: VirtQueue,
+    buffers: Vec<DMARegion>,
+}
+impl Queue {
+    /// Sets up a queue of the device, chaining a header and a frame
+    /// descriptor for each buffer. Descriptors `2 * i` and `2 * i + 1`
+    /// belong to buffer `i`.
+    fn new(device: &VirtioNet, index: u16, buffer_count: u16, device_writes: bool) -> Self {
+        let size = unsafe {
+            device.write16(reg::QUEUE_SELECT, index);
+            device.read16(reg::QUEUE_SIZE)
+        };
+        let mut virtq = VirtQueue::new(index, size);
+        let buffer_count = buffer_count.min(size / 2);
+        let flags = if device_writes { desc_flags::WRITE } else { 0 };
+        let buffers: Vec<DMARegion> = (0..buffer_count)
+            .map(|_| DMARegion::allocate(BUFFER_SIZE))
+            .collect();
+        for (i, buffer) in buffers.iter().enumerate() {
+            let head = 2 * i as u16;
+            let phys = buffer.phys.as_u64();
+            virtq.set_desc(
+                head,
+                phys,
+                HEADER_SIZE as u32,
+                flags | desc_flags::NEXT,
+                head + 1,
+            );
+            virtq.set_desc(
+                head + 1,
+                phys + HEADER_SIZE as u64,
+                (BUFFER_SIZE - HEADER_SIZE) as u32,
+                flags,
+                0,
+            );
+        }
+        unsafe {
+            device.write32(reg::QUEUE_ADDRESS, virtq.pfn());
+        }
+        Self { virtq, buffers }
+    }
+}
+
+pub struct VirtioNet {
+    pub irq: u8,
+    io_base: u16,
+    features: Features,
+    mac: MacAddr,
+    rx: Option<Queue>,
+    tx: Option<Queue>,
+    /// Transmit buffers not in use by the device
+    tx_free: Vec<u16>,
+    link_up: bool,
+}
+impl VirtioNet {
+    pub unsafe fn new(pci_device: Device) -> Self {
+        // Transitional network device, legacy devices have the same ids
+        assert_eq!((pci_device.vendor, pci_device.id), (0x1af4, 0x1000));
+        // Subsystem id 1 is a network card
+        assert_eq!(pci_device.subsystem_id(), 1, "Not a network device");
+
+        pci_device.enable_bus_mastering();
+
+        let bar0 = pci_device.get_bar(0);
+        assert!(bar0 & 1 == 1, "Legacy header BAR is not an I/O BAR");
+
+        let mut device = Self {
+            irq: pci_device
+                .get_interrupt_line()
+                .expect("Missing interrupt line"),
+            io_base: (bar0 & !0b11) as u16,
+            features: Features::empty(),
+            mac: MacAddr::ZERO,
+            rx: None,
+            tx: None,
+            tx_free: Vec::new(),
+            link_up: false,
+        };
+        device.reset();
+        device
+    }
+
+    unsafe fn read8(&self, register: u16) -> u8 {
+        UnsafePort::<u8>::new(self.io_base + register).read()
+    }
+
+    unsafe fn read16(&self, register: u16) -> u16 {
+        UnsafePort::<u16>::new(self.io_base + register).read()
+    }
+
+    unsafe fn read32(&self, register: u16) -> u32 {
+        UnsafePort::<u32>::new(self.io_base + register).read()
+    }
+
+    unsafe fn write8(&self, register: u16, value: u8) {
+        UnsafePort::<u8>::new(self.io_base + register).write(value)
+    }
+
+    unsafe fn write16(&self, register: u16, value: u16) {
+        UnsafePort::<u16>::new(self.io_base + register).write(value)
+    }
+
+    unsafe fn write32(&self, register: u16, value: u32) {
+        UnsafePort::<u32>::new(self.io_base + register).write(value)
+    }
+
+    fn set_status(&mut self, status: DeviceStatus) {
+        unsafe { self.write8(reg::DEVICE_STATUS, status.bits()) }
+    }
+
+    /// Initialization sequence of the legacy interface:
+    /// reset, acknowledge, negotiate features, set up queues, and start
+    fn reset(&mut self) {
+        self.set_status(DeviceStatus::empty());
+        let mut status = DeviceStatus::ACKNOWLEDGE;
+        self.set_status(status);
+        status |= DeviceStatus::DRIVER;
+        self.set_status(status);
+
+        // Accept the supported features that the device offers
+        self.features = Features::from_bits_truncate(unsafe { self.read32(reg::DEVICE_FEATURES) });
+        unsafe { self.write32(reg::GUEST_FEATURES, self.features.bits()) };
+        log::info!("features {:?}", self.features);
+
+        if !self.features.contains(Features::MAC) {
+            self.set_status(status | DeviceStatus::FAILED);
+            panic!("VirtioNet: device has no MAC address");
+        }
+        let mut mac = [0; 6];
+        for (i, byte) in mac.iter_mut().enumerate() {
+            *byte = unsafe { self.read8(reg::MAC + i as u16) };
+        }
+        self.mac = MacAddr(mac);
+        self.link_up = self.read_link_status();
+
+        // Receive buffers are all given to the device
+        let mut rx = Queue::new(self, RX_QUEUE, RX_BUFFER_COUNT, true);
+        for i in 0..rx.buffers.len() {
+            rx.virtq.push_available(2 * i as u16);
+        }
+        let tx = Queue::new(self, TX_QUEUE, TX_BUFFER_COUNT, false);
+        self.tx_free = (0..tx.buffers.len() as u16).rev().collect();
+        self.rx = Some(rx);
+        self.tx = Some(tx);
+
+        self.set_status(status | DeviceStatus::DRIVER_OK);
+        self.notify(RX_QUEUE);
+    }
+
+    fn notify(&self, queue: u16) {
+        unsafe { self.write16(reg::QUEUE_NOTIFY, queue) }
+    }
+
+    /// Link is reported up if the device doesn't support the status field
+    fn read_link_status(&self) -> bool {
+        !self.features.contains(Features::STATUS)
+            || unsafe { self.read16(reg::LINK_STATUS) } & LINK_UP != 0
+    }
+
+    /// Takes the packets the device has written to the receive queue,
+    /// and gives the buffers back to it
+    fn receive(&mut self) -> Vec<Vec<u8>> {
+        let mut received_packets = Vec::new();
+        let rx = self.rx.as_mut().expect("Device not initialized");
+        while let Some((head, written)) = rx.virtq.pop_used() {
+            let length = (written as usize).saturating_sub(HEADER_SIZE);
+            let length = length.min(BUFFER_SIZE - HEADER_SIZE);
+            let buffer = &rx.buffers[(head / 2) as usize];
+            let mut packet = vec![0; length];
+            unsafe {
+                let src = buffer.virt.as_ptr::<u8>().add(HEADER_SIZE);
+                ptr::copy_nonoverlapping(src, packet.as_mut_ptr(), length);
+            }
+            received_packets.push(packet);
+            rx.virtq.push_available(head);
+        }
+        if !received_packets.is_empty() {
+            self.notify(RX_QUEUE);
+        }
+        received_packets
+    }
+
+    /// Frees the transmit buffers the device has sent
+    fn reclaim_sent(&mut self) {
+        let tx = self.tx.as_mut().expect("Device not initialized");
+        while let Some((head, _)) = tx.virtq.pop_used() {
+            self.tx_free.push(head / 2);
+        }
+    }
+
+    /// Queues a frame for sending. The frame is dropped if all
+    /// transmit buffers are still in use.
+    pub fn send(&mut self, packet: &[u8]) {
+        log::debug!(" send [length={}]", packet.len());
+
+        if packet.len() > PACKET_SIZE_MAX {
+            panic!("VirtioNet: packet too large");
+        }
+
+        self.reclaim_sent();
+        let i = match self.tx_free.pop() {
+            Some(i) => i,
+            None => {
+                log::warn!("VirtioNet: transmit queue full, dropping frame");
+                return;
+            },
+        };
+
+        let tx = self.tx.as_mut().expect("Device not initialized");
+        let buffer = tx.buffers[i as usize];
+        unsafe {
+            // No offloads, so the header is all zeroes
+            let dst = buffer.virt.as_mut_ptr::<u8>();
+            ptr::write_bytes(dst, 0, HEADER_SIZE);
+            ptr::copy_nonoverlapping(packet.as_ptr(), dst.add(HEADER_SIZE), packet.len());
+        }
+        let head = 2 * i;
+        tx.virtq.set_desc(
+            head + 1,
+            buffer.phys.as_u64() + HEADER_SIZE as u64,
+            packet.len() as u32,
+            0,
+            0,
+        );
+        tx.virtq.push_available(head);
+        self.notify(TX_QUEUE);
+    }
+
+    /// Handles a device interrupt. Reading the ISR status acknowledges it.
+    /// Returns the received packets.
+    pub fn notify_irq(&mut self) -> Vec<Vec<u8>> {
+        let cause = IsrStatus::from_bits_truncate(unsafe { self.read8(reg::ISR_STATUS) });
+        log::info!("IRQ cause={:?}", cause);
+
+        if cause.contains(IsrStatus::CONFIG) {
+            self.link_up = self.read_link_status();
+            log::info!("link {}", if self.link_up { "up" } else { "down" });
+        }
+
+        // The used rings are checked even without a cause, as the
+        // interrupt line may be shared with other devices
+        self.reclaim_sent();
+        self.receive()
+    }
+
+    /// Read from the device configuration at initialization
+    pub fn mac_addr(&self) -> MacAddr {
+        self.mac
+    }
+}
@@ -0,0 +1,117 @@
+//! Split virtqueues in the legacy layout
+//! https://ozlabs.org/~rusty/virtio-spec/virtio-0.9.5.pdf, section 2.3
+//!
+//! The available ring follows the descriptor table, and the used ring
+//! starts on the next page boundary.
+
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+use super::dma::DMARegion;
+
+/// Legacy devices require this alignment for the queue and the used ring
+const QUEUE_ALIGN: usize = 0x1000;
+const DESC_SIZE: usize = 16;
+
+pub mod desc_flags {
+    /// The buffer continues in the `next` descriptor
+    pub const NEXT: u16 = 1 << 0;
+    /// The device writes to the buffer, instead of reading from it
+    pub const WRITE: u16 = 1 << 1;
+}
+
+fn align_up(value: usize) -> usize {
+    (value + QUEUE_ALIGN - 1) & !(QUEUE_ALIGN - 1)
+}
+
+pub struct VirtQueue {
+    /// Number of descriptors, chosen by the device
+    pub size: u16,
+    memory: DMARegion,
+    /// Next free position in the available ring
+    next_available: u16,
+    /// Position in the used ring up to which entries have been taken
+    last_used: u16,
+}
+impl VirtQueue {
+    pub fn new(index: u16, size: u16) -> Self {
+        assert!(size > 0, "Virtqueue {} is not available", index);
+        let bytes = Self::used_offset(size) + align_up(6 + 8 * size as usize);
+        let memory = DMARegion::allocate(bytes);
+        unsafe {
+            ptr::write_bytes(memory.virt.as_mut_ptr::<u8>(), 0, bytes);
+        }
+        Self {
+            size,
+            memory,
+            next_available: 0,
+            last_used: 0,
+        }
+    }
+
+    fn available_offset(size: u16) -> usize {
+        size as usize * DESC_SIZE
+    }
+
+    fn used_offset(size: u16) -> usize {
+        align_up(Self::available_offset(size) + 6 + 2 * size as usize)
+    }
+
+    /// Page frame number of the queue, as given to the device
+    pub fn pfn(&self) -> u32 {
+        (self.memory.phys.as_u64() / QUEUE_ALIGN as u64) as u32
+    }
+
+    fn at<T>(&self, offset: usize) -> *mut T {
+        unsafe { self.memory.virt.as_mut_ptr::<u8>().add(offset) as *mut T }
+    }
+
+    /// Writes a descriptor. `next` is only used with `desc_flags::NEXT`.
+    pub fn set_desc(&mut self, index: u16, address: u64, length: u32, flags: u16, next: u16) {
+        assert!(index < self.size);
+        let offset = index as usize * DESC_SIZE;
+        unsafe {
+            ptr::write_volatile(self.at(offset), address);
+            ptr::write_volatile(self.at(offset + 8), length);
+            ptr::write_volatile(self.at(offset + 12), flags);
+            ptr::write_volatile(self.at(offset + 14), next);
+        }
+    }
+
+    /// Gives a descriptor chain to the device. The device is only
+    /// informed after a notification.
+    pub fn push_available(&mut self, head: u16) {
+        let ring = Self::available_offset(self.size);
+        let slot = (self.next_available % self.size) as usize;
+        unsafe {
+            ptr::write_volatile(self.at(ring + 4 + 2 * slot), head);
+        }
+        self.next_available = self.next_available.wrapping_add(1);
+        // The entry must be visible before the index is
+        fence(Ordering::SeqCst);
+        unsafe {
+            ptr::write_volatile(self.at(ring + 2), self.next_available);
+        }
+        fence(Ordering::SeqCst);
+    }
+
+    /// Takes a chain the device has completed,
+    /// returning the head descriptor and the number of bytes written
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let ring = Self::used_offset(self.size);
+        let index: u16 = unsafe { ptr::read_volatile(self.at(ring + 2)) };
+        if index == self.last_used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let slot = (self.last_used % self.size) as usize;
+        let (id, length): (u32, u32) = unsafe {
+            (
+                ptr::read_volatile(self.at(ring + 4 + 8 * slot)),
+                ptr::read_volatile(self.at(ring + 8 + 8 * slot)),
+            )
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+        Some((id as u16, length))
+    }
+}
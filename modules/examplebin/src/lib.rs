@@ -10,16 +10,52 @@ use libd7::{
     // process::Process,
     syscall,
     // net::{tcp, d7net::*},
+    VirtAddr,
 };
 
 #[macro_use]
 extern crate alloc;
 
 use alloc::prelude::v1::*;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use libd7::d7abi::process::ProcessId;
+
+const THREAD_STACK_SIZE: usize = 0x1_0000;
+
+/// Pid seen by the thread, set before `THREAD_DONE`
+static THREAD_PID: AtomicU64 = AtomicU64::new(0);
+static THREAD_DONE: AtomicU32 = AtomicU32::new(0);
+
+/// Makes system calls on a stack that is not the process stack
+extern "C" fn thread_main(arg: u64) -> ! {
+    assert_eq!(arg, 0xd7);
+    THREAD_PID.store(syscall::get_pid().as_u64(), Ordering::SeqCst);
+    syscall::sched_yield();
+    THREAD_DONE.store(1, Ordering::SeqCst);
+    syscall::futex_wake(&THREAD_DONE).unwrap();
+    syscall::exit(0)
+}
+
+/// Checks that system calls from a thread return their results to the thread
+fn test_thread_syscalls(pid: ProcessId) {
+    let stack: &'static mut [u8] = Box::leak(vec![0u8; THREAD_STACK_SIZE].into_boxed_slice());
+    let stack_end = stack.as_ptr() as u64 + stack.len() as u64;
+    let stack_top = VirtAddr::new(stack_end).align_down(16u64);
+    let thread = unsafe { syscall::thread_create(thread_main, stack_top, 0xd7) }.unwrap();
+
+    while THREAD_DONE.load(Ordering::SeqCst) == 0 {
+        // Fails when the value has already changed
+        let _ = syscall::futex_wait(&THREAD_DONE, 0);
+    }
+    assert_ne!(thread, pid);
+    assert_eq!(THREAD_PID.load(Ordering::SeqCst), thread.as_u64());
+    syscall::debug_print("Thread system calls ok");
+}
 
 #[no_mangle]
 fn main() -> u64 {
     let pid = syscall::get_pid();
+    test_thread_syscalls(pid);
 
     // let tcp_server = tcp::Socket::bind(SocketAddr {
     //     host: IpAddr::V4(Ipv4Addr([0,0,0,0])),
@@ -5,6 +5,7 @@
 
 ; Push and pop macros. RFLAGS not pushed, as it is part of the IST
 ; System calls rely on the order of registers in the stack.
+; Keep in sync with `RegisterState` in src/interrupt/registers.rs
 %define stack_stored_registers 15
 %macro push_all 0
     push rbp
@@ -30,6 +30,8 @@ bitflags::bitflags! {
         const XSAVE        = 1 << 26;
         const OSXSAVE      = 1 << 27;
         const AVX          = 1 << 28;
+        const F16C         = 1 << 29;
+        const RDRAND       = 1 << 30;
     }
 }
 
@@ -126,6 +128,36 @@ fn get_max_levels() -> (u32, u32) {
     (max_standard_level, max_extended_level)
 }
 
+/// Feature bits from CPUID_GETFEATURES
+fn features() -> (FlagsECX, FlagsEDX) {
+    let ecx: u32;
+    let edx: u32;
+    unsafe {
+        asm!(
+            "cpuid",
+            inout("eax") 1 => _,
+            out("ebx") _,
+            out("ecx") ecx,
+            out("edx") edx,
+            options(nostack, nomem)
+        );
+    }
+    (
+        FlagsECX::from_bits_truncate(ecx),
+        FlagsEDX::from_bits_truncate(edx),
+    )
+}
+
+/// The local APIC supports x2APIC mode
+pub fn has_x2apic() -> bool {
+    features().0.contains(FlagsECX::X2APIC)
+}
+
+/// The `rdrand` instruction is available
+pub fn has_rdrand() -> bool {
+    features().0.contains(FlagsECX::RDRAND)
+}
+
 macro_rules! assert_feature {
     ($register:expr, $feature:expr) => {
         assert!(
@@ -149,23 +181,8 @@ fn run_feature_checks() {
         level_ext
     );
 
-    let ecx: u32;
-    let edx: u32;
-    unsafe {
-        // CPUID_GETFEATURES
-        asm!(
-            "cpuid",
-            inout("eax") 1 => _,
-            out("ebx") _,
-            out("ecx") ecx,
-            out("edx") edx,
-            options(nostack, nomem)
-        );
-    }
-    log::debug!("CPU: FEATURE BITS: {:b} {:b}", ecx, edx);
-
-    let f_ecx = FlagsECX::from_bits_truncate(ecx);
-    let f_edx = FlagsEDX::from_bits_truncate(edx);
+    let (f_ecx, f_edx) = features();
+    log::debug!("CPU: FEATURE BITS: {:b} {:b}", f_ecx.bits(), f_edx.bits());
 
     assert_feature!(f_edx, FlagsEDX::TSC);
     assert_feature!(f_ecx, FlagsECX::TSCD);
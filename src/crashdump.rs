@@ -0,0 +1,380 @@
+//! Crash dumps to a reserved raw area at the end of the primary ATA drive.
+//!
+//! When enabled with `CRASH_DUMP_ENABLED`, the panic handler writes the panic
+//! report and the unread part of the kernel log to the last
+//! `CRASH_DUMP_SIZE_SECTORS` sectors of the drive. On the next boot the dump is
+//! read back before any processes are started, the area is cleared, and the
+//! dump is made available through the `crashdump/read` kernel service.
+//!
+//! The disk is accessed with polling ATA PIO, as the normal disk driver is a
+//! process and cannot be used during a panic. Polling gives up after
+//! `MAX_POLLS` status reads, and a floating bus (status `0xff`) means that
+//! there is no drive. The area is not used if a partition in the MBR overlaps it.
+//!
+//! # Layout
+//! The first sector is a header: magic `D7CRASH1` followed by the payload
+//! length as little-endian `u64`. The payload starts from the second sector.
+
+use alloc::prelude::v1::*;
+use core::fmt;
+use cpuio::{inb, inw, outb, outw};
+use spin::Mutex;
+
+use crate::memory::constants::{CRASH_DUMP_ENABLED, CRASH_DUMP_SIZE_SECTORS};
+
+const SECTOR_SIZE: usize = 0x200;
+const MAGIC: &[u8; 8] = b"D7CRASH1";
+
+const PORT_DATA: u16 = 0x1F0;
+const PORT_SECCOUNT: u16 = 0x1F2;
+const PORT_LBA0: u16 = 0x1F3;
+const PORT_LBA1: u16 = 0x1F4;
+const PORT_LBA2: u16 = 0x1F5;
+const PORT_DRIVESELECT: u16 = 0x1F6;
+const PORT_COMMAND: u16 = 0x1F7;
+const PORT_DEV_CTRL: u16 = 0x3F6;
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_BSY: u8 = 1 << 7;
+/// Read from a bus with no drives attached
+const STATUS_FLOATING: u8 = 0xff;
+
+/// Status reads before a wait gives up, a few seconds at most
+const MAX_POLLS: usize = 1_000_000;
+
+/// Dump found on boot, if any
+static PREVIOUS_DUMP: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+unsafe fn status() -> u8 {
+    // 400ns delay before the status is valid
+    for _ in 0..4 {
+        let _ = inb(PORT_DEV_CTRL);
+    }
+    inb(PORT_COMMAND)
+}
+
+/// Returns `None` on timeout, or if there is no drive
+unsafe fn wait_not_busy() -> Option<u8> {
+    for _ in 0..MAX_POLLS {
+        let s = status();
+        if s == STATUS_FLOATING {
+            return None;
+        }
+        if s & STATUS_BSY == 0 {
+            return Some(s);
+        }
+    }
+    None
+}
+
+/// Returns false on drive error or timeout
+unsafe fn wait_drq() -> bool {
+    for _ in 0..MAX_POLLS {
+        let s = match wait_not_busy() {
+            Some(s) => s,
+            None => return false,
+        };
+        if s & STATUS_ERR != 0 {
+            return false;
+        }
+        if s & STATUS_DRQ != 0 {
+            return true;
+        }
+    }
+    false
+}
+
+/// Returns false if the drive is missing or doesn't recover
+unsafe fn reset() -> bool {
+    outb(0b100, PORT_DEV_CTRL); // Software reset, interrupts disabled
+    outb(0b010, PORT_DEV_CTRL);
+    wait_not_busy().is_some()
+}
+
+/// LBA28 sector count of the first drive, if present
+unsafe fn identify() -> Option<u64> {
+    outb(0xa0, PORT_DRIVESELECT);
+    outb(0, PORT_SECCOUNT);
+    outb(0, PORT_LBA0);
+    outb(0, PORT_LBA1);
+    outb(0, PORT_LBA2);
+    outb(0xec, PORT_COMMAND);
+
+    let s = status();
+    if s == 0 || s == STATUS_FLOATING || !wait_drq() {
+        return None;
+    }
+
+    let mut data = [0u16; 256];
+    for word in data.iter_mut() {
+        *word = inw(PORT_DATA);
+    }
+
+    let sectors = (data[60] as u64) | ((data[61] as u64) << 16);
+    Some(sectors)
+}
+
+unsafe fn select(lba: u64, command: u8) {
+    outb(0xe0 | ((lba >> 24) & 0x0f) as u8, PORT_DRIVESELECT);
+    outb(1, PORT_SECCOUNT);
+    outb(lba as u8, PORT_LBA0);
+    outb((lba >> 8) as u8, PORT_LBA1);
+    outb((lba >> 16) as u8, PORT_LBA2);
+    outb(command, PORT_COMMAND);
+}
+
+unsafe fn read_sector(lba: u64, buffer: &mut [u8; SECTOR_SIZE]) -> bool {
+    select(lba, 0x20);
+    if !wait_drq() {
+        return false;
+    }
+    for i in 0..(SECTOR_SIZE / 2) {
+        let [lo, hi] = inw(PORT_DATA).to_le_bytes();
+        buffer[2 * i] = lo;
+        buffer[2 * i + 1] = hi;
+    }
+    true
+}
+
+unsafe fn write_sector(lba: u64, buffer: &[u8; SECTOR_SIZE]) -> bool {
+    select(lba, 0x30);
+    if !wait_drq() {
+        return false;
+    }
+    for i in 0..(SECTOR_SIZE / 2) {
+        outw(u16::from_le_bytes([buffer[2 * i], buffer[2 * i + 1]]), PORT_DATA);
+    }
+    outb(0xe7, PORT_COMMAND); // Cache flush
+    wait_not_busy().map_or(false, |s| s & STATUS_ERR == 0)
+}
+
+/// Whether a partition in the MBR overlaps sectors `start..end`.
+/// A GPT protective entry covers the whole drive. Entries with an invalid
+/// boot flag are skipped, as the D7 boot sector has code and data there.
+fn overlaps_partition(mbr: &[u8; SECTOR_SIZE], start: u64, end: u64) -> bool {
+    if mbr[510..] != [0x55, 0xaa] {
+        return false;
+    }
+    mbr[446..510].chunks_exact(16).any(|entry| {
+        if entry[0] != 0x00 && entry[0] != 0x80 {
+            return false;
+        }
+        let kind = entry[4];
+        let first = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) as u64;
+        let count = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]) as u64;
+        kind != 0 && count != 0 && first < end && start < first + count
+    })
+}
+
+/// First sector of the reserved area, if the drive is large enough,
+/// and the area is not used by a partition
+unsafe fn area_start() -> Option<u64> {
+    let sectors = identify()?.min(1 << 28);
+    let start = sectors.checked_sub(CRASH_DUMP_SIZE_SECTORS)?;
+
+    let mut mbr = [0u8; SECTOR_SIZE];
+    if !read_sector(0, &mut mbr) {
+        return None;
+    }
+    if overlaps_partition(&mbr, start, sectors) {
+        log::warn!("Crash dump area overlaps a partition");
+        return None;
+    }
+    Some(start)
+}
+
+/// Streams the payload to the disk without allocating,
+/// as the heap might not be usable during a panic
+struct DumpWriter {
+    area_start: u64,
+    buffer: [u8; SECTOR_SIZE],
+    buffered: usize,
+    /// Payload sectors written so far
+    sectors: u64,
+    /// Payload bytes written so far
+    length: u64,
+    failed: bool,
+}
+impl DumpWriter {
+    fn new(area_start: u64) -> Self {
+        Self {
+            area_start,
+            buffer: [0; SECTOR_SIZE],
+            buffered: 0,
+            sectors: 0,
+            length: 0,
+            failed: false,
+        }
+    }
+
+    fn flush_sector(&mut self) {
+        // Sector zero is the header
+        if !self.failed && self.sectors + 1 < CRASH_DUMP_SIZE_SECTORS {
+            let lba = self.area_start + 1 + self.sectors;
+            self.failed = !unsafe { write_sector(lba, &self.buffer) };
+            self.sectors += 1;
+        }
+        self.buffer = [0; SECTOR_SIZE];
+        self.buffered = 0;
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if self.sectors + 1 >= CRASH_DUMP_SIZE_SECTORS {
+                return; // Area full, truncate
+            }
+            self.buffer[self.buffered] = b;
+            self.buffered += 1;
+            self.length += 1;
+            if self.buffered == SECTOR_SIZE {
+                self.flush_sector();
+            }
+        }
+    }
+
+    /// Writes the header, making the dump valid
+    fn finish(mut self) -> bool {
+        if self.buffered != 0 {
+            self.flush_sector();
+        }
+        let mut header = [0u8; SECTOR_SIZE];
+        header[..8].copy_from_slice(MAGIC);
+        header[8..16].copy_from_slice(&self.length.to_le_bytes());
+        !self.failed && unsafe { write_sector(self.area_start, &header) }
+    }
+}
+impl fmt::Write for DumpWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Called from the panic handler, after other cores have been stopped
+pub fn write_panic(info: &core::panic::PanicInfo) {
+    use core::fmt::Write;
+
+    if !CRASH_DUMP_ENABLED {
+        return;
+    }
+
+    unsafe {
+        // The disk driver process might have been in the middle of a command
+        if !reset() {
+            return;
+        }
+        let area_start = match area_start() {
+            Some(s) => s,
+            None => return,
+        };
+
+        let mut w = DumpWriter::new(area_start);
+        let _ = writeln!(w, "Kernel Panic: {}", info);
+        let _ = writeln!(w, "Unread kernel log:");
+        crate::syslog::with_unread(|bytes| w.write_bytes(bytes));
+
+        if w.finish() {
+            log::error!("Crash dump written");
+        } else {
+            log::error!("Crash dump failed");
+        }
+    }
+}
+
+/// Reads and clears the crash dump from the previous boot.
+/// Must be called before the disk driver process is started.
+pub fn init() {
+    if !CRASH_DUMP_ENABLED {
+        return;
+    }
+
+    unsafe {
+        if !reset() {
+            log::warn!("Crash dump area unavailable (no drive)");
+            return;
+        }
+        let area_start = match area_start() {
+            Some(s) => s,
+            None => {
+                log::warn!("Crash dump area unavailable");
+                return;
+            },
+        };
+
+        let mut header = [0u8; SECTOR_SIZE];
+        if !read_sector(area_start, &mut header) || &header[..8] != MAGIC {
+            return;
+        }
+
+        let mut length_bytes = [0u8; 8];
+        length_bytes.copy_from_slice(&header[8..16]);
+        let max_length = (CRASH_DUMP_SIZE_SECTORS - 1) * (SECTOR_SIZE as u64);
+        let length = u64::from_le_bytes(length_bytes).min(max_length) as usize;
+
+        let mut data = Vec::with_capacity(length);
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut lba = area_start + 1;
+        while data.len() < length {
+            if !read_sector(lba, &mut sector) {
+                log::warn!("Crash dump read failed");
+                break;
+            }
+            let count = (length - data.len()).min(SECTOR_SIZE);
+            data.extend_from_slice(&sector[..count]);
+            lba += 1;
+        }
+
+        // Clear the header, so that the dump is reported only once
+        if !write_sector(area_start, &[0u8; SECTOR_SIZE]) {
+            log::warn!("Crash dump could not be cleared");
+        }
+
+        log::warn!("Crash dump from the previous boot found ({} bytes)", data.len());
+        *PREVIOUS_DUMP.lock() = Some(data);
+    }
+}
+
+/// Crash dump from the previous boot, if any
+pub fn previous() -> Option<Vec<u8>> {
+    PREVIOUS_DUMP.lock().clone()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mbr(partitions: &[(u8, u32, u32)]) -> [u8; SECTOR_SIZE] {
+        let mut mbr = [0u8; SECTOR_SIZE];
+        for (i, &(kind, first, count)) in partitions.iter().enumerate() {
+            let entry = &mut mbr[446 + 16 * i..446 + 16 * (i + 1)];
+            entry[4] = kind;
+            entry[8..12].copy_from_slice(&first.to_le_bytes());
+            entry[12..16].copy_from_slice(&count.to_le_bytes());
+        }
+        mbr[510] = 0x55;
+        mbr[511] = 0xaa;
+        mbr
+    }
+
+    #[test]
+    fn test_overlaps_partition() {
+        let (start, end) = (10_000, 18_000);
+        assert!(!overlaps_partition(&mbr(&[]), start, end));
+        assert!(!overlaps_partition(&mbr(&[(0x0c, 2048, 7952)]), start, end));
+        assert!(overlaps_partition(&mbr(&[(0x0c, 2048, 7953)]), start, end));
+        assert!(overlaps_partition(&mbr(&[(0x0c, 2048, 100), (0x83, 17_999, 1)]), start, end));
+        assert!(!overlaps_partition(&mbr(&[(0x00, 2048, 20_000)]), start, end));
+        assert!(overlaps_partition(&mbr(&[(0xee, 1, u32::MAX)]), start, end));
+
+        let mut code = mbr(&[(0x0c, 2048, 20_000)]);
+        code[446] = 0x31;
+        assert!(!overlaps_partition(&code, start, end));
+        code[446] = 0x80;
+        assert!(overlaps_partition(&code, start, end));
+
+        let mut unformatted = mbr(&[(0x0c, 2048, 20_000)]);
+        unformatted[510] = 0;
+        assert!(!overlaps_partition(&unformatted, start, end));
+    }
+}
@@ -5,6 +5,7 @@ use core::sync::atomic::AtomicU32;
 use x86_64::PhysAddr;
 
 use crate::memory;
+use crate::smp::ProcessorId;
 
 use super::SDTHeader;
 
@@ -18,6 +19,25 @@ pub mod entry {
         pub acpi_id: u8,
         flags: u32,
     }
+    impl ProcessorLocalAPIC {
+        pub fn is_enabled(&self) -> bool {
+            self.flags & 1 != 0
+        }
+    }
+    /// Used instead of `ProcessorLocalAPIC` for APIC ids above 254
+    #[derive(Debug, Copy, Clone)]
+    #[repr(C, packed)]
+    pub struct ProcessorLocalX2APIC {
+        reserved: u16,
+        pub x2apic_id: u32,
+        flags: u32,
+        pub acpi_uid: u32,
+    }
+    impl ProcessorLocalX2APIC {
+        pub fn is_enabled(&self) -> bool {
+            self.flags & 1 != 0
+        }
+    }
     #[derive(Debug, Copy, Clone)]
     #[repr(C, packed)]
     pub struct IoAPIC {
@@ -48,11 +68,21 @@ pub mod entry {
         reserved: u16,
         local_apic_addr: u64,
     }
+    #[derive(Debug, Copy, Clone)]
+    #[repr(C, packed)]
+    pub struct LocalX2APICNonMaskableInterrupts {
+        flags: u16,
+        /// 0xffff_ffff for all processors
+        acpi_uid: u32,
+        lint: u8,
+        reserved: [u8; 3],
+    }
 }
 
 pub struct ACPIData {
     pub local_apic_addr: PhysAddr,
-    pub cpus: Vec<entry::ProcessorLocalAPIC>,
+    /// APIC ids of the enabled processors
+    pub cpus: Vec<ProcessorId>,
 }
 
 pub static ACPI_DATA: spin::Once<ACPIData> = spin::Once::new();
@@ -91,7 +121,9 @@ pub fn init() {
                 assert_eq!(entry_body_size, mem::size_of::<entry::ProcessorLocalAPIC>());
                 let entry: entry::ProcessorLocalAPIC = unsafe { *(ptr as *const _) };
                 log::trace!("{:#?}", entry);
-                cpus.push(entry);
+                if entry.is_enabled() {
+                    cpus.push(ProcessorId(entry.acpi_id as u32));
+                }
             },
             1 => {
                 assert_eq!(entry_body_size, mem::size_of::<entry::IoAPIC>());
@@ -122,6 +154,28 @@ pub fn init() {
                 let entry: entry::LocalAPICAddressOverride = unsafe { *(ptr as *const _) };
                 log::trace!("{:#?}", entry);
             },
+            9 => {
+                assert_eq!(
+                    entry_body_size,
+                    mem::size_of::<entry::ProcessorLocalX2APIC>()
+                );
+                let entry: entry::ProcessorLocalX2APIC = unsafe { *(ptr as *const _) };
+                log::trace!("{:#?}", entry);
+                let id = ProcessorId(entry.x2apic_id);
+                // Firmware can list a processor in both forms
+                if entry.is_enabled() && !cpus.contains(&id) {
+                    cpus.push(id);
+                }
+            },
+            0xa => {
+                assert_eq!(
+                    entry_body_size,
+                    mem::size_of::<entry::LocalX2APICNonMaskableInterrupts>()
+                );
+                let entry: entry::LocalX2APICNonMaskableInterrupts =
+                    unsafe { *(ptr as *const _) };
+                log::trace!("{:#?}", entry);
+            },
             other => panic!("Unknown APIC entry type {}", other),
         }
 
@@ -134,6 +188,7 @@ pub fn init() {
         assert!(ptr < table_end);
     }
 
+    log::info!("{} enabled processors", cpus.len());
     ACPI_DATA.call_once(|| ACPIData {
         local_apic_addr,
         cpus: cpus.clone(),
@@ -1,8 +1,16 @@
 //! Processor-local APIC
+//!
+//! If the processor supports it, the local APICs are used in x2APIC mode,
+//! where the registers are MSRs and the APIC ids are 32 bits wide, so that
+//! systems with more than 255 cores or sparse APIC ids can be used.
+//! Otherwise the registers are memory-mapped (xAPIC mode). All cores use
+//! the same mode, selected by the BSP.
 
 use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
 use x86_64::VirtAddr;
 
+use crate::cpuid;
 use crate::driver::acpi::ACPI_DATA;
 use crate::memory;
 use crate::smp::ProcessorId;
@@ -32,6 +40,7 @@ mod reg {
     pub const ERROR_STATUS: LapicReg = LapicReg(0x280);
     pub const LVT_CMCI: LapicReg = LapicReg(0x2f0);
     pub const INTERRUPT_COMMAND_BASE: LapicReg = LapicReg(0x300);
+    pub const INTERRUPT_COMMAND_HIGH: LapicReg = LapicReg(0x310);
     pub const LVT_TIMER: LapicReg = LapicReg(0x320);
     pub const LVT_THERMAL_SENSOR: LapicReg = LapicReg(0x330);
     pub const LVT_PM_COUNTERS: LapicReg = LapicReg(0x340);
@@ -43,7 +52,64 @@ mod reg {
     pub const TIMER_DIVIDE_CONFIG: LapicReg = LapicReg(0x3e0);
 }
 
-/// Address of the processor-local APIC
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_X2APIC_ENABLE: u64 = 1 << 10;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+/// MSR of the register at offset zero in x2APIC mode
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+/// Delivery status bit of the interrupt command register, xAPIC mode only
+const COMMAND_PENDING: u32 = 1 << 12;
+
+static X2APIC: AtomicBool = AtomicBool::new(false);
+
+pub fn is_x2apic() -> bool {
+    X2APIC.load(Ordering::SeqCst)
+}
+
+/// Selects the mode for all cores. Must be called by the BSP before `enable_mode`.
+pub fn select_mode() {
+    let x2apic = cpuid::has_x2apic();
+    X2APIC.store(x2apic, Ordering::SeqCst);
+    log::info!("Local APIC mode: {}", if x2apic { "x2APIC" } else { "xAPIC" });
+}
+
+/// Switches the local APIC of the current core to the selected mode.
+/// APs must call this before anything reads the APIC id, e.g. logging.
+pub fn enable_mode() {
+    if is_x2apic() {
+        unsafe {
+            let base = rdmsr(IA32_APIC_BASE);
+            wrmsr(
+                IA32_APIC_BASE,
+                base | APIC_BASE_ENABLE | APIC_BASE_X2APIC_ENABLE,
+            );
+        }
+    }
+}
+
+unsafe fn rdmsr(msr: u32) -> u64 {
+    let high: u32;
+    let low: u32;
+    asm!("rdmsr",
+        in("ecx") msr,
+        out("edx") high,
+        out("eax") low,
+        options(nostack, nomem)
+    );
+    ((high as u64) << 32) | (low as u64)
+}
+
+unsafe fn wrmsr(msr: u32, value: u64) {
+    asm!("wrmsr",
+        in("ecx") msr,
+        in("edx") (value >> 32) as u32,
+        in("eax") value as u32,
+        options(nostack, nomem)
+    );
+}
+
+/// Address of the processor-local APIC, in xAPIC mode
 pub fn addr() -> VirtAddr {
     let phys_addr = ACPI_DATA
         .r#try()
@@ -53,17 +119,65 @@ pub fn addr() -> VirtAddr {
     memory::phys_to_virt(phys_addr)
 }
 
+fn x2apic_msr(offset: reg::LapicReg) -> u32 {
+    X2APIC_MSR_BASE + (offset.get() >> 4) as u32
+}
+
 pub fn read_u32(offset: reg::LapicReg) -> u32 {
-    unsafe { ptr::read_volatile((addr().as_u64() + offset.get()) as *const u32) }
+    if is_x2apic() {
+        unsafe { rdmsr(x2apic_msr(offset)) as u32 }
+    } else {
+        unsafe { ptr::read_volatile((addr().as_u64() + offset.get()) as *const u32) }
+    }
 }
 
 pub fn write_u32(offset: reg::LapicReg, value: u32) {
-    unsafe { ptr::write_volatile((addr().as_u64() + offset.get()) as *mut u32, value) };
+    if is_x2apic() {
+        unsafe { wrmsr(x2apic_msr(offset), value as u64) };
+    } else {
+        unsafe { ptr::write_volatile((addr().as_u64() + offset.get()) as *mut u32, value) };
+    }
 }
 
 /// Get APIC ID of the current CPU
 pub fn processor_id() -> ProcessorId {
-    ProcessorId((read_u32(reg::LAPIC_ID) >> 24) as u8)
+    let value = read_u32(reg::LAPIC_ID);
+    if is_x2apic() {
+        ProcessorId(value)
+    } else {
+        ProcessorId(value >> 24)
+    }
+}
+
+/// Enables the local APIC, and sets the spurious interrupt vector to 0xff
+pub fn enable() {
+    // https://wiki.osdev.org/APIC#Spurious_Interrupt_Vector_Register
+    let value = read_u32(reg::SPURIOUS_IV);
+    write_u32(reg::SPURIOUS_IV, value | 0xff | 0x100);
+}
+
+/// Sends an IPI by writing the interrupt command register.
+/// `command` is the low half: vector, delivery mode and destination shorthand.
+pub fn send_command(destination: ProcessorId, command: u32) {
+    if is_x2apic() {
+        // A single 64-bit register, with the full APIC id in the high half
+        let value = ((destination.0 as u64) << 32) | (command as u64);
+        unsafe { wrmsr(x2apic_msr(reg::INTERRUPT_COMMAND_BASE), value) };
+    } else {
+        assert!(destination.0 <= 0xff, "APIC id {} needs x2APIC", destination);
+        write_u32(reg::INTERRUPT_COMMAND_HIGH, destination.0 << 24);
+        write_u32(reg::INTERRUPT_COMMAND_BASE, command);
+    }
+}
+
+/// Waits until the previous IPI has been accepted.
+/// In x2APIC mode the command register has no delivery status.
+pub fn wait_command_sent() {
+    if !is_x2apic() {
+        while read_u32(reg::INTERRUPT_COMMAND_BASE) & COMMAND_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
 }
 
 /// Tell LAPIC that the interrupt has been processed
@@ -4,7 +4,6 @@ use core::ptr;
 use core::sync::atomic::{AtomicBool, Ordering};
 use x86_64::{PhysAddr, VirtAddr};
 
-use crate::driver::acpi::ACPI_DATA;
 use crate::memory;
 use crate::smp::ProcessorId;
 
@@ -97,30 +96,15 @@ enum DeliveryMode {
 /// TODO: move to somewhere else?
 const STARTUP_CODE: &[u8] = include_bytes!("../../../build/smp_ap_startup.bin");
 
-fn enable_local_apic() {
-    // Enable APIC
-    let local_apic_addr = ACPI_DATA
-        .r#try()
-        .expect("acpi::init not called")
-        .local_apic_addr;
-
-    let addr = memory::phys_to_virt(local_apic_addr);
-
-    // https://wiki.osdev.org/APIC#Spurious_Interrupt_Vector_Register
-    let field = (addr.as_u64() + 0xf0) as *mut u32;
-    unsafe {
-        let value = ptr::read_volatile(field);
-        ptr::write_volatile(field, value | 0xff | 0x100);
-    }
-}
-
 /// Global IO APIC enable function, only ran by the BSP
 pub fn init_bsp() {
     // Disable old PICs
     crate::driver::pic::disable();
 
     // Do per-processor initialization
+    lapic::select_mode();
     per_processor_init();
+    crate::smp::set_bsp_id(lapic::processor_id());
 
     // Mark APIC as enabled
     APIC_ENABLED.store(true, Ordering::SeqCst);
@@ -138,85 +122,43 @@ pub fn init_bsp() {
 
 /// LAPIC initalization, done for each processor
 pub fn per_processor_init() {
-    enable_local_apic();
+    lapic::enable_mode();
+    lapic::enable();
     lapic::configure_timer(0x30);
 }
 
 /// Wake up a CPU Core
-pub fn apic_wakeup_processor(acpi_id: u8) {
-    let local_apic_addr = ACPI_DATA
-        .r#try()
-        .expect("acpi::init not called")
-        .local_apic_addr;
-
-    let addr = memory::phys_to_virt(local_apic_addr);
-
+pub fn apic_wakeup_processor(apic_id: ProcessorId) {
     // https://wiki.osdev.org/APIC#Interrupt_Command_Register
-    let field_lo = (addr.as_u64() + 0x300) as *mut u32;
-    let field_hi = (addr.as_u64() + 0x310) as *mut u32;
-
-    unsafe {
-        // Init IPI
-        log::trace!("Sending Init IPI to core {}", acpi_id);
-        ptr::write_volatile(field_hi, (acpi_id as u32) << 24);
-        ptr::write_volatile(field_lo, 0x00004500);
-
-        crate::driver::tsc::sleep_ns(10_000_000);
-
-        // Startup IPI
-        log::trace!("Sending Startup IPI to core {}", acpi_id);
-        // startup addr: 0x2000
-        // ^ TODO: constant for this
-        ptr::write_volatile(field_hi, (acpi_id as u32) << 24);
-        ptr::write_volatile(field_lo, 0x4600 | 0x0002);
-    }
-}
-
-pub fn send_ipi(acpi_id: u8, int_vector: u8, synchronous: bool) {
-    let local_apic_addr = ACPI_DATA
-        .r#try()
-        .expect("acpi::init not called")
-        .local_apic_addr;
+    log::trace!("Sending Init IPI to core {}", apic_id);
+    lapic::send_command(apic_id, 0x00004500);
 
-    let addr = memory::phys_to_virt(local_apic_addr);
+    crate::driver::tsc::sleep_ns(10_000_000);
 
-    // https://wiki.osdev.org/APIC#Interrupt_Command_Register
-    let field_lo = (addr.as_u64() + 0x300) as *mut u32;
-    let field_hi = (addr.as_u64() + 0x310) as *mut u32;
+    // Startup IPI
+    log::trace!("Sending Startup IPI to core {}", apic_id);
+    // startup addr: 0x2000
+    // ^ TODO: constant for this
+    lapic::send_command(apic_id, 0x4600 | 0x0002);
+}
 
-    unsafe {
-        log::trace!("Sending IPI to core {} (vector {})", acpi_id, int_vector);
-        ptr::write_volatile(field_hi, (acpi_id as u32) << 24);
-        ptr::write_volatile(field_lo, int_vector as u32);
+pub fn send_ipi(apic_id: ProcessorId, int_vector: u8, synchronous: bool) {
+    log::trace!("Sending IPI to core {} (vector {})", apic_id, int_vector);
+    lapic::send_command(apic_id, int_vector as u32);
 
-        if synchronous {
-            while ptr::read_volatile(field_lo) & (1 << 12) != 0 {}
-        }
+    if synchronous {
+        lapic::wait_command_sent();
     }
 }
 
 pub fn broadcast_ipi(include_self: bool, int_vector: u8) {
-    let local_apic_addr = ACPI_DATA
-        .r#try()
-        .expect("acpi::init not called")
-        .local_apic_addr;
-
-    let addr = memory::phys_to_virt(local_apic_addr);
-
-    // https://wiki.osdev.org/APIC#Interrupt_Command_Register
-    let field_lo = (addr.as_u64() + 0x300) as *mut u32;
-    let field_hi = (addr.as_u64() + 0x310) as *mut u32;
-
-    unsafe {
-        log::trace!(
-            "Broadcasting IPI (self: {}) (vector {})",
-            include_self,
-            int_vector
-        );
-
-        let mode: u32 = if include_self { 0b10 << 18 } else { 0b11 << 18 };
-
-        ptr::write_volatile(field_hi, 0u32);
-        ptr::write_volatile(field_lo, (int_vector as u32) | mode);
-    }
+    log::trace!(
+        "Broadcasting IPI (self: {}) (vector {})",
+        include_self,
+        int_vector
+    );
+
+    // The destination is given by the shorthand
+    let mode: u32 = if include_self { 0b10 << 18 } else { 0b11 << 18 };
+    lapic::send_command(ProcessorId(0), (int_vector as u32) | mode);
 }
@@ -14,6 +14,17 @@ pub fn freq_hz() -> u64 {
     value
 }
 
+/// TSC frequency, or `None` if `init` hasn't been called yet
+#[inline]
+pub fn try_freq_hz() -> Option<u64> {
+    let value = TSC_FREQ_HZ.load(Ordering::SeqCst);
+    if value == 0 {
+        None
+    } else {
+        Some(value)
+    }
+}
+
 /// Convert nanoseconds to TSC ticks
 pub fn ns_to_ticks(ns: u64) -> u64 {
     // Limit tick counts to one year
@@ -94,9 +105,9 @@ pub fn read() -> u64 {
     (rdx << 32) | (rax & 0xffff_ffff)
 }
 
-/// Sets deadline
+/// Sets deadline, as an absolute TSC value
 #[inline]
-fn set_deadline(deadline: u64) {
+pub fn set_deadline(deadline: u64) {
     // log::trace!("Set deadline {} (current {})", deadline, read());
     unsafe {
         asm!("wrmsr",
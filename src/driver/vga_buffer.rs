@@ -6,8 +6,8 @@ use volatile::Volatile;
 
 use crate::memory::prelude::{PhysAddr, VirtAddr};
 
-const SCREEN_HEIGHT: usize = 25;
-const SCREEN_WIDTH: usize = 80;
+pub const SCREEN_HEIGHT: usize = 25;
+pub const SCREEN_WIDTH: usize = 80;
 pub const VGA_BUFFER_ADDR_U64: u64 = 0xb8000;
 pub const VGA_BUFFER_PHYSADDR: PhysAddr = unsafe { PhysAddr::new_unchecked(VGA_BUFFER_ADDR_U64) };
 pub const VGA_BUFFER_VIRTADDR: VirtAddr = unsafe { VirtAddr::new_unsafe(VGA_BUFFER_ADDR_U64) };
@@ -273,6 +273,13 @@ impl Terminal<'static> {
         }
     }
 
+    /// Read a single cell, e.g. for screen capture
+    pub fn cell(&self, row: usize, col: usize) -> CharCell {
+        let b = self.get_buffer();
+        let buffer = unsafe { b.as_ref() };
+        buffer.chars[row][col].read()
+    }
+
     /// Get pointer to memory buffer
     fn get_buffer(&self) -> NonNull<Buffer> {
         unsafe { NonNull::new(self.buffer.as_mut_ptr()).unwrap() }
@@ -0,0 +1,171 @@
+//! Starting and supervising the first process.
+//!
+//! The programs in `INIT_PROGRAMS` are tried in order, until one of them is
+//! found from the initrd and is a valid executable. The reasons the earlier
+//! ones failed are logged, and can be read later from the `boot/init` kernel
+//! service. The kernel panics only if none of them can be started.
+//!
+//! The first process starts everything else, so the system is unusable
+//! without it. When it terminates outside of a shutdown, its result is logged
+//! and `INIT_FAILURE_ACTION` selects what happens: it is started again from
+//! the initrd on the next scheduler tick, the kernel panics, so that the panic
+//! action and crash dump apply, or the system is rebooted with an orderly
+//! shutdown. After `INIT_MAX_RESTARTS` restarts the kernel panics instead.
+
+use alloc::prelude::v1::*;
+use spin::Mutex;
+
+use d7abi::ipc::protocol::service::InitStatus;
+use d7abi::process::{ProcessId, ProcessResult};
+use d7abi::ShutdownAction;
+
+use crate::memory::constants::{INIT_FAILURE_ACTION, INIT_MAX_RESTARTS};
+use crate::memory::MemoryController;
+use crate::multitasking::process::{self, Privilege};
+use crate::multitasking::Scheduler;
+
+/// Initrd files to start, in order of preference. The service daemon starts
+/// everything else, and the console daemon is a fallback that at least shows
+/// the kernel log.
+const INIT_PROGRAMS: &[&str] = &["serviced", "consoled"];
+
+/// What to do when the first process terminates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureAction {
+    Restart,
+    Panic,
+    Reboot,
+}
+impl FailureAction {
+    fn configured() -> Self {
+        match INIT_FAILURE_ACTION {
+            0 => Self::Restart,
+            1 => Self::Panic,
+            2 => Self::Reboot,
+            other => panic!("Invalid INIT_FAILURE_ACTION {}", other),
+        }
+    }
+}
+
+struct Supervisor {
+    /// The running first process
+    pid: Option<ProcessId>,
+    status: Option<InitStatus>,
+    /// Start it again on the next tick
+    restart: bool,
+}
+
+static SUPERVISOR: Mutex<Supervisor> = Mutex::new(Supervisor {
+    pid: None,
+    status: None,
+    restart: false,
+});
+
+/// Starts the first program that can be loaded
+pub fn start(mm: &mut MemoryController, sched: &mut Scheduler) {
+    match spawn(mm, sched) {
+        Ok((pid, status)) => {
+            let mut supervisor = SUPERVISOR.lock();
+            supervisor.pid = Some(pid);
+            supervisor.status = Some(status);
+        },
+        Err(failed) => panic!("No init program could be started: {:?}", failed),
+    }
+}
+
+/// Tries the programs in order, and returns the pid and status
+/// of the one started, or why all of them failed
+fn spawn(
+    mm: &mut MemoryController, sched: &mut Scheduler,
+) -> Result<(ProcessId, InitStatus), Vec<(String, String)>> {
+    let mut failed = Vec::new();
+    for name in INIT_PROGRAMS {
+        let bytes = match crate::initrd::read(name) {
+            Some(bytes) => bytes,
+            None => {
+                failed.push((name.to_string(), "missing from initrd".to_owned()));
+                continue;
+            },
+        };
+        match process::load_elf(mm, bytes) {
+            Ok(elf) => {
+                if !failed.is_empty() {
+                    log::error!("Init programs failed, starting {}: {:?}", name, failed);
+                }
+                let pid = sched.spawn(mm, elf, Privilege::Full, None);
+                return Ok((pid, InitStatus {
+                    started: name.to_string(),
+                    failed,
+                    terminated: Vec::new(),
+                }));
+            },
+            Err(error) => {
+                failed.push((name.to_string(), format!("invalid executable: {:?}", error)));
+            },
+        }
+    }
+    Err(failed)
+}
+
+/// Cleanup hook, applies `INIT_FAILURE_ACTION` if the first process terminated
+pub fn on_process_over(sched: &mut Scheduler, pid: ProcessId, result: &ProcessResult) {
+    let mut supervisor = SUPERVISOR.lock();
+    if supervisor.pid != Some(pid) {
+        return;
+    }
+    supervisor.pid = None;
+    if sched.is_shutting_down() {
+        return;
+    }
+
+    log::error!("Init process (pid {}) terminated: {:?}", pid, result);
+    let status = supervisor.status.as_mut().expect("Init process not started");
+    status.terminated.push(result.clone());
+    let restarts = status.terminated.len() as u64 - 1;
+
+    let mut action = FailureAction::configured();
+    if action == FailureAction::Restart && restarts >= INIT_MAX_RESTARTS {
+        log::error!("Init process already restarted {} times", restarts);
+        action = FailureAction::Panic;
+    }
+    match action {
+        FailureAction::Restart => supervisor.restart = true,
+        FailureAction::Panic => {
+            drop(supervisor);
+            panic!("Init process terminated: {:?}", result);
+        },
+        FailureAction::Reboot => {
+            sched.begin_shutdown(ShutdownAction::Reboot, pid);
+        },
+    }
+}
+
+/// Starts the first process again, if requested by `on_process_over`.
+/// Called from the scheduler tick, as cleanup hooks may run while
+/// the memory controller is in use.
+pub fn on_tick(sched: &mut Scheduler) {
+    let mut supervisor = SUPERVISOR.lock();
+    if !supervisor.restart {
+        return;
+    }
+    supervisor.restart = false;
+
+    match crate::memory::configure(|mm| spawn(mm, sched)) {
+        Ok((pid, new_status)) => {
+            log::warn!("Restarted init process {} as pid {}", new_status.started, pid);
+            let status = supervisor.status.as_mut().expect("Init process not started");
+            status.started = new_status.started;
+            status.failed = new_status.failed;
+            supervisor.pid = Some(pid);
+        },
+        Err(failed) => {
+            drop(supervisor);
+            panic!("No init program could be restarted: {:?}", failed);
+        },
+    }
+}
+
+/// Status of the first process, once started
+pub fn status() -> Option<InitStatus> {
+    SUPERVISOR.lock().status.clone()
+}
@@ -7,7 +7,7 @@ use x86_64::{PrivilegeLevel, VirtAddr};
 
 pub use x86_64::structures::gdt::Descriptor;
 
-use crate::memory::constants::GDT_ADDR;
+use crate::memory::constants::{GDT_ADDR, TSS_ADDR};
 
 pub const DOUBLE_FAULT_IST_INDEX: usize = 0;
 
@@ -67,5 +67,10 @@ pub fn create_new() -> GdtBuilder {
     let index = USED_GDTS.fetch_add(1, Ordering::SeqCst);
     let new_gdt_base =
         GDT_ADDR.as_u64() + (index as u64) * (GDT_MAX_SIZE * size_of::<u64>()) as u64;
+    assert!(
+        new_gdt_base + (GDT_MAX_SIZE * size_of::<u64>()) as u64 <= TSS_ADDR.as_u64(),
+        "No space for the GDT of core {}",
+        index
+    );
     unsafe { GdtBuilder::new(VirtAddr::new(new_gdt_base)) }
 }
@@ -1,14 +1,21 @@
+use core::mem::size_of;
 use core::sync::atomic::Ordering;
 use x86_64::structures::idt::{InterruptStackFrame, InterruptStackFrameValue, PageFaultErrorCode};
 use x86_64::{PhysAddr, VirtAddr};
 
-use crate::driver::pic;
+use d7abi::input_ring::{self, InputEvent};
+
+use crate::driver::{pic, tsc};
+use crate::latency::IrqTimer;
 use crate::multitasking::{
     process, Process, ProcessId, ProcessSwitch, SCHEDULER, SCHEDULER_ENABLED,
 };
 use crate::smp;
 use crate::syscall::RawSyscall;
 
+use super::registers::RegisterState;
+use super::stats;
+
 /// Breakpoint handler
 pub(super) unsafe fn exception_bp(stack_frame: &InterruptStackFrame) {
     rforce_unlock!();
@@ -99,6 +106,8 @@ pub(super) unsafe fn exception_snp(stack_frame: &InterruptStackFrame, error_code
 /// LAPIC TSC-deadline timer ticked
 pub(super) unsafe extern "sysv64" fn exception_tsc_deadline() -> u128 {
     // log::trace!("TSC_DEADLINE");
+    stats::record(0x30);
+    let timer = IrqTimer::start(0x30);
     crate::driver::ioapic::lapic::write_eoi();
 
     if crate::smp::is_bsp() && SCHEDULER_ENABLED.load(Ordering::SeqCst) {
@@ -106,8 +115,8 @@ pub(super) unsafe extern "sysv64" fn exception_tsc_deadline() -> u128 {
             let mut sched = SCHEDULER.try_lock().expect("SCHEDUELR LOCKED");
             sched.tick()
         };
+        drop(timer);
 
-        crate::driver::tsc::set_deadline_ns(1_000_000);
         match next_process {
             ProcessSwitch::Switch(p) => return_process(p),
             ProcessSwitch::RepeatSyscall(p) => {
@@ -127,6 +136,8 @@ pub(super) unsafe extern "sysv64" fn exception_tsc_deadline() -> u128 {
 
 /// PIT timer ticked while the kernel was running
 pub(super) unsafe fn exception_irq0() {
+    stats::record(0x20);
+    let _timer = IrqTimer::start(0x20);
     crate::driver::pit::callback();
     pic::PICS.try_lock().unwrap().notify_eoi(0x20);
 }
@@ -134,6 +145,8 @@ pub(super) unsafe fn exception_irq0() {
 /// First ps/2 device, keyboard, sent data.
 /// Read the byte and then send it to the keyboard driver.
 pub(super) unsafe fn exception_irq1() {
+    stats::record(0x21);
+    let _timer = IrqTimer::start(0x21);
     let mut port_ps2_data = cpuio::UnsafePort::<u8>::new(0x60);
     let mut port_ps2_status = cpuio::UnsafePort::<u8>::new(0x64);
 
@@ -147,9 +160,14 @@ pub(super) unsafe fn exception_irq1() {
     // Read byte
     let byte = port_ps2_data.read();
 
-    // Send to driver
+    // Send to driver, and to the processes reading the input ring
     let mut sched = SCHEDULER.try_lock().unwrap();
     crate::ipc::kernel_publish(&mut sched, "irq/keyboard", &byte);
+    sched.deliver_input(InputEvent {
+        tsc: tsc::read(),
+        source: input_ring::SOURCE_KEYBOARD,
+        value: byte as u32,
+    });
 
     // Interrupt over
     pic::PICS.lock().notify_eoi(0x21);
@@ -157,6 +175,8 @@ pub(super) unsafe fn exception_irq1() {
 
 /// First ATA device is ready for data transfer
 pub(super) unsafe fn exception_irq14() {
+    stats::record(0x2e);
+    let _timer = IrqTimer::start(0x2e);
     // Since we are polling the drive, just ignore the IRQ
     pic::PICS.lock().notify_eoi(0x2e);
 }
@@ -168,9 +188,13 @@ pub(super) unsafe fn exception_irq7() {
     // Check if this is a real IRQ
     let is_real = pics.read_isr() & (1 << 7) != 0;
     if is_real {
+        stats::record(0x27);
+        let _timer = IrqTimer::start(0x27);
         pic::PICS.lock().notify_eoi(0x27);
+    } else {
+        // Ignore spurious interrupts
+        stats::record_spurious(7);
     }
-    // Ignore spurious interrupts
 }
 
 /// (Possibly) spurious interrupt for the secondary PIC
@@ -180,15 +204,20 @@ pub(super) unsafe fn exception_irq15() {
     // Check if this is a real IRQ
     let is_real = pics.read_isr() & (1 << 15) != 0;
     if is_real {
+        stats::record(0x2f);
+        let _timer = IrqTimer::start(0x2f);
         pics.notify_eoi(0x2f);
     } else {
         // Inform primary PIC about spurious interrupt
+        stats::record_spurious(15);
         pics.notify_eoi_primary();
     }
 }
 
 /// Free IRQs, i.e. {9,10,11} for peripherals
 pub(super) unsafe fn exception_irq_free(interrupt: u8) {
+    stats::record(interrupt);
+    let _timer = IrqTimer::start(interrupt);
     let irq = interrupt - 0x20;
     log::info!("Triggering free IRQ {:02x}", irq);
 
@@ -286,13 +315,20 @@ unsafe extern "C" fn process_interrupt_inner(
     let page_table = PhysAddr::new_unchecked(page_table);
     let process_stack = VirtAddr::new_unsafe(process_stack);
 
-    let pid = {
+    let (pid, stack_pages_missing) = {
         let mut sched = SCHEDULER.try_lock().unwrap();
         let pid = sched.get_running_pid().expect("No process running?");
         sched.store_state(pid, page_table, process_stack);
-        pid
+        let process = sched.process_by_id_mut(pid).expect("Process not found");
+        (pid, process.stack_pages_missing(process_stack))
     };
 
+    // Interrupts push to the process stack, so keep it mapped below the stack pointer.
+    // The memory controller is only locked when the stack has to grow.
+    if stack_pages_missing.map_or(false, |pages| pages > 0) {
+        use_stack(pid, process_stack);
+    }
+
     macro_rules! handle_switch {
         ($next_process:expr) => {{
             match $next_process {
@@ -332,17 +368,20 @@ unsafe extern "C" fn process_interrupt_inner(
             // TSC deadline
 
             // log::trace!("TSC_DEADLINE");
+            stats::record(0x30);
+            let timer = IrqTimer::start(0x30);
             crate::driver::ioapic::lapic::write_eoi();
 
             assert!(SCHEDULER_ENABLED.load(Ordering::SeqCst)); // TODO: remove
             if crate::smp::is_bsp() {
-                crate::driver::tsc::set_deadline_ns(1_000_000);
                 let switch_target = {
                     let mut sched = SCHEDULER.try_lock().expect("SCHEDUELR LOCKED");
                     sched.tick()
                 };
+                drop(timer);
                 handle_switch!(switch_target);
             } else {
+                drop(timer);
                 handle_switch!(ProcessSwitch::Idle);
             }
         },
@@ -369,22 +408,24 @@ unsafe extern "C" fn process_interrupt_inner(
         },
         0x00 => fail(pid, process::Error::DivideByZero(stack_frame)),
         0x0e => {
-            // TODO:
-            // * Error code, if any, must be removed from the stack before returning
-            fail(
-                pid,
-                process::Error::PageFault(
-                    stack_frame,
-                    Cr2::read(),
-                    PageFaultErrorCode::from_bits(error_code as u64)
-                        .expect("Invalid page fault error code"),
-                ),
-            )
+            let address = Cr2::read();
+            let code = PageFaultErrorCode::from_bits(error_code as u64)
+                .expect("Invalid page fault error code");
+
+            // Accessing the stack area below the mapped stack grows the stack
+            if code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) || !use_stack(pid, address) {
+                fail(pid, process::Error::PageFault(stack_frame, address, code))
+            }
+            let process_stack = remove_error_code(pid, page_table, process_stack);
+            return process_pair_to_u128(process_stack, page_table);
         },
         0x08 | 0x0a | 0x0b | 0x0c | 0x0d | 0x11 | 0x1e => fail(
             pid,
             process::Error::InterruptWithCode(interrupt, stack_frame, error_code),
         ),
+        vector if super::vectors::is_dynamic(vector) => {
+            super::vectors::dispatch(vector);
+        },
         _ => fail(pid, process::Error::Interrupt(interrupt, stack_frame)),
     }
 
@@ -392,6 +433,41 @@ unsafe extern "C" fn process_interrupt_inner(
     process_pair_to_u128(process_stack, page_table)
 }
 
+/// Records stack use of a process, and grows its stack when needed.
+/// Returns `false` if the address is not in the stack area of the process.
+fn use_stack(pid: ProcessId, address: VirtAddr) -> bool {
+    crate::memory::configure(|mm| {
+        let mut sched = SCHEDULER.try_lock().unwrap();
+        let process = sched.process_by_id_mut(pid).expect("Process not found");
+        process.use_stack(mm, address)
+    })
+}
+
+/// Removes the exception error code from the process stack, so that the
+/// process can be continued. The registers and the interrupt entry address
+/// pushed by `process_common.asm` are moved over it.
+/// Returns the new process stack pointer.
+fn remove_error_code(pid: ProcessId, page_table: PhysAddr, process_stack: VirtAddr) -> VirtAddr {
+    /// Registers in `push_all`, and the interrupt entry address
+    const ITEMS: usize = size_of::<RegisterState>() / 8 + 1;
+
+    crate::memory::configure(|mm| {
+        let mut sched = SCHEDULER.try_lock().unwrap();
+        let process = sched.process_by_id(pid).expect("Process not found");
+        let (area, slice) = unsafe {
+            mm.process_slice_mut(process, ((ITEMS + 1) * 8) as u64, process_stack)
+                .expect("Process stack not mapped")
+        };
+        slice.copy_within(..ITEMS * 8, 8);
+        unsafe { mm.unmap_area(area) };
+        mm.free_virtual_area(area);
+
+        let process_stack = process_stack + 8u64;
+        sched.store_state(pid, page_table, process_stack);
+        process_stack
+    })
+}
+
 fn fail(pid: ProcessId, error: process::Error) -> ! {
     terminate(pid, process::ProcessResult::Failed(error))
 }
@@ -440,6 +516,7 @@ fn idle() -> ! {
 fn immediate_switch_to(process: Process) -> ! {
     use crate::memory::process_common_code::COMMON_ADDRESS_VIRT;
 
+    process.load_fs_base();
     unsafe {
         asm!("
             mov rcx, [rcx]  // Get procedure offset
@@ -493,6 +570,7 @@ unsafe fn handle_repeat_syscall(p: Process) -> Option<Process> {
 /// If no switch should be done then function must return `(0, 0)`.
 #[inline]
 fn return_process(p: Process) -> u128 {
+    p.load_fs_base();
     process_pair_to_u128(p.stack_pointer, p.page_table.p4_addr())
 }
 
@@ -12,14 +12,20 @@ use core::fmt;
 use core::mem::{self, MaybeUninit};
 use core::ptr;
 
+use alloc::string::String;
+
 use crate::memory::{self, MemoryController};
+use crate::smp::ProcessorId;
 
 #[macro_use]
 mod macros;
 mod gdt;
 mod handler;
 pub mod idt;
+pub mod registers;
+pub mod stats;
 mod tss;
+pub mod vectors;
 
 use self::handler::*;
 
@@ -115,27 +121,32 @@ pub fn init() {
     handlers[0xdd] = exception_handler!(ipi_panic);
 
     for index in 0..idt::ENTRY_COUNT {
-        log::trace!(
-            "WRITE IDT {:x} @ {:04x}",
-            index,
-            idt::ADDRESS + index * mem::size_of::<idt::Descriptor>()
-        );
         unsafe {
-            ptr::write_volatile(
-                (idt::ADDRESS + index * mem::size_of::<idt::Descriptor>()) as *mut _,
-                handlers[index],
-            );
+            write_idt_entry(index, handlers[index]);
         }
     }
 
     load_idt();
 }
 
+/// Overwrites an entry of the kernel IDT, which all cores share
+unsafe fn write_idt_entry(index: usize, descriptor: idt::Descriptor) {
+    log::trace!(
+        "WRITE IDT {:x} @ {:04x}",
+        index,
+        idt::ADDRESS + index * mem::size_of::<idt::Descriptor>()
+    );
+    ptr::write_volatile(
+        (idt::ADDRESS + index * mem::size_of::<idt::Descriptor>()) as *mut _,
+        descriptor,
+    );
+}
+
 /// SMP AP just reuses the kernel IVT,
 /// but has own GDT and TSS
-pub fn init_smp_ap() {
+pub fn init_smp_ap(processor_id: ProcessorId) {
     load_idt();
-    init_gdt_and_tss();
+    init_gdt_and_tss(format!("double fault {}", processor_id));
 }
 
 fn load_idt() {
@@ -153,7 +164,7 @@ fn load_idt() {
 
 /// Called on BSP after the memory module (i.e. paging) has been initialized
 pub fn init_after_memory() {
-    init_gdt_and_tss();
+    init_gdt_and_tss(String::from("double fault bsp"));
     unsafe {
         // Write syscall address
         ptr::write(
@@ -163,12 +174,16 @@ pub fn init_after_memory() {
     }
 }
 
-fn init_gdt_and_tss() {
+/// `stack_name` identifies the double fault stack in `memory::kernel_stacks`
+fn init_gdt_and_tss(stack_name: String) {
     // Initialize TSS
     let double_fault_stack = memory::configure(|mem_ctrl: &mut MemoryController| {
-        mem_ctrl
-            .alloc_stack(1)
-            .expect("could not allocate double fault stack")
+        memory::kernel_stacks::alloc(
+            mem_ctrl,
+            stack_name.clone(),
+            memory::constants::DOUBLE_FAULT_STACK_SIZE_PAGES as usize,
+        )
+        .expect("could not allocate double fault stack")
     });
 
     let tss = tss::store({
@@ -0,0 +1,100 @@
+//! Register state of a process, as stored in its stack by `process_common.asm`.
+//!
+//! `push_all` pushes the general purpose registers when the process is
+//! interrupted, and `pop_all` restores them before `iretq`, so the field
+//! order of `RegisterState` must match those macros, `rax` being at the
+//! lowest address. Rust code accesses the registers through these types
+//! instead of raw stack offsets.
+//!
+//! The asm is not visible to the Rust compiler, so `STORED_REGISTERS` is a
+//! hand-kept copy of `stack_stored_registers`, and must be updated with it.
+//! The assertions below only check the sizes against that copy, and the
+//! tests check the field offsets against the order of `pop_all`.
+
+use core::mem::{align_of, size_of};
+
+/// Number of registers pushed by `push_all`, `stack_stored_registers` in the asm
+pub const STORED_REGISTERS: usize = 15;
+
+/// General purpose registers, in `pop_all` order
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RegisterState {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rbp: u64,
+}
+
+/// Frame popped by `iretq`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IretFrame {
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Stack of a process that is not running, as `switch_to` pops it.
+/// When a process is interrupted, `entry` is the return address of the
+/// interrupt table call, and the error code of an exception, if any,
+/// is between it and `frame` until removed.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SwitchStack {
+    pub registers: RegisterState,
+    pub entry: u64,
+    pub frame: IretFrame,
+}
+
+static_assertions::const_assert_eq!(size_of::<RegisterState>(), STORED_REGISTERS * 8);
+static_assertions::const_assert_eq!(align_of::<RegisterState>(), 8);
+static_assertions::const_assert_eq!(size_of::<IretFrame>(), 5 * 8);
+static_assertions::const_assert_eq!(size_of::<SwitchStack>(), (STORED_REGISTERS + 1 + 5) * 8);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn offset<T, F>(base: &T, field: &F) -> usize {
+        field as *const F as usize - base as *const T as usize
+    }
+
+    #[test]
+    fn test_register_offsets() {
+        let r = RegisterState::default();
+        let order = [
+            &r.rax, &r.rbx, &r.rcx, &r.rdx, &r.rsi, &r.rdi, &r.r8, &r.r9, &r.r10, &r.r11, &r.r12,
+            &r.r13, &r.r14, &r.r15, &r.rbp,
+        ];
+        assert_eq!(order.len(), STORED_REGISTERS);
+        for (i, field) in order.iter().enumerate() {
+            assert_eq!(offset(&r, *field), i * 8);
+        }
+        // Used by `handle_syscall`
+        assert_eq!(offset(&r, &r.rdi), 5 * 8);
+        assert_eq!(offset(&r, &r.rsi), 4 * 8);
+    }
+
+    #[test]
+    fn test_switch_stack_offsets() {
+        let s = SwitchStack::default();
+        assert_eq!(offset(&s, &s.entry), STORED_REGISTERS * 8);
+        assert_eq!(offset(&s, &s.frame.rip), (STORED_REGISTERS + 1) * 8);
+        assert_eq!(offset(&s, &s.frame.rsp), (STORED_REGISTERS + 1 + 3) * 8);
+        assert_eq!(offset(&s, &s.frame.ss), (STORED_REGISTERS + 1 + 4) * 8);
+    }
+}
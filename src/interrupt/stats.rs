@@ -0,0 +1,153 @@
+//! Per-core interrupt counters, for diagnosing interrupt storms.
+//!
+//! Counts are kept for the hardware interrupt vectors `FIRST_VECTOR..=LAST_VECTOR`,
+//! i.e. the PIC IRQs and the TSC deadline timer. Spurious PIC interrupts are
+//! counted separately. The counters are plain statics so that interrupts can be
+//! counted before the heap is available.
+//!
+//! If a vector fires more than `INTERRUPT_STORM_THRESHOLD` times within a second,
+//! a warning is logged, at most once per second.
+
+use alloc::prelude::v1::*;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::driver::tsc;
+use crate::memory::constants::INTERRUPT_STORM_THRESHOLD;
+use crate::smp;
+
+pub const FIRST_VECTOR: u8 = 0x20;
+pub const LAST_VECTOR: u8 = 0x30;
+const VECTOR_COUNT: usize = (LAST_VECTOR - FIRST_VECTOR) as usize + 1;
+
+/// Interrupts on cores with higher ids are not counted
+const MAX_CORES: usize = 32;
+
+/// Indexed by `[core][vector - FIRST_VECTOR]`. Only accessed through `counter`.
+static mut COUNTS: [[u64; VECTOR_COUNT]; MAX_CORES] = [[0; VECTOR_COUNT]; MAX_CORES];
+/// Indexed by `[core][0 for IRQ7, 1 for IRQ15]`. Only accessed through `counter`.
+static mut SPURIOUS: [[u64; 2]; MAX_CORES] = [[0; 2]; MAX_CORES];
+
+/// Start of the current rate window in TSC ticks, per vector
+static mut WINDOW_START: [u64; VECTOR_COUNT] = [0; VECTOR_COUNT];
+/// Interrupts in the current rate window, per vector
+static mut WINDOW_COUNT: [u64; VECTOR_COUNT] = [0; VECTOR_COUNT];
+
+/// `AtomicU64` has the same in-memory representation as `u64`.
+/// Also used for the histograms in `latency`.
+pub(crate) fn counter(value: *mut u64) -> &'static AtomicU64 {
+    unsafe { &*(value as *const AtomicU64) }
+}
+
+/// Called from the interrupt handlers of the counted vectors
+pub fn record(vector: u8) {
+    if !(FIRST_VECTOR..=LAST_VECTOR).contains(&vector) {
+        return;
+    }
+    let index = (vector - FIRST_VECTOR) as usize;
+    let core = smp::current_processor_id().0 as usize;
+    if core < MAX_CORES {
+        counter(unsafe { &mut COUNTS[core][index] }).fetch_add(1, Ordering::Relaxed);
+    }
+    check_rate(vector, index);
+}
+
+/// Called when a PIC interrupt turns out to be spurious
+pub fn record_spurious(irq: u8) {
+    let index = match irq {
+        7 => 0,
+        15 => 1,
+        _ => panic!("IRQ{} cannot be spurious", irq),
+    };
+    let core = smp::current_processor_id().0 as usize;
+    if core < MAX_CORES {
+        counter(unsafe { &mut SPURIOUS[core][index] }).fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn check_rate(vector: u8, index: usize) {
+    // TSC frequency is measured using the PIT, which is interrupting already
+    let freq = match tsc::try_freq_hz() {
+        Some(f) => f,
+        None => return,
+    };
+
+    let start = counter(unsafe { &mut WINDOW_START[index] });
+    let count = counter(unsafe { &mut WINDOW_COUNT[index] });
+
+    let now = tsc::read();
+    let window_start = start.load(Ordering::Relaxed);
+    if now.saturating_sub(window_start) < freq {
+        count.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    // Window over, start a new one
+    if start
+        .compare_exchange(window_start, now, Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+    {
+        let total = count.swap(1, Ordering::Relaxed);
+        if total > INTERRUPT_STORM_THRESHOLD {
+            log::warn!(
+                "Possible interrupt storm: vector {:#04x} fired {} times in {} ms",
+                vector,
+                total,
+                (now - window_start) / (freq / 1000)
+            );
+        }
+    }
+}
+
+/// Interrupt counts as a text table, one row per vector that has fired,
+/// and one column per core that has received interrupts
+pub fn table() -> Vec<String> {
+    let read = |value: *mut u64| counter(value).load(Ordering::Relaxed);
+
+    let counts: Vec<[u64; VECTOR_COUNT]> = (0..MAX_CORES)
+        .map(|core| {
+            let mut row = [0; VECTOR_COUNT];
+            for (i, c) in row.iter_mut().enumerate() {
+                *c = read(unsafe { &mut COUNTS[core][i] });
+            }
+            row
+        })
+        .collect();
+    let spurious: Vec<[u64; 2]> = (0..MAX_CORES)
+        .map(|core| {
+            [
+                read(unsafe { &mut SPURIOUS[core][0] }),
+                read(unsafe { &mut SPURIOUS[core][1] }),
+            ]
+        })
+        .collect();
+
+    let cores: Vec<usize> = (0..MAX_CORES)
+        .filter(|&core| counts[core].iter().chain(spurious[core].iter()).any(|&c| c != 0))
+        .collect();
+
+    let mut lines = Vec::new();
+    let mut header = format!("{:<16}", "vector");
+    for core in &cores {
+        header.push_str(&format!(" {:>12}", format!("cpu{}", core)));
+    }
+    lines.push(header);
+
+    let mut push_row = |name: String, value: &dyn Fn(usize) -> u64| {
+        if cores.iter().any(|&core| value(core) != 0) {
+            let mut line = format!("{:<16}", name);
+            for &core in &cores {
+                line.push_str(&format!(" {:>12}", value(core)));
+            }
+            lines.push(line);
+        }
+    };
+
+    for i in 0..VECTOR_COUNT {
+        let vector = FIRST_VECTOR + i as u8;
+        push_row(format!("{:#04x}", vector), &|core| counts[core][i]);
+    }
+    push_row("irq7 spurious".to_owned(), &|core| spurious[core][0]);
+    push_row("irq15 spurious".to_owned(), &|core| spurious[core][1]);
+
+    lines
+}
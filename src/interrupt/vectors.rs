@@ -0,0 +1,284 @@
+//! Interrupt vectors allocated at runtime for kernel drivers.
+//!
+//! Free vectors in `FIRST_VECTOR..=LAST_VECTOR` are handed out by `allocate`,
+//! and the handler is installed into the IDT. Vectors with a fixed use are
+//! never handed out. Each vector belongs to a driver, identified by name,
+//! which can hold it multiple times, e.g. once per device instance.
+//! The vector is freed when the last reference is released, or when the
+//! driver deregisters. `smp` allocates the vector of its ping IPI here.
+//!
+//! Interrupts arriving while a process runs are dispatched from
+//! `handler::process_interrupt_inner`, and the rest by the IDT stubs here.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::PrivilegeLevel;
+
+use super::idt;
+
+pub const FIRST_VECTOR: u8 = 0x30;
+pub const LAST_VECTOR: u8 = 0xef;
+
+/// Vectors in the range that are bound when the IDT is created
+const RESERVED: &[u8] = &[
+    0x30, // TSC deadline
+    0xd7, // System calls
+    0xdd, // Panic IPI
+];
+
+/// Handler of an allocated vector, called with the vector number.
+/// The allocated vectors are raised by devices and other cores,
+/// which never push an error code, so there is none to pass on.
+pub type Handler = fn(u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorError {
+    /// All vectors in the range are in use
+    Exhausted,
+    /// The vector is not allocated
+    NotAllocated,
+    /// The vector is allocated to another driver
+    NotOwner,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    owner: &'static str,
+    handler: Handler,
+    refcount: usize,
+}
+
+/// Allocations, indexed by vector. Kept separate from the IDT.
+struct Table {
+    entries: [Option<Entry>; idt::ENTRY_COUNT],
+}
+impl Table {
+    const fn new() -> Self {
+        Self {
+            entries: [None; idt::ENTRY_COUNT],
+        }
+    }
+
+    fn allocate(&mut self, owner: &'static str, handler: Handler) -> Result<u8, VectorError> {
+        let vector = (FIRST_VECTOR..=LAST_VECTOR)
+            .find(|&v| is_dynamic(v) && self.entries[v as usize].is_none())
+            .ok_or(VectorError::Exhausted)?;
+        self.entries[vector as usize] = Some(Entry {
+            owner,
+            handler,
+            refcount: 1,
+        });
+        Ok(vector)
+    }
+
+    fn acquire(&mut self, owner: &'static str, vector: u8) -> Result<(), VectorError> {
+        let entry = self.owned_entry(owner, vector)?;
+        entry.refcount += 1;
+        Ok(())
+    }
+
+    fn release(&mut self, owner: &'static str, vector: u8) -> Result<bool, VectorError> {
+        let entry = self.owned_entry(owner, vector)?;
+        entry.refcount -= 1;
+        if entry.refcount > 0 {
+            return Ok(false);
+        }
+        self.free(vector);
+        Ok(true)
+    }
+
+    fn deregister(&mut self, owner: &'static str) -> usize {
+        let mut count = 0;
+        for vector in FIRST_VECTOR..=LAST_VECTOR {
+            if self.entries[vector as usize].map_or(false, |e| e.owner == owner) {
+                self.free(vector);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn owned_entry(
+        &mut self, owner: &'static str, vector: u8,
+    ) -> Result<&mut Entry, VectorError> {
+        let entry = self.entries[vector as usize]
+            .as_mut()
+            .ok_or(VectorError::NotAllocated)?;
+        if entry.owner != owner {
+            return Err(VectorError::NotOwner);
+        }
+        Ok(entry)
+    }
+
+    /// The stub stays in the IDT, so that an interrupt still in flight
+    /// doesn't fault on a missing entry
+    fn free(&mut self, vector: u8) {
+        let entry = self.entries[vector as usize]
+            .take()
+            .expect("Vector not allocated");
+        log::debug!("Freed interrupt vector {:#x} of {}", vector, entry.owner);
+    }
+
+    fn handler(&self, vector: u8) -> Option<Handler> {
+        self.entries[vector as usize].map(|e| e.handler)
+    }
+}
+
+/// Modified with interrupts disabled, so that a dispatch
+/// on the same core cannot wait for the lock.
+static VECTORS: Mutex<Table> = Mutex::new(Table::new());
+
+type Stub = unsafe extern "x86-interrupt" fn(&mut InterruptStackFrame);
+
+macro_rules! stub {
+    ($vector:expr) => {{
+        unsafe extern "x86-interrupt" fn wrapper(_: &mut InterruptStackFrame) {
+            dispatch($vector);
+        }
+        wrapper as Stub
+    }};
+}
+
+#[rustfmt::skip]
+macro_rules! stub_row {
+    ($high:expr) => {[
+        stub!($high + 0x0), stub!($high + 0x1), stub!($high + 0x2), stub!($high + 0x3),
+        stub!($high + 0x4), stub!($high + 0x5), stub!($high + 0x6), stub!($high + 0x7),
+        stub!($high + 0x8), stub!($high + 0x9), stub!($high + 0xa), stub!($high + 0xb),
+        stub!($high + 0xc), stub!($high + 0xd), stub!($high + 0xe), stub!($high + 0xf),
+    ]};
+}
+
+/// Kernel IDT entry points of the range, indexed by `[high nibble - 3][low nibble]`
+#[rustfmt::skip]
+static STUBS: [[Stub; 0x10]; 0xc] = [
+    stub_row!(0x30), stub_row!(0x40), stub_row!(0x50), stub_row!(0x60),
+    stub_row!(0x70), stub_row!(0x80), stub_row!(0x90), stub_row!(0xa0),
+    stub_row!(0xb0), stub_row!(0xc0), stub_row!(0xd0), stub_row!(0xe0),
+];
+
+fn stub_descriptor(vector: u8) -> idt::Descriptor {
+    let index = (vector - FIRST_VECTOR) as usize;
+    let stub = STUBS[index / 0x10][index % 0x10];
+    idt::Descriptor::new(true, stub as u64, PrivilegeLevel::Ring0, None)
+}
+
+/// Allocates a free vector for `owner`, and installs the handler
+pub fn allocate(owner: &'static str, handler: Handler) -> Result<u8, VectorError> {
+    interrupts::without_interrupts(|| {
+        let vector = VECTORS.lock().allocate(owner, handler)?;
+        unsafe { super::write_idt_entry(vector as usize, stub_descriptor(vector)) };
+        log::debug!("Allocated interrupt vector {:#x} to {}", vector, owner);
+        Ok(vector)
+    })
+}
+
+/// Takes another reference to a vector already allocated to `owner`
+pub fn acquire(owner: &'static str, vector: u8) -> Result<(), VectorError> {
+    interrupts::without_interrupts(|| VECTORS.lock().acquire(owner, vector))
+}
+
+/// Releases a reference to a vector. Returns whether the vector was freed.
+pub fn release(owner: &'static str, vector: u8) -> Result<bool, VectorError> {
+    interrupts::without_interrupts(|| VECTORS.lock().release(owner, vector))
+}
+
+/// Frees all vectors of `owner`, regardless of their references.
+/// Returns the number of freed vectors.
+pub fn deregister(owner: &'static str) -> usize {
+    interrupts::without_interrupts(|| VECTORS.lock().deregister(owner))
+}
+
+/// Whether `vector` can be allocated, i.e. whether interrupts on it go to `dispatch`
+pub fn is_dynamic(vector: u8) -> bool {
+    (FIRST_VECTOR..=LAST_VECTOR).contains(&vector) && !RESERVED.contains(&vector)
+}
+
+/// Calls the handler of an allocated vector, and signals the end of the interrupt.
+/// An interrupt on a vector freed meanwhile is only acknowledged.
+pub fn dispatch(vector: u8) {
+    let handler = VECTORS.lock().handler(vector);
+    match handler {
+        Some(f) => f(vector),
+        None => log::warn!("Interrupt on unallocated vector {:#x}", vector),
+    }
+    crate::driver::ioapic::lapic::write_eoi();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn nop(_: u8) {}
+
+    #[test]
+    fn test_allocate_skips_reserved() {
+        let mut table = Table::new();
+        let first = table.allocate("a", nop).unwrap();
+        assert_eq!(first, FIRST_VECTOR + 1);
+        assert!(RESERVED.contains(&FIRST_VECTOR));
+        assert_eq!(table.owned_entry("a", first).unwrap().refcount, 1);
+    }
+
+    #[test]
+    fn test_exhausted() {
+        let mut table = Table::new();
+        let mut allocated = Vec::new();
+        while let Ok(vector) = table.allocate("a", nop) {
+            assert!(!RESERVED.contains(&vector));
+            assert!(!allocated.contains(&vector));
+            allocated.push(vector);
+        }
+        let range = (LAST_VECTOR - FIRST_VECTOR) as usize + 1;
+        assert_eq!(allocated.len(), range - RESERVED.len());
+        assert_eq!(table.allocate("b", nop), Err(VectorError::Exhausted));
+
+        // A freed vector can be allocated again
+        assert_eq!(table.release("a", allocated[3]), Ok(true));
+        assert_eq!(table.allocate("b", nop), Ok(allocated[3]));
+    }
+
+    #[test]
+    fn test_refcount() {
+        let mut table = Table::new();
+        let vector = table.allocate("a", nop).unwrap();
+        assert_eq!(table.acquire("a", vector), Ok(()));
+        assert_eq!(table.acquire("a", vector), Ok(()));
+        assert_eq!(table.release("a", vector), Ok(false));
+        assert_eq!(table.release("a", vector), Ok(false));
+        assert!(table.handler(vector).is_some());
+        assert_eq!(table.release("a", vector), Ok(true));
+        assert!(table.handler(vector).is_none());
+        assert_eq!(table.release("a", vector), Err(VectorError::NotAllocated));
+        assert_eq!(table.acquire("a", vector), Err(VectorError::NotAllocated));
+    }
+
+    #[test]
+    fn test_not_owner() {
+        let mut table = Table::new();
+        let vector = table.allocate("a", nop).unwrap();
+        assert_eq!(table.acquire("b", vector), Err(VectorError::NotOwner));
+        assert_eq!(table.release("b", vector), Err(VectorError::NotOwner));
+        assert_eq!(table.owned_entry("a", vector).unwrap().refcount, 1);
+    }
+
+    #[test]
+    fn test_deregister() {
+        let mut table = Table::new();
+        let a1 = table.allocate("a", nop).unwrap();
+        let b = table.allocate("b", nop).unwrap();
+        let a2 = table.allocate("a", nop).unwrap();
+        table.acquire("a", a2).unwrap();
+
+        assert_eq!(table.deregister("a"), 2);
+        assert!(table.handler(a1).is_none());
+        assert!(table.handler(a2).is_none());
+        assert!(table.handler(b).is_some());
+        assert_eq!(table.deregister("a"), 0);
+
+        // The lowest free vector is reused
+        assert_eq!(table.allocate("c", nop), Ok(a1));
+    }
+}
@@ -22,6 +22,10 @@ impl<T> EventQueue<T> {
         self.queue.is_empty()
     }
 
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
     /// Returns:
     /// * Ok(Some(event)) when push successful and event should be triggered
     /// * Ok(None) when push successful but no event
@@ -35,6 +39,12 @@ impl<T> EventQueue<T> {
         }
     }
 
+    /// Return an item to the front of the queue. Ignores the size limit,
+    /// as the item was already in the queue. Doesn't trigger the event.
+    pub fn push_front(&mut self, item: T) {
+        self.queue.push_front(item);
+    }
+
     /// Nonblocking, returns None if the queue is empty
     pub fn pop(&mut self) -> Option<T> {
         self.queue.pop_front()
@@ -55,8 +65,4 @@ impl<T> EventQueue<T> {
             WaitFor::None
         }
     }
-
-    pub fn into_iter(self) -> impl Iterator<Item = T> {
-        self.queue.into_iter()
-    }
 }
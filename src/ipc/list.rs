@@ -47,6 +47,11 @@ impl SubscriptionList {
         return false;
     }
 
+    /// Tuples of (filter, reliable, subscription_id), sorted by filter
+    pub fn iter(&self) -> impl Iterator<Item = &(TopicFilter, bool, SubscriptionId)> {
+        self.targets.iter()
+    }
+
     pub fn remove(&mut self, subscription: SubscriptionId) {
         self.targets.retain(|(_, _, id)| subscription != *id);
     }
@@ -5,14 +5,14 @@
 //! * System shutdown requested: Unreliable
 //!
 //! TODO: multi-reader reliable delivery?
-//! TODO: permission system
 //! TODO: page mapping for large messages
 
 use alloc::prelude::v1::*;
+use core::fmt;
 use hashbrown::{HashMap, HashSet};
 use spin::Mutex;
 
-use d7abi::process::ProcessResult;
+use d7abi::process::{Privilege, ProcessResult};
 
 pub use d7abi::ipc::{AcknowledgeId, Message, SubscriptionId};
 
@@ -33,14 +33,23 @@ pub use self::topic::{Topic, TopicFilter, TopicPrefix};
 /// messages after it's buffer contains this many messages.
 const MAILBOX_BUFFER_LIMIT: usize = 100;
 
+/// A mailbox will also reject or drop messages after the total size of their
+/// payloads would exceed this, so that a process that never receives its
+/// messages cannot exhaust the kernel heap. A single message is always
+/// accepted into an empty mailbox, as replies can be large, e.g. file contents.
+const MAILBOX_BYTE_LIMIT: usize = 0x10_0000;
+
 #[derive(Debug)]
 struct Mailbox {
     queue: EventQueue<Message>,
+    /// Total payload size of the queued messages
+    bytes: usize,
 }
 impl Mailbox {
     pub fn new() -> Self {
         Self {
             queue: EventQueue::new(MAILBOX_BUFFER_LIMIT),
+            bytes: 0,
         }
     }
 
@@ -48,35 +57,68 @@ impl Mailbox {
         self.queue.is_empty()
     }
 
+    fn has_room_for(&self, message: &Message) -> bool {
+        self.queue.is_empty() || self.bytes + message.data.len() <= MAILBOX_BYTE_LIMIT
+    }
+
+    fn push(&mut self, message: Message) -> Result<Option<TriggerEvent>, ()> {
+        if !self.has_room_for(&message) {
+            return Err(());
+        }
+        let size = message.data.len();
+        let trigger = self.queue.push(message)?;
+        self.bytes += size;
+        Ok(trigger.map(TriggerEvent))
+    }
+
     #[must_use]
     pub fn push_unreliable(&mut self, message: Message) -> Option<TriggerEvent> {
-        match self.queue.push(message) {
-            Ok(v) => v.map(TriggerEvent),
-            Err(()) => None,
-        }
+        self.push(message).unwrap_or(None)
     }
 
     #[must_use]
     pub fn push_reliable(
         &mut self, message: Message,
     ) -> Result<Option<TriggerEvent>, DeliveryError> {
-        self.queue
-            .push(message)
-            .map(|opt| opt.map(TriggerEvent))
-            .map_err(|()| DeliveryError::QueueFull)
+        self.push(message).map_err(|()| DeliveryError::QueueFull)
+    }
+
+    /// Return a received message to the front of the queue
+    pub fn push_front(&mut self, message: Message) {
+        self.bytes += message.data.len();
+        self.queue.push_front(message);
     }
 
     #[must_use]
     pub fn pop_or_event(&mut self) -> Result<Message, ExplicitEventId> {
-        self.queue.pop_or_event()
+        let message = self.queue.pop_or_event()?;
+        self.bytes -= message.data.len();
+        Ok(message)
     }
 }
 
+/// Maximum number of topics with a retained message
+const RETAINED_TOPIC_LIMIT: usize = 256;
+
+/// Maximum number of topics with a retained message published by a single process
+const RETAINED_TOPIC_LIMIT_PER_PROCESS: usize = 16;
+
+/// Topic prefixes that only the kernel can publish to
+const KERNEL_TOPICS: &[&str] = &["irq/", "memory/", "process/", "system/"];
+
+/// Whether processes are allowed to publish to the topic
+pub fn is_kernel_topic(topic: &Topic) -> bool {
+    KERNEL_TOPICS.iter().any(|p| topic.as_str().starts_with(p))
+}
+
+/// Maximum payload size of a retained message
+const RETAINED_BYTE_LIMIT: usize = 0x1000;
+
 /// Result of Manager::deliver
 #[derive(Debug)]
 pub enum Deliver {
-    /// Wait for this event
-    Process(ExplicitEventId),
+    /// Wait for this event, triggered by the receiving process
+    Process(ExplicitEventId, ProcessId),
     /// Just wake up with ok
     Kernel,
 }
@@ -97,8 +139,13 @@ pub struct Manager {
     /// Mailbox is None if the message is handled byu the kernel instead.
     mailboxes: HashMap<SubscriptionId, Option<Mailbox>>,
     /// Reliable messages waiting for the receiver acknowledgement.
-    /// The value field contains are sender wakeup id and process id.
-    waiting_for_delivery: HashMap<AcknowledgeId, (ExplicitEventId, ProcessId)>,
+    /// The value field contains are sender wakeup id, sender process id,
+    /// the receiver process id, and the receiving subscription.
+    waiting_for_delivery:
+        HashMap<AcknowledgeId, (ExplicitEventId, ProcessId, ProcessId, SubscriptionId)>,
+    /// Reliable messages whose sender stopped waiting, see `cancel_delivery`.
+    /// The value field contains the sender and the receiving subscription.
+    cancelled: HashMap<AcknowledgeId, (ProcessId, SubscriptionId)>,
     /// Reliable messages that have been delivered (or caused an error).
     /// The value field contains success status.
     delivery_result: HashMap<ProcessId, Result<(), DeliveryError>>,
@@ -106,6 +153,13 @@ pub struct Manager {
     next_acknowledge_id: AcknowledgeId,
     /// ProcessId -> SubscriptionId mapping for process-exit cleanup
     process_subscriptions: HashMap<ProcessId, HashSet<SubscriptionId>>,
+    /// Latest retained message of each topic, and the publishing process,
+    /// or `None` for the kernel. See `publish_retained`.
+    retained: HashMap<Topic, (Option<ProcessId>, Vec<u8>)>,
+    /// Lowest sender privilege accepted by a subscription, see `restrict`
+    min_privilege: HashMap<SubscriptionId, Privilege>,
+    /// Topic prefixes whose operations are logged, see `set_trace`
+    trace: Vec<String>,
 }
 impl Manager {
     pub fn new() -> Self {
@@ -113,10 +167,45 @@ impl Manager {
             subscriptions: SubscriptionList::new(),
             mailboxes: HashMap::new(),
             waiting_for_delivery: HashMap::new(),
+            cancelled: HashMap::new(),
             delivery_result: HashMap::new(),
             next_acknowledge_id: AcknowledgeId::from_u64(0),
             process_subscriptions: HashMap::new(),
+            retained: HashMap::new(),
+            min_privilege: HashMap::new(),
+            trace: Vec::new(),
+        }
+    }
+
+    /// Starts or stops logging the operations on topics starting with `prefix`:
+    /// subscribing, publishing, delivering, receiving, acknowledging and
+    /// unsubscribing, with the process, subscription, sizes and results.
+    /// Subscriptions are matched by their filter. Returns the traced prefixes.
+    pub fn set_trace(&mut self, prefix: String, enabled: bool) -> Vec<String> {
+        self.trace.retain(|p| *p != prefix);
+        if enabled {
+            self.trace.push(prefix);
         }
+        self.trace.clone()
+    }
+
+    pub fn traced_prefixes(&self) -> Vec<String> {
+        self.trace.clone()
+    }
+
+    /// Logs an operation if the topic or filter is traced
+    fn trace(&self, topic: &str, operation: fmt::Arguments) {
+        if self.trace.iter().any(|prefix| topic.starts_with(prefix.as_str())) {
+            log::info!("IPC trace: {}", operation);
+        }
+    }
+
+    /// Filter of a subscription, for tracing operations without a topic
+    fn subscription_filter(&self, sub: SubscriptionId) -> &str {
+        self.subscriptions
+            .iter()
+            .find(|(_, _, id)| *id == sub)
+            .map_or("", |(filter, _, _)| filter.inner())
     }
 
     /// Return if a subscription is owned by a process
@@ -127,6 +216,14 @@ impl Manager {
             .unwrap_or(false)
     }
 
+    /// Process owning a subscription, if any
+    fn subscription_owner(&self, sub: SubscriptionId) -> Option<ProcessId> {
+        self.process_subscriptions
+            .iter()
+            .find(|(_, subs)| subs.contains(&sub))
+            .map(|(pid, _)| *pid)
+    }
+
     /// Return an error if process doesn't own a subscription
     #[must_use]
     fn verify_process_owns(
@@ -142,11 +239,26 @@ impl Manager {
     /// Subscribe to events by a filter
     /// Reliable subscriptions are mutually exclusive: there cannot be
     /// any other endpoint subscribed to the any events matched by this.
+    /// Unreliable subscriptions start with the retained messages matching the filter.
     pub fn subscribe(
         &mut self, pid: ProcessId, filter: TopicFilter, reliable: bool,
     ) -> Result<SubscriptionId, SubscriptionError> {
-        if let Some(id) = self.subscriptions.insert(filter, reliable) {
-            self.mailboxes.insert(id, Some(Mailbox::new()));
+        let mut mailbox = Mailbox::new();
+        if !reliable {
+            for (topic, (_, data)) in self.retained.iter() {
+                if filter.matches(topic) {
+                    // Nobody can be waiting for a new mailbox, so there is no event
+                    let _ = mailbox.push_unreliable(Message {
+                        topic: topic.string(),
+                        data: data.clone(),
+                        ack_id: None,
+                    });
+                }
+            }
+        }
+        let filter_str = filter.inner().to_owned();
+        let result = if let Some(id) = self.subscriptions.insert(filter, reliable) {
+            self.mailboxes.insert(id, Some(mailbox));
             self.process_subscriptions
                 .entry(pid)
                 .or_default()
@@ -154,7 +266,12 @@ impl Manager {
             Ok(id)
         } else {
             Err(SubscriptionError::Exclusion.into())
-        }
+        };
+        self.trace(&filter_str, format_args!(
+            "pid {} subscribe {:?} reliable={} -> {:?}",
+            pid, filter_str, reliable, result
+        ));
+        result
     }
 
     /// Subscribe to events by a filter as a kernel.
@@ -170,41 +287,97 @@ impl Manager {
         }
     }
 
-    /// Internal function for removing subscriptions on process termination
-    pub fn _force_unsubscribe(&mut self, subscription: SubscriptionId) -> IpcResult<()> {
+    /// Internal function for removing subscriptions on process termination.
+    /// Reliable messages fail with the given error, both the queued ones
+    /// and the ones that were received but not acknowledged yet.
+    pub fn _force_unsubscribe(
+        &mut self, subscription: SubscriptionId, error: DeliveryError,
+    ) -> IpcResult<()> {
         self.subscriptions.remove(subscription);
-        let mailbox = self
-            .mailboxes
+        self.min_privilege.remove(&subscription);
+        self.cancelled.retain(|_, (_, sub)| *sub != subscription);
+        self.mailboxes
             .remove(&subscription)
             .unwrap()
             .expect("Kernel cannot unsubscribe");
-        // Release reliable messages
+        // Release reliable messages, queued or received
+        let pending: Vec<AcknowledgeId> = self
+            .waiting_for_delivery
+            .iter()
+            .filter(|(_, (_, _, _, sub))| *sub == subscription)
+            .map(|(ack_id, _)| *ack_id)
+            .collect();
         let mut events = HashSet::new();
-        for msg in mailbox.queue.into_iter() {
-            if let Some(ack_id) = msg.ack_id {
-                let (event, pid) = self.waiting_for_delivery.remove(&ack_id).unwrap();
-                self.delivery_result
-                    .insert(pid, Err(DeliveryError::NoSubscriber));
-                events.insert(TriggerEvent(event));
-            }
+        for ack_id in pending {
+            let (event, pid, _, _) = self.waiting_for_delivery.remove(&ack_id).unwrap();
+            self.delivery_result.insert(pid, Err(error));
+            events.insert(TriggerEvent(event));
         }
         IpcResult::success(()).with_events(events.into_iter())
     }
 
     /// Remove a subscription.
+    /// Subscription ids are never reused, so removing a subscription
+    /// that doesn't exist anymore succeeds without doing anything.
     pub fn unsubscribe(&mut self, pid: ProcessId, subscription: SubscriptionId) -> IpcResult<()> {
+        if !self.mailboxes.contains_key(&subscription) {
+            return IpcResult::success(());
+        }
         verify_owner!(self, pid, subscription);
+        self.trace(
+            self.subscription_filter(subscription),
+            format_args!("pid {} unsubscribe {:?}", pid, subscription),
+        );
         self.process_subscriptions
             .get_mut(&pid)
             .unwrap()
             .remove(&subscription);
-        self._force_unsubscribe(subscription)
+        self._force_unsubscribe(subscription, DeliveryError::NoSubscriber)
+    }
+
+    /// Transfer ownership of a subscription to another process.
+    /// Queued messages stay in the mailbox, and are received by the new owner.
+    pub fn transfer(
+        &mut self, pid: ProcessId, subscription: SubscriptionId, target: ProcessId,
+    ) -> IpcResult<()> {
+        verify_owner!(self, pid, subscription);
+        self.process_subscriptions
+            .get_mut(&pid)
+            .unwrap()
+            .remove(&subscription);
+        self.process_subscriptions
+            .entry(target)
+            .or_default()
+            .insert(subscription);
+        IpcResult::success(())
+    }
+
+    /// Only accept reliable deliveries from processes with at least the given
+    /// privilege. Others get a permission error, so that e.g. a driver can
+    /// keep its control topic away from user processes.
+    pub fn restrict(
+        &mut self, pid: ProcessId, subscription: SubscriptionId, privilege: Privilege,
+    ) -> IpcResult<()> {
+        verify_owner!(self, pid, subscription);
+        if privilege == Privilege::User {
+            self.min_privilege.remove(&subscription);
+        } else {
+            self.min_privilege.insert(subscription, privilege);
+        }
+        IpcResult::success(())
     }
 
     /// Unreliable (fire-and-forget) publish to a key group
     pub fn publish(&mut self, topic: Topic, data: &[u8]) -> IpcResult<()> {
         let mut events = HashSet::new();
-        for sub in self.subscriptions.find_all(&topic, false) {
+        let subs = self.subscriptions.find_all(&topic, false);
+        self.trace(topic.as_str(), format_args!(
+            "publish {:?} {} bytes to {} subscriptions",
+            topic.as_str(),
+            data.len(),
+            subs.len()
+        ));
+        for sub in subs {
             let mailbox = self
                 .mailboxes
                 .get_mut(&sub)
@@ -224,11 +397,69 @@ impl Manager {
         IpcResult::success(()).with_events(events.into_iter())
     }
 
+    /// Unreliable publish, that also replaces the retained message of the topic.
+    /// New subscribers receive the retained message first, so that they always
+    /// get the latest value of a status topic. Empty data clears the retained message.
+    ///
+    /// The publisher is `None` for the kernel. Processes cannot publish to kernel
+    /// topics, or replace a message retained by another process. Their messages
+    /// are cleared when they exit, see `on_process_over`.
+    pub fn publish_retained(
+        &mut self, publisher: Option<ProcessId>, topic: Topic, data: &[u8],
+    ) -> IpcResult<()> {
+        if let Some(pid) = publisher {
+            let foreign = match self.retained.get(&topic) {
+                Some((owner, _)) => *owner != publisher,
+                None => false,
+            };
+            if is_kernel_topic(&topic) || foreign {
+                log::warn!("Retained publish to {:?} by pid {} denied", topic, pid);
+                return IpcResult::error(PermissionError::NoAccess.into());
+            }
+        }
+
+        if data.is_empty() {
+            self.retained.remove(&topic);
+        } else {
+            let new_topic = !self.retained.contains_key(&topic);
+            let published = self.retained.values().filter(|(o, _)| *o == publisher);
+            if data.len() > RETAINED_BYTE_LIMIT
+                || (new_topic && self.retained.len() >= RETAINED_TOPIC_LIMIT)
+                || (new_topic
+                    && publisher.is_some()
+                    && published.count() >= RETAINED_TOPIC_LIMIT_PER_PROCESS)
+            {
+                return IpcResult::error(Error::RetainedLimit);
+            }
+            self.retained.insert(topic.clone(), (publisher, data.to_vec()));
+        }
+        self.publish(topic, data)
+    }
+
     /// Reliable delivery to exclusive topic.
     /// The caller must repeat the call after the returned event has been
     /// triggered by the receiving process, i.e. `WaitFor::Event`
     /// (or `WaitFor::None` if kernel processes the message immediately).
-    pub fn deliver(&mut self, pid: ProcessId, topic: Topic, data: &[u8]) -> IpcResult<Deliver> {
+    /// Fails with a permission error if the sender is less privileged than
+    /// the subscription requires.
+    pub fn deliver(
+        &mut self, pid: ProcessId, privilege: Privilege, topic: Topic, data: &[u8],
+    ) -> IpcResult<Deliver> {
+        let topic_str = topic.string();
+        let result = self.deliver_untraced(pid, privilege, topic, data);
+        self.trace(&topic_str, format_args!(
+            "pid {} deliver {:?} {} bytes -> {:?}",
+            pid,
+            topic_str,
+            data.len(),
+            result.value()
+        ));
+        result
+    }
+
+    fn deliver_untraced(
+        &mut self, pid: ProcessId, privilege: Privilege, topic: Topic, data: &[u8],
+    ) -> IpcResult<Deliver> {
         let all = self.subscriptions.find_all(&topic, true);
         let count = all.len();
         if all.len() == 0 {
@@ -239,9 +470,15 @@ impl Manager {
             count == 1,
             "Multiple targets selected for reliable delivery"
         );
+        let sub = all.into_iter().next().unwrap();
+        if let Some(&required) = self.min_privilege.get(&sub) {
+            if privilege < required {
+                log::warn!("Delivery error: {:?} requires {:?}", topic, required);
+                return IpcResult::error(PermissionError::NoAccess.into());
+            }
+        }
         let ack_id = self.next_acknowledge_id;
         self.next_acknowledge_id = self.next_acknowledge_id.next();
-        let sub = all.into_iter().next().unwrap();
         if let Some(mailbox) = self.mailboxes.get_mut(&sub).unwrap() {
             // Deliver to another process
             let result = mailbox.push_reliable(Message {
@@ -253,9 +490,12 @@ impl Manager {
             match result {
                 Ok(trigger) => {
                     let sender_wakeup_id = WaitFor::new_event_id();
+                    let receiver = self
+                        .subscription_owner(sub)
+                        .expect("Mailbox without an owner");
                     self.waiting_for_delivery
-                        .insert(ack_id, (sender_wakeup_id, pid));
-                    IpcResult::success(Deliver::Process(sender_wakeup_id))
+                        .insert(ack_id, (sender_wakeup_id, pid, receiver, sub));
+                    IpcResult::success(Deliver::Process(sender_wakeup_id, receiver))
                         .with_events(trigger.into_iter())
                 },
                 Err(error) => IpcResult::error(error.into()),
@@ -308,6 +548,28 @@ impl Manager {
     pub fn kernel_deliver_reply<T: serde::Serialize + ?Sized>(
         &mut self, topic: Topic, data: &T,
     ) -> Result<(), DeliveryError> {
+        let result = self.push_kernel_reply(topic, data)?;
+        assert!(
+            result.is_none(),
+            "Kernel reply delivery must not cause events"
+        );
+        Ok(())
+    }
+
+    /// Like `kernel_deliver_reply`, but for a reply sent after the delivery
+    /// has completed, so the receiver may already be waiting for it
+    pub fn kernel_deliver_reply_later<T: serde::Serialize + ?Sized>(
+        &mut self, topic: Topic, data: &T,
+    ) -> IpcResult<()> {
+        match self.push_kernel_reply(topic, data) {
+            Ok(event) => IpcResult::success(()).with_events(event.into_iter()),
+            Err(error) => IpcResult::error(error.into()),
+        }
+    }
+
+    fn push_kernel_reply<T: serde::Serialize + ?Sized>(
+        &mut self, topic: Topic, data: &T,
+    ) -> Result<Option<TriggerEvent>, DeliveryError> {
         let all = self.subscriptions.find_all(&topic, true);
         let count = all.len();
         if all.len() == 0 {
@@ -329,16 +591,11 @@ impl Manager {
             .expect("Kernel cannot reply to itself");
 
         // Deliver to process, returning any errors to the caller
-        let result = mailbox.push_reliable(Message {
+        mailbox.push_reliable(Message {
             topic: topic.string(),
             data: pinecone::to_vec(data).unwrap(),
             ack_id: None,
-        })?;
-        assert!(
-            result.is_none(),
-            "Kernel reply delivery must not cause events"
-        );
-        Ok(())
+        })
     }
 
     /// Used to see if this is a new delivery or a completed one
@@ -346,6 +603,22 @@ impl Manager {
         self.delivery_result.contains_key(&pid)
     }
 
+    /// Stop waiting for the pending delivery of a process, e.g. on timeout.
+    /// The message stays with the receiver, and its acknowledgement is ignored.
+    pub fn cancel_delivery(&mut self, pid: ProcessId) {
+        let pending: Vec<AcknowledgeId> = self
+            .waiting_for_delivery
+            .iter()
+            .filter(|(_, (_, sender, _, _))| *sender == pid)
+            .map(|(ack_id, _)| *ack_id)
+            .collect();
+        for ack_id in pending {
+            let (_, sender, _, sub) = self.waiting_for_delivery.remove(&ack_id).unwrap();
+            self.cancelled.insert(ack_id, (sender, sub));
+        }
+        self.delivery_result.remove(&pid);
+    }
+
     /// Called when process that called deliver wakes up again
     pub fn after_delivery(&mut self, pid: ProcessId) -> IpcResult<()> {
         let result = self
@@ -360,7 +633,10 @@ impl Manager {
 
     /// What event this subscription triggers when selected.
     /// Returns WaitFor::None if there are messages available immediately.
-    pub fn waiting_for(&mut self, subscription: SubscriptionId) -> WaitFor {
+    pub fn waiting_for(
+        &mut self, pid: ProcessId, subscription: SubscriptionId,
+    ) -> Result<WaitFor, Error> {
+        self.verify_process_owns(pid, subscription)?;
         let mailbox = self
             .mailboxes
             .get_mut(&subscription)
@@ -368,7 +644,7 @@ impl Manager {
             .as_mut()
             .expect("The kernel cannot manually check for events");
 
-        mailbox.queue.wait_for()
+        Ok(mailbox.queue.wait_for())
     }
 
     /// Read message from a subscription, if any available.
@@ -384,18 +660,59 @@ impl Manager {
             .as_mut()
             .expect("The kernel cannot manually receive events");
 
-        IpcResult::success(mailbox.pop_or_event())
+        let result = mailbox.pop_or_event();
+        if let Ok(message) = &result {
+            self.trace(&message.topic, format_args!(
+                "pid {} receive {:?} {} bytes from {:?}",
+                pid,
+                message.topic,
+                message.data.len(),
+                subscription
+            ));
+        }
+        IpcResult::success(result)
+    }
+
+    /// Returns a received message to the front of the mailbox,
+    /// e.g. if it didn't fit into the buffer of the receiver
+    pub fn unreceive(&mut self, subscription: SubscriptionId, message: Message) {
+        if let Some(Some(mailbox)) = self.mailboxes.get_mut(&subscription) {
+            mailbox.push_front(message);
+        }
     }
 
     /// Acknowledge reliable delivery.
-    /// If positive==false, then negative-adknowledge
+    /// If positive==false, then negative-adknowledge.
+    /// Returns the sender process of the message.
     pub fn acknowledge(
-        &mut self, _subscription: SubscriptionId, ack_id: AcknowledgeId, positive: bool,
-    ) -> IpcResult<()> {
-        let (event, pid) = self
-            .waiting_for_delivery
-            .remove(&ack_id)
-            .expect("Attempt to re-acknowledge a message"); //TODO: client error
+        &mut self, receiver: ProcessId, subscription: SubscriptionId, ack_id: AcknowledgeId,
+        positive: bool,
+    ) -> IpcResult<ProcessId> {
+        verify_owner!(self, receiver, subscription);
+        self.trace(
+            self.subscription_filter(subscription),
+            format_args!(
+                "pid {} acknowledge {:?} from {:?} positive={}",
+                receiver, ack_id, subscription, positive
+            ),
+        );
+        let (event, pid, _, _) = match self.waiting_for_delivery.get(&ack_id) {
+            Some((_, _, _, sub)) if *sub != subscription => {
+                return IpcResult::error(PermissionError::NotOwner.into());
+            },
+            Some(_) => self.waiting_for_delivery.remove(&ack_id).unwrap(),
+            None => {
+                // The sender is not waiting anymore
+                return match self.cancelled.get(&ack_id) {
+                    Some((pid, sub)) if *sub == subscription => {
+                        let pid = *pid;
+                        self.cancelled.remove(&ack_id);
+                        IpcResult::success(pid)
+                    },
+                    _ => IpcResult::error(Error::ReAcknowledge),
+                };
+            },
+        };
         self.delivery_result.insert(
             pid,
             if positive {
@@ -404,21 +721,103 @@ impl Manager {
                 Err(DeliveryError::NegativeAcknowledgement)
             },
         );
-        IpcResult::success(()).with_event(TriggerEvent(event))
+        IpcResult::success(pid).with_event(TriggerEvent(event))
     }
 
     /// Update when a process completes.
-    /// Unsubscribes from all events, cleans mailboxes, and send wakeup signals if required
-    pub fn on_process_over(
-        &mut self, sched: &mut Scheduler, pid: ProcessId, status: ProcessResult,
-    ) {
+    /// Unsubscribes from all events, cleans mailboxes, and send wakeup signals if required.
+    /// Messages to its subscriptions that were not acknowledged fail.
+    pub fn on_process_over(&mut self, pid: ProcessId, status: ProcessResult) -> IpcResult<()> {
+        let mut result = IpcResult::success(());
         if let Some(subs) = self.process_subscriptions.remove(&pid) {
             for subscription in subs {
-                self._force_unsubscribe(subscription)
-                    .consume_events(sched)
-                    .unwrap();
+                let (value, events) = self
+                    ._force_unsubscribe(subscription, DeliveryError::ReceiverTerminated)
+                    .separate_events();
+                value.unwrap();
+                result = result.with_events(events.into_iter());
             }
         }
+
+        // Retained messages of the process are cleared, and the subscribers
+        // get the empty message like when the process clears them itself
+        let owned: Vec<Topic> = self
+            .retained
+            .iter()
+            .filter(|(_, (owner, _))| *owner == Some(pid))
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        for topic in owned {
+            self.retained.remove(&topic);
+            let (value, events) = self.publish(topic, &[]).separate_events();
+            value.unwrap();
+            result = result.with_events(events.into_iter());
+        }
+        result
+    }
+
+    /// Subscriptions, unacknowledged reliable messages and retained messages
+    /// as text lines, for finding leaked subscriptions and stuck deliveries
+    pub fn dump(&self) -> Vec<String> {
+        let owners: HashMap<SubscriptionId, ProcessId> = self
+            .process_subscriptions
+            .iter()
+            .flat_map(|(pid, subs)| subs.iter().map(move |sub| (*sub, *pid)))
+            .collect();
+
+        let mut lines = vec![format!(
+            "{:<6} {:<8} {:<10} {:>6} {:>8} {:>7} filter",
+            "sub", "owner", "kind", "queued", "bytes", "unacked"
+        )];
+        for (filter, reliable, sub) in self.subscriptions.iter() {
+            let owner = owners
+                .get(sub)
+                .map_or_else(|| "kernel".to_owned(), |pid| pid.to_string());
+            let (queued, bytes) = match self.mailboxes.get(sub) {
+                Some(Some(mailbox)) => (mailbox.queue.len(), mailbox.bytes),
+                _ => (0, 0),
+            };
+            let unacked = self
+                .waiting_for_delivery
+                .values()
+                .filter(|(_, _, _, s)| s == sub)
+                .count();
+            let filter = match filter {
+                TopicFilter::Exact(_) => filter.inner().to_owned(),
+                TopicFilter::Prefix(_) => format!("{}*", filter.inner()),
+            };
+            lines.push(format!(
+                "{:<6} {:<8} {:<10} {:>6} {:>8} {:>7} {}",
+                sub.as_u64(),
+                owner,
+                if *reliable { "reliable" } else { "unreliable" },
+                queued,
+                bytes,
+                unacked,
+                filter
+            ));
+        }
+
+        let mut pending: Vec<_> = self.waiting_for_delivery.iter().collect();
+        pending.sort_by_key(|(ack_id, _)| **ack_id);
+        lines.push(format!("{:<6} {:<8} {:<8} sub", "ack", "sender", "receiver"));
+        for (ack_id, (_, sender, receiver, sub)) in pending {
+            lines.push(format!(
+                "{:<6} {:<8} {:<8} {}",
+                ack_id.as_u64(),
+                sender,
+                receiver,
+                sub.as_u64()
+            ));
+        }
+
+        let mut retained: Vec<_> = self.retained.iter().collect();
+        retained.sort();
+        lines.push(format!("{:<8} retained topic", "bytes"));
+        for (topic, (_, data)) in retained {
+            lines.push(format!("{:<8} {}", data.len(), topic.as_str()));
+        }
+        lines
     }
 }
 
@@ -426,13 +825,384 @@ lazy_static::lazy_static! {
     pub static ref IPC: Mutex<Manager> = Mutex::new(Manager::new());
 }
 
+/// Runs `f` with the IPC manager locked. The wakeups it produces are
+/// collected, and given to the scheduler only after the lock is released,
+/// so that the IPC lock is never held while the scheduler processes events.
+pub fn with_manager<T, F>(sched: &mut Scheduler, f: F) -> Result<T, Error>
+where F: FnOnce(&mut Manager) -> IpcResult<T> {
+    let deferred = {
+        let mut ipc_manager = IPC.try_lock().expect("IPC locked");
+        f(&mut ipc_manager)
+    };
+    deferred.consume_events(sched)
+}
+
 /// Publish message as the kernel
 pub fn kernel_publish<T: serde::Serialize>(sched: &mut Scheduler, topic: &str, message: &T) {
     log::trace!("kernel_publish {}", topic);
     let data = pinecone::to_vec(message).unwrap();
-    let mut ipc_manager = crate::ipc::IPC.try_lock().expect("IPC locked");
-    ipc_manager
-        .publish(Topic::new(topic).expect("Invalid topic name"), &data)
-        .consume_events(sched)
-        .expect("Publish failed");
+    let topic = Topic::new(topic).expect("Invalid topic name");
+    with_manager(sched, |ipc_manager| ipc_manager.publish(topic, &data)).expect("Publish failed");
+}
+
+/// Publish a retained message as the kernel
+pub fn kernel_publish_retained<T: serde::Serialize>(
+    sched: &mut Scheduler, topic: &str, message: &T,
+) {
+    log::trace!("kernel_publish_retained {}", topic);
+    let data = pinecone::to_vec(message).unwrap();
+    let topic = Topic::new(topic).expect("Invalid topic name");
+    // Userspace may have used up the retained message limits
+    let result = with_manager(sched, |ipc_manager| {
+        ipc_manager.publish_retained(None, topic, &data)
+    });
+    if let Err(error) = result {
+        log::warn!("Retained publish failed: {:?}", error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TOPIC: &str = "test/server";
+
+    /// Manager with a reliable subscription owned by the server process
+    fn setup() -> (Manager, ProcessId, ProcessId) {
+        let mut m = Manager::new();
+        let client = ProcessId::from_u64(1);
+        let server = ProcessId::from_u64(2);
+        let filter = TopicFilter::try_new(TOPIC, true).unwrap();
+        m.subscribe(server, filter, true).unwrap();
+        (m, client, server)
+    }
+
+    fn sub_of(m: &Manager, pid: ProcessId) -> SubscriptionId {
+        *m.process_subscriptions[&pid].iter().next().unwrap()
+    }
+
+    /// Returns the wakeup event of the client
+    fn deliver(m: &mut Manager, client: ProcessId) -> ExplicitEventId {
+        let topic = Topic::try_new(TOPIC).unwrap();
+        match m
+            .deliver(client, Privilege::User, topic, b"data")
+            .separate_events()
+            .0
+        {
+            Ok(Deliver::Process(event, _)) => event,
+            other => panic!("Unexpected delivery result {:?}", other),
+        }
+    }
+
+    fn receive(m: &mut Manager, server: ProcessId) -> AcknowledgeId {
+        let sub = sub_of(m, server);
+        let message = m.receive(server, sub).separate_events().0.unwrap().unwrap();
+        message.ack_id.unwrap()
+    }
+
+    fn crash(m: &mut Manager, pid: ProcessId) -> HashSet<TriggerEvent> {
+        let (value, events) = m
+            .on_process_over(pid, ProcessResult::Completed(1))
+            .separate_events();
+        value.unwrap();
+        events
+    }
+
+    fn delivery_result(m: &mut Manager, client: ProcessId) -> Result<(), Error> {
+        assert!(m.delivery_complete(client));
+        m.after_delivery(client).separate_events().0
+    }
+
+    #[test]
+    fn test_mailbox_byte_limit() {
+        let message = |size| Message {
+            topic: TOPIC.to_owned(),
+            data: vec![0; size],
+            ack_id: None,
+        };
+        let mut mailbox = Mailbox::new();
+        // A single large message fits into an empty mailbox
+        mailbox.push_reliable(message(2 * MAILBOX_BYTE_LIMIT)).unwrap();
+        assert_eq!(
+            mailbox.push_reliable(message(1)),
+            Err(DeliveryError::QueueFull)
+        );
+        assert!(mailbox.push_unreliable(message(1)).is_none());
+        mailbox.pop_or_event().unwrap();
+        assert_eq!(mailbox.bytes, 0);
+
+        mailbox.push_reliable(message(MAILBOX_BYTE_LIMIT - 1)).unwrap();
+        mailbox.push_reliable(message(1)).unwrap();
+        assert!(mailbox.push_reliable(message(1)).is_err());
+        let first = mailbox.pop_or_event().unwrap();
+        mailbox.push_front(first);
+        assert_eq!(mailbox.bytes, MAILBOX_BYTE_LIMIT);
+    }
+
+    #[test]
+    fn test_retained() {
+        let mut m = Manager::new();
+        let pid = ProcessId::from_u64(1);
+        let topic = Topic::try_new("status/battery").unwrap();
+        let filter = || TopicFilter::try_new("status/", false).unwrap();
+        let latest = |m: &mut Manager, sub| {
+            let message = m.receive(pid, sub).separate_events().0.unwrap();
+            message.map(|msg| msg.data)
+        };
+
+        m.publish_retained(Some(pid), topic.clone(), b"old").separate_events().0.unwrap();
+        m.publish_retained(Some(pid), topic.clone(), b"new").separate_events().0.unwrap();
+        let sub = m.subscribe(pid, filter(), false).unwrap();
+        assert_eq!(latest(&mut m, sub), Ok(b"new".to_vec()));
+        assert!(latest(&mut m, sub).is_err());
+
+        // Updates are received like normal messages
+        m.publish_retained(Some(pid), topic.clone(), b"newer").separate_events().0.unwrap();
+        assert_eq!(latest(&mut m, sub), Ok(b"newer".to_vec()));
+
+        // Clearing
+        m.publish_retained(Some(pid), topic.clone(), b"").separate_events().0.unwrap();
+        let sub = m.subscribe(pid, filter(), false).unwrap();
+        assert!(latest(&mut m, sub).is_err());
+
+        let (value, _) = m
+            .publish_retained(Some(pid), topic, &[0; RETAINED_BYTE_LIMIT + 1])
+            .separate_events();
+        assert_eq!(value, Err(Error::RetainedLimit));
+    }
+
+    #[test]
+    fn test_retained_ownership() {
+        let mut m = Manager::new();
+        let owner = ProcessId::from_u64(1);
+        let other = ProcessId::from_u64(2);
+        let topic = |s: &str| Topic::try_new(s).unwrap();
+        let publish = |m: &mut Manager, pid, name: &str, data: &[u8]| {
+            m.publish_retained(pid, topic(name), data).separate_events().0
+        };
+        let denied = Err(Error::Permission(PermissionError::NoAccess));
+
+        // Kernel topics
+        assert_eq!(publish(&mut m, Some(owner), "memory/pressure", b"x"), denied);
+        assert_eq!(publish(&mut m, Some(owner), "process/events/2", b"x"), denied);
+        assert_eq!(publish(&mut m, None, "memory/pressure", b"x"), Ok(()));
+        assert_eq!(publish(&mut m, Some(owner), "memory/pressure", b""), denied);
+
+        // Topics retained by another process
+        assert_eq!(publish(&mut m, Some(owner), "status/a", b"x"), Ok(()));
+        assert_eq!(publish(&mut m, Some(other), "status/a", b"y"), denied);
+        assert_eq!(publish(&mut m, Some(other), "status/a", b""), denied);
+        assert_eq!(publish(&mut m, Some(owner), "status/a", b"z"), Ok(()));
+
+        // Per-process limit, the owned topics can still be updated
+        for i in 1..RETAINED_TOPIC_LIMIT_PER_PROCESS {
+            let name = format!("status/{}", i);
+            assert_eq!(publish(&mut m, Some(owner), &name, b"x"), Ok(()));
+        }
+        let limited = Err(Error::RetainedLimit);
+        assert_eq!(publish(&mut m, Some(owner), "status/full", b"x"), limited);
+        assert_eq!(publish(&mut m, Some(owner), "status/a", b"x"), Ok(()));
+        assert_eq!(publish(&mut m, Some(other), "status/other", b"x"), Ok(()));
+
+        // Cleared when the owner exits
+        let sub = m
+            .subscribe(other, TopicFilter::try_new("status/a", true).unwrap(), false)
+            .unwrap();
+        m.receive(other, sub).separate_events().0.unwrap().unwrap();
+        crash(&mut m, owner);
+        let cleared = m.receive(other, sub).separate_events().0.unwrap().unwrap();
+        assert!(cleared.data.is_empty());
+        assert!(!m.retained.contains_key(&topic("status/a")));
+        assert!(m.retained.contains_key(&topic("status/other")));
+        assert!(m.retained.contains_key(&topic("memory/pressure")));
+        assert_eq!(publish(&mut m, Some(other), "status/a", b"y"), Ok(()));
+    }
+
+    #[test]
+    fn test_receiver_crash_before_receive() {
+        let (mut m, client, server) = setup();
+        let event = deliver(&mut m, client);
+        assert!(crash(&mut m, server).contains(&TriggerEvent(event)));
+        assert_eq!(
+            delivery_result(&mut m, client),
+            Err(Error::Delivery(DeliveryError::ReceiverTerminated))
+        );
+    }
+
+    #[test]
+    fn test_receiver_crash_before_acknowledge() {
+        let (mut m, client, server) = setup();
+        let event = deliver(&mut m, client);
+        receive(&mut m, server);
+        assert!(crash(&mut m, server).contains(&TriggerEvent(event)));
+        assert_eq!(
+            delivery_result(&mut m, client),
+            Err(Error::Delivery(DeliveryError::ReceiverTerminated))
+        );
+        assert!(m.waiting_for_delivery.is_empty());
+    }
+
+    #[test]
+    fn test_receiver_unsubscribe_before_acknowledge() {
+        let (mut m, client, server) = setup();
+        let event = deliver(&mut m, client);
+        receive(&mut m, server);
+        let sub = sub_of(&m, server);
+        let (value, events) = m.unsubscribe(server, sub).separate_events();
+        value.unwrap();
+        assert!(events.contains(&TriggerEvent(event)));
+        assert_eq!(
+            delivery_result(&mut m, client),
+            Err(Error::Delivery(DeliveryError::NoSubscriber))
+        );
+    }
+
+    #[test]
+    fn test_receiver_crash_after_acknowledge() {
+        let (mut m, client, server) = setup();
+        deliver(&mut m, client);
+        let ack_id = receive(&mut m, server);
+        let sub = sub_of(&m, server);
+        let (sender, _) = m.acknowledge(server, sub, ack_id, true).separate_events();
+        assert_eq!(sender, Ok(client));
+        assert!(crash(&mut m, server).is_empty());
+        assert_eq!(delivery_result(&mut m, client), Ok(()));
+    }
+
+    #[test]
+    fn test_transferred_subscription_survives_crash() {
+        let (mut m, client, server) = setup();
+        let new_owner = ProcessId::from_u64(3);
+        deliver(&mut m, client);
+        let ack_id = receive(&mut m, server);
+        let sub = sub_of(&m, server);
+        m.transfer(server, sub, new_owner).separate_events().0.unwrap();
+        assert!(crash(&mut m, server).is_empty());
+        assert!(!m.delivery_complete(client));
+        let (sender, _) = m.acknowledge(new_owner, sub, ack_id, true).separate_events();
+        assert_eq!(sender, Ok(client));
+        assert_eq!(delivery_result(&mut m, client), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_subscription_ids() {
+        let (mut m, client, server) = setup();
+        let sub = sub_of(&m, server);
+        let garbage = SubscriptionId::from_u64(0xdead_beef);
+        let not_owner = Err(Error::Permission(PermissionError::NotOwner));
+
+        assert_eq!(m.waiting_for(server, garbage), not_owner);
+        assert_eq!(m.waiting_for(client, sub), not_owner);
+        assert!(m.receive(client, sub).separate_events().0.is_err());
+
+        // Only the owner can acknowledge, and only messages to its subscription
+        deliver(&mut m, client);
+        let ack_id = receive(&mut m, server);
+        let (value, _) = m.acknowledge(client, sub, ack_id, true).separate_events();
+        assert_eq!(value, not_owner);
+        let (value, _) = m.acknowledge(server, garbage, ack_id, true).separate_events();
+        assert_eq!(value, not_owner);
+        assert!(!m.delivery_complete(client));
+
+        // Repeated unsubscribe succeeds, but others cannot unsubscribe
+        let (value, _) = m.unsubscribe(client, sub).separate_events();
+        assert_eq!(value, not_owner);
+        m.unsubscribe(server, sub).separate_events().0.unwrap();
+        m.unsubscribe(server, sub).separate_events().0.unwrap();
+        m.unsubscribe(client, garbage).separate_events().0.unwrap();
+    }
+
+    #[test]
+    fn test_restricted_subscription() {
+        let (mut m, client, server) = setup();
+        let sub = sub_of(&m, server);
+        let (value, _) = m
+            .restrict(client, sub, Privilege::Driver)
+            .separate_events();
+        assert_eq!(value, Err(Error::Permission(PermissionError::NotOwner)));
+        m.restrict(server, sub, Privilege::Driver)
+            .separate_events()
+            .0
+            .unwrap();
+
+        let topic = Topic::try_new(TOPIC).unwrap();
+        let (value, _) = m
+            .deliver(client, Privilege::User, topic.clone(), b"data")
+            .separate_events();
+        assert_eq!(value.err(), Some(Error::Permission(PermissionError::NoAccess)));
+        let (value, _) = m
+            .deliver(client, Privilege::Driver, topic, b"data")
+            .separate_events();
+        assert!(value.is_ok());
+    }
+
+    #[test]
+    fn test_cancelled_delivery() {
+        let (mut m, client, server) = setup();
+        deliver(&mut m, client);
+        m.cancel_delivery(client);
+        assert!(!m.delivery_complete(client));
+
+        // The receiver still gets the message, and can acknowledge it
+        let ack_id = receive(&mut m, server);
+        let sub = sub_of(&m, server);
+        let (sender, events) = m.acknowledge(server, sub, ack_id, true).separate_events();
+        assert_eq!(sender, Ok(client));
+        assert!(events.is_empty());
+        assert!(!m.delivery_complete(client));
+        let (value, _) = m.acknowledge(server, sub, ack_id, true).separate_events();
+        assert_eq!(value, Err(Error::ReAcknowledge));
+    }
+
+    /// A delivery interrupted by a signal is cancelled, and the late
+    /// acknowledgement must not complete the next delivery
+    #[test]
+    fn test_interrupted_delivery_redeliver() {
+        let (mut m, client, server) = setup();
+        deliver(&mut m, client);
+        let old_ack_id = receive(&mut m, server);
+        m.cancel_delivery(client);
+
+        let sub = sub_of(&m, server);
+        let (sender, events) = m.acknowledge(server, sub, old_ack_id, true).separate_events();
+        assert_eq!(sender, Ok(client));
+        assert!(events.is_empty());
+        assert!(!m.delivery_complete(client));
+
+        let event = deliver(&mut m, client);
+        assert!(!m.delivery_complete(client));
+        let pending = m.waiting_for_delivery.values();
+        assert_eq!(pending.filter(|(_, sender, _, _)| *sender == client).count(), 1);
+
+        let ack_id = receive(&mut m, server);
+        assert_ne!(ack_id, old_ack_id);
+        let (sender, events) = m.acknowledge(server, sub, ack_id, false).separate_events();
+        assert_eq!(sender, Ok(client));
+        assert!(events.contains(&TriggerEvent(event)));
+        assert_eq!(
+            delivery_result(&mut m, client),
+            Err(Error::Delivery(DeliveryError::NegativeAcknowledgement))
+        );
+        assert!(m.waiting_for_delivery.is_empty());
+    }
+
+    #[test]
+    fn test_trace_prefixes() {
+        let (mut m, client, server) = setup();
+        assert!(m.traced_prefixes().is_empty());
+        assert_eq!(m.set_trace("test/".to_owned(), true), vec!["test/".to_owned()]);
+        // Enabling twice doesn't duplicate the prefix
+        assert_eq!(m.set_trace("test/".to_owned(), true).len(), 1);
+
+        // Traced operations behave as usual
+        let event = deliver(&mut m, client);
+        let ack_id = receive(&mut m, server);
+        let sub = sub_of(&m, server);
+        let (value, events) = m.acknowledge(server, sub, ack_id, true).separate_events();
+        assert_eq!(value.unwrap(), client);
+        assert!(events.contains(&TriggerEvent(event)));
+        assert_eq!(m.subscription_filter(sub), TOPIC);
+
+        assert!(m.set_trace("test/".to_owned(), false).is_empty());
+    }
 }
@@ -36,6 +36,10 @@ impl<T> IpcResult<T> {
         }
     }
 
+    pub fn value(&self) -> &Result<T, Error> {
+        &self.value
+    }
+
     pub fn with_event(mut self, new_event: TriggerEvent) -> Self {
         self.events.insert(new_event);
         self
@@ -71,6 +75,8 @@ pub enum Error {
     InvalidTopic,
     Unsubscribed,
     ReAcknowledge,
+    /// Too many or too large retained messages
+    RetainedLimit,
     Subscription(SubscriptionError),
     Delivery(DeliveryError),
     Permission(PermissionError),
@@ -96,6 +102,7 @@ impl core::convert::Into<SyscallErrorCode> for Error {
             Self::InvalidTopic => SyscallErrorCode::ipc_invalid_topic,
             Self::Unsubscribed => SyscallErrorCode::ipc_unsubscribed,
             Self::ReAcknowledge => SyscallErrorCode::ipc_re_acknowledge,
+            Self::RetainedLimit => SyscallErrorCode::quota_exceeded,
             Self::Subscription(e) => e.into(),
             Self::Delivery(e) => e.into(),
             Self::Permission(e) => e.into(),
@@ -139,6 +146,8 @@ pub enum DeliveryError {
     QueueFull,
     /// Subscriber negative-acknowledged the message
     NegativeAcknowledgement,
+    /// Subscriber process terminated before acknowledging the message
+    ReceiverTerminated,
 }
 impl core::convert::Into<SyscallErrorCode> for DeliveryError {
     fn into(self) -> SyscallErrorCode {
@@ -146,6 +155,7 @@ impl core::convert::Into<SyscallErrorCode> for DeliveryError {
             Self::NoSubscriber => SyscallErrorCode::ipc_delivery_no_target,
             Self::QueueFull => SyscallErrorCode::ipc_delivery_target_full,
             Self::NegativeAcknowledgement => SyscallErrorCode::ipc_delivery_target_nack,
+            Self::ReceiverTerminated => SyscallErrorCode::ipc_delivery_target_terminated,
         }
     }
 }
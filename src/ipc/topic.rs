@@ -1,5 +1,42 @@
 use super::*;
 
+/// Maximum length of a topic name or a prefix, in bytes
+pub const MAX_TOPIC_LEN: usize = 256;
+/// Maximum length of a single `/`-separated component, in bytes
+pub const MAX_COMPONENT_LEN: usize = 64;
+/// Maximum number of `/`-separated components
+pub const MAX_DEPTH: usize = 16;
+
+/// Shared validation for topics and prefixes.
+/// All characters must be in `a-zA-Z0-9_/`, and components must be nonempty,
+/// except that a prefix can end with a `/`. As `.` is not allowed,
+/// there are no relative components to normalize.
+fn is_valid(s: &str, prefix: bool) -> bool {
+    if s.is_empty() || s.len() > MAX_TOPIC_LEN {
+        return false;
+    }
+
+    if !s.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'/') {
+        return false;
+    }
+
+    let body = if prefix && s.ends_with('/') {
+        &s[..s.len() - 1]
+    } else {
+        s
+    };
+
+    let mut depth = 0;
+    for component in body.split('/') {
+        depth += 1;
+        if component.is_empty() || component.len() > MAX_COMPONENT_LEN || depth > MAX_DEPTH {
+            return false;
+        }
+    }
+
+    true
+}
+
 /// While reliable and unreliable messages cannot be sent to each
 /// others endpoints, topic names still use the same namespace
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -7,23 +44,15 @@ pub struct Topic(String);
 impl Topic {
     /// Checks that topic name is valid and if yes, returns a new Topic.
     /// These requirements may be reduced later.
-    /// Currently all characters in `a-zA-Z0-9_/`, no leading or trailing `/`s
+    /// Currently all characters in `a-zA-Z0-9_/`, no leading, trailing or
+    /// repeated `/`s, and within `MAX_TOPIC_LEN`, `MAX_COMPONENT_LEN`
+    /// and `MAX_DEPTH` limits.
     pub fn new(s: &str) -> Option<Self> {
-        if s.is_empty() {
-            return None;
-        }
-
-        if s.starts_with('/') || s.ends_with('/') {
-            return None;
-        }
-
-        for c in s.chars() {
-            if !(c.is_ascii_alphanumeric() || c == '_' || c == '/') {
-                return None;
-            }
+        if is_valid(s, false) {
+            Some(Self(s.to_owned()))
+        } else {
+            None
         }
-
-        Some(Self(s.to_owned()))
     }
 
     pub fn try_new(s: &str) -> Result<Self, result::Error> {
@@ -42,22 +71,13 @@ impl Topic {
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TopicPrefix(String);
 impl TopicPrefix {
-    /// Mirrors `Topic::new`, but allows all prefixes`
+    /// Mirrors `Topic::new`, but allows a trailing `/`
     pub fn new(s: &str) -> Option<Self> {
-        if s.is_empty() {
-            return None;
-        }
-
-        if s.starts_with('/') {
-            return None;
-        }
-
-        for c in s.chars() {
-            if !(c.is_ascii_alphanumeric() || c == '_' || c == '/') {
-                return None;
-            }
+        if is_valid(s, true) {
+            Some(Self(s.to_owned()))
+        } else {
+            None
         }
-        Some(Self(s.to_owned()))
     }
 
     pub fn try_new(s: &str) -> Result<Self, result::Error> {
@@ -81,7 +101,7 @@ impl TopicFilter {
         })
     }
 
-    fn inner(&self) -> &str {
+    pub(super) fn inner(&self) -> &str {
         match self {
             Self::Exact(t) => t.0.as_str(),
             Self::Prefix(t) => t.0.as_str(),
@@ -102,6 +122,24 @@ impl TopicFilter {
         }
     }
 
+    /// Can this match a topic starting with `prefix`?
+    /// Used to guard topic namespaces, so short prefix filters covering
+    /// the whole namespace are included.
+    pub fn covers_prefix(&self, prefix: &str) -> bool {
+        match self {
+            Self::Exact(a) => a.0.starts_with(prefix),
+            Self::Prefix(a) => a.0.starts_with(prefix) || prefix.starts_with(&a.0),
+        }
+    }
+
+    /// Is this an exact filter for `topic`?
+    pub fn is_exact(&self, topic: &str) -> bool {
+        match self {
+            Self::Exact(a) => a.0 == topic,
+            Self::Prefix(_) => false,
+        }
+    }
+
     /// Does this match a topic
     pub(super) fn matches(&self, other: &Topic) -> bool {
         match self {
@@ -110,3 +148,131 @@ impl TopicFilter {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_topic_valid() {
+        assert!(Topic::new("a").is_some());
+        assert!(Topic::new("netd/socket").is_some());
+        assert!(Topic::new("libd7/ipc/request/12/345").is_some());
+        assert!(Topic::new(&"a".repeat(MAX_COMPONENT_LEN)).is_some());
+    }
+
+    #[test]
+    fn test_topic_invalid() {
+        assert!(Topic::new("").is_none());
+        assert!(Topic::new("/").is_none());
+        assert!(Topic::new("/a").is_none());
+        assert!(Topic::new("a/").is_none());
+        assert!(Topic::new("a//b").is_none());
+        assert!(Topic::new("a/../b").is_none());
+        assert!(Topic::new("a/./b").is_none());
+        assert!(Topic::new("a\0b").is_none());
+        assert!(Topic::new("a b").is_none());
+        assert!(Topic::new("ä").is_none());
+        assert!(Topic::new(&"a".repeat(MAX_COMPONENT_LEN + 1)).is_none());
+        assert!(Topic::new(&vec!["a"; MAX_DEPTH + 1].join("/")).is_none());
+        assert!(Topic::new(&vec!["a".repeat(40); 7].join("/")).is_none());
+    }
+
+    #[test]
+    fn test_prefix() {
+        assert!(TopicPrefix::new("irq/").is_some());
+        assert!(TopicPrefix::new("irq").is_some());
+        assert!(TopicPrefix::new("/").is_none());
+        assert!(TopicPrefix::new("irq//").is_none());
+        assert!(TopicPrefix::new("a//b").is_none());
+        assert!(TopicPrefix::new("").is_none());
+    }
+
+    #[test]
+    fn test_covers_prefix() {
+        let exact = |s| TopicFilter::try_new(s, true).unwrap();
+        let prefix = |s| TopicFilter::try_new(s, false).unwrap();
+        assert!(exact("irq/1").covers_prefix("irq/"));
+        assert!(!exact("irq").covers_prefix("irq/"));
+        assert!(!exact("irqs/1").covers_prefix("irq/"));
+        assert!(prefix("irq/").covers_prefix("irq/"));
+        assert!(prefix("irq/1").covers_prefix("irq/"));
+        assert!(prefix("irq").covers_prefix("irq/"));
+        assert!(prefix("i").covers_prefix("irq/"));
+        assert!(!prefix("irqs").covers_prefix("irq/"));
+        assert!(!prefix("netd/").covers_prefix("irq/"));
+    }
+
+    /// Only the exact events topic of a process is allowed,
+    /// and short prefixes must not cover the others
+    #[test]
+    fn test_covers_prefix_events() {
+        let other_events = |f: &TopicFilter| {
+            f.covers_prefix("process/events/") && !f.is_exact("process/events/5")
+        };
+        let exact = |s| TopicFilter::try_new(s, true).unwrap();
+        let prefix = |s| TopicFilter::try_new(s, false).unwrap();
+        assert!(!other_events(&exact("process/events/5")));
+        assert!(!other_events(&exact("process/terminated")));
+        assert!(other_events(&exact("process/events/6")));
+        assert!(other_events(&exact("process/events/55")));
+        assert!(other_events(&prefix("process/events/5")));
+        assert!(other_events(&prefix("process/events/")));
+        assert!(other_events(&prefix("process/")));
+        assert!(other_events(&prefix("process")));
+        assert!(other_events(&prefix("p")));
+        assert!(!other_events(&prefix("process/terminated")));
+    }
+
+    /// Topics hidden by name, without a trailing slash, and their parent prefixes
+    #[test]
+    fn test_covers_prefix_hidden() {
+        let exact = |s| TopicFilter::try_new(s, true).unwrap();
+        let prefix = |s| TopicFilter::try_new(s, false).unwrap();
+        assert!(exact("process/terminated").covers_prefix("process/terminated"));
+        assert!(prefix("process/").covers_prefix("process/terminated"));
+        assert!(prefix("process/term").covers_prefix("process/terminated"));
+        assert!(!exact("process/events/5").covers_prefix("process/terminated"));
+        assert!(exact("system/shutdown").covers_prefix("system/shutdown"));
+        assert!(!exact("system/time").covers_prefix("system/shutdown"));
+        assert!(prefix("nic/").covers_prefix("nic/rtl8139/capture/"));
+        assert!(exact("nic/rtl8139/capture/read").covers_prefix("nic/rtl8139/capture/"));
+        assert!(!exact("nic/rtl8139/packet").covers_prefix("nic/rtl8139/capture/"));
+    }
+
+    /// Checks the invariants against random byte strings
+    #[test]
+    fn test_fuzz_random() {
+        let alphabet = b"ab_/0.\0 \xff";
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..10_000 {
+            let len = (next() % 80) as usize;
+            let bytes: Vec<u8> = (0..len)
+                .map(|_| alphabet[(next() as usize) % alphabet.len()])
+                .collect();
+            let s = match core::str::from_utf8(&bytes) {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+
+            if let Some(topic) = Topic::new(s) {
+                let t = topic.as_str();
+                assert!(!t.is_empty() && t.len() <= MAX_TOPIC_LEN);
+                assert!(!t.starts_with('/') && !t.ends_with('/'));
+                assert!(!t.contains("//") && !t.contains('.') && !t.contains('\0'));
+                assert!(TopicPrefix::new(s).is_some());
+            }
+
+            if TopicPrefix::new(s).is_some() {
+                assert!(!s.starts_with('/') && !s.contains("//"));
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Latency histograms for system calls and hardware interrupts.
+//!
+//! Durations are measured with the TSC and collected into log2-sized
+//! nanosecond buckets: bucket `0` holds everything below `2^FIRST_SHIFT` ns,
+//! bucket `i` the range `2^(FIRST_SHIFT + i - 1) .. 2^(FIRST_SHIFT + i)` ns,
+//! and the last bucket everything above that. System calls are tracked per
+//! syscall number and interrupts per vector, from the start of the handler
+//! until it has done its work, excluding the switch to the next process.
+//!
+//! Like the interrupt counters, the histograms are plain statics, so
+//! recording never allocates. Samples taken before the TSC frequency
+//! has been measured are dropped.
+
+use alloc::prelude::v1::*;
+use core::convert::TryFrom;
+use core::sync::atomic::Ordering;
+
+use crate::driver::tsc;
+use crate::interrupt::stats::{counter, FIRST_VECTOR, LAST_VECTOR};
+
+const BUCKETS: usize = 24;
+const FIRST_SHIFT: u32 = 8;
+
+/// Syscall numbers are below `0x100`, larger ones are not recorded
+const SYSCALL_COUNT: usize = 0x100;
+const VECTOR_COUNT: usize = (LAST_VECTOR - FIRST_VECTOR) as usize + 1;
+
+type Histogram = [u64; BUCKETS];
+
+/// Indexed by syscall number. Only accessed through `counter`.
+static mut SYSCALLS: [Histogram; SYSCALL_COUNT] = [[0; BUCKETS]; SYSCALL_COUNT];
+/// Indexed by `vector - FIRST_VECTOR`. Only accessed through `counter`.
+static mut INTERRUPTS: [Histogram; VECTOR_COUNT] = [[0; BUCKETS]; VECTOR_COUNT];
+
+fn bucket_of(ns: u64) -> usize {
+    let bits = 64 - ns.leading_zeros();
+    (bits.saturating_sub(FIRST_SHIFT) as usize).min(BUCKETS - 1)
+}
+
+/// Exclusive upper bound of a bucket in ns, `None` for the last one
+fn bucket_limit(bucket: usize) -> Option<u64> {
+    if bucket + 1 < BUCKETS {
+        Some(1 << (FIRST_SHIFT as usize + bucket))
+    } else {
+        None
+    }
+}
+
+fn record(histogram: &mut Histogram, start: u64) {
+    if tsc::try_freq_hz().is_none() {
+        return;
+    }
+    let ns = tsc::ticks_to_ns(tsc::read().saturating_sub(start));
+    counter(&mut histogram[bucket_of(ns)]).fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record the duration of a system call that started at TSC value `start`
+pub fn record_syscall(routine: u64, start: u64) {
+    if (routine as usize) < SYSCALL_COUNT {
+        record(unsafe { &mut SYSCALLS[routine as usize] }, start);
+    }
+}
+
+/// Measures an interrupt handler until dropped
+#[must_use]
+pub struct IrqTimer {
+    vector: u8,
+    start: u64,
+}
+impl IrqTimer {
+    pub fn start(vector: u8) -> Self {
+        Self {
+            vector,
+            start: tsc::read(),
+        }
+    }
+}
+impl Drop for IrqTimer {
+    fn drop(&mut self) {
+        if (FIRST_VECTOR..=LAST_VECTOR).contains(&self.vector) {
+            let index = (self.vector - FIRST_VECTOR) as usize;
+            record(unsafe { &mut INTERRUPTS[index] }, self.start);
+        }
+    }
+}
+
+fn snapshot(histogram: &mut Histogram) -> Histogram {
+    let mut result = [0; BUCKETS];
+    for (i, value) in result.iter_mut().enumerate() {
+        *value = counter(&mut histogram[i]).load(Ordering::Relaxed);
+    }
+    result
+}
+
+fn format_ns(ns: u64) -> String {
+    if ns >= 1_000_000_000 {
+        format!("{}s", ns / 1_000_000_000)
+    } else if ns >= 1_000_000 {
+        format!("{}ms", ns / 1_000_000)
+    } else if ns >= 1_000 {
+        format!("{}us", ns / 1_000)
+    } else {
+        format!("{}ns", ns)
+    }
+}
+
+/// Upper bound of the bucket containing the given percentile
+fn percentile(histogram: &Histogram, total: u64, percent: u64) -> String {
+    let target = (total * percent + 99) / 100;
+    let mut seen = 0;
+    for (bucket, count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen >= target {
+            return match bucket_limit(bucket) {
+                Some(limit) => format!("<{}", format_ns(limit)),
+                None => format!(">{}", format_ns(1 << (FIRST_SHIFT as usize + bucket - 1))),
+            };
+        }
+    }
+    unreachable!()
+}
+
+fn row(name: String, histogram: &Histogram) -> Option<String> {
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let mut line = format!(
+        "{:<24} {:>10} {:>8} {:>8} {:>8}",
+        name,
+        total,
+        percentile(histogram, total, 50),
+        percentile(histogram, total, 90),
+        percentile(histogram, total, 99),
+    );
+    for (bucket, &count) in histogram.iter().enumerate() {
+        if count != 0 {
+            match bucket_limit(bucket) {
+                Some(limit) => line.push_str(&format!(" <{}:{}", format_ns(limit), count)),
+                None => line.push_str(&format!(" rest:{}", count)),
+            }
+        }
+    }
+    Some(line)
+}
+
+/// Histograms as a text table, one row per syscall or vector that has samples.
+/// Each row lists the sample count, bucket bounds of the 50th, 90th and 99th
+/// percentiles, and the non-empty buckets.
+pub fn table() -> Vec<String> {
+    let mut lines = vec![format!(
+        "{:<24} {:>10} {:>8} {:>8} {:>8} buckets",
+        "source", "count", "p50", "p90", "p99"
+    )];
+
+    for routine in 0..SYSCALL_COUNT {
+        let histogram = snapshot(unsafe { &mut SYSCALLS[routine] });
+        let name = match d7abi::SyscallNumber::try_from(routine as u64) {
+            Ok(number) => format!("{:?}", number),
+            Err(_) => format!("syscall {:#04x}", routine),
+        };
+        lines.extend(row(name, &histogram));
+    }
+
+    for index in 0..VECTOR_COUNT {
+        let histogram = snapshot(unsafe { &mut INTERRUPTS[index] });
+        let vector = FIRST_VECTOR + index as u8;
+        lines.extend(row(format!("irq {:#04x}", vector), &histogram));
+    }
+
+    lines
+}
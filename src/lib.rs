@@ -63,12 +63,18 @@ mod driver;
 
 // Everything else
 mod cpuid;
+mod crashdump;
+mod init_process;
 mod initrd;
 mod interrupt;
 mod ipc;
+mod latency;
 mod memory;
 mod multitasking;
+mod panic_action;
+mod selftest;
 mod services;
+mod shutdown;
 mod smp;
 mod syscall;
 mod syslog;
@@ -91,11 +97,14 @@ pub extern "C" fn rust_main() -> ! {
     cpuid::init();
     driver::uart::init();
     driver::tsc::init();
+    time::init_time_page();
     unsafe {
         driver::acpi::init();
         driver::ioapic::init_bsp();
         smp::start_all();
     }
+    crashdump::init();
+    multitasking::cleanup::init();
     services::init();
 
     rreset!();
@@ -103,13 +112,14 @@ pub extern "C" fn rust_main() -> ! {
 
     syslog::disable_direct_vga();
 
-    // Start service daemon
-    crate::memory::configure(|mut mem_ctrl| {
-        let mut sched = SCHEDULER.lock();
+    if memory::constants::SELFTEST {
+        selftest::run();
+    }
 
-        let bytes = crate::initrd::read("serviced").expect("serviced missing from initrd");
-        let elfimage = multitasking::process::load_elf(mem_ctrl, bytes);
-        sched.spawn(mem_ctrl, elfimage);
+    // Start service daemon, or a fallback
+    crate::memory::configure(|mem_ctrl| {
+        let mut sched = SCHEDULER.lock();
+        init_process::start(mem_ctrl, &mut sched);
     });
 
     // Hand over to the process scheduler
@@ -130,11 +140,14 @@ pub unsafe extern "C" fn rust_ap_get_stack() -> u64 {
 #[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn rust_ap_main() -> ! {
+    // The log prefix reads the APIC id, which needs the APIC mode of the BSP
+    driver::ioapic::lapic::enable_mode();
+
     log::info!("AP core online, getting id...");
     let processor_id = smp::current_processor_id();
     log::info!("AP core {} online", processor_id);
 
-    interrupt::init_smp_ap();
+    interrupt::init_smp_ap(processor_id);
     log::info!("Interrupt handler initialized");
 
     driver::ioapic::per_processor_init();
@@ -144,14 +157,21 @@ pub extern "C" fn rust_ap_main() -> ! {
     log::info!("AP core {} ready", processor_id);
 
     log::trace!("INTO @ {}", self::driver::ioapic::lapic::processor_id());
-    loop {
-        self::driver::tsc::sleep_ns(1_000_000_000);
+    while !smp::ap_should_stop() {
+        self::driver::tsc::sleep_ns(1_000_000);
         // log::info!("TICK @ {}", self::driver::ioapic::lapic::processor_id());
     }
+
+    log::info!("AP core {} stopping", processor_id);
+    smp::ap_mark_stopped();
+    loop {
+        unsafe {
+            asm!("cli; hlt");
+        }
+    }
 }
 
-#[global_allocator]
-#[cfg(not(test))]
+#[cfg_attr(not(test), global_allocator)]
 static HEAP_ALLOCATOR: d7alloc::GlobAlloc = d7alloc::GlobAlloc::new(d7alloc::BumpAllocator::new(
     d7alloc::HEAP_START,
     d7alloc::HEAP_START + d7alloc::HEAP_SIZE,
@@ -206,6 +226,9 @@ extern "C" fn panic(info: &PanicInfo) -> ! {
             // Stop other cores as well
             driver::ioapic::broadcast_ipi(false, 0xdd);
 
+            crashdump::write_panic(info);
+            panic_action::run();
+
             asm!("jmp panic_stop");
         } else {
             panic_indicator!(0x4f254f21); // !%
@@ -1,7 +1,14 @@
 //! DMA / VirtIO memory buffers (requiring "low" memory)
+//!
+//! Drivers hold the physical addresses of their buffers, so allocated blocks
+//! cannot be moved to compact the region. Instead, freed blocks are merged
+//! back to the free runs immediately, and an allocation that doesn't fit
+//! fails with an error instead of panicking. The counters in `stats` tell
+//! whether failures are caused by fragmentation or by the region being full.
 
-use x86_64::structures::paging as pg;
-use x86_64::{PhysAddr, VirtAddr};
+use alloc::prelude::v1::*;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::PhysAddr;
 
 use super::super::constants::{DMA_MEMORY_SIZE, DMA_MEMORY_START};
 
@@ -12,9 +19,17 @@ const fn round_up_block(s: usize) -> usize {
     (s + (DMA_BLOCK_SIZE - 1)) / DMA_BLOCK_SIZE
 }
 
+/// For sizes given by processes, `None` on overflow
+fn checked_round_up_block(s: usize) -> Option<usize> {
+    Some(s.checked_add(DMA_BLOCK_SIZE - 1)? / DMA_BLOCK_SIZE)
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum BlockState {
     Free,
+    /// First block of an allocation
+    Start,
+    /// Rest of the blocks of an allocation
     Used,
 }
 pub struct Allocator {
@@ -25,42 +40,99 @@ pub struct Allocator {
 impl Allocator {
     /// Unsafe, as the caller is responsibe that this is not intialized multiple times
     pub unsafe fn new() -> Self {
-        Self {
+        let result = Self {
             blocks: [BlockState::Free; DMA_BLOCKS],
-        }
+        };
+        result.update_stats();
+        result
     }
 
-    pub fn allocate(&mut self, size: usize) -> DMARegion {
+    /// Allocates the first free run that is large enough,
+    /// or returns `None` if there is no such run
+    pub fn allocate(&mut self, size: usize) -> Option<DMARegion> {
         assert!(size != 0);
 
-        let size_blocks = round_up_block(size) as usize;
+        let size_blocks = checked_round_up_block(size).unwrap_or(usize::MAX);
+        let result = self.find_free_run(size_blocks).map(|start| {
+            self.blocks[start] = BlockState::Start;
+            for offset in 1..size_blocks {
+                self.blocks[start + offset] = BlockState::Used;
+            }
+            DMARegion {
+                start: DMA_MEMORY_START + start * DMA_BLOCK_SIZE,
+                size_blocks,
+            }
+        });
 
-        if size_blocks > self.blocks.len() {
-            panic!("Not enough of DMA memory");
+        if result.is_some() {
+            stats::ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        } else {
+            stats::FAILURES.fetch_add(1, Ordering::Relaxed);
+            log::warn!(
+                "DMA allocation of {} blocks failed, largest free run is {} blocks",
+                size_blocks,
+                self.largest_free_run()
+            );
         }
+        self.update_stats();
+        result
+    }
 
-        'outer: for start in 0..(self.blocks.len() - size_blocks) {
-            for offset in 0..size_blocks {
-                if self.blocks[start + offset] != BlockState::Free {
-                    continue 'outer;
-                }
+    fn find_free_run(&self, size_blocks: usize) -> Option<usize> {
+        let mut run_start = 0;
+        for (index, state) in self.blocks.iter().enumerate() {
+            if *state != BlockState::Free {
+                run_start = index + 1;
+            } else if index + 1 - run_start == size_blocks {
+                return Some(run_start);
             }
+        }
+        None
+    }
 
-            for offset in 0..size_blocks {
-                self.blocks[start + offset] = BlockState::Used;
+    fn largest_free_run(&self) -> usize {
+        let mut largest = 0;
+        let mut current = 0;
+        for state in self.blocks.iter() {
+            if *state == BlockState::Free {
+                current += 1;
+                largest = largest.max(current);
+            } else {
+                current = 0;
             }
+        }
+        largest
+    }
 
-            return DMARegion {
-                start: DMA_MEMORY_START + start * DMA_BLOCK_SIZE,
-                size_blocks,
-            };
+    /// Frees an allocated region. Returns `false` if the region
+    /// doesn't match an allocation exactly, e.g. when freed twice.
+    pub fn free(&mut self, region: DMARegion) -> bool {
+        let offset = region.start - DMA_MEMORY_START;
+        if region.size_blocks == 0 || offset % DMA_BLOCK_SIZE as u64 != 0 {
+            return false;
+        }
+        let start = (offset / DMA_BLOCK_SIZE as u64) as usize;
+        let end = start + region.size_blocks;
+        if end > self.blocks.len()
+            || self.blocks[start] != BlockState::Start
+            || self.blocks[start + 1..end].iter().any(|b| *b != BlockState::Used)
+            || self.blocks.get(end) == Some(&BlockState::Used)
+        {
+            return false;
         }
 
-        panic!("Out of DMA memory");
+        for block in &mut self.blocks[start..end] {
+            *block = BlockState::Free;
+        }
+        stats::FREES.fetch_add(1, Ordering::Relaxed);
+        self.update_stats();
+        true
     }
 
-    pub fn free(&mut self, region: DMARegion) {
-        todo!()
+    fn update_stats(&self) {
+        let free = self.blocks.iter().filter(|b| **b == BlockState::Free).count();
+        stats::FREE_BLOCKS.store(free as u64, Ordering::Relaxed);
+        stats::LARGEST_FREE_RUN.store(self.largest_free_run() as u64, Ordering::Relaxed);
     }
 }
 
@@ -69,3 +141,42 @@ pub struct DMARegion {
     pub start: PhysAddr,
     size_blocks: usize,
 }
+impl DMARegion {
+    /// Region given by a process, `None` if it doesn't start inside the DMA memory
+    /// or the size overflows. The rest is validated on `Allocator::free`.
+    pub fn from_raw(start: u64, size: usize) -> Option<Self> {
+        let dma_start = DMA_MEMORY_START.as_u64();
+        if start < dma_start || start >= dma_start + DMA_MEMORY_SIZE as u64 {
+            return None;
+        }
+        Some(Self {
+            start: PhysAddr::new(start),
+            size_blocks: checked_round_up_block(size)?,
+        })
+    }
+}
+
+/// Counters updated by the allocator, readable without locking the memory controller
+pub mod stats {
+    use super::*;
+
+    pub(super) static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+    pub(super) static FREES: AtomicU64 = AtomicU64::new(0);
+    pub(super) static FAILURES: AtomicU64 = AtomicU64::new(0);
+    pub(super) static FREE_BLOCKS: AtomicU64 = AtomicU64::new(0);
+    pub(super) static LARGEST_FREE_RUN: AtomicU64 = AtomicU64::new(0);
+
+    /// Counters as text lines, sizes in bytes
+    pub fn table() -> Vec<String> {
+        let read = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let block = DMA_BLOCK_SIZE as u64;
+        vec![
+            format!("{:<20} {:>12}", "total", DMA_BLOCKS as u64 * block),
+            format!("{:<20} {:>12}", "free", read(&FREE_BLOCKS) * block),
+            format!("{:<20} {:>12}", "largest free run", read(&LARGEST_FREE_RUN) * block),
+            format!("{:<20} {:>12}", "allocations", read(&ALLOCATIONS)),
+            format!("{:<20} {:>12}", "frees", read(&FREES)),
+            format!("{:<20} {:>12}", "failed allocations", read(&FAILURES)),
+        ]
+    }
+}
@@ -8,6 +8,7 @@ use x86_64::PhysAddr;
 use super::super::area::PhysMemoryRange;
 use super::super::map::MAX_OK_ENTRIES;
 use super::super::prelude::*;
+use super::super::pressure;
 
 pub struct Allocator {
     /// Physical memory map, i.e. usable ram regions
@@ -23,12 +24,14 @@ pub struct Allocator {
 impl Allocator {
     /// Unsafe, as the caller is responsibe that this is not intialized multiple times
     pub unsafe fn new(areas: [Option<PhysMemoryRange>; MAX_OK_ENTRIES]) -> Self {
+        let total_frames = areas
+            .iter()
+            .filter_map(|opt| opt.map(|a| a.size_pages() as usize))
+            .sum();
+        pressure::update(total_frames, total_frames);
         Self {
             areas,
-            total_frames: areas
-                .iter()
-                .filter_map(|opt| opt.map(|a| a.size_pages() as usize))
-                .sum(),
+            total_frames,
             next_free: 0,
             // bookkeep_frames: 0,
         }
@@ -51,6 +54,11 @@ impl Allocator {
     fn is_free(&self, index: usize) -> bool {
         self.next_free <= index
     }
+
+    /// Number of frames that can still be allocated
+    pub fn free_frame_count(&self) -> usize {
+        self.total_frames - self.next_free
+    }
 }
 unsafe impl pg::FrameAllocator<PageSizeType> for Allocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
@@ -60,6 +68,7 @@ unsafe impl pg::FrameAllocator<PageSizeType> for Allocator {
             let frame = PhysFrame::from_start_address(self.to_page_addr(self.next_free))
                 .expect("to_page_addr generated misaligned address");
             self.next_free += 1;
+            pressure::update(self.total_frames - self.next_free, self.total_frames);
             Some(frame)
         }
     }
@@ -2,11 +2,16 @@
 
 use x86_64::structures::paging::PageTableFlags as Flags;
 
+use alloc::string::String;
+
 use crate::memory::{self, prelude::*};
 
 use super::super::MemoryController;
 
-/// Creates and maps the system call stack.
+/// Part of the page used as the stack, `kernel_syscall_stack_size` in `process_common.asm`
+const SIZE_BYTES: u64 = 0x2_0000;
+
+/// Creates and maps the system call stack, and fills it for `kernel_stacks`.
 /// There is no need to zero the memory, as it will not be read,
 /// and it is inaccessible for user processes.
 pub fn init() {
@@ -25,5 +30,11 @@ pub fn init() {
                 Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE,
             )
             .flush();
+
+        memory::kernel_stacks::track(
+            String::from("syscall"),
+            memory::SYSCALL_STACK,
+            memory::SYSCALL_STACK + SIZE_BYTES,
+        );
     });
 }
@@ -0,0 +1,47 @@
+//! Kernel heap usage levels, published to userspace as `memory/kernel_heap`.
+//!
+//! The heap is a bump allocator that never reuses freed memory, so its usage
+//! only grows, and is also its high watermark. The scheduler checks the level
+//! on its tick, and logs a warning when it has risen, so that a leak in the
+//! kernel is noticed before allocations fail and the kernel stops.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use d7abi::ipc::protocol::{KernelHeap, MemoryPressureLevel};
+
+use super::prelude::*;
+
+/// Level of the latest `changed` result, `Normal` before that
+static PUBLISHED: AtomicU8 = AtomicU8::new(MemoryPressureLevel::Normal as u8);
+
+fn level_of(used: u64, size: u64) -> MemoryPressureLevel {
+    let free = size.saturating_sub(used);
+    if free * 100 <= size * KERNEL_HEAP_CRITICAL_PERCENT {
+        MemoryPressureLevel::Critical
+    } else if free * 100 <= size * KERNEL_HEAP_LOW_PERCENT {
+        MemoryPressureLevel::Low
+    } else {
+        MemoryPressureLevel::Normal
+    }
+}
+
+pub fn current() -> KernelHeap {
+    let used = crate::HEAP_ALLOCATOR.used_bytes();
+    let size = crate::HEAP_ALLOCATOR.size_bytes();
+    KernelHeap {
+        level: level_of(used, size),
+        used_bytes: used,
+        size_bytes: size,
+    }
+}
+
+/// The current state, if the level has changed since the previous call
+pub fn changed() -> Option<KernelHeap> {
+    let state = current();
+    let previous = PUBLISHED.swap(state.level as u8, Ordering::Relaxed);
+    if previous != state.level as u8 {
+        Some(state)
+    } else {
+        None
+    }
+}
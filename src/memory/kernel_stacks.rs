@@ -0,0 +1,100 @@
+//! High-water marks of kernel stacks.
+//!
+//! Kernel stacks have a fixed size, so a deep call chain can overflow them.
+//! Tracked stacks are filled with `FILL_PATTERN` before use, and `scan` finds
+//! the lowest overwritten word of each, which is the deepest the stack has
+//! been used. Scanning reads the whole unused part of the stacks, so it is
+//! only done on request, from the `memory/kernel_stacks` kernel service and
+//! at shutdown, and not on every tick. Stacks that come close to their size
+//! can be made larger with the `*_STACK_SIZE_PAGES` constants.
+//! The boot stack of the BSP is set up by the bootloader, and is not tracked.
+
+use alloc::prelude::v1::*;
+use core::ptr;
+use spin::Mutex;
+
+use super::prelude::*;
+use super::{MemoryController, Stack};
+
+const FILL_PATTERN: u64 = 0x57ac_57ac_57ac_57ac;
+
+/// Usage, as a percentage of the size, above which a grown mark is a warning
+const WARN_PERCENT: u64 = 75;
+
+struct Tracked {
+    name: String,
+    bottom: VirtAddr,
+    top: VirtAddr,
+    /// Deepest usage seen by `scan`, in bytes
+    max_depth: u64,
+}
+impl Tracked {
+    fn size(&self) -> u64 {
+        self.top.as_u64() - self.bottom.as_u64()
+    }
+
+    /// Bytes from the top to the lowest overwritten word
+    fn measure(&self) -> u64 {
+        let mut addr = self.bottom;
+        while addr < self.top {
+            let value = unsafe { ptr::read_volatile(addr.as_ptr::<u64>()) };
+            if value != FILL_PATTERN {
+                break;
+            }
+            addr += 8u64;
+        }
+        self.top.as_u64() - addr.as_u64()
+    }
+}
+
+static STACKS: Mutex<Vec<Tracked>> = Mutex::new(Vec::new());
+
+/// Fills a stack with the pattern, and tracks its usage from now on.
+/// # Safety
+/// The stack must be mapped, and must not be in use yet.
+pub unsafe fn track(name: String, bottom: VirtAddr, top: VirtAddr) {
+    let words = (top.as_u64() - bottom.as_u64()) / 8;
+    for i in 0..words {
+        ptr::write_volatile(bottom.as_mut_ptr::<u64>().add(i as usize), FILL_PATTERN);
+    }
+    STACKS.lock().push(Tracked {
+        name,
+        bottom,
+        top,
+        max_depth: 0,
+    });
+}
+
+/// Allocates a stack and tracks it
+pub fn alloc(mm: &mut MemoryController, name: String, size_in_pages: usize) -> Option<Stack> {
+    let stack = mm.alloc_stack(size_in_pages)?;
+    unsafe { track(name, stack.bottom, stack.top) };
+    Some(stack)
+}
+
+/// Updates the high-water marks, and logs the ones that have grown.
+/// Returns the marks as text lines, sizes in bytes.
+pub fn scan() -> Vec<String> {
+    let mut stacks = STACKS.lock();
+    let mut lines = vec![format!(
+        "{:<16} {:>12} {:>12} {:>4}",
+        "stack", "max depth", "size", "%"
+    )];
+    for stack in stacks.iter_mut() {
+        let depth = stack.measure();
+        let percent = depth * 100 / stack.size();
+        if depth > stack.max_depth {
+            stack.max_depth = depth;
+            if percent >= WARN_PERCENT {
+                log::warn!("Kernel stack {} used {} bytes ({}%)", stack.name, depth, percent);
+            } else {
+                log::debug!("Kernel stack {} used {} bytes ({}%)", stack.name, depth, percent);
+            }
+        }
+        lines.push(format!(
+            "{:<16} {:>12} {:>12} {:>4}",
+            stack.name, stack.max_depth, stack.size(), percent
+        ));
+    }
+    lines
+}
@@ -11,11 +11,16 @@ use x86_64::structures::paging::PageTableFlags as Flags;
 mod allocators;
 mod area;
 pub mod constants;
+pub mod heap;
+pub mod kernel_stacks;
 mod map;
 pub mod paging;
 pub mod prelude;
+pub mod pressure;
+pub mod shared;
 mod utils;
 
+use crate::multitasking::process::{MemoryRegion, MemoryRegionKind};
 use crate::multitasking::{ElfImage, Process};
 use crate::util::elf_parser::{self, ELFData, ELFHeader, ELFProgramHeader};
 
@@ -284,6 +289,8 @@ impl MemoryController {
     }
 
     /// Allocate or free memory for a process.
+    /// Returns `None` if there are not enough free frames.
+    /// Freed frames are leaked, like with all deallocations for now.
     ///
     /// This function flushes the TLB multiple times.
     ///
@@ -302,6 +309,13 @@ impl MemoryController {
 
         assert!(old_size_bytes == page_align_u64(old_size_bytes, false));
         let new_size_bytes = page_align_u64(new_size_bytes, true);
+        let new_frame_count = new_size_bytes / PAGE_SIZE_BYTES;
+
+        if new_frame_count > old_frame_count
+            && new_frame_count - old_frame_count > self.frame_allocator.free_frame_count() as u64
+        {
+            return None;
+        }
 
         // Map process tables to kernel memory
         let tmp_area = self.alloc_virtual_area(1);
@@ -336,9 +350,34 @@ impl MemoryController {
 
             // Store frame information into the Process struct
             process.dynamic_memory_frames.extend(new_frames);
+            process.set_memory_region(MemoryRegion {
+                start: PROCESS_DYNAMIC_MEMORY,
+                size_bytes: new_size_bytes,
+                kind: MemoryRegionKind::Dynamic,
+                writable: true,
+                executable: false,
+            });
         } else {
             // Deallocate memory
-            unimplemented!("Process: deallocate memory");
+            for i in new_frame_count..old_frame_count {
+                let page =
+                    Page::from_start_address(PROCESS_DYNAMIC_MEMORY + i * PAGE_SIZE_BYTES).unwrap();
+
+                unsafe {
+                    process.page_table.unmap(tmp_area.start, page).ignore();
+                }
+            }
+
+            process
+                .dynamic_memory_frames
+                .truncate(new_frame_count as usize);
+            process.set_memory_region(MemoryRegion {
+                start: PROCESS_DYNAMIC_MEMORY,
+                size_bytes: new_size_bytes,
+                kind: MemoryRegionKind::Dynamic,
+                writable: true,
+                executable: false,
+            });
         }
 
         // Unmap process tables
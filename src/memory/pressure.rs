@@ -0,0 +1,53 @@
+//! Free physical memory levels, published to userspace as `memory/pressure`,
+//! so that processes can release cached memory before allocations fail.
+//!
+//! The frame allocator updates the counters on every allocation, and the
+//! scheduler publishes the level on its tick when it has changed.
+
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use d7abi::ipc::protocol::{MemoryPressure, MemoryPressureLevel};
+
+use super::prelude::*;
+
+static TOTAL_FRAMES: AtomicU64 = AtomicU64::new(0);
+static FREE_FRAMES: AtomicU64 = AtomicU64::new(0);
+/// Level of the latest `changed` result, `Normal` before that
+static PUBLISHED: AtomicU8 = AtomicU8::new(MemoryPressureLevel::Normal as u8);
+
+/// Called by the frame allocator
+pub(super) fn update(free_frames: usize, total_frames: usize) {
+    FREE_FRAMES.store(free_frames as u64, Ordering::Relaxed);
+    TOTAL_FRAMES.store(total_frames as u64, Ordering::Relaxed);
+}
+
+fn level_of(free: u64, total: u64) -> MemoryPressureLevel {
+    if free * 100 <= total * MEMORY_PRESSURE_CRITICAL_PERCENT {
+        MemoryPressureLevel::Critical
+    } else if free * 100 <= total * MEMORY_PRESSURE_LOW_PERCENT {
+        MemoryPressureLevel::Low
+    } else {
+        MemoryPressureLevel::Normal
+    }
+}
+
+pub fn current() -> MemoryPressure {
+    let free = FREE_FRAMES.load(Ordering::Relaxed);
+    let total = TOTAL_FRAMES.load(Ordering::Relaxed);
+    MemoryPressure {
+        level: level_of(free, total),
+        free_bytes: free * PAGE_SIZE_BYTES,
+        total_bytes: total * PAGE_SIZE_BYTES,
+    }
+}
+
+/// The current state, if the level has changed since the previous call
+pub fn changed() -> Option<MemoryPressure> {
+    let state = current();
+    let previous = PUBLISHED.swap(state.level as u8, Ordering::Relaxed);
+    if previous != state.level as u8 {
+        Some(state)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,99 @@
+//! Named shared memory objects, for transferring large amounts of data
+//! between processes without copying it through IPC messages.
+//!
+//! A process creates an object with `shm_create`, and other processes map
+//! the same frames with `shm_map`. Objects are never removed, as frames
+//! cannot be freed yet, so their total size is limited by `SHARED_MEMORY_QUOTA`,
+//! and only drivers can create them. The creator chooses the lowest privilege
+//! level of the other processes that can map the object, and processes with
+//! a restricted view can only map their own objects.
+//! Processes synchronize their access themselves, e.g. with futexes and IPC.
+
+use alloc::prelude::v1::*;
+use hashbrown::HashMap;
+use spin::Mutex;
+
+use super::prelude::*;
+use super::MemoryController;
+use crate::multitasking::process::Privilege;
+use crate::multitasking::{Process, ProcessId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// An object with the name already exists
+    Exists,
+    NotFound,
+    /// More bytes requested than the object has
+    TooLarge,
+    /// The shared memory quota would be exceeded
+    Quota,
+    /// The process is not allowed to map the object
+    Permission,
+}
+
+struct Object {
+    frames: Vec<PhysFrame>,
+    /// Address space of the creating process, which can always map the object
+    creator: ProcessId,
+    /// Lowest privilege level of the other processes that can map the object
+    privilege: Privilege,
+}
+impl Object {
+    fn can_map(&self, process: &Process) -> bool {
+        process.address_space_owner() == self.creator
+            || (!process.restricted_view && process.privilege >= self.privilege)
+    }
+}
+
+struct Objects {
+    objects: HashMap<String, Object>,
+    total_bytes: u64,
+}
+
+lazy_static::lazy_static! {
+    static ref OBJECTS: Mutex<Objects> = Mutex::new(Objects {
+        objects: HashMap::new(),
+        total_bytes: 0,
+    });
+}
+
+/// Creates a zeroed object, rounded up to whole pages, and returns its frames.
+/// Other processes need at least `privilege` to map it.
+pub fn create(
+    mm: &mut MemoryController, creator: &Process, name: &str, size_bytes: u64,
+    privilege: Privilege,
+) -> Result<Vec<PhysFrame>, Error> {
+    let size_bytes = page_align_u64(size_bytes, true);
+    let mut objects = OBJECTS.lock();
+    if objects.objects.contains_key(name) {
+        return Err(Error::Exists);
+    }
+    if objects.total_bytes + size_bytes > SHARED_MEMORY_QUOTA {
+        return Err(Error::Quota);
+    }
+
+    let frames = mm.alloc_frames_zeroed((size_bytes / PAGE_SIZE_BYTES) as usize);
+    objects.total_bytes += size_bytes;
+    objects.objects.insert(name.to_owned(), Object {
+        frames: frames.clone(),
+        creator: creator.address_space_owner(),
+        privilege,
+    });
+    log::debug!("Shared memory object {:?} created, {:#x} bytes", name, size_bytes);
+    Ok(frames)
+}
+
+/// Frames covering the first `size_bytes` bytes of an object, for mapping into `process`
+pub fn frames(process: &Process, name: &str, size_bytes: u64) -> Result<Vec<PhysFrame>, Error> {
+    let objects = OBJECTS.lock();
+    let object = objects.objects.get(name).ok_or(Error::NotFound)?;
+    if !object.can_map(process) {
+        return Err(Error::Permission);
+    }
+    let frames = &object.frames;
+    let count = (page_align_u64(size_bytes, true) / PAGE_SIZE_BYTES) as usize;
+    if count > frames.len() {
+        return Err(Error::TooLarge);
+    }
+    Ok(frames[..count].to_vec())
+}
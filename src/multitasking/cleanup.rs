@@ -0,0 +1,61 @@
+//! Cleanup of per-process kernel state when a process terminates.
+//!
+//! Subsystems that keep state by `ProcessId` outside of the scheduler
+//! register a hook here, instead of `Scheduler::terminate` calling each
+//! of them directly. Hooks run after the process has been removed from
+//! the scheduler queues, ordered by `Stage`, and in registration order
+//! within a stage. The scheduler's own state, e.g. timers, futexes and
+//! threads, is still cleaned up by `terminate` itself.
+
+use alloc::prelude::v1::*;
+use spin::Mutex;
+
+use super::process::ProcessResult;
+use super::{ProcessId, Scheduler};
+
+/// When a hook runs, relative to the other hooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Release resources that other processes may be waiting for,
+    /// e.g. IPC subscriptions with undelivered messages
+    Release,
+    /// Drop bookkeeping that nothing waits for
+    Forget,
+}
+
+pub type Hook = fn(&mut Scheduler, ProcessId, &ProcessResult);
+
+static HOOKS: Mutex<Vec<(Stage, &'static str, Hook)>> = Mutex::new(Vec::new());
+
+pub fn init() {
+    register(Stage::Release, "ipc", |sched, pid, status| {
+        crate::ipc::with_manager(sched, |ipc_manager| {
+            ipc_manager.on_process_over(pid, status.clone())
+        })
+        .unwrap()
+    });
+    register(Stage::Forget, "time namespace", |_, pid, _| {
+        crate::time::leave_namespace(pid)
+    });
+    register(Stage::Forget, "init supervision", |sched, pid, status| {
+        crate::init_process::on_process_over(sched, pid, status)
+    });
+}
+
+/// Adds a hook, run for every process terminated after this
+pub fn register(stage: Stage, name: &'static str, hook: Hook) {
+    let mut hooks = HOOKS.lock();
+    hooks.push((stage, name, hook));
+    // Stable, so the registration order is kept within a stage
+    hooks.sort_by_key(|(stage, _, _)| *stage);
+}
+
+/// Runs all hooks for a terminated process
+pub(super) fn run(sched: &mut Scheduler, pid: ProcessId, status: &ProcessResult) {
+    // Not locked while running, as a hook may terminate other processes
+    let hooks = HOOKS.lock().clone();
+    for (_, name, hook) in hooks {
+        log::trace!("Cleanup of pid {}: {}", pid, name);
+        hook(sched, pid, status);
+    }
+}
@@ -21,10 +21,10 @@ impl ElfImage {
         }
     }
 
-    pub fn verify(&self) {
-        unsafe {
-            parse_elf(self.as_ptr() as usize).expect("Invalid ELF image");
-        }
+    /// Validates an untrusted image of `len` bytes
+    pub fn verify(&self, len: usize) -> Result<(), ELFParsingError> {
+        assert!(len as u64 <= self.area.size_bytes());
+        unsafe { parse_elf_checked(self.as_ptr() as usize, len).map(|_| ()) }
     }
 
     pub fn as_ptr(&self) -> *const u8 {
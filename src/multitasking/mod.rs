@@ -1,7 +1,10 @@
+pub mod cleanup;
 mod loader;
+mod policy;
 pub mod process;
 mod queues;
 mod scheduler;
+pub mod stats;
 mod waitfor;
 
 pub use self::loader::ElfImage;
@@ -0,0 +1,144 @@
+use alloc::collections::VecDeque;
+use alloc::prelude::v1::*;
+
+use crate::multitasking::ProcessId;
+use crate::time::BSPInstant;
+
+use super::{Policy, Wake};
+
+/// Latency targets, by the reason the process became runnable
+const EVENT_LATENCY_NS: u64 = 1_000_000;
+const CONDITION_LATENCY_NS: u64 = 10_000_000;
+const REQUEUE_LATENCY_NS: u64 = 100_000_000;
+
+/// Earliest deadline first. A process that becomes runnable gets a deadline
+/// based on why it was woken, so processes woken by events run soon,
+/// and processes that used their time slice wait for the others.
+///
+/// A runnable process with an earlier deadline than the current process
+/// preempts it immediately.
+#[derive(Debug)]
+pub struct Deadline {
+    /// Runnable processes, sorted by deadline
+    running: VecDeque<(BSPInstant, ProcessId)>,
+    /// The last picked process and its deadline, until it blocks or is requeued
+    current: Option<(BSPInstant, ProcessId)>,
+}
+impl Deadline {
+    pub fn new() -> Self {
+        Self {
+            running: VecDeque::new(),
+            current: None,
+        }
+    }
+
+    fn insert(&mut self, deadline: BSPInstant, pid: ProcessId) {
+        let i = self
+            .running
+            .iter()
+            .position(|(d, _)| *d > deadline)
+            .unwrap_or(self.running.len());
+        self.running.insert(i, (deadline, pid));
+    }
+
+    /// Removes a runnable process, returning its deadline
+    fn remove(&mut self, pid: ProcessId) -> Option<BSPInstant> {
+        let i = self.running.iter().position(|(_, p)| *p == pid)?;
+        self.running.remove(i).map(|(d, _)| d)
+    }
+
+    fn forget_current(&mut self, pid: ProcessId) {
+        if self.current.map_or(false, |(_, p)| p == pid) {
+            self.current = None;
+        }
+    }
+}
+impl Policy for Deadline {
+    fn name(&self) -> &'static str {
+        "deadline"
+    }
+
+    fn on_wake(&mut self, pid: ProcessId, wake: Wake) {
+        self.forget_current(pid);
+        let latency = match wake {
+            Wake::Requeue => REQUEUE_LATENCY_NS,
+            Wake::Condition => CONDITION_LATENCY_NS,
+            Wake::Event => EVENT_LATENCY_NS,
+        };
+        self.insert(BSPInstant::now().add_ns(latency), pid);
+    }
+
+    fn on_block(&mut self, pid: ProcessId) {
+        self.forget_current(pid);
+    }
+
+    fn on_exit(&mut self, pid: ProcessId) {
+        self.forget_current(pid);
+        self.remove(pid);
+    }
+
+    fn pick_next(&mut self) -> Option<ProcessId> {
+        let (deadline, pid) = self.running.pop_front()?;
+        self.current = Some((deadline, pid));
+        Some(pid)
+    }
+
+    fn tick_deadline(&self, now: BSPInstant) -> Option<BSPInstant> {
+        if self.should_preempt(self.current.map(|(_, p)| p)) {
+            Some(now)
+        } else {
+            None
+        }
+    }
+
+    fn is_runnable(&self, pid: ProcessId) -> bool {
+        self.running.iter().any(|(_, p)| *p == pid)
+    }
+
+    fn has_runnable(&self) -> bool {
+        !self.running.is_empty()
+    }
+
+    fn should_preempt(&self, current: Option<ProcessId>) -> bool {
+        let next = match self.running.front() {
+            Some((deadline, _)) => *deadline,
+            None => return false,
+        };
+        match self.current {
+            Some((deadline, pid)) if Some(pid) == current => next < deadline,
+            _ => current.is_none(),
+        }
+    }
+
+    /// Gives the process the earliest deadline of the runnable processes
+    fn make_next(&mut self, pid: ProcessId) -> bool {
+        if let Some(deadline) = self.remove(pid) {
+            let first = self.running.front().map_or(deadline, |(d, _)| *d);
+            self.running.push_front((deadline.min(first), pid));
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The server gets the deadline of the current process, if it's the client
+    /// and the deadline is earlier. The deadline is kept after the server
+    /// has acknowledged the client.
+    fn inherit(&mut self, client: ProcessId, server: ProcessId) {
+        if let Some((client_deadline, pid)) = self.current {
+            if pid == client && client != server {
+                if let Some(deadline) = self.remove(server) {
+                    self.insert(deadline.min(client_deadline), server);
+                }
+            }
+        }
+    }
+
+    fn debug_string(&self) -> String {
+        format!(
+            "Running queue {:?} current {:?}",
+            self.running.iter().map(|(_, p)| p).collect::<Vec<_>>(),
+            self.current.map(|(_, p)| p)
+        )
+    }
+}
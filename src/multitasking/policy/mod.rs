@@ -0,0 +1,99 @@
+//! Scheduling policies, i.e. the order in which runnable processes are run.
+//!
+//! `Queues` keeps track of waiting processes, and hands processes to the
+//! policy when they become runnable. `Scheduler` handles locking, time slices
+//! and context switches. A policy only decides which runnable process goes
+//! next, and whether it should preempt the current one.
+//!
+//! The policy is selected with the `SCHEDULER_POLICY` build constant.
+
+use alloc::prelude::v1::*;
+use core::fmt;
+
+use d7abi::process::Privilege;
+
+use crate::memory::constants::SCHEDULER_POLICY;
+use crate::multitasking::ProcessId;
+use crate::time::BSPInstant;
+
+mod deadline;
+mod priority;
+mod round_robin;
+
+pub use self::deadline::Deadline;
+pub use self::priority::Priority;
+pub use self::round_robin::RoundRobin;
+
+/// Why a process became runnable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wake {
+    /// New process, or the running process yielded or used its time slice
+    Requeue,
+    /// A wait condition was met, or the wait was cancelled
+    Condition,
+    /// Woken by an explicit event, i.e. an IPC message or an interrupt
+    Event,
+}
+
+pub trait Policy: fmt::Debug + Send {
+    /// Name shown in logs
+    fn name(&self) -> &'static str;
+
+    /// A process was created. It is woken with `Wake::Requeue` after this.
+    fn on_spawn(&mut self, _pid: ProcessId, _privilege: Privilege) {}
+
+    /// A process became runnable. It is not runnable already.
+    fn on_wake(&mut self, pid: ProcessId, wake: Wake);
+
+    /// The process returned by `pick_next` started waiting for a condition
+    fn on_block(&mut self, _pid: ProcessId) {}
+
+    /// A process terminated. It may be runnable.
+    fn on_exit(&mut self, pid: ProcessId);
+
+    /// Removes the process to run next from the runnable processes
+    fn pick_next(&mut self) -> Option<ProcessId>;
+
+    /// Called on every scheduler tick
+    fn on_tick(&mut self, _now: &BSPInstant) {}
+
+    /// Earliest time the policy needs a tick, if it needs ticks
+    /// other than the time slice ends
+    fn tick_deadline(&self, _now: BSPInstant) -> Option<BSPInstant> {
+        None
+    }
+
+    fn is_runnable(&self, pid: ProcessId) -> bool;
+
+    fn has_runnable(&self) -> bool;
+
+    /// Should the next runnable process preempt the current one
+    /// without waiting for its time slice to end
+    fn should_preempt(&self, current: Option<ProcessId>) -> bool;
+
+    /// Makes a runnable process the next one to be picked, if possible.
+    /// Returns false if the process is not runnable.
+    fn make_next(&mut self, pid: ProcessId) -> bool;
+
+    /// Client is waiting for the server, which should be run as if it were
+    /// the client until `end_inherit`
+    fn inherit(&mut self, _client: ProcessId, _server: ProcessId) {}
+
+    fn end_inherit(&mut self, _server: ProcessId, _client: ProcessId) {}
+
+    /// Client is no longer waiting for any server
+    fn end_inherits_of(&mut self, _client: ProcessId) {}
+
+    /// Runnable processes and policy state, for the queue overview
+    fn debug_string(&self) -> String;
+}
+
+/// The policy selected by `SCHEDULER_POLICY`
+pub fn boot_policy() -> Box<dyn Policy> {
+    match SCHEDULER_POLICY {
+        0 => Box::new(RoundRobin::new()),
+        1 => Box::new(Priority::new()),
+        2 => Box::new(Deadline::new()),
+        other => panic!("Unknown SCHEDULER_POLICY {}", other),
+    }
+}
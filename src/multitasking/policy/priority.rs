@@ -0,0 +1,200 @@
+use alloc::collections::VecDeque;
+use alloc::prelude::v1::*;
+use hashbrown::HashMap;
+
+use d7abi::process::Privilege;
+
+use crate::multitasking::ProcessId;
+
+use super::{Policy, Wake};
+
+/// One level for each privilege
+const LEVELS: usize = 3;
+
+/// Strict priority by privilege level: the service daemon runs before drivers,
+/// and drivers before user processes. Each level is a round-robin queue.
+///
+/// A runnable process of a higher level preempts the current process
+/// immediately, so busy high-priority processes starve the lower ones.
+/// A server waited for by a higher-level client runs at the client's level.
+#[derive(Debug)]
+pub struct Priority {
+    /// Running queue of each level
+    running: [VecDeque<ProcessId>; LEVELS],
+    /// Base level of each process
+    levels: HashMap<ProcessId, usize>,
+    /// Higher-level clients currently waiting for a server process, by server,
+    /// with the level of the client when it started waiting
+    inherited: HashMap<ProcessId, HashMap<ProcessId, usize>>,
+}
+impl Priority {
+    pub fn new() -> Self {
+        Self {
+            running: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            levels: HashMap::new(),
+            inherited: HashMap::new(),
+        }
+    }
+
+    fn level(&self, pid: ProcessId) -> usize {
+        let base = self.levels.get(&pid).copied().unwrap_or(0);
+        self.inherited.get(&pid).map_or(base, |clients| {
+            clients.values().copied().fold(base, usize::max)
+        })
+    }
+
+    /// Removes a runnable process from its queue.
+    /// Returns false if the process is not runnable.
+    fn remove(&mut self, pid: ProcessId) -> bool {
+        for queue in self.running.iter_mut() {
+            if let Some(i) = queue.iter().position(|p| *p == pid) {
+                queue.remove(i);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Moves a runnable process to the front of the queue of its current level
+    fn requeue_front(&mut self, pid: ProcessId) -> bool {
+        if self.remove(pid) {
+            let level = self.level(pid);
+            self.running[level].push_front(pid);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Highest level with runnable processes
+    fn top_level(&self) -> Option<usize> {
+        (0..LEVELS).rev().find(|l| !self.running[*l].is_empty())
+    }
+}
+impl Policy for Priority {
+    fn name(&self) -> &'static str {
+        "priority"
+    }
+
+    fn on_spawn(&mut self, pid: ProcessId, privilege: Privilege) {
+        let level = match privilege {
+            Privilege::User => 0,
+            Privilege::Driver => 1,
+            Privilege::Full => 2,
+        };
+        self.levels.insert(pid, level);
+    }
+
+    fn on_wake(&mut self, pid: ProcessId, wake: Wake) {
+        let level = self.level(pid);
+        match wake {
+            Wake::Requeue => self.running[level].push_back(pid),
+            Wake::Condition | Wake::Event => self.running[level].push_front(pid),
+        }
+    }
+
+    fn on_exit(&mut self, pid: ProcessId) {
+        self.remove(pid);
+        self.levels.remove(&pid);
+        self.inherited.remove(&pid);
+        self.end_inherits_of(pid);
+    }
+
+    fn pick_next(&mut self) -> Option<ProcessId> {
+        let level = self.top_level()?;
+        self.running[level].pop_front()
+    }
+
+    fn is_runnable(&self, pid: ProcessId) -> bool {
+        self.running.iter().any(|queue| queue.contains(&pid))
+    }
+
+    fn has_runnable(&self) -> bool {
+        self.top_level().is_some()
+    }
+
+    fn should_preempt(&self, current: Option<ProcessId>) -> bool {
+        match (self.top_level(), current) {
+            (Some(top), Some(pid)) => top > self.level(pid),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Only moves the process before others of the same level
+    fn make_next(&mut self, pid: ProcessId) -> bool {
+        self.requeue_front(pid)
+    }
+
+    fn inherit(&mut self, client: ProcessId, server: ProcessId) {
+        let level = self.level(client);
+        if client != server && level > self.level(server) {
+            self.inherited.entry(server).or_default().insert(client, level);
+            self.requeue_front(server);
+        }
+    }
+
+    fn end_inherit(&mut self, server: ProcessId, client: ProcessId) {
+        if let Some(clients) = self.inherited.get_mut(&server) {
+            clients.remove(&client);
+            if clients.is_empty() {
+                self.inherited.remove(&server);
+            }
+            self.requeue_front(server);
+        }
+    }
+
+    fn end_inherits_of(&mut self, client: ProcessId) {
+        let servers: Vec<ProcessId> = self
+            .inherited
+            .iter()
+            .filter(|(_, clients)| clients.contains_key(&client))
+            .map(|(server, _)| *server)
+            .collect();
+        for server in servers {
+            self.end_inherit(server, client);
+        }
+    }
+
+    fn debug_string(&self) -> String {
+        format!(
+            "Running queues (low to high) {:?} inherited {:?}",
+            self.running, self.inherited
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pid(n: u64) -> ProcessId {
+        ProcessId::from_u64(n)
+    }
+
+    #[test]
+    fn test_levels_and_inheritance() {
+        let mut p = Priority::new();
+        p.on_spawn(pid(1), Privilege::User);
+        p.on_spawn(pid(2), Privilege::User);
+        p.on_spawn(pid(3), Privilege::Driver);
+        p.on_wake(pid(1), Wake::Requeue);
+        p.on_wake(pid(2), Wake::Requeue);
+        assert!(!p.should_preempt(Some(pid(1))));
+
+        p.on_wake(pid(3), Wake::Requeue);
+        assert!(p.should_preempt(Some(pid(1))));
+        assert_eq!(p.pick_next(), Some(pid(3)));
+
+        // The driver waits for the second user process
+        p.inherit(pid(3), pid(2));
+        assert!(p.should_preempt(Some(pid(1))));
+        assert_eq!(p.pick_next(), Some(pid(2)));
+        p.end_inherit(pid(2), pid(3));
+        p.on_wake(pid(2), Wake::Requeue);
+        assert!(!p.should_preempt(Some(pid(3))));
+        assert_eq!(p.pick_next(), Some(pid(1)));
+        assert_eq!(p.pick_next(), Some(pid(2)));
+        assert_eq!(p.pick_next(), None);
+    }
+}
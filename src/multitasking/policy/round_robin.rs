@@ -0,0 +1,145 @@
+use alloc::collections::VecDeque;
+use alloc::prelude::v1::*;
+use hashbrown::{HashMap, HashSet};
+
+use crate::multitasking::ProcessId;
+use crate::time::BSPInstant;
+
+use super::{Policy, Wake};
+
+/// Number of scheduler ticks a process woken by an explicit event,
+/// i.e. an IPC message or an interrupt, is allowed to preempt others
+const WAKEUP_BOOST_TICKS: u8 = 3;
+
+/// Tick interval while processes are boosted, as boosts last a number of ticks
+const BOOST_TICK_NS: u64 = 1_000_000;
+
+/// Single running queue. Woken processes are run before the others,
+/// and processes woken by an explicit event are boosted for a few ticks.
+#[derive(Debug)]
+pub struct RoundRobin {
+    /// Processes currently in the running queue
+    running: VecDeque<ProcessId>,
+    /// Processes recently woken by an explicit event, with remaining boost ticks.
+    /// A boosted process at the front of the running queue preempts
+    /// the current process without waiting for its time slice to end.
+    boosted: HashMap<ProcessId, u8>,
+    /// Boosted clients currently waiting for a server process, by server.
+    /// The server is boosted until it has acknowledged all of them,
+    /// so that a busy server doesn't delay latency-sensitive clients.
+    inherited: HashMap<ProcessId, HashSet<ProcessId>>,
+}
+impl RoundRobin {
+    pub fn new() -> Self {
+        Self {
+            running: VecDeque::new(),
+            boosted: HashMap::new(),
+            inherited: HashMap::new(),
+        }
+    }
+
+    /// Is the process boosted, either directly or through inheritance
+    fn is_boosted(&self, pid: ProcessId) -> bool {
+        self.boosted.contains_key(&pid) || self.inherited.contains_key(&pid)
+    }
+}
+impl Policy for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn on_wake(&mut self, pid: ProcessId, wake: Wake) {
+        match wake {
+            Wake::Requeue => self.running.push_back(pid),
+            Wake::Condition => self.running.push_front(pid),
+            Wake::Event => {
+                // TODO: can this cause starvation?
+                self.running.push_front(pid);
+                self.boosted.insert(pid, WAKEUP_BOOST_TICKS);
+            },
+        }
+    }
+
+    fn on_exit(&mut self, pid: ProcessId) {
+        self.boosted.remove(&pid);
+        self.inherited.remove(&pid);
+        self.end_inherits_of(pid);
+        if let Some(i) = self.running.iter().position(|p| *p == pid) {
+            self.running.remove(i);
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<ProcessId> {
+        self.running.pop_front()
+    }
+
+    fn on_tick(&mut self, _now: &BSPInstant) {
+        self.boosted.retain(|_, ticks| {
+            *ticks -= 1;
+            *ticks > 0
+        });
+    }
+
+    fn tick_deadline(&self, now: BSPInstant) -> Option<BSPInstant> {
+        if self.boosted.is_empty() {
+            None
+        } else {
+            Some(now.add_ns(BOOST_TICK_NS))
+        }
+    }
+
+    fn is_runnable(&self, pid: ProcessId) -> bool {
+        self.running.contains(&pid)
+    }
+
+    fn has_runnable(&self) -> bool {
+        !self.running.is_empty()
+    }
+
+    fn should_preempt(&self, current: Option<ProcessId>) -> bool {
+        let current_boosted = current.map_or(false, |pid| self.is_boosted(pid));
+        let next_boosted = self.running.front().map_or(false, |pid| self.is_boosted(*pid));
+        next_boosted && !current_boosted
+    }
+
+    fn make_next(&mut self, pid: ProcessId) -> bool {
+        if let Some(i) = self.running.iter().position(|p| *p == pid) {
+            self.running.remove(i);
+            self.running.push_front(pid);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// If the client is boosted, the server inherits the boost
+    fn inherit(&mut self, client: ProcessId, server: ProcessId) {
+        if client != server && self.is_boosted(client) {
+            self.inherited.entry(server).or_default().insert(client);
+            self.make_next(server);
+        }
+    }
+
+    fn end_inherit(&mut self, server: ProcessId, client: ProcessId) {
+        if let Some(clients) = self.inherited.get_mut(&server) {
+            clients.remove(&client);
+            if clients.is_empty() {
+                self.inherited.remove(&server);
+            }
+        }
+    }
+
+    fn end_inherits_of(&mut self, client: ProcessId) {
+        self.inherited.retain(|_, clients| {
+            clients.remove(&client);
+            !clients.is_empty()
+        });
+    }
+
+    fn debug_string(&self) -> String {
+        format!(
+            "Running queue {:?} boosted {:?} inherited {:?}",
+            self.running, self.boosted, self.inherited
+        )
+    }
+}
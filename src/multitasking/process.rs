@@ -1,18 +1,31 @@
 use alloc::prelude::v1::*;
 use core::intrinsics::copy_nonoverlapping;
+use core::mem::size_of;
 use core::ptr;
 use serde::{Deserialize, Serialize};
 use x86_64::structures::idt::{InterruptStackFrameValue, PageFaultErrorCode};
 use x86_64::structures::paging::PageTableFlags as Flags;
 use x86_64::{PhysAddr, VirtAddr};
 
-pub use d7abi::process::{Error, ProcessId, ProcessResult};
+use d7abi::input_ring::{InputEvent, InputRing, INPUT_RING_SIZE};
+use d7abi::output_ring::{OutputRing, OUTPUT_RING_SIZE};
+pub use d7abi::process::{
+    events_topic, Error, MemoryRegion, MemoryRegionKind, Privilege, ProcessEvent, ProcessId,
+    ProcessResult, Signal,
+};
 
+use crate::interrupt::registers::{IretFrame, RegisterState, SwitchStack};
+use crate::memory;
 use crate::memory::paging::PageMap;
 use crate::memory::prelude::*;
 use crate::memory::process_common_code as pcc;
 use crate::memory::MemoryController;
-use crate::memory::{PROCESS_COMMON_CODE, PROCESS_STACK};
+use crate::memory::{
+    PROCESS_COMMON_CODE, PROCESS_SHARED_MEMORY, PROCESS_SHARED_MEMORY_SIZE, PROCESS_STACK,
+    PROCESS_STACK_END, PROCESS_STACK_GUARD_PAGES, PROCESS_STACK_MAX_SIZE_BYTES,
+    PROCESS_STACK_MAX_SIZE_PAGES, PROCESS_TIME_PAGE,
+};
+use crate::time::BSPInstant;
 use crate::util::elf_parser;
 
 use super::loader::ElfImage;
@@ -40,25 +53,72 @@ pub struct Process {
     pub page_table: PageMap,
     /// Stack pointer in process address space
     pub stack_pointer: VirtAddr,
-    /// Stack frames
+    /// Stack frames, from the lowest address to `PROCESS_STACK_END`
     pub stack_frames: Vec<PhysFrame>,
+    /// Stack depth and growth, for statistics
+    pub stack_usage: StackUsage,
     /// Dynamic memory frames
     pub dynamic_memory_frames: Vec<PhysFrame>,
     /// Pending system call for repeating IO operations after waking up
     pub repeat_syscall: bool,
+    /// Timeout of the pending system call, kept over repeats
+    pub syscall_deadline: Option<BSPInstant>,
+    /// Timeout of `ipc_deliver` in nanoseconds, zero for none
+    pub deliver_timeout_ns: u64,
+    /// The pending system call returns `interrupted` instead of repeating
+    pub syscall_interrupted: bool,
+    /// Privilege level, checked by privileged system calls
+    pub privilege: Privilege,
+    /// Process that spawned this one, notified when this process terminates
+    pub parent: Option<ProcessId>,
+    /// Spawned with `ExecFlags::RESTRICTED_VIEW`, or by such a process
+    pub restricted_view: bool,
+    /// Signals sent while masked, as `Signal::bit` values
+    pub pending_signals: u64,
+    /// Signals held back until unmasked with `process_signal_mask`
+    pub signal_mask: u64,
+    /// Mapped memory regions, for introspection
+    pub memory_map: Vec<MemoryRegion>,
+    /// Frame of the output ring, if enabled with `debug_output_ring`
+    pub output_ring: Option<PhysFrame>,
+    /// Frame of the input event ring, if enabled with `input_ring`
+    pub input_ring: Option<PhysFrame>,
+    /// For threads, the process whose address space is shared.
+    /// Terminating it terminates its threads.
+    pub leader: Option<ProcessId>,
+    /// Thread-local storage template of the executable, if any
+    pub tls_template: Option<TlsTemplate>,
+    /// FS segment base, loaded on every switch to this process
+    pub fs_base: u64,
     /// Metadata used for scheduling etc.
     metadata: ProcessMetadata,
 }
 impl Process {
     fn new(
         id: ProcessId, page_table: PageMap, stack_pointer: VirtAddr, stack_frames: Vec<PhysFrame>,
+        privilege: Privilege, memory_map: Vec<MemoryRegion>,
     ) -> Self {
         Self {
             page_table,
             stack_pointer,
             stack_frames,
+            stack_usage: StackUsage::default(),
             dynamic_memory_frames: Vec::new(),
             repeat_syscall: false,
+            syscall_deadline: None,
+            deliver_timeout_ns: 0,
+            syscall_interrupted: false,
+            privilege,
+            parent: None,
+            restricted_view: false,
+            pending_signals: 0,
+            signal_mask: 0,
+            memory_map,
+            output_ring: None,
+            input_ring: None,
+            leader: None,
+            tls_template: None,
+            fs_base: 0,
             metadata: ProcessMetadata {
                 id,
                 status: Status::Running,
@@ -67,8 +127,88 @@ impl Process {
     }
 
     /// Creates a new process
-    pub unsafe fn create(mm: &mut MemoryController, pid: ProcessId, elf: ElfImage) -> Self {
-        create_process(mm, pid, elf)
+    pub unsafe fn create(
+        mm: &mut MemoryController, pid: ProcessId, elf: ElfImage, privilege: Privilege,
+    ) -> Self {
+        create_process(mm, pid, elf, privilege)
+    }
+
+    /// Creates a thread sharing the address space of this process.
+    /// The thread starts at `entry` with `arg` in `rdi`, and `stack_top` as its
+    /// stack pointer. The initial register state is written below `stack_top`.
+    /// Returns `None` if that part of the stack is not in a writable region.
+    pub unsafe fn create_thread(
+        &self, mm: &mut MemoryController, tid: ProcessId, entry: VirtAddr, stack_top: VirtAddr,
+        arg: u64,
+    ) -> Option<Self> {
+        let (fs_base, tls_end) = match self.tls_template {
+            Some(template) => (template.thread_pointer(stack_top), template.block_start(stack_top)),
+            None => (VirtAddr::zero(), stack_top),
+        };
+        let frame = initial_stack(entry, tls_end, arg);
+        let rsp = tls_end - size_of::<SwitchStack>() as u64;
+        let writable = self.memory_map.iter().any(|r| {
+            r.writable && r.start <= rsp && stack_top.as_u64() <= r.start.as_u64() + r.size_bytes
+        });
+        if !writable {
+            return None;
+        }
+        if let Some(template) = self.tls_template {
+            self.write_tls_block(mm, template, stack_top)?;
+        }
+        mm.process_write_value(self, frame, rsp)?;
+
+        let mut thread = Process::new(
+            tid,
+            self.page_table.clone(),
+            rsp,
+            Vec::new(),
+            self.privilege,
+            self.memory_map.clone(),
+        );
+        thread.leader = Some(self.address_space_owner());
+        thread.restricted_view = self.restricted_view;
+        thread.tls_template = self.tls_template;
+        thread.fs_base = fs_base.as_u64();
+        Some(thread)
+    }
+
+    /// Initializes a thread-local storage block, and the thread control block
+    /// after it, below `stack_top`. See `TlsTemplate::thread_pointer`.
+    unsafe fn write_tls_block(
+        &self, mm: &mut MemoryController, template: TlsTemplate, stack_top: VirtAddr,
+    ) -> Option<()> {
+        let start = template.block_start(stack_top);
+        let tp = template.thread_pointer(stack_top);
+
+        let mut block = vec![0u8; (tp - start) as usize + 8];
+        if template.file_size > 0 {
+            let (area, image) = mm.process_slice(self, template.file_size, template.start)?;
+            block[..image.len()].copy_from_slice(image);
+            mm.unmap_area(area);
+            mm.free_virtual_area(area);
+        }
+        // The first word of the thread control block points to itself
+        let tcb = block.len() - 8;
+        block[tcb..].copy_from_slice(&tp.as_u64().to_le_bytes());
+        mm.process_write_bytes(self, &block, start)
+    }
+
+    /// Sets the FS base of the processor to that of this process
+    pub fn load_fs_base(&self) {
+        unsafe {
+            asm!("wrmsr",
+                in("ecx") 0xc000_0100u32, // IA32_FS_BASE
+                in("edx") (self.fs_base >> 32) as u32,
+                in("eax") self.fs_base as u32,
+                options(nostack, nomem)
+            )
+        }
+    }
+
+    /// The process whose address space this process uses
+    pub fn address_space_owner(&self) -> ProcessId {
+        self.leader.unwrap_or_else(|| self.id())
     }
 
     pub fn metadata(&self) -> ProcessMetadata {
@@ -79,6 +219,201 @@ impl Process {
         self.metadata.id
     }
 
+    /// Lowest address of the mapped stack
+    pub fn stack_start(&self) -> VirtAddr {
+        PROCESS_STACK_END - (self.stack_frames.len() as u64) * PAGE_SIZE_BYTES
+    }
+
+    /// Records stack use down to `address`, and returns the number of pages
+    /// `use_stack` would map for it, or `None` if the address is not in the
+    /// stack area. Doesn't need the memory controller, so it can be called
+    /// on every interrupt.
+    ///
+    /// Threads have their stacks in the memory of the process,
+    /// so only the process itself is tracked.
+    pub fn stack_pages_missing(&mut self, address: VirtAddr) -> Option<usize> {
+        let limit = PROCESS_STACK_END - PROCESS_STACK_MAX_SIZE_BYTES;
+        if self.leader.is_some() || address < limit || address >= PROCESS_STACK_END {
+            return None;
+        }
+
+        let depth = PROCESS_STACK_END - address;
+        self.stack_usage.max_depth = self.stack_usage.max_depth.max(depth);
+
+        let wanted_pages = (to_pages_round_up(depth) + PROCESS_STACK_GUARD_PAGES)
+            .min(PROCESS_STACK_MAX_SIZE_PAGES) as usize;
+        Some(wanted_pages.saturating_sub(self.stack_frames.len()))
+    }
+
+    /// Records stack use down to `address`, and grows the stack so that
+    /// `PROCESS_STACK_GUARD_PAGES` stay mapped below it, up to the size limit.
+    /// Returns `false` if the address is not in the stack area.
+    ///
+    /// Kernel page tables must be active when this is called.
+    pub fn use_stack(&mut self, mm: &mut MemoryController, address: VirtAddr) -> bool {
+        match self.stack_pages_missing(address) {
+            Some(0) => true,
+            Some(pages) => {
+                self.grow_stack(mm, pages);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Maps zeroed pages below the stack
+    fn grow_stack(&mut self, mm: &mut MemoryController, pages: usize) {
+        let new_frames = mm.alloc_frames_zeroed(pages);
+        let new_start = self.stack_start() - (pages as u64) * PAGE_SIZE_BYTES;
+        unsafe {
+            self.modify_tables(mm, |pt, curr_addr| {
+                for (i, frame) in new_frames.iter().enumerate() {
+                    let vaddr = new_start + (i as u64) * PAGE_SIZE_BYTES;
+                    pt.map_to(
+                        curr_addr,
+                        Page::from_start_address(vaddr).unwrap(),
+                        *frame,
+                        Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE,
+                    )
+                    .ignore();
+                }
+            });
+        }
+        self.stack_frames.splice(0..0, new_frames);
+        self.stack_usage.grows += 1;
+
+        let size_bytes = (self.stack_frames.len() as u64) * PAGE_SIZE_BYTES;
+        let region = self.memory_map.iter_mut().find(|r| r.kind == MemoryRegionKind::Stack);
+        if let Some(region) = region {
+            region.start = new_start;
+            region.size_bytes = size_bytes;
+        }
+        log::debug!("[pid={:8}] Stack grown to {:#x} bytes", self.id(), size_bytes);
+    }
+
+    /// Adds a region to the memory map, or replaces one with the same start address
+    pub fn set_memory_region(&mut self, region: MemoryRegion) {
+        if let Some(old) = self.memory_map.iter_mut().find(|r| r.start == region.start) {
+            *old = region;
+        } else {
+            self.memory_map.push(region);
+        }
+    }
+
+    /// Prints the complete lines written to the output ring, if enabled.
+    /// With `flush`, an incomplete last line is printed too.
+    pub fn drain_output_ring(&self, flush: bool) {
+        let frame = match self.output_ring {
+            Some(frame) => frame,
+            None => return,
+        };
+        let ring = unsafe {
+            OutputRing::from_raw(
+                memory::phys_to_virt(frame.start_address()).as_mut_ptr(),
+                OUTPUT_RING_SIZE,
+            )
+        };
+
+        let bytes: Vec<u8> = match ring.unread() {
+            Some(unread) => unread.collect(),
+            None => {
+                log::warn!("[pid={:8}] Output ring corrupted", self.id());
+                ring.reset();
+                return;
+            },
+        };
+
+        // Incomplete lines are kept until the rest is written, unless the ring is full
+        let end = if flush || bytes.len() as u64 == ring.capacity() {
+            bytes.len()
+        } else {
+            match bytes.iter().rposition(|&b| b == b'\n') {
+                Some(i) => i + 1,
+                None => return,
+            }
+        };
+
+        for line in String::from_utf8_lossy(&bytes[..end]).lines() {
+            crate::syscall::PROCESS_OUTPUT::print(self.id(), line);
+        }
+        ring.consume(end as u64);
+    }
+
+    /// Appends an event to the input ring, if enabled.
+    /// Returns `false` if the process has no input ring.
+    pub fn push_input(&self, event: InputEvent) -> bool {
+        let frame = match self.input_ring {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let ring = unsafe {
+            InputRing::from_raw(
+                memory::phys_to_virt(frame.start_address()).as_mut_ptr(),
+                INPUT_RING_SIZE,
+            )
+        };
+        if !ring.push(event) {
+            log::trace!("[pid={:8}] Input ring full, event dropped", self.id());
+        }
+        true
+    }
+
+    /// Maps the frames of a shared memory object after the previously
+    /// mapped ones. Returns the start address, or `None` if the shared
+    /// memory area of the process is full.
+    pub fn map_shared_memory(
+        &mut self, mm: &mut MemoryController, frames: &[PhysFrame],
+    ) -> Option<VirtAddr> {
+        let start = self
+            .memory_map
+            .iter()
+            .filter(|r| r.kind == MemoryRegionKind::SharedMemory)
+            .map(|r| r.start + r.size_bytes)
+            .max()
+            .unwrap_or(PROCESS_SHARED_MEMORY);
+        let size_bytes = (frames.len() as u64) * PAGE_SIZE_BYTES;
+        if start + size_bytes > PROCESS_SHARED_MEMORY + PROCESS_SHARED_MEMORY_SIZE {
+            return None;
+        }
+
+        unsafe {
+            self.modify_tables(mm, |pt, curr_addr| {
+                for (i, frame) in frames.iter().enumerate() {
+                    pt.map_to(
+                        curr_addr,
+                        Page::from_start_address(start + (i as u64) * PAGE_SIZE_BYTES).unwrap(),
+                        *frame,
+                        Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE,
+                    )
+                    .ignore();
+                }
+            });
+        }
+        self.set_memory_region(MemoryRegion {
+            start,
+            size_bytes,
+            kind: MemoryRegionKind::SharedMemory,
+            writable: true,
+            executable: false,
+        });
+        Some(start)
+    }
+
+    /// Replaces the time page of the address space, for time namespaces
+    pub fn map_time_page(&mut self, mm: &mut MemoryController, frame: PhysFrame) {
+        unsafe {
+            self.modify_tables(mm, |pt, curr_addr| {
+                pt.map_to(
+                    curr_addr,
+                    Page::from_start_address(PROCESS_TIME_PAGE).unwrap(),
+                    frame,
+                    Flags::PRESENT | Flags::NO_EXECUTE,
+                )
+                .ignore();
+            });
+        }
+    }
+
     /// Kernel page tables must be active when this is called.
     /// Tables will be flushed after the parameter function has been called.
     pub unsafe fn modify_tables<F, R>(&mut self, mm: &mut MemoryController, f: F) -> R
@@ -105,6 +440,69 @@ impl Process {
     }
 }
 
+/// Stack usage of a process
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StackUsage {
+    /// Deepest stack pointer or stack fault seen, in bytes below `PROCESS_STACK_END`
+    pub max_depth: u64,
+    /// Number of times the stack has been grown
+    pub grows: u64,
+}
+
+/// Thread-local storage template, from the PT_TLS segment of the executable.
+/// The ELF loader has checked that the template is within a loaded segment.
+#[derive(Debug, Clone, Copy)]
+pub struct TlsTemplate {
+    /// Initialization image, followed by zeroed bytes up to `mem_size`
+    pub start: VirtAddr,
+    pub file_size: u64,
+    pub mem_size: u64,
+    pub align: u64,
+}
+impl TlsTemplate {
+    fn from_program_header(ph: elf_parser::ELFProgramHeader) -> Self {
+        Self {
+            start: VirtAddr::new(ph.virtual_address),
+            file_size: ph.size_in_file,
+            mem_size: ph.size_in_memory,
+            align: ph.alignment.max(16),
+        }
+    }
+
+    /// Thread pointer, i.e. the FS base, for a block placed below `stack_top`.
+    /// The x86-64 TLS layout (variant II) places the block just before the
+    /// thread pointer, and the thread control block at it.
+    pub fn thread_pointer(&self, stack_top: VirtAddr) -> VirtAddr {
+        (stack_top - 8u64).align_down(self.align)
+    }
+
+    /// Start of the block placed below `stack_top`, 16-byte aligned.
+    /// The stack of the thread starts from here.
+    pub fn block_start(&self, stack_top: VirtAddr) -> VirtAddr {
+        let size = (self.mem_size + self.align - 1) & !(self.align - 1);
+        self.thread_pointer(stack_top) - size
+    }
+}
+
+/// Stack contents for starting at `entry`, with other registers zeroed
+fn initial_stack(entry: VirtAddr, stack_top: VirtAddr, rdi: u64) -> SwitchStack {
+    SwitchStack {
+        registers: RegisterState {
+            rdi,
+            ..RegisterState::default()
+        },
+        entry: 0,
+        // Interrupt flag on
+        frame: IretFrame {
+            rip: entry.as_u64(),
+            cs: 0x8,
+            rflags: 1 << 9,
+            rsp: stack_top.as_u64(),
+            ss: 0,
+        },
+    }
+}
+
 /// Creates a new process
 /// This function:
 /// * Creates a stack for the new process, and populates it for returning to the process
@@ -112,8 +510,11 @@ impl Process {
 /// * Loads executable from an ELF image
 /// Requires that the kernel page table is active.
 /// Returns ProcessId and PageMap for the process.
-unsafe fn create_process(mm: &mut MemoryController, pid: ProcessId, elf: ElfImage) -> Process {
+unsafe fn create_process(
+    mm: &mut MemoryController, pid: ProcessId, elf: ElfImage, privilege: Privilege,
+) -> Process {
     // Load image
+    let tls_template = elf.parse_elf().tls.map(TlsTemplate::from_program_header);
     let (elf_header, elf_frames) = unsafe { mm.load_elf(elf) };
 
     // Allocate a stack for the process
@@ -123,12 +524,8 @@ unsafe fn create_process(mm: &mut MemoryController, pid: ProcessId, elf: ElfImag
     // Set rsp
     // Offset to leave registers zero when they are popped,
     // plus space for the return address and other iretq data
-    let registers_popped = 15; // process_common.asm : push_all
-    let inthandler_tmpvar = 1;
-    let iretq_structure = 5;
-    let stack_items = registers_popped + inthandler_tmpvar + iretq_structure;
     let stack_size_bytes = PROCESS_STACK_SIZE_PAGES * PAGE_SIZE_BYTES;
-    let stack_offset = stack_size_bytes - 8 * stack_items;
+    let stack_offset = stack_size_bytes - size_of::<SwitchStack>() as u64;
     let stack_end = PROCESS_STACK + stack_size_bytes;
     let rsp: VirtAddr = PROCESS_STACK + stack_offset;
 
@@ -153,25 +550,13 @@ unsafe fn create_process(mm: &mut MemoryController, pid: ProcessId, elf: ElfImag
             if page_index == (PROCESS_STACK_SIZE_PAGES as usize) - 1 {
                 // Push interrupt stack frame for
                 // https://os.phil-opp.com/returning-from-exceptions/#returning-from-exceptions
-
-                macro_rules! qwords_from_end {
-                    ($n:literal) => {
-                        vaddr
-                            .as_mut_ptr::<u64>()
-                            .add((PAGE_SIZE_BYTES as usize) / 8 - $n)
-                    };
-                }
-
-                // SS
-                ptr::write(qwords_from_end!(1), 0);
-                // RSP
-                ptr::write(qwords_from_end!(2), stack_end.as_u64());
-                // RFLAGFS: Interrupt flag on (https://en.wikipedia.org/wiki/FLAGS_register#FLAGS)
-                ptr::write(qwords_from_end!(3), 1 << 9);
-                // CS
-                ptr::write(qwords_from_end!(4), 0x8u64);
-                // RIP
-                ptr::write(qwords_from_end!(5), elf_header.program_entry_pos);
+                let frame = initial_stack(
+                    VirtAddr::new(elf_header.program_entry_pos),
+                    stack_end,
+                    0,
+                );
+                let offset = PAGE_SIZE_BYTES as usize - size_of::<SwitchStack>();
+                ptr::write(vaddr.as_mut_ptr::<u8>().add(offset) as *mut SwitchStack, frame);
             }
 
             // Unmap from kernel table
@@ -226,11 +611,20 @@ unsafe fn create_process(mm: &mut MemoryController, pid: ProcessId, elf: ElfImag
         )
         .ignore();
 
+        // Clock data page, read-only
+        pm.map_to(
+            pt_area.start,
+            Page::from_start_address(PROCESS_TIME_PAGE).unwrap(),
+            crate::time::time_page_frame(),
+            Flags::PRESENT | Flags::NO_EXECUTE,
+        )
+        .ignore();
+
         // TODO: Rest of the structures? Are there any?
     }
 
-    // Map process stack its own page table
-    // No guard page is needed, as the page below the stack is read-only
+    // Map process stack its own page table.
+    // The stack is grown on demand, see `Process::use_stack`.
     for (page_index, frame) in stack_frames.iter().enumerate() {
         let vaddr = PROCESS_STACK + (page_index as u64) * PAGE_SIZE_BYTES;
         unsafe {
@@ -244,6 +638,30 @@ unsafe fn create_process(mm: &mut MemoryController, pid: ProcessId, elf: ElfImag
         }
     }
 
+    let mut memory_map = vec![
+        MemoryRegion {
+            start: PROCESS_COMMON_CODE,
+            size_bytes: PAGE_SIZE_BYTES,
+            kind: MemoryRegionKind::CommonCode,
+            writable: false,
+            executable: true,
+        },
+        MemoryRegion {
+            start: PROCESS_TIME_PAGE,
+            size_bytes: PAGE_SIZE_BYTES,
+            kind: MemoryRegionKind::TimePage,
+            writable: false,
+            executable: false,
+        },
+        MemoryRegion {
+            start: PROCESS_STACK,
+            size_bytes: stack_size_bytes,
+            kind: MemoryRegionKind::Stack,
+            writable: true,
+            executable: false,
+        },
+    ];
+
     // Map the executable image to its own page table
     for (ph, frames) in elf_frames {
         assert!(ph.virtual_address >= 0x400_000);
@@ -260,6 +678,14 @@ unsafe fn create_process(mm: &mut MemoryController, pid: ProcessId, elf: ElfImag
             flags |= Flags::WRITABLE;
         }
 
+        memory_map.push(MemoryRegion {
+            start,
+            size_bytes: PAGE_SIZE_BYTES * (frames.len() as u64),
+            kind: MemoryRegionKind::Executable,
+            writable: flags.contains(Flags::WRITABLE),
+            executable: !flags.contains(Flags::NO_EXECUTE),
+        });
+
         for (i, frame) in frames.into_iter().enumerate() {
             let page = Page::from_start_address(start + PAGE_SIZE_BYTES * (i as u64)).unwrap();
             unsafe {
@@ -273,11 +699,31 @@ unsafe fn create_process(mm: &mut MemoryController, pid: ProcessId, elf: ElfImag
 
     // TODO: Unmap process structures from kernel page map (if any?)
 
-    Process::new(pid, pm, rsp, stack_frames)
+    let mut process = Process::new(pid, pm, rsp, stack_frames, privilege, memory_map);
+
+    // Place the thread-local storage block of the main thread
+    // at the top of the stack, and move the initial registers below it
+    if let Some(template) = tls_template {
+        process.tls_template = Some(template);
+        process
+            .write_tls_block(mm, template, stack_end)
+            .expect("Process stack not writable");
+        let tls_end = template.block_start(stack_end);
+        let frame = initial_stack(VirtAddr::new(elf_header.program_entry_pos), tls_end, 0);
+        process.stack_pointer = tls_end - size_of::<SwitchStack>() as u64;
+        mm.process_write_value(&process, frame, process.stack_pointer)
+            .expect("Process stack not writable");
+        process.fs_base = template.thread_pointer(stack_end).as_u64();
+    }
+
+    process
 }
 
-/// Loads elf image to ram and returns it
-pub fn load_elf(mem_ctrl: &mut MemoryController, bytes: &[u8]) -> ElfImage {
+/// Loads elf image to ram and returns it, or an error if the image is invalid.
+/// The image is untrusted, and validated before use.
+pub fn load_elf(
+    mem_ctrl: &mut MemoryController, bytes: &[u8],
+) -> Result<ElfImage, elf_parser::ELFParsingError> {
     use core::ptr;
     use x86_64::structures::paging::PageTableFlags as Flags;
 
@@ -296,6 +742,11 @@ pub fn load_elf(mem_ctrl: &mut MemoryController, bytes: &[u8]) -> ElfImage {
     }
 
     let elf = unsafe { ElfImage::new(area) };
-    elf.verify();
-    elf
+    if let Err(error) = elf.verify(bytes.len()) {
+        // TODO: Free the frames as well
+        unsafe { mem_ctrl.unmap_area(area) };
+        mem_ctrl.free_virtual_area(area);
+        return Err(error);
+    }
+    Ok(elf)
 }
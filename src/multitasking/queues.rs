@@ -2,9 +2,12 @@ use alloc::collections::VecDeque;
 use alloc::prelude::v1::*;
 use hashbrown::{HashMap, HashSet};
 
+use d7abi::process::Privilege;
+
 use crate::multitasking::ProcessId;
 use crate::time::BSPInstant;
 
+use super::policy::{Policy, Wake};
 use super::{ExplicitEventId, WaitFor};
 
 /// Internal wait id for scheduler queues.
@@ -20,10 +23,24 @@ impl WaitId {
     }
 }
 
+/// Sizes of the wait queues, for detecting leaked wait conditions
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WaitStats {
+    /// Processes waiting, i.e. unconsumed WaitIds
+    pub waiting: usize,
+    /// Explicit events with waiters
+    pub events: usize,
+    pub event_waits: usize,
+    pub process_waits: usize,
+    pub sleeping: usize,
+    /// Conditions of consumed WaitIds still in the queues, should be zero
+    pub stale: usize,
+}
+
 #[derive(Debug)]
 pub struct Queues {
-    /// Processes currently in the running queue
-    running: VecDeque<ProcessId>,
+    /// Runnable processes, ordered by the scheduling policy
+    policy: Box<dyn Policy>,
     /// Processes waiting for some trigger. Target for items in wait_*` queues.
     ///
     /// When a trigger has been reached once, the WaitId is consumed,
@@ -34,6 +51,12 @@ pub struct Queues {
     waiting: HashMap<WaitId, ProcessId>,
     /// Next available WaitId
     next_waitid: WaitId,
+    /// Conditions left before the WaitId of a `WaitFor::AllOf` is triggered
+    wait_remaining: HashMap<WaitId, usize>,
+    /// Conditions of each WaitId, so that they can be removed from the
+    /// `wait_*` queues when the WaitId is consumed. Otherwise conditions that
+    /// are never met, e.g. events of a closed subscription, would accumulate.
+    conditions: HashMap<WaitId, Vec<WaitFor>>,
     /// Processes which are sleeping until specified time
     /// Must be kept sorted by the wake-up time
     /// TODO: Switch to a proper priority queue for faster insert time
@@ -44,11 +67,13 @@ pub struct Queues {
     wait_event: HashMap<ExplicitEventId, HashSet<WaitId>>,
 }
 impl Queues {
-    pub fn new() -> Self {
+    pub fn new(policy: Box<dyn Policy>) -> Self {
         Self {
-            running: VecDeque::new(),
+            policy,
             waiting: HashMap::new(),
             next_waitid: WaitId(0),
+            wait_remaining: HashMap::new(),
+            conditions: HashMap::new(),
             wait_sleeping: VecDeque::new(),
             wait_process: HashMap::new(),
             wait_event: HashMap::new(),
@@ -57,7 +82,7 @@ impl Queues {
 
     /// Is there a process with this in any queue
     pub fn process_exists(&self, pid: ProcessId) -> bool {
-        self.running.contains(&pid) || self.waiting.values().any(|p| p == &pid)
+        self.policy.is_runnable(pid) || self.waiting.values().any(|p| p == &pid)
     }
 
     fn create_wait(&mut self, pid: ProcessId) -> WaitId {
@@ -69,16 +94,82 @@ impl Queues {
     /// If wait_id has been consumed, ignores it.
     /// Otherwise the wait_id is consumed, and
     /// the associated process is scheduled for running.
-    fn trigger_wait(&mut self, wait_id: WaitId) {
-        if let Some(pid) = self.waiting.remove(&wait_id) {
-            log::trace!("wakeup {:?}", pid);
+    /// Returns the process, if it was woken up.
+    fn trigger_wait(&mut self, wait_id: WaitId, wake: Wake) -> Option<ProcessId> {
+        if !self.waiting.contains_key(&wait_id) {
+            return None;
+        }
+        if let Some(remaining) = self.wait_remaining.get_mut(&wait_id) {
+            *remaining -= 1;
+            if *remaining > 0 {
+                return None;
+            }
+        }
+
+        let pid = self.consume_wait(wait_id)?;
+        log::trace!("wakeup {:?}", pid);
+        debug_assert!(!self.policy.is_runnable(pid), "Woken process already running");
+
+        self.policy.on_wake(pid, wake);
+        Some(pid)
+    }
+
+    /// Makes a runnable process the next one to run, if the policy allows.
+    /// Returns false if the process is not runnable.
+    pub fn move_to_front(&mut self, pid: ProcessId) -> bool {
+        self.policy.make_next(pid)
+    }
+
+    /// Should the next runnable process preempt the current one
+    pub fn should_preempt(&self, current: Option<ProcessId>) -> bool {
+        self.policy.should_preempt(current)
+    }
+
+    /// Client is waiting for the server, which inherits the scheduling
+    /// priority of the client until `end_inherited_boost`
+    pub fn inherit_boost(&mut self, client: ProcessId, server: ProcessId) {
+        self.policy.inherit(client, server);
+    }
+
+    pub fn end_inherited_boost(&mut self, server: ProcessId, client: ProcessId) {
+        self.policy.end_inherit(server, client);
+    }
+
+    /// Client is no longer waiting for any server,
+    /// e.g. the delivery failed without an acknowledgement
+    pub fn end_inherited_boosts_of(&mut self, client: ProcessId) {
+        self.policy.end_inherits_of(client);
+    }
+
+    /// A new process was created, before it's given to the queues
+    pub fn on_spawn(&mut self, pid: ProcessId, privilege: Privilege) {
+        self.policy.on_spawn(pid, privilege);
+    }
 
-            // TODO: can this cause starvation?
-            self.running.push_front(pid);
+    /// Removes a WaitId and all of its remaining conditions.
+    /// Returns the process, if the WaitId was not consumed already.
+    fn consume_wait(&mut self, wait_id: WaitId) -> Option<ProcessId> {
+        let pid = self.waiting.remove(&wait_id)?;
+        self.wait_remaining.remove(&wait_id);
+        for condition in self.conditions.remove(&wait_id).unwrap_or_default() {
+            match condition {
+                WaitFor::Time(_) => {
+                    self.wait_sleeping.retain(|(_, w)| *w != wait_id);
+                },
+                WaitFor::Process(target) => {
+                    remove_from_set(&mut self.wait_process, target, wait_id);
+                },
+                WaitFor::Event(event_id) => {
+                    remove_from_set(&mut self.wait_event, event_id, wait_id);
+                },
+                _ => unreachable!("Only flat conditions are stored"),
+            }
         }
+        Some(pid)
     }
 
     fn give_inner(&mut self, s: WaitFor, wait_id: WaitId) {
+        self.conditions.entry(wait_id).or_default().push(s.clone());
         match s {
             WaitFor::Time(instant) => {
                 let i = p_index_vecdeque(&self.wait_sleeping, &instant);
@@ -96,10 +187,10 @@ impl Queues {
             WaitFor::None => {
                 panic!("WaitFor::None inside of WaitFor::FirstOf");
             },
-            WaitFor::FirstOf(targets) => {
+            WaitFor::FirstOf(_) | WaitFor::AllOf(_) => {
                 // Possible to support (simply recurse), but these
                 // imply ineffiency or more serious issues elsewhere
-                panic!("Nested WaitFor::FirstOf");
+                panic!("Nested WaitFor::FirstOf or WaitFor::AllOf");
             },
         }
     }
@@ -108,19 +199,75 @@ impl Queues {
         s = s.reduce_queues(&self, pid);
 
         if s == WaitFor::None {
-            self.running.push_back(pid);
+            self.policy.on_wake(pid, Wake::Requeue);
             return;
         }
 
         log::trace!("Queuing process {} until {:?}", pid, s);
+        self.policy.on_block(pid);
 
         let wait_id = self.create_wait(pid);
-        if let WaitFor::FirstOf(targets) = s {
-            for target in targets {
-                self.give_inner(target, wait_id);
-            }
-        } else {
-            self.give_inner(s, wait_id);
+        match s {
+            WaitFor::FirstOf(targets) => {
+                for target in targets {
+                    self.give_inner(target, wait_id);
+                }
+            },
+            WaitFor::AllOf(targets) => {
+                self.wait_remaining.insert(wait_id, targets.len());
+                for target in targets {
+                    self.give_inner(target, wait_id);
+                }
+            },
+            other => self.give_inner(other, wait_id),
+        }
+    }
+
+    /// Ends the wait of a process without any condition being met,
+    /// and schedules it for running.
+    /// Returns false if the process was not waiting.
+    pub fn cancel_wait(&mut self, pid: ProcessId) -> bool {
+        if !self.remove_waits_of(pid) {
+            return false;
+        }
+        self.policy.on_wake(pid, Wake::Condition);
+        true
+    }
+
+    /// Consumes the WaitIds of a process, so that its conditions are ignored.
+    /// Returns false if there were none.
+    fn remove_waits_of(&mut self, pid: ProcessId) -> bool {
+        let wait_ids: Vec<WaitId> = self
+            .waiting
+            .iter()
+            .filter(|(_, p)| **p == pid)
+            .map(|(w, _)| *w)
+            .collect();
+        for wait_id in &wait_ids {
+            self.consume_wait(*wait_id);
+        }
+        !wait_ids.is_empty()
+    }
+
+    /// Sizes of the wait queues
+    pub fn wait_stats(&self) -> WaitStats {
+        let event_waits: usize = self.wait_event.values().map(|s| s.len()).sum();
+        let process_waits: usize = self.wait_process.values().map(|s| s.len()).sum();
+        let stale = self
+            .wait_event
+            .values()
+            .chain(self.wait_process.values())
+            .flatten()
+            .chain(self.wait_sleeping.iter().map(|(_, w)| w))
+            .filter(|w| !self.waiting.contains_key(w))
+            .count();
+        WaitStats {
+            waiting: self.waiting.len(),
+            events: self.wait_event.len(),
+            event_waits,
+            process_waits,
+            sleeping: self.wait_sleeping.len(),
+            stale,
         }
     }
 
@@ -129,15 +276,32 @@ impl Queues {
     /// and will not be returned again unless
     /// added using one of the give calls.
     pub fn take(&mut self) -> Option<ProcessId> {
-        self.running.pop_front()
+        self.policy.pick_next()
+    }
+
+    /// Are there processes waiting to run
+    pub fn has_runnable(&self) -> bool {
+        self.policy.has_runnable()
+    }
+
+    /// Earliest tick needed by the scheduling policy, if any
+    pub fn policy_tick_deadline(&self, now: BSPInstant) -> Option<BSPInstant> {
+        self.policy.tick_deadline(now)
+    }
+
+    /// Earliest wake-up time of the sleeping processes
+    pub fn next_wakeup(&self) -> Option<BSPInstant> {
+        self.wait_sleeping.front().map(|(wakeup, _)| *wakeup)
     }
 
     /// Update when clock ticks
     pub fn on_tick(&mut self, now: &BSPInstant) {
+        self.policy.on_tick(now);
+
         while let Some((wakeup, _)) = self.wait_sleeping.front() {
             if now >= wakeup {
                 let (_, wait_id) = self.wait_sleeping.pop_front().unwrap();
-                self.trigger_wait(wait_id);
+                self.trigger_wait(wait_id, Wake::Condition);
             } else {
                 break;
             }
@@ -147,16 +311,12 @@ impl Queues {
     /// Update when a process completes
     pub fn on_process_over(&mut self, completed: ProcessId) {
         log::trace!("on_process_over {:?}", completed);
-        for (i, pid) in self.running.iter().enumerate() {
-            if *pid == completed {
-                self.running.remove(i);
-                break;
-            }
-        }
+        self.policy.on_exit(completed);
+        self.remove_waits_of(completed);
 
         if let Some(wait_ids) = self.wait_process.remove(&completed) {
             for wait_id in wait_ids {
-                self.trigger_wait(wait_id);
+                self.trigger_wait(wait_id, Wake::Condition);
             }
         }
     }
@@ -166,7 +326,7 @@ impl Queues {
         log::trace!("on_explicit_event {:?}", event_id);
         if let Some(wait_ids) = self.wait_event.remove(&event_id) {
             for wait_id in wait_ids {
-                self.trigger_wait(wait_id);
+                self.trigger_wait(wait_id, Wake::Event);
             }
         }
     }
@@ -174,8 +334,9 @@ impl Queues {
     /// Full-screen view of the current scheduler queue status
     pub fn debug_view_string(&self) -> String {
         let mut lines = format!(
-            "## QUEUE     OVERVIEW ##  Running queue {:?}\n",
-            self.running
+            "## QUEUE     OVERVIEW ##  {} policy: {}\n",
+            self.policy.name(),
+            self.policy.debug_string()
         );
         let processes: HashSet<_> = self.waiting.values().collect();
         for process in processes {
@@ -231,6 +392,18 @@ impl Queues {
     }
 }
 
+/// Removes a WaitId from the set of a key, and the set if it becomes empty
+fn remove_from_set<K: Eq + core::hash::Hash>(
+    map: &mut HashMap<K, HashSet<WaitId>>, key: K, wait_id: WaitId,
+) {
+    if let Some(set) = map.get_mut(&key) {
+        set.remove(&wait_id);
+        if set.is_empty() {
+            map.remove(&key);
+        }
+    }
+}
+
 /// Priority queue like index in the vecdeque of pairs
 /// The first item of the pair is used as the priority key
 fn p_index_vecdeque<K: Ord, V>(v: &VecDeque<(K, V)>, t: &K) -> usize {
@@ -244,3 +417,87 @@ fn p_index_vecdeque<K: Ord, V>(v: &VecDeque<(K, V)>, t: &K) -> usize {
     }
     v.len()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::multitasking::policy::RoundRobin;
+
+    fn new_queues() -> Queues {
+        Queues::new(Box::new(RoundRobin::new()))
+    }
+
+    fn pid(n: u64) -> ProcessId {
+        ProcessId::from_u64(n)
+    }
+
+    #[test]
+    fn test_all_of() {
+        let mut qs = new_queues();
+        let a = WaitFor::new_event_id();
+        let b = WaitFor::new_event_id();
+        qs.give(pid(1), WaitFor::AllOf(vec![WaitFor::Event(a), WaitFor::Event(b)]));
+
+        qs.on_explicit_event(a);
+        assert_eq!(qs.take(), None);
+        qs.on_explicit_event(a);
+        assert_eq!(qs.take(), None);
+        qs.on_explicit_event(b);
+        assert_eq!(qs.take(), Some(pid(1)));
+
+        // Duplicate conditions are only waited for once
+        qs.give(pid(1), WaitFor::AllOf(vec![WaitFor::Event(a), WaitFor::Event(a)]));
+        qs.on_explicit_event(a);
+        assert_eq!(qs.take(), Some(pid(1)));
+    }
+
+    #[test]
+    fn test_cancel_wait() {
+        let mut qs = new_queues();
+        let a = WaitFor::new_event_id();
+        assert!(!qs.cancel_wait(pid(1)));
+
+        qs.give(pid(1), WaitFor::Event(a));
+        assert!(qs.cancel_wait(pid(1)));
+        assert_eq!(qs.take(), Some(pid(1)));
+
+        // The condition no longer wakes the process
+        qs.on_explicit_event(a);
+        assert_eq!(qs.take(), None);
+        assert!(!qs.cancel_wait(pid(1)));
+    }
+
+    #[test]
+    fn test_process_over_ends_waits() {
+        let mut qs = new_queues();
+        let a = WaitFor::new_event_id();
+        qs.give(pid(2), WaitFor::Event(a));
+        qs.give(pid(1), WaitFor::AllOf(vec![WaitFor::Event(a), WaitFor::Process(pid(2))]));
+        assert!(qs.process_exists(pid(2)));
+
+        qs.on_process_over(pid(2));
+        assert!(!qs.process_exists(pid(2)));
+        qs.on_explicit_event(a);
+        assert_eq!(qs.take(), Some(pid(1)));
+        assert_eq!(qs.take(), None);
+    }
+
+    #[test]
+    fn test_consumed_conditions_removed() {
+        let mut qs = new_queues();
+        let a = WaitFor::new_event_id();
+        let b = WaitFor::new_event_id();
+        qs.give(pid(1), WaitFor::FirstOf(vec![WaitFor::Event(a), WaitFor::Event(b)]));
+        qs.give(pid(2), WaitFor::Event(a));
+        assert_eq!(qs.wait_stats().event_waits, 3);
+
+        qs.on_explicit_event(b);
+        assert_eq!(qs.take(), Some(pid(1)));
+        let stats = qs.wait_stats();
+        assert_eq!((stats.waiting, stats.events, stats.event_waits), (1, 1, 1));
+
+        assert!(qs.cancel_wait(pid(2)));
+        let stats = qs.wait_stats();
+        assert_eq!((stats.waiting, stats.events, stats.stale), (0, 0, 0));
+    }
+}
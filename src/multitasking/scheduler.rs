@@ -1,20 +1,32 @@
 use alloc::prelude::v1::*;
+use core::convert::TryFrom;
 use core::sync::atomic::{AtomicBool, Ordering};
 use hashbrown::HashMap;
 use spin::Mutex;
 use x86_64::{PhysAddr, VirtAddr};
 
+use d7abi::input_ring::{self, InputEvent};
+
 use crate::memory;
 use crate::memory::MemoryController;
 use crate::multitasking::{loader::ElfImage, ExplicitEventId};
-use crate::time::BSPInstant;
+use crate::shutdown::{self, Shutdown};
+use crate::time::{self, BSPInstant};
 
-use super::process::{Process, ProcessResult};
+use super::cleanup;
+use super::policy;
+use super::process::{Error, Privilege, Process, ProcessEvent, ProcessResult, Signal};
 use super::queues::Queues;
 use super::{ProcessId, WaitFor};
 
 const TIME_SLICE_NS: u64 = 100_000_000;
 
+/// The timer is programmed to fire at the next instant the scheduler has
+/// something to do, instead of ticking at a fixed rate. These bound the
+/// interval between ticks.
+const MIN_TICK_NS: u64 = 10_000;
+const MAX_TICK_NS: u64 = TIME_SLICE_NS;
+
 /// Process switch an related alternatives
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
@@ -44,15 +56,29 @@ pub struct Scheduler {
     running: Option<ProcessId>,
     /// Next available process id
     next_pid: ProcessId,
+    /// Pending timers set by processes: `(deadline, pid, token)`
+    timers: Vec<(BSPInstant, ProcessId, u64)>,
+    /// Time the timer interrupt is currently programmed for, if any
+    next_tick: Option<BSPInstant>,
+    /// Shutdown in progress, if any
+    shutdown: Option<Shutdown>,
+    /// Futex words with waiting threads: `(address space owner, address)`
+    futexes: HashMap<(ProcessId, VirtAddr), ExplicitEventId>,
 }
 impl Scheduler {
     pub unsafe fn new() -> Self {
+        let policy = policy::boot_policy();
+        log::info!("Scheduler policy: {}", policy.name());
         Self {
             next_switch: None,
             processes: HashMap::new(),
-            queues: Queues::new(),
+            queues: Queues::new(policy),
             running: None,
             next_pid: ProcessId::first(),
+            timers: Vec::new(),
+            next_tick: None,
+            shutdown: None,
+            futexes: HashMap::new(),
         }
     }
 
@@ -79,21 +105,60 @@ impl Scheduler {
         self.processes.keys().copied().collect()
     }
 
-    /// Creates a new process, and returns its pid
-    pub fn spawn(&mut self, m: &mut MemoryController, elf: ElfImage) -> ProcessId {
+    /// Creates a new process, and returns its pid.
+    /// The parent, if any, is notified when the process terminates.
+    pub fn spawn(
+        &mut self, m: &mut MemoryController, elf: ElfImage, privilege: Privilege,
+        parent: Option<ProcessId>,
+    ) -> ProcessId {
         let pid = self.next_pid;
         self.next_pid = self.next_pid.next();
-        let process = unsafe { Process::create(m, pid, elf) };
+        let mut process = unsafe { Process::create(m, pid, elf, privilege) };
+        process.parent = parent;
+        // Children share the clock of their parent
+        if let Some(frame) = parent.and_then(|parent| time::join_namespace(parent, pid)) {
+            process.map_time_page(m, frame);
+        }
         self.processes.insert(pid, process);
+        self.queues.on_spawn(pid, privilege);
         self.queues.give(pid, WaitFor::None);
+        self.reprogram_tick();
         pid
     }
 
+    /// Creates a thread sharing the address space of the creator, and returns its pid.
+    /// The creator is notified when the thread terminates.
+    /// Returns `None` if the stack is not writable memory of the process.
+    pub fn spawn_thread(
+        &mut self, m: &mut MemoryController, creator: ProcessId, entry: VirtAddr,
+        stack_top: VirtAddr, arg: u64,
+    ) -> Option<ProcessId> {
+        let pid = self.next_pid;
+        let owner = self.processes.get(&creator)?.address_space_owner();
+        let mut thread = unsafe {
+            self.processes
+                .get(&owner)
+                .expect("Thread outlived its process")
+                .create_thread(m, pid, entry, stack_top, arg)?
+        };
+        self.next_pid = self.next_pid.next();
+        thread.parent = Some(creator);
+        // The time page is already mapped in the shared address space
+        let _ = time::join_namespace(creator, pid);
+        let privilege = thread.privilege;
+        self.processes.insert(pid, thread);
+        self.queues.on_spawn(pid, privilege);
+        self.queues.give(pid, WaitFor::None);
+        self.reprogram_tick();
+        Some(pid)
+    }
+
     /// Terminates process if it's alive.
     /// Doesn't attempt to switch to a new process.
     /// Used to terminate processes when e.g. their owner process dies.
     pub fn terminate(&mut self, target: ProcessId, status: ProcessResult) {
         if let Some(process) = self.processes.remove(&target) {
+            process.drain_output_ring(true);
             log::info!("Stopping pid {} with status {:?}", target, status);
 
             if process.repeat_syscall {
@@ -101,14 +166,17 @@ impl Scheduler {
             }
 
             // Do not scheduler this process again, and wake up all
-            // processes waiting for the termination of this one
+            // processes waiting for the termination of this one.
+            // A pending system call is dropped with its wait conditions,
+            // so that they cannot wake the process after this.
             self.queues.on_process_over(process.id());
+            debug_assert!(
+                !self.queues.process_exists(target),
+                "Terminated process still queued"
+            );
 
-            // Close open ipc subscriptions and mailboxes
-            {
-                let mut ipc_manager = crate::ipc::IPC.try_lock().expect("IPC locked");
-                ipc_manager.on_process_over(self, process.id(), status.clone());
-            }
+            // Clean up other subsystems, e.g. close open ipc subscriptions and mailboxes
+            cleanup::run(self, target, &status);
 
             // Publish the death of the process
             crate::ipc::kernel_publish(
@@ -116,10 +184,30 @@ impl Scheduler {
                 "process/terminated",
                 &d7abi::ipc::protocol::ProcessTerminated {
                     pid: process.id(),
-                    result: status,
+                    result: status.clone(),
                 },
             );
 
+            // Notify the parent, and cancel timers of the process
+            if let Some(parent) = process.parent {
+                self.post_event(parent, &ProcessEvent::ChildTerminated(target, status));
+            }
+            self.timers.retain(|(_, pid, _)| *pid != target);
+
+            // Threads cannot outlive the address space they use
+            if process.leader.is_none() {
+                self.futexes.retain(|(owner, _), _| *owner != target);
+                let threads: Vec<ProcessId> = self
+                    .processes
+                    .values()
+                    .filter(|p| p.leader == Some(target))
+                    .map(|p| p.id())
+                    .collect();
+                for thread in threads {
+                    self.terminate(thread, ProcessResult::Failed(Error::ChainedTermination));
+                }
+            }
+
             // TODO: Remove process data:
             // * Free stack frames, etc.
         }
@@ -168,9 +256,11 @@ impl Scheduler {
                 self.queues.give(running_pid, s);
             }
         }
+        self.reprogram_tick();
 
         if let Some(pid) = self.queues.take() {
             self.running = Some(pid);
+            // Queues forget terminated processes, see `terminate`
             let process = self
                 .processes
                 .get_mut(&pid)
@@ -208,12 +298,282 @@ impl Scheduler {
         }
     }
 
+    /// Posts an event to the events topic of a process.
+    /// The event is dropped if the process is not listening.
+    pub fn post_event(&mut self, target: ProcessId, event: &ProcessEvent) {
+        if self.processes.contains_key(&target) {
+            let topic = d7abi::process::events_topic(target);
+            crate::ipc::kernel_publish(self, &topic, event);
+        }
+    }
+
+    /// Cancels the blocking system call of a process, which then returns
+    /// `SyscallErrorCode::interrupted` instead of being repeated.
+    /// Returns false if the process is not blocked in a system call.
+    pub fn interrupt_wait(&mut self, pid: ProcessId) -> bool {
+        let blocked = self.processes.get(&pid).map_or(false, |p| p.repeat_syscall);
+        if !blocked || !self.queues.cancel_wait(pid) {
+            return false;
+        }
+        self.processes.get_mut(&pid).unwrap().syscall_interrupted = true;
+        self.reprogram_tick();
+        true
+    }
+
+    /// Sends a signal to a process. `Signal::Kill` terminates it, and
+    /// the other signals are delivered now, or once they are unmasked.
+    pub fn send_signal(&mut self, sender: ProcessId, target: ProcessId, signal: Signal) {
+        if signal == Signal::Kill {
+            self.terminate(target, ProcessResult::Failed(Error::Killed(sender)));
+            return;
+        }
+        if let Some(process) = self.processes.get_mut(&target) {
+            process.pending_signals |= signal.bit();
+            self.deliver_signals(target);
+        }
+    }
+
+    /// Posts the pending signals that are not masked to the events topic
+    /// of the process, and interrupts its blocking system call if any
+    pub fn deliver_signals(&mut self, pid: ProcessId) {
+        let deliverable = match self.processes.get_mut(&pid) {
+            Some(process) => {
+                let deliverable = process.pending_signals & !process.signal_mask;
+                process.pending_signals &= !deliverable;
+                deliverable
+            },
+            None => return,
+        };
+        if deliverable == 0 {
+            return;
+        }
+        for n in 0..64 {
+            if deliverable & (1 << n) != 0 {
+                let signal = Signal::try_from(n).expect("Invalid pending signal");
+                self.post_event(pid, &ProcessEvent::Signal(signal));
+            }
+        }
+        self.interrupt_wait(pid);
+    }
+
+    /// Moves a process, with its threads, to a time namespace with the given
+    /// clock, or reconfigures its namespace. See `time::set_namespace`.
+    /// Returns false if the namespace limit has been reached.
+    pub fn set_time_namespace(
+        &mut self, m: &mut MemoryController, target: ProcessId, offset_ns: u64, scale: u64,
+    ) -> bool {
+        let owner = self.processes[&target].address_space_owner();
+        let frame = match time::set_namespace(m, owner, offset_ns, scale) {
+            Some(frame) => frame,
+            None => return false,
+        };
+        for process in self.processes.values() {
+            if process.leader == Some(owner) {
+                let _ = time::join_namespace(owner, process.id());
+            }
+        }
+        self.processes.get_mut(&owner).unwrap().map_time_page(m, frame);
+        log::debug!("Time namespace of pid {}: offset {} ns, scale {:#x}", owner, offset_ns, scale);
+        true
+    }
+
+    /// Makes a runnable process the next one to be scheduled.
+    /// Returns false if the process is not runnable.
+    pub fn make_next(&mut self, pid: ProcessId) -> bool {
+        self.queues.move_to_front(pid)
+    }
+
+    /// Priority inheritance: a server process handling a reliable delivery
+    /// from a boosted client is boosted until it acknowledges the message
+    pub fn inherit_boost(&mut self, client: ProcessId, server: ProcessId) {
+        self.queues.inherit_boost(client, server);
+    }
+
+    /// Server acknowledged a message from the client
+    pub fn end_inherited_boost(&mut self, server: ProcessId, client: ProcessId) {
+        self.queues.end_inherited_boost(server, client);
+    }
+
+    /// Delivery from the client completed, successfully or not
+    pub fn end_inherited_boosts_of(&mut self, client: ProcessId) {
+        self.queues.end_inherited_boosts_of(client);
+    }
+
+    /// Event for waiting on a futex word. The event is triggered,
+    /// waking all waiters, by `futex_wake`.
+    pub fn futex_event(&mut self, owner: ProcessId, addr: VirtAddr) -> ExplicitEventId {
+        *self
+            .futexes
+            .entry((owner, addr))
+            .or_insert_with(WaitFor::new_event_id)
+    }
+
+    /// Wakes all waiters of a futex word.
+    /// Returns false if there were none.
+    pub fn futex_wake(&mut self, owner: ProcessId, addr: VirtAddr) -> bool {
+        match self.futexes.remove(&(owner, addr)) {
+            Some(event_id) => {
+                self.on_explicit_event(event_id);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Appends an input event to the input rings of all processes,
+    /// and wakes the processes waiting on the doorbells
+    pub fn deliver_input(&mut self, event: InputEvent) {
+        let doorbell = memory::PROCESS_INPUT_RING + input_ring::DOORBELL_OFFSET;
+        let receivers: Vec<ProcessId> = self
+            .processes
+            .values()
+            .filter(|p| p.push_input(event))
+            .map(|p| p.id())
+            .collect();
+        for pid in receivers {
+            self.futex_wake(pid, doorbell);
+        }
+    }
+
+    /// Sets a timer that posts `ProcessEvent::TimerFired(token)`
+    /// to the process after the deadline
+    pub fn set_timer(&mut self, pid: ProcessId, deadline: BSPInstant, token: u64) {
+        self.timers.push((deadline, pid, token));
+        self.reprogram_tick();
+    }
+
+    /// Starts an orderly shutdown, see `crate::shutdown`.
+    /// Returns false if a shutdown is already in progress.
+    pub fn begin_shutdown(&mut self, action: d7abi::ShutdownAction, initiator: ProcessId) -> bool {
+        if self.shutdown.is_some() {
+            return false;
+        }
+        log::info!("Shutdown ({:?}) started by pid {}", action, initiator);
+        self.shutdown = Some(Shutdown::new(action, initiator));
+        crate::ipc::kernel_publish(
+            self,
+            "system/shutdown",
+            &d7abi::ipc::protocol::ShutdownStarted {
+                action,
+                timeout_ns: shutdown::timeout_ns(),
+            },
+        );
+        self.reprogram_tick();
+        true
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_some()
+    }
+
+    /// Terminates the remaining processes and shuts down,
+    /// once the grace period of a shutdown is over
+    fn on_tick_shutdown(&mut self, now: &BSPInstant) {
+        let (action, initiator, deadline) = match &self.shutdown {
+            Some(s) => (s.action, s.initiator, s.deadline),
+            None => return,
+        };
+        let only_initiator = self.processes.keys().all(|pid| *pid == initiator);
+        if !only_initiator && *now < deadline {
+            return;
+        }
+
+        for pid in self.process_ids() {
+            self.terminate(pid, ProcessResult::Failed(Error::Shutdown));
+        }
+        shutdown::finish(action);
+    }
+
+    /// Fires expired timers
+    fn on_tick_timers(&mut self, now: &BSPInstant) {
+        let (expired, pending): (Vec<_>, Vec<_>) =
+            self.timers.drain(..).partition(|(d, _, _)| d <= now);
+        self.timers = pending;
+        for (_, pid, token) in expired {
+            self.post_event(pid, &ProcessEvent::TimerFired(token));
+        }
+    }
+
+    /// Earliest instant when the scheduler has something to do
+    fn tick_deadline(&self, now: BSPInstant) -> BSPInstant {
+        let mut deadline = now.add_ns(MAX_TICK_NS);
+        if self.queues.has_runnable() {
+            deadline = deadline.min(self.next_switch.unwrap_or(now));
+        }
+        if let Some(policy_tick) = self.queues.policy_tick_deadline(now) {
+            deadline = deadline.min(policy_tick);
+        }
+        if let Some(wakeup) = self.queues.next_wakeup() {
+            deadline = deadline.min(wakeup);
+        }
+        for (timer, _, _) in &self.timers {
+            deadline = deadline.min(*timer);
+        }
+        if let Some(shutdown) = &self.shutdown {
+            deadline = deadline.min(shutdown.deadline);
+        }
+        deadline.max(now.add_ns(MIN_TICK_NS))
+    }
+
+    /// Moves the next timer interrupt earlier if something new needs it
+    fn reprogram_tick(&mut self) {
+        let deadline = self.tick_deadline(BSPInstant::now());
+        if self.next_tick.map_or(true, |t| deadline < t) {
+            self.next_tick = Some(deadline);
+            deadline.set_deadline();
+        }
+    }
+
     pub fn tick(&mut self) -> ProcessSwitch {
         let now = BSPInstant::now();
+        crate::time::update_time_page();
+        for process in self.processes.values() {
+            process.drain_output_ring(false);
+        }
         self.queues.on_tick(&now);
+        self.on_tick_timers(&now);
+        self.on_tick_shutdown(&now);
+        self.on_tick_memory_pressure();
+        crate::init_process::on_tick(self);
+        crate::services::on_tick(self);
+        let switch = self.tick_switch(now);
+
+        // The programmed deadline has passed, so always program a new one
+        self.next_tick = None;
+        self.reprogram_tick();
+        self.store_stats();
+        switch
+    }
+
+    /// Publishes the memory pressure and kernel heap levels when they have changed
+    fn on_tick_memory_pressure(&mut self) {
+        if let Some(pressure) = crate::memory::pressure::changed() {
+            log::warn!("Memory pressure: {:?}", pressure);
+            crate::ipc::kernel_publish_retained(self, "memory/pressure", &pressure);
+        }
+        if let Some(heap) = crate::memory::heap::changed() {
+            log::warn!("Kernel heap usage: {:?}", heap);
+            crate::ipc::kernel_publish_retained(self, "memory/kernel_heap", &heap);
+        }
+    }
+
+    /// Stores queue sizes and stack usage for `super::stats`,
+    /// and reports leaked wait conditions
+    fn store_stats(&mut self) {
+        let waits = self.queues.wait_stats();
+        debug_assert_eq!(waits.stale, 0, "Conditions of consumed waits left in queues");
+        super::stats::store(self.processes.len(), waits, self.futexes.len());
+        let stacks = self.processes.values().filter(|p| p.leader.is_none());
+        super::stats::store_stacks(stacks.map(|p| {
+            let mapped = (p.stack_frames.len() as u64) * memory::PAGE_SIZE_BYTES;
+            (p.id(), mapped, p.stack_usage)
+        }));
+    }
+
+    fn tick_switch(&mut self, now: BSPInstant) -> ProcessSwitch {
         match self.next_switch {
             Some(s) => {
-                if now >= s {
+                if now >= s || self.queues.should_preempt(self.running) {
                     self.next_switch = Some(now.add_ns(TIME_SLICE_NS));
                     unsafe { self.switch(Some(WaitFor::None)) }
                 } else {
@@ -237,6 +597,7 @@ impl Scheduler {
     /// Relay events to queues
     pub fn on_explicit_event(&mut self, event_id: ExplicitEventId) {
         self.queues.on_explicit_event(event_id);
+        self.reprogram_tick();
     }
 
     /// Full-screen view of the current scheduler status
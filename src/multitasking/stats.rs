@@ -0,0 +1,69 @@
+//! Snapshot of the scheduler queue sizes and process stack usage, stored on
+//! every scheduler tick, so that kernel services can read them without
+//! locking the scheduler.
+
+use alloc::prelude::v1::*;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use super::process::StackUsage;
+use super::queues::WaitStats;
+use super::ProcessId;
+
+static PROCESSES: AtomicU64 = AtomicU64::new(0);
+static WAITING: AtomicU64 = AtomicU64::new(0);
+static EVENTS: AtomicU64 = AtomicU64::new(0);
+static EVENT_WAITS: AtomicU64 = AtomicU64::new(0);
+static PROCESS_WAITS: AtomicU64 = AtomicU64::new(0);
+static SLEEPING: AtomicU64 = AtomicU64::new(0);
+static STALE: AtomicU64 = AtomicU64::new(0);
+static FUTEXES: AtomicU64 = AtomicU64::new(0);
+
+/// Stack usage of each process: `(pid, mapped bytes, usage)`
+static STACKS: Mutex<Vec<(ProcessId, u64, StackUsage)>> = Mutex::new(Vec::new());
+
+pub fn store(processes: usize, waits: WaitStats, futexes: usize) {
+    let set = |counter: &AtomicU64, value: usize| counter.store(value as u64, Ordering::Relaxed);
+    set(&PROCESSES, processes);
+    set(&WAITING, waits.waiting);
+    set(&EVENTS, waits.events);
+    set(&EVENT_WAITS, waits.event_waits);
+    set(&PROCESS_WAITS, waits.process_waits);
+    set(&SLEEPING, waits.sleeping);
+    set(&STALE, waits.stale);
+    set(&FUTEXES, futexes);
+}
+
+/// The latest snapshot as text lines
+pub fn table() -> Vec<String> {
+    let rows: [(&str, &AtomicU64); 8] = [
+        ("processes", &PROCESSES),
+        ("waiting", &WAITING),
+        ("events", &EVENTS),
+        ("event waits", &EVENT_WAITS),
+        ("process waits", &PROCESS_WAITS),
+        ("sleeping", &SLEEPING),
+        ("stale waits", &STALE),
+        ("futex words", &FUTEXES),
+    ];
+    rows.iter()
+        .map(|(name, counter)| format!("{:<16} {:>12}", name, counter.load(Ordering::Relaxed)))
+        .collect()
+}
+
+pub fn store_stacks(stacks: impl Iterator<Item = (ProcessId, u64, StackUsage)>) {
+    let mut stored = STACKS.lock();
+    stored.clear();
+    stored.extend(stacks);
+}
+
+/// Stack usage of each process in the latest snapshot as text lines, sizes in bytes
+pub fn stack_table() -> Vec<String> {
+    let mut stacks = STACKS.lock().clone();
+    stacks.sort_by_key(|(pid, _, _)| *pid);
+    let header = format!("{:<8} {:>12} {:>12} {:>6}", "pid", "max depth", "mapped", "grows");
+    let rows = stacks.into_iter().map(|(pid, mapped, usage)| {
+        format!("{:<8} {:>12} {:>12} {:>6}", pid, usage.max_depth, mapped, usage.grows)
+    });
+    Some(header).into_iter().chain(rows).collect()
+}
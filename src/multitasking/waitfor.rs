@@ -21,6 +21,9 @@ pub enum WaitFor {
     /// First of multiple wait conditions.
     /// Should never contain `None`.
     FirstOf(Vec<WaitFor>),
+    /// All of multiple wait conditions, in any order.
+    /// Should never contain `None`.
+    AllOf(Vec<WaitFor>),
 }
 impl WaitFor {
     /// Resolve the condition immediately, if possible
@@ -54,6 +57,20 @@ impl WaitFor {
         use WaitFor::*;
 
         let process_done = |p| current != p && !qs.process_exists(p);
+        if let AllOf(subevents) = self {
+            // Completed processes are no longer waited for
+            return AllOf(
+                subevents
+                    .into_iter()
+                    .filter(|e| match e {
+                        Process(p) => !process_done(*p),
+                        _ => true,
+                    })
+                    .collect(),
+            )
+            .reduce();
+        }
+
         match &self {
             Process(p) if process_done(*p) => {
                 return None;
@@ -95,7 +112,7 @@ impl WaitFor {
                                 earliest = Some(instant);
                             }
                         },
-                        FirstOf(_) => {
+                        FirstOf(_) | AllOf(_) => {
                             panic!("NESTED FirstOf in reduce");
                         },
                         other => {
@@ -116,6 +133,41 @@ impl WaitFor {
                     FirstOf(new_se)
                 }
             },
+            AllOf(subevents) => {
+                let mut new_se = Vec::new();
+                let mut latest: Option<BSPInstant> = Option::None;
+                for e in subevents.into_iter() {
+                    match e {
+                        None => {
+                            panic!("None in AllOf");
+                        },
+                        Time(instant) => {
+                            latest = Some(latest.map_or(instant, |l| l.max(instant)));
+                        },
+                        FirstOf(_) | AllOf(_) => {
+                            panic!("NESTED AllOf in reduce");
+                        },
+                        other => {
+                            // Each condition is counted once, even if given twice
+                            if !new_se.contains(&other) {
+                                new_se.push(other);
+                            }
+                        },
+                    }
+                }
+
+                if let Some(l) = latest {
+                    new_se.push(Time(l));
+                }
+
+                if new_se.is_empty() {
+                    None
+                } else if new_se.len() == 1 {
+                    new_se.pop().unwrap()
+                } else {
+                    AllOf(new_se)
+                }
+            },
             other => other,
         }
     }
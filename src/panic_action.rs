@@ -0,0 +1,66 @@
+//! Configurable action after a kernel panic has been reported
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use cpuio::outb;
+use d7abi::{PanicAction, PANIC_REBOOT_DELAY_MAX_SECONDS};
+use x86_64::instructions::tables::lidt;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+use crate::driver::tsc;
+use crate::memory::constants::{PANIC_REBOOT, PANIC_REBOOT_DELAY_SECONDS};
+
+/// Value used to represent `PanicAction::Halt`
+const HALT: u64 = u64::MAX;
+
+/// Reboot delay in seconds, or `HALT`.
+/// An atomic, as locks cannot be used during a panic.
+static REBOOT_AFTER: AtomicU64 = AtomicU64::new(if PANIC_REBOOT {
+    PANIC_REBOOT_DELAY_SECONDS
+} else {
+    HALT
+});
+
+pub fn set(action: PanicAction) {
+    let value = match action {
+        PanicAction::Halt => HALT,
+        PanicAction::Reboot { after_seconds } => {
+            assert!(after_seconds <= PANIC_REBOOT_DELAY_MAX_SECONDS);
+            after_seconds
+        },
+    };
+    REBOOT_AFTER.store(value, Ordering::SeqCst);
+}
+
+/// Called from the panic handler, after the panic has been reported.
+/// Returns if the system should be halted.
+pub fn run() {
+    let after_seconds = REBOOT_AFTER.load(Ordering::SeqCst);
+    if after_seconds == HALT {
+        return;
+    }
+
+    log::error!("Rebooting in {} seconds", after_seconds);
+
+    // Busy wait, as interrupt handlers must not run anymore
+    let deadline = tsc::read() + tsc::ns_to_ticks(after_seconds.saturating_mul(1_000_000_000));
+    while tsc::read() < deadline {
+        core::hint::spin_loop();
+    }
+
+    reboot();
+}
+
+/// Reset using the keyboard controller,
+/// and if that fails, triple fault with an empty IDT
+pub fn reboot() -> ! {
+    unsafe {
+        outb(0xfe, 0x64);
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::zero(),
+        });
+        asm!("int3");
+    }
+    loop {}
+}
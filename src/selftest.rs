@@ -0,0 +1,160 @@
+//! Boot-time self-test, enabled with the `SELFTEST` build constant.
+//!
+//! Runs after the kernel has been initialized, instead of starting the
+//! service daemon. Each test briefly exercises one subsystem, and logs a
+//! `SELFTEST <name> PASS` or `SELFTEST <name> FAIL: <reason>` line, so the
+//! results can be read from the serial port. The system is powered off
+//! afterwards, making this usable as a CI smoke check.
+//!
+//! There is no filesystem in the kernel, so the initrd is read instead.
+
+use alloc::prelude::v1::*;
+
+use d7abi::process::ProcessResult;
+use d7abi::ShutdownAction;
+
+use crate::driver::{acpi, ioapic, tsc};
+use crate::ipc::{self, Topic, TopicFilter};
+use crate::multitasking::{process, ProcessId, SCHEDULER};
+use crate::{initrd, memory, smp};
+
+type TestResult = Result<(), String>;
+
+const TESTS: &[(&str, fn() -> TestResult)] = &[
+    ("heap", test_heap),
+    ("dma", test_dma),
+    ("initrd", test_initrd),
+    ("ipc", test_ipc),
+    ("process", test_process),
+    ("timer", test_timer),
+    ("smp_ipi", test_smp_ipi),
+];
+
+pub fn run() -> ! {
+    log::info!("SELFTEST starting {} tests", TESTS.len());
+    let mut failed = 0;
+    for (name, test) in TESTS {
+        match test() {
+            Ok(()) => log::info!("SELFTEST {} PASS", name),
+            Err(reason) => {
+                log::error!("SELFTEST {} FAIL: {}", name, reason);
+                failed += 1;
+            },
+        }
+    }
+    log::info!("SELFTEST done: {} passed, {} failed", TESTS.len() - failed, failed);
+    crate::shutdown::finish(ShutdownAction::PowerOff)
+}
+
+fn check(condition: bool, reason: &str) -> TestResult {
+    if condition {
+        Ok(())
+    } else {
+        Err(reason.to_owned())
+    }
+}
+
+/// Allocate, fill, verify and drop buffers of varying sizes
+fn test_heap() -> TestResult {
+    for round in 0..64usize {
+        let size = 1 + (round * 37) % 4096;
+        let buffer: Vec<u8> = (0..size).map(|i| (i ^ round) as u8).collect();
+        for (i, byte) in buffer.iter().enumerate() {
+            check(*byte == (i ^ round) as u8, "Heap buffer corrupted")?;
+        }
+    }
+    Ok(())
+}
+
+/// Freeing a region must make the same space available again
+fn test_dma() -> TestResult {
+    memory::configure(|mm| {
+        let a = mm.dma_allocator.allocate(0x2000);
+        let b = mm.dma_allocator.allocate(0x1000);
+        let (a, b) = match (a, b) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Err("DMA allocation failed".to_owned()),
+        };
+        check(a.start != b.start, "Overlapping DMA regions")?;
+        check(mm.dma_allocator.free(a), "Freeing a DMA region failed")?;
+        check(!mm.dma_allocator.free(a), "DMA region freed twice")?;
+        let c = mm.dma_allocator.allocate(0x2000);
+        check(c.map(|c| c.start) == Some(a.start), "Freed DMA region not reused")?;
+        check(mm.dma_allocator.free(c.unwrap()), "Freeing a DMA region failed")?;
+        check(mm.dma_allocator.free(b), "Freeing a DMA region failed")
+    })
+}
+
+fn test_initrd() -> TestResult {
+    let bytes = initrd::read("serviced").ok_or("serviced missing from initrd")?;
+    check(bytes.starts_with(b"\x7fELF"), "serviced is not an ELF file")
+}
+
+/// Publish and receive with a separate IPC manager, using a fake process id
+fn test_ipc() -> TestResult {
+    let mut manager = ipc::Manager::new();
+    let pid = ProcessId::from_u64(u64::MAX);
+    let filter = TopicFilter::try_new("selftest/ipc", true).map_err(|e| format!("{:?}", e))?;
+    let topic = Topic::try_new("selftest/ipc").map_err(|e| format!("{:?}", e))?;
+
+    let sub = manager
+        .subscribe(pid, filter, false)
+        .map_err(|e| format!("Subscribe: {:?}", e))?;
+    let (result, _) = manager.publish(topic, b"ping").separate_events();
+    result.map_err(|e| format!("Publish: {:?}", e))?;
+    let (result, _) = manager.receive(pid, sub).separate_events();
+    let message = match result {
+        Ok(Ok(message)) => message,
+        Ok(Err(_)) => return Err("Published message not received".to_owned()),
+        Err(e) => return Err(format!("Receive: {:?}", e)),
+    };
+    check(message.data == b"ping", "Received data differs")?;
+    let _ = manager.on_process_over(pid, ProcessResult::Completed(0));
+    Ok(())
+}
+
+/// Spawn a process without running it, and terminate it
+fn test_process() -> TestResult {
+    let bytes = initrd::read("serviced").ok_or("serviced missing from initrd")?;
+    memory::configure(|mm| {
+        let elf = process::load_elf(mm, bytes).map_err(|e| format!("{:?}", e))?;
+        let mut sched = SCHEDULER.try_lock().ok_or("Scheduler locked")?;
+        let pid = sched.spawn(mm, elf, process::Privilege::User, None);
+        check(sched.process_by_id(pid).is_some(), "Spawned process not found")?;
+        sched.terminate(pid, ProcessResult::Completed(0));
+        check(sched.process_by_id(pid).is_none(), "Terminated process still exists")
+    })
+}
+
+/// The TSC deadline interrupt must wake the core on time
+fn test_timer() -> TestResult {
+    const SLEEP_NS: u64 = 10_000_000;
+    let start = tsc::read();
+    tsc::sleep_ns(SLEEP_NS);
+    let elapsed = tsc::ticks_to_ns(tsc::read() - start);
+    if elapsed < SLEEP_NS || elapsed > SLEEP_NS + SLEEP_NS / 2 {
+        Err(format!("Slept {} ns instead of {} ns", elapsed, SLEEP_NS))
+    } else {
+        Ok(())
+    }
+}
+
+/// Every AP core must answer a ping IPI within 100 ms
+fn test_smp_ipi() -> TestResult {
+    let acpi_data = acpi::ACPI_DATA.r#try().ok_or("ACPI not initialized")?;
+    let current = smp::current_processor_id();
+    let vector = smp::ipi_ping_vector();
+    check(vector != 0, "Ping vector not allocated")?;
+    for apic_id in acpi_data.cpus.iter().filter(|id| **id != current) {
+        let before = smp::ipi_ping_count();
+        ioapic::send_ipi(*apic_id, vector, true);
+        let deadline = tsc::read() + tsc::ns_to_ticks(100_000_000);
+        while smp::ipi_ping_count() == before {
+            if tsc::read() > deadline {
+                return Err(format!("Core {} did not answer", apic_id));
+            }
+            core::hint::spin_loop();
+        }
+    }
+    Ok(())
+}
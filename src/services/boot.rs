@@ -0,0 +1,16 @@
+use alloc::prelude::v1::*;
+
+use d7abi::process::ProcessId;
+
+use crate::init_process;
+use crate::ipc::{DeliveryError, Manager, Message, Topic};
+
+use super::parse_request;
+
+/// How the first process was selected
+pub fn init(manager: &mut Manager, pid: ProcessId, message: Message) -> Result<(), DeliveryError> {
+    let (reply_to, ()): (Topic, ()) = parse_request(pid, &message, "boot/init request")?;
+
+    let status = init_process::status().expect("Init process not started");
+    manager.kernel_deliver_reply(reply_to, &status)
+}
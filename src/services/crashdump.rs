@@ -0,0 +1,17 @@
+use alloc::prelude::v1::*;
+
+use d7abi::process::ProcessId;
+
+use crate::crashdump;
+use crate::ipc::{DeliveryError, Manager, Message, Topic};
+
+use super::parse_request;
+
+/// Crash dump from the previous boot, if any
+pub fn read(manager: &mut Manager, pid: ProcessId, message: Message) -> Result<(), DeliveryError> {
+    let (reply_to, ()): (Topic, ()) = parse_request(pid, &message, "crashdump request")?;
+
+    let dump: Option<String> =
+        crashdump::previous().map(|data| String::from_utf8_lossy(&data).into_owned());
+    manager.kernel_deliver_reply(reply_to, &dump)
+}
@@ -0,0 +1,35 @@
+use alloc::prelude::v1::*;
+
+use d7abi::ipc::protocol::IpcTraceRequest;
+use d7abi::process::ProcessId;
+
+use crate::ipc::{DeliveryError, Manager, Message, Topic};
+
+use super::parse_request;
+
+/// Snapshot of the IPC state, see `Manager::dump`.
+/// Only drivers can deliver to `debug/` topics.
+pub fn ipc(manager: &mut Manager, pid: ProcessId, message: Message) -> Result<(), DeliveryError> {
+    let (reply_to, ()): (Topic, ()) = parse_request(pid, &message, "IPC dump request")?;
+
+    let dump = manager.dump();
+    manager.kernel_deliver_reply(reply_to, &dump)
+}
+
+/// Enables or disables tracing of a topic prefix, see `Manager::set_trace`
+pub fn ipc_trace(
+    manager: &mut Manager, pid: ProcessId, message: Message,
+) -> Result<(), DeliveryError> {
+    let (reply_to, request): (Topic, IpcTraceRequest) =
+        parse_request(pid, &message, "IPC trace request")?;
+
+    let prefixes = match request {
+        IpcTraceRequest::List => manager.traced_prefixes(),
+        IpcTraceRequest::Enable(prefix) => {
+            log::info!("IPC trace enabled for {:?} by pid {}", prefix, pid);
+            manager.set_trace(prefix, true)
+        },
+        IpcTraceRequest::Disable(prefix) => manager.set_trace(prefix, false),
+    };
+    manager.kernel_deliver_reply(reply_to, &prefixes)
+}
@@ -1,22 +1,44 @@
 use alloc::prelude::v1::*;
 
+use d7abi::ipc::protocol::initrd::{ReadAt, ReadAtReply, READ_AT_MAX_BYTES};
 use d7abi::process::ProcessId;
 
 use crate::ipc::{DeliveryError, IpcResult, Manager, Message, Topic};
 
+use super::parse_request;
+
 pub fn read(manager: &mut Manager, pid: ProcessId, message: Message) -> Result<(), DeliveryError> {
-    let (reply_to, path): (String, String) = pinecone::from_bytes(&message.data)
-        .expect("Invalid message: TODO: just reply client error");
+    let (reply_to, path): (Topic, String) = parse_request(pid, &message, "initrd read request")?;
 
-    let reply_to = Topic::new(&reply_to).ok_or_else(|| {
-        log::warn!("Invalid reply_to topic name from {:?}", pid);
+    let data = crate::initrd::read(&path).ok_or_else(|| {
+        log::warn!("Missing initrd file requested by {:?}", pid);
         DeliveryError::NegativeAcknowledgement
     })?;
 
-    let data = crate::initrd::read(&path).ok_or_else(|| {
+    manager.kernel_deliver_reply(reply_to, data)
+}
+
+/// Part of a file, so that clients can seek without transferring the whole file
+pub fn read_at(
+    manager: &mut Manager, pid: ProcessId, message: Message,
+) -> Result<(), DeliveryError> {
+    let (reply_to, request): (Topic, ReadAt) =
+        parse_request(pid, &message, "initrd read_at request")?;
+
+    if request.len > READ_AT_MAX_BYTES {
+        log::warn!("Too large initrd read_at requested by {:?}", pid);
+        return Err(DeliveryError::NegativeAcknowledgement);
+    }
+
+    let file = crate::initrd::read(&request.path).ok_or_else(|| {
         log::warn!("Missing initrd file requested by {:?}", pid);
         DeliveryError::NegativeAcknowledgement
     })?;
 
-    manager.kernel_deliver_reply(reply_to, data)
+    let start = request.offset.min(file.len() as u64) as usize;
+    let end = (start + request.len as usize).min(file.len());
+    manager.kernel_deliver_reply(reply_to, &ReadAtReply {
+        size: file.len() as u64,
+        data: file[start..end].to_vec(),
+    })
 }
@@ -0,0 +1,15 @@
+use alloc::prelude::v1::*;
+
+use d7abi::process::ProcessId;
+
+use crate::interrupt::stats;
+use crate::ipc::{DeliveryError, Manager, Message, Topic};
+
+use super::parse_request;
+
+/// Interrupt counts per vector and core, as text table rows
+pub fn stats(manager: &mut Manager, pid: ProcessId, message: Message) -> Result<(), DeliveryError> {
+    let (reply_to, ()): (Topic, ()) = parse_request(pid, &message, "interrupt stats request")?;
+
+    manager.kernel_deliver_reply(reply_to, &stats::table())
+}
@@ -0,0 +1,63 @@
+use alloc::prelude::v1::*;
+use spin::Mutex;
+
+use d7abi::process::ProcessId;
+
+use crate::ipc::{self, DeliveryError, Manager, Message, Topic};
+use crate::multitasking::Scheduler;
+use crate::syslog;
+
+use super::parse_request;
+
+/// Maximum number of lines in a single reply
+const MAX_LINES: usize = 64;
+
+/// Maximum number of readers waiting for new lines
+const MAX_WAITING: usize = 16;
+
+/// Readers waiting for lines from a sequence number, with their reply topics
+static WAITING: Mutex<Vec<(Topic, u64)>> = Mutex::new(Vec::new());
+
+/// Kernel log lines starting from a sequence number. Each reader keeps its own
+/// position, and continues from the returned sequence number plus line count.
+/// With `wait`, the reply is postponed until there is at least one line.
+/// Only drivers can deliver to `log/` topics.
+pub fn read(manager: &mut Manager, pid: ProcessId, message: Message) -> Result<(), DeliveryError> {
+    let (reply_to, (seq, wait)): (Topic, (u64, bool)) =
+        parse_request(pid, &message, "log request")?;
+
+    if wait && seq >= syslog::next_seq() {
+        let mut waiting = WAITING.lock();
+        if waiting.len() >= MAX_WAITING {
+            log::warn!("Too many waiting log readers, refusing {:?}", pid);
+            return Err(DeliveryError::NegativeAcknowledgement);
+        }
+        waiting.push((reply_to, seq));
+        return Ok(());
+    }
+
+    let lines: (u64, Vec<String>) = syslog::lines_from(seq, MAX_LINES);
+    manager.kernel_deliver_reply(reply_to, &lines)
+}
+
+/// Replies to the waiting readers that have new lines available.
+/// Readers that have exited meanwhile are dropped.
+pub fn on_tick(sched: &mut Scheduler) {
+    let next_seq = syslog::next_seq();
+    let ready: Vec<(Topic, u64)> = {
+        let mut waiting = WAITING.lock();
+        if waiting.iter().all(|(_, seq)| *seq >= next_seq) {
+            return;
+        }
+        let (ready, still_waiting) = waiting.drain(..).partition(|(_, seq)| *seq < next_seq);
+        *waiting = still_waiting;
+        ready
+    };
+
+    for (reply_to, seq) in ready {
+        let lines: (u64, Vec<String>) = syslog::lines_from(seq, MAX_LINES);
+        let _ = ipc::with_manager(sched, |ipc_manager| {
+            ipc_manager.kernel_deliver_reply_later(reply_to, &lines)
+        });
+    }
+}
@@ -0,0 +1,15 @@
+use alloc::prelude::v1::*;
+
+use d7abi::process::ProcessId;
+
+use crate::ipc::{DeliveryError, Manager, Message, Topic};
+use crate::latency;
+
+use super::parse_request;
+
+/// Syscall and interrupt latency histograms, as text table rows
+pub fn stats(manager: &mut Manager, pid: ProcessId, message: Message) -> Result<(), DeliveryError> {
+    let (reply_to, ()): (Topic, ()) = parse_request(pid, &message, "latency stats request")?;
+
+    manager.kernel_deliver_reply(reply_to, &latency::table())
+}
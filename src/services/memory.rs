@@ -0,0 +1,27 @@
+use alloc::prelude::v1::*;
+
+use d7abi::process::ProcessId;
+
+use crate::ipc::{DeliveryError, Manager, Message, Topic};
+use crate::memory::dma_allocator::stats;
+use crate::memory::kernel_stacks;
+
+use super::parse_request;
+
+/// DMA memory usage and allocation counters, as text lines
+pub fn dma_stats(
+    manager: &mut Manager, pid: ProcessId, message: Message,
+) -> Result<(), DeliveryError> {
+    let (reply_to, ()): (Topic, ()) = parse_request(pid, &message, "DMA stats request")?;
+
+    manager.kernel_deliver_reply(reply_to, &stats::table())
+}
+
+/// High-water marks of the kernel stacks, as text lines
+pub fn kernel_stacks(
+    manager: &mut Manager, pid: ProcessId, message: Message,
+) -> Result<(), DeliveryError> {
+    let (reply_to, ()): (Topic, ()) = parse_request(pid, &message, "kernel stacks request")?;
+
+    manager.kernel_deliver_reply(reply_to, &kernel_stacks::scan())
+}
@@ -3,19 +3,50 @@
 
 use alloc::prelude::v1::*;
 use hashbrown::HashMap;
+use serde::de::DeserializeOwned;
 use serde::Serialize;
 use spin::Mutex;
 
 use d7abi::process::ProcessId;
 
 use crate::ipc::{
-    AcknowledgeId, DeliveryError, IpcResult, Manager, Message, SubscriptionId, TopicFilter, IPC,
+    AcknowledgeId, DeliveryError, IpcResult, Manager, Message, SubscriptionId, Topic, TopicFilter,
+    IPC,
 };
+use crate::multitasking::Scheduler;
 
+mod boot;
+mod crashdump;
+mod debug;
 mod initrd;
+mod interrupts;
+mod kernel_log;
+mod latency;
+mod memory;
+mod scheduler;
+mod screen;
+mod time;
 
 pub fn init() {
+    register_exact("boot/init", boot::init);
+    register_exact("crashdump/read", crashdump::read);
+    register_exact("console/screen", screen::read);
+    register_exact("debug/ipc", debug::ipc);
+    register_exact("debug/ipc_trace", debug::ipc_trace);
     register_exact("initrd/read", initrd::read);
+    register_exact("initrd/read_at", initrd::read_at);
+    register_exact("interrupts/stats", interrupts::stats);
+    register_exact("latency/stats", latency::stats);
+    register_exact("log/kernel", kernel_log::read);
+    register_exact("memory/dma_stats", memory::dma_stats);
+    register_exact("memory/kernel_stacks", memory::kernel_stacks);
+    register_exact("scheduler/stats", scheduler::stats);
+    register_exact("time/monotonic", time::monotonic);
+}
+
+/// Completes the requests that services have postponed
+pub fn on_tick(sched: &mut Scheduler) {
+    kernel_log::on_tick(sched);
 }
 
 fn register(filter: TopicFilter, service: Service) {
@@ -41,6 +72,24 @@ fn register_prefix(filter: &str, service: Service) {
     )
 }
 
+/// Parses a request of the form `(reply_to, payload)`. Invalid requests are
+/// logged with `what` describing the request, and negatively acknowledged.
+fn parse_request<T: DeserializeOwned>(
+    pid: ProcessId, message: &Message, what: &str,
+) -> Result<(Topic, T), DeliveryError> {
+    let (reply_to, payload): (String, T) = pinecone::from_bytes(&message.data).map_err(|_| {
+        log::warn!("Invalid {} from {:?}", what, pid);
+        DeliveryError::NegativeAcknowledgement
+    })?;
+
+    let reply_to = Topic::new(&reply_to).ok_or_else(|| {
+        log::warn!("Invalid reply_to topic name from {:?}", pid);
+        DeliveryError::NegativeAcknowledgement
+    })?;
+
+    Ok((reply_to, payload))
+}
+
 /// For now, scheduler and ipc are unavailable for services
 type Service = fn(&mut Manager, ProcessId, Message) -> Result<(), DeliveryError>;
 
@@ -0,0 +1,18 @@
+use alloc::prelude::v1::*;
+
+use d7abi::process::ProcessId;
+
+use crate::ipc::{DeliveryError, Manager, Message, Topic};
+use crate::multitasking::stats;
+
+use super::parse_request;
+
+/// Scheduler queue sizes from the latest tick, followed by
+/// the stack usage of each process, as text lines
+pub fn stats(manager: &mut Manager, pid: ProcessId, message: Message) -> Result<(), DeliveryError> {
+    let (reply_to, ()): (Topic, ()) = parse_request(pid, &message, "scheduler stats request")?;
+
+    let mut lines = stats::table();
+    lines.extend(stats::stack_table());
+    manager.kernel_deliver_reply(reply_to, &lines)
+}
@@ -0,0 +1,29 @@
+use alloc::prelude::v1::*;
+
+use d7abi::process::ProcessId;
+
+use crate::driver::vga_buffer::{hardware_terminal, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::ipc::{DeliveryError, Manager, Message, Topic};
+
+use super::parse_request;
+
+/// Snapshot of the VGA text buffer, one string per row with trailing spaces
+/// removed. Characters outside printable ASCII are replaced with U+FFFD.
+pub fn read(manager: &mut Manager, pid: ProcessId, message: Message) -> Result<(), DeliveryError> {
+    let (reply_to, ()): (Topic, ()) = parse_request(pid, &message, "screen request")?;
+
+    let terminal = hardware_terminal();
+    let rows: Vec<String> = (0..SCREEN_HEIGHT)
+        .map(|row| {
+            let line: String = (0..SCREEN_WIDTH)
+                .map(|col| match terminal.cell(row, col).character {
+                    0 => ' ',
+                    c @ 0x20..=0x7e => c as char,
+                    _ => '\u{fffd}',
+                })
+                .collect();
+            line.trim_end().to_owned()
+        })
+        .collect();
+    manager.kernel_deliver_reply(reply_to, &rows)
+}
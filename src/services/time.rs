@@ -0,0 +1,18 @@
+use alloc::prelude::v1::*;
+
+use d7abi::process::ProcessId;
+
+use crate::ipc::{DeliveryError, Manager, Message, Topic};
+
+use super::parse_request;
+
+/// Monotonic time, as a duration from an unspecified fixed point.
+/// Uses the clock of the time namespace of the caller, if any.
+pub fn monotonic(
+    manager: &mut Manager, pid: ProcessId, message: Message,
+) -> Result<(), DeliveryError> {
+    let (reply_to, ()): (Topic, ()) = parse_request(pid, &message, "time request")?;
+
+    let now = crate::time::monotonic(pid);
+    manager.kernel_deliver_reply(reply_to, &now)
+}
@@ -0,0 +1,70 @@
+//! Orderly shutdown, started with the `kernel_shutdown` system call:
+//!
+//! 1. `ShutdownStarted` is published to `system/shutdown`, and processes have
+//!    `SHUTDOWN_TIMEOUT_SECONDS` to flush their state and exit. Their results
+//!    are logged and published to `process/terminated` as usual.
+//! 2. When only the initiating process is left, or the timeout has passed,
+//!    the remaining processes are terminated with `Error::Shutdown`.
+//! 3. The AP cores are stopped, and the system is powered off or rebooted.
+//!
+//! Filesystems and block caches live in driver processes, which must flush
+//! them on the shutdown event, as the kernel has none of its own.
+
+use cpuio::outw;
+use d7abi::ShutdownAction;
+
+use crate::memory::constants::SHUTDOWN_TIMEOUT_SECONDS;
+use crate::multitasking::ProcessId;
+use crate::time::BSPInstant;
+
+#[derive(Debug)]
+pub struct Shutdown {
+    pub action: ShutdownAction,
+    /// Process that started the shutdown
+    pub initiator: ProcessId,
+    /// End of the grace period
+    pub deadline: BSPInstant,
+}
+impl Shutdown {
+    pub fn new(action: ShutdownAction, initiator: ProcessId) -> Self {
+        Self {
+            action,
+            initiator,
+            deadline: BSPInstant::now().add_ns(timeout_ns()),
+        }
+    }
+}
+
+pub fn timeout_ns() -> u64 {
+    SHUTDOWN_TIMEOUT_SECONDS * 1_000_000_000
+}
+
+/// Called once no processes are left
+pub fn finish(action: ShutdownAction) -> ! {
+    // Logs the grown high-water marks, to size the stacks for the next boot
+    crate::memory::kernel_stacks::scan();
+    crate::smp::stop_aps();
+    match action {
+        ShutdownAction::PowerOff => power_off(),
+        ShutdownAction::Reboot => {
+            log::info!("Rebooting");
+            crate::panic_action::reboot()
+        },
+    }
+}
+
+/// Uses the ACPI sleep ports of QEMU and Bochs, as the kernel has no AML
+/// interpreter to read the `_S5` sleep type from the DSDT
+fn power_off() -> ! {
+    log::info!("Powering off");
+    unsafe {
+        outw(0x2000, 0x604);
+        outw(0x2000, 0xb004);
+    }
+    log::warn!("Power off not supported, halting");
+    loop {
+        unsafe {
+            asm!("cli; hlt");
+        }
+    }
+}
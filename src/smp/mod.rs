@@ -2,7 +2,7 @@
 
 use alloc::vec::Vec;
 use core::fmt;
-use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
 use spin::Mutex;
 use x86_64::VirtAddr;
 
@@ -18,25 +18,37 @@ pub fn current_processor_id() -> ProcessorId {
     }
 }
 
+/// APIC id of the BSP, which is not always zero
+static BSP_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Called by the BSP when enabling the APIC
+pub fn set_bsp_id(id: ProcessorId) {
+    BSP_ID.store(id.0, Ordering::SeqCst);
+}
+
 /// If current core is BSP
 pub fn is_bsp() -> bool {
     if ioapic::is_enabled() {
-        ioapic::apic_processor_id().0 == 0
+        ioapic::apic_processor_id().0 == BSP_ID.load(Ordering::SeqCst)
     } else {
         true
     }
 }
 
-/// Processor (ACPI) id
+/// Processor (local APIC) id, 32 bits wide in x2APIC mode
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(transparent)]
-pub struct ProcessorId(pub u8);
+pub struct ProcessorId(pub u32);
 impl fmt::Display for ProcessorId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
+/// Cores that can be started, limited by the space for
+/// the per-core GDTs, see `docs/plan.md`
+pub const MAX_CORES: usize = 128;
+
 /// Stores pointers to stacks available for new cores
 /// Used by rust_ap_entry to create a new stack
 static AP_FREE_STACK: AtomicU64 = AtomicU64::new(0);
@@ -57,16 +69,19 @@ pub unsafe fn ap_take_stack() -> u64 {
 static AP_READY_COUNT: AtomicU64 = AtomicU64::new(0);
 
 /// Must not be executed parallely
-unsafe fn start_one(acpi_id: ProcessorId) {
-    log::debug!("Waking up core {}", acpi_id);
+unsafe fn start_one(apic_id: ProcessorId) {
+    log::debug!("Waking up core {}", apic_id);
 
     assert!(AP_FREE_STACK.load(Ordering::SeqCst) == 0);
 
     // Set up stack
     let stack = memory::configure(|mem_ctrl| {
-        mem_ctrl
-            .alloc_stack(5)
-            .expect("could not allocate stack for smp core")
+        memory::kernel_stacks::alloc(
+            mem_ctrl,
+            format!("core {}", apic_id),
+            memory::constants::AP_STACK_SIZE_PAGES as usize,
+        )
+        .expect("could not allocate stack for smp core")
     });
 
     log::debug!("STACKSETUP {:x}", stack.top.as_u64());
@@ -74,9 +89,9 @@ unsafe fn start_one(acpi_id: ProcessorId) {
     AP_FREE_STACK.store(stack.top.as_u64(), Ordering::SeqCst);
 
     // Senc init signal
-    ioapic::apic_wakeup_processor(acpi_id.0);
+    ioapic::apic_wakeup_processor(apic_id);
 
-    log::trace!("Waiting for core {} to be up", acpi_id);
+    log::trace!("Waiting for core {} to be up", apic_id);
 
     // Sleep until the core is online, one second timeout
     let mut is_online = false;
@@ -88,10 +103,10 @@ unsafe fn start_one(acpi_id: ProcessorId) {
         }
     }
     if !is_online {
-        panic!("Failed to bringh core {} online (timeout)", acpi_id);
+        panic!("Failed to bringh core {} online (timeout)", apic_id);
     }
 
-    log::trace!("Core {} online", acpi_id);
+    log::trace!("Core {} online", apic_id);
 }
 
 /// Called by the AP once it has finished initialization
@@ -99,15 +114,80 @@ pub fn ap_mark_ready() {
     AP_READY_COUNT.fetch_add(1, Ordering::SeqCst);
 }
 
+/// Set by `stop_aps`, polled by the AP idle loop
+static AP_STOP: AtomicBool = AtomicBool::new(false);
+
+pub fn ap_should_stop() -> bool {
+    AP_STOP.load(Ordering::SeqCst)
+}
+
+/// Called by the AP when it stops, just before halting
+pub fn ap_mark_stopped() {
+    AP_READY_COUNT.fetch_sub(1, Ordering::SeqCst);
+}
+
+/// Halts all AP cores, for shutdown. Waits at most one second.
+/// Busy waits, as this is called from the scheduler tick with interrupts disabled.
+pub fn stop_aps() {
+    use crate::driver::tsc;
+
+    AP_STOP.store(true, Ordering::SeqCst);
+    let deadline = tsc::read() + tsc::ns_to_ticks(1_000_000_000);
+    while tsc::read() < deadline {
+        if AP_READY_COUNT.load(Ordering::SeqCst) == 0 {
+            log::info!("All AP cores stopped");
+            return;
+        }
+        core::hint::spin_loop();
+    }
+    log::warn!("{} AP cores did not stop", AP_READY_COUNT.load(Ordering::SeqCst));
+}
+
+/// Interrupt vector of the self-test ping IPI, allocated by `start_all`
+static IPI_PING_VECTOR: AtomicU8 = AtomicU8::new(0);
+
+/// Number of ping IPIs received by any core
+static IPI_PINGS: AtomicU64 = AtomicU64::new(0);
+
+pub fn ipi_ping_vector() -> u8 {
+    IPI_PING_VECTOR.load(Ordering::SeqCst)
+}
+
+/// Handler of the ping IPI
+fn ipi_ping_received(_: u8) {
+    IPI_PINGS.fetch_add(1, Ordering::SeqCst);
+}
+
+pub fn ipi_ping_count() -> u64 {
+    IPI_PINGS.load(Ordering::SeqCst)
+}
+
 pub fn start_all() {
     let acpi_data = acpi::ACPI_DATA.r#try().expect("acpi::init not called");
 
-    // TODO: actually check which CPU is BSP
-    // TODO: check for disabled CPUs
+    match crate::interrupt::vectors::allocate("smp", ipi_ping_received) {
+        Ok(vector) => IPI_PING_VECTOR.store(vector, Ordering::SeqCst),
+        Err(error) => log::warn!("No vector for the ping IPI: {:?}", error),
+    }
+
+    // Disabled processors are not listed
+    let bsp = current_processor_id();
+    if acpi_data.cpus.len() > MAX_CORES {
+        log::warn!(
+            "Only {} of {} cores are used",
+            MAX_CORES,
+            acpi_data.cpus.len()
+        );
+    }
     let mut count = 0;
-    for cpu in acpi_data.cpus.iter().skip(1) {
+    for apic_id in acpi_data
+        .cpus
+        .iter()
+        .filter(|id| **id != bsp)
+        .take(MAX_CORES - 1)
+    {
         unsafe {
-            start_one(ProcessorId(cpu.acpi_id));
+            start_one(*apic_id);
         }
         count += 1;
     }
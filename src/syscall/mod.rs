@@ -1,23 +1,43 @@
 use alloc::prelude::v1::*;
 use core::convert::{TryFrom, TryInto};
 use core::mem;
-use core::ptr;
 use core::time::Duration;
 use hashbrown::HashSet;
 use x86_64::structures::paging::PageTableFlags as Flags;
 use x86_64::{PhysAddr, VirtAddr};
 
+use d7abi::ExecFlags;
 use d7abi::SyscallErrorCode as ErrorCode;
 
+use crate::driver::tsc;
+use crate::interrupt::registers::RegisterState;
 use crate::ipc;
+use crate::latency;
 use crate::memory::prelude::*;
 use crate::memory::{self, MemoryController};
 use crate::multitasking::{process, Process, ProcessId, Scheduler, WaitFor, SCHEDULER};
 use crate::time::BSPInstant;
 
+/// Longest accepted timeout, as TSC deadlines are limited to a year
+const MAX_TIMEOUT_NS: u64 = 364 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Converts a timeout from the clock of the process to real nanoseconds.
+/// A slowed-down clock lengthens the timeout, so the limit applies to the result.
+fn real_timeout_ns(pid: ProcessId, ns: u64) -> Option<u64> {
+    let real_ns = crate::time::to_real_ns(pid, ns);
+    if real_ns > MAX_TIMEOUT_NS {
+        None
+    } else {
+        Some(real_ns)
+    }
+}
+
+/// Longest range copied by a single `process_vm_read` or `process_vm_write`
+const MAX_PROCESS_VM_BYTES: u64 = 0x10_0000;
+
 /// Separate module to get distinct logging path
 #[allow(non_snake_case)]
-mod PROCESS_OUTPUT {
+pub(crate) mod PROCESS_OUTPUT {
     use d7abi::process::ProcessId;
 
     pub fn print(pid: ProcessId, string: &str) {
@@ -51,7 +71,9 @@ pub enum SyscallResult {
 macro_rules! try_str {
     ($slice:expr) => {{
         // Sanity check
-        assert!($slice.len() < 1000, "String length sanity check"); // TODO: client error
+        if $slice.len() >= 1000 {
+            return SyscallResult::Continue(Err(ErrorCode::name_too_long.into()));
+        }
         match ::core::str::from_utf8($slice) {
             Ok(value) => value,
             Err(err) => {
@@ -72,6 +94,40 @@ macro_rules! try_ipc {
     };
 }
 
+macro_rules! require_privilege {
+    ($process:expr, $level:expr) => {
+        if $process.privilege < $level {
+            log::warn!(
+                "[pid={:8}] Privilege {:?} required, process has {:?}",
+                $process.id(),
+                $level,
+                $process.privilege
+            );
+            return SyscallResult::Continue(Err(ErrorCode::permission_denied.into()));
+        }
+    };
+}
+
+/// Kernel service topics that only processes with `Privilege::Driver` can deliver to
+const DRIVER_ONLY: &[&str] = &["crashdump/", "debug/", "log/"];
+
+/// Topic prefixes that processes with `ExecFlags::RESTRICTED_VIEW` cannot deliver to or
+/// subscribe to, as they describe other processes or the whole system
+const RESTRICTED_VIEW_HIDDEN: &[&str] = &[
+    "boot/",
+    "console/screen",
+    "crashdump/",
+    "debug/",
+    "interrupts/",
+    "latency/",
+    "log/",
+    "memory/",
+    "nic/rtl8139/capture/",
+    "process/terminated",
+    "scheduler/",
+    "system/shutdown",
+];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RawSyscall {
     pub routine: u64,
@@ -111,46 +167,446 @@ fn syscall(
                 m.free_virtual_area(area);
                 SyscallResult::Continue(Ok(0))
             },
+            SC::debug_output_ring => {
+                let (_, _, _, _) = rsc.args;
+                if process.leader.is_some() {
+                    // The ring of the process is shared by its threads
+                    return SyscallResult::Continue(Err(ErrorCode::not_supported.into()));
+                }
+                if process.output_ring.is_none() {
+                    assert_eq!(d7abi::output_ring::OUTPUT_RING_SIZE as u64, PAGE_SIZE_BYTES);
+                    let frame = m.alloc_frames_zeroed(1)[0];
+                    unsafe {
+                        process.modify_tables(m, |pt, curr_addr| {
+                            pt.map_to(
+                                curr_addr,
+                                Page::from_start_address(memory::PROCESS_OUTPUT_RING).unwrap(),
+                                frame,
+                                Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE,
+                            )
+                            .ignore();
+                        });
+                    }
+                    process.output_ring = Some(frame);
+                    process.set_memory_region(process::MemoryRegion {
+                        start: memory::PROCESS_OUTPUT_RING,
+                        size_bytes: PAGE_SIZE_BYTES,
+                        kind: process::MemoryRegionKind::OutputRing,
+                        writable: true,
+                        executable: false,
+                    });
+                }
+                SyscallResult::Continue(Ok(memory::PROCESS_OUTPUT_RING.as_u64()))
+            },
+            SC::input_ring => {
+                let (_, _, _, _) = rsc.args;
+                // Raw keyboard input is only available for drivers, like `irq/` topics
+                require_privilege!(process, process::Privilege::Driver);
+                if process.leader.is_some() {
+                    // The ring of the process is shared by its threads
+                    return SyscallResult::Continue(Err(ErrorCode::not_supported.into()));
+                }
+                if process.input_ring.is_none() {
+                    assert_eq!(d7abi::input_ring::INPUT_RING_SIZE as u64, PAGE_SIZE_BYTES);
+                    let frame = m.alloc_frames_zeroed(1)[0];
+                    unsafe {
+                        process.modify_tables(m, |pt, curr_addr| {
+                            pt.map_to(
+                                curr_addr,
+                                Page::from_start_address(memory::PROCESS_INPUT_RING).unwrap(),
+                                frame,
+                                Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE,
+                            )
+                            .ignore();
+                        });
+                    }
+                    process.input_ring = Some(frame);
+                    process.set_memory_region(process::MemoryRegion {
+                        start: memory::PROCESS_INPUT_RING,
+                        size_bytes: PAGE_SIZE_BYTES,
+                        kind: process::MemoryRegionKind::InputRing,
+                        writable: true,
+                        executable: false,
+                    });
+                }
+                SyscallResult::Continue(Ok(memory::PROCESS_INPUT_RING.as_u64()))
+            },
             SC::mem_set_size => {
                 let (size_bytes, _, _, _) = rsc.args;
+                if process.leader.is_some() {
+                    // Dynamic memory belongs to the process, not to its threads
+                    return SyscallResult::Continue(Err(ErrorCode::not_supported.into()));
+                }
+                if size_bytes > memory::constants::PROCESS_DYNAMIC_MEMORY_QUOTA {
+                    log::warn!("[pid={:8}] mem_set_size: quota exceeded", pid);
+                    return SyscallResult::Continue(Err(ErrorCode::quota_exceeded.into()));
+                }
                 match m.process_set_dynamic_memory(process, size_bytes) {
                     Some(total_bytes) => SyscallResult::Continue(Ok(total_bytes)),
-                    _ => unimplemented!("OutOfMemory case not implmented yet"),
+                    None => {
+                        log::warn!("[pid={:8}] mem_set_size: out of memory", pid);
+                        SyscallResult::Continue(Err(ErrorCode::out_of_memory.into()))
+                    },
                 }
             },
             SC::exec => {
-                let (image_len, image_ptr, _, _) = rsc.args;
+                let (image_len, image_ptr, privilege, flags) = rsc.args;
                 let image_ptr = VirtAddr::new(image_ptr);
+                let flags = match ExecFlags::from_bits(flags) {
+                    Some(flags) => flags,
+                    None => {
+                        return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                    },
+                };
+                // Restrictions are inherited
+                let restricted_view =
+                    process.restricted_view || flags.contains(ExecFlags::RESTRICTED_VIEW);
+
+                // Processes cannot spawn processes more privileged than themselves
+                let privilege = match process::Privilege::try_from(privilege) {
+                    Ok(p) => p,
+                    Err(_) => {
+                        return SyscallResult::Continue(Err(ErrorCode::permission_denied.into()));
+                    },
+                };
+                require_privilege!(process, privilege);
+
                 if let Some((area, slice)) =
                     unsafe { m.process_slice(process, image_len, image_ptr) }
                 {
-                    log::debug!("[pid={:8}] exec len={:?}", pid, slice.len());
+                    log::debug!(
+                        "[pid={:8}] exec len={:?} privilege={:?}",
+                        pid,
+                        slice.len(),
+                        privilege
+                    );
 
-                    let elfimage = crate::multitasking::process::load_elf(m, slice);
-                    let pid = sched.spawn(m, elfimage);
+                    let result = crate::multitasking::process::load_elf(m, slice);
 
                     unsafe { m.unmap_area(area) };
                     m.free_virtual_area(area);
 
-                    SyscallResult::Continue(Ok(unsafe { pid.as_u64() }))
+                    match result {
+                        Ok(elfimage) => {
+                            let pid = sched.spawn(m, elfimage, privilege, Some(pid));
+                            let child = sched.process_by_id_mut(pid).unwrap();
+                            child.restricted_view = restricted_view;
+                            SyscallResult::Continue(Ok(unsafe { pid.as_u64() }))
+                        },
+                        Err(error) => {
+                            log::warn!("[pid={:8}] exec: invalid image: {:?}", pid, error);
+                            SyscallResult::Continue(Err(ErrorCode::invalid_executable.into()))
+                        },
+                    }
                 } else {
                     SyscallResult::Terminate(process::ProcessResult::Failed(
                         process::Error::Pointer(image_ptr),
                     ))
                 }
             },
+            SC::thread_create => {
+                let (entry, stack_top, arg, _) = rsc.args;
+                if entry == 0 || stack_top % 16 != 0 {
+                    return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                }
+                let entry = VirtAddr::new(entry);
+                let stack_top = VirtAddr::new(stack_top);
+                match sched.spawn_thread(m, pid, entry, stack_top, arg) {
+                    Some(tid) => {
+                        log::debug!("[pid={:8}] thread_create tid={}", pid, tid);
+                        SyscallResult::Continue(Ok(unsafe { tid.as_u64() }))
+                    },
+                    None => SyscallResult::Continue(Err(ErrorCode::invalid_argument.into())),
+                }
+            },
+            SC::thread_set_fs_base => {
+                let (fs_base, _, _, _) = rsc.args;
+                // Loading a non-canonical address would fault in the kernel
+                let upper = fs_base >> 47;
+                if upper != 0 && upper != 0x1_ffff {
+                    return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                }
+                process.fs_base = fs_base;
+                process.load_fs_base();
+                SyscallResult::Continue(Ok(0))
+            },
+            SC::process_memory_map => {
+                let (target, buf_len, buf_ptr, _) = rsc.args;
+                let buf_ptr = VirtAddr::new(buf_ptr);
+                let target = if target == 0 {
+                    pid
+                } else {
+                    ProcessId::from_u64(target)
+                };
+
+                // Processes can always inspect themselves
+                if target != pid {
+                    require_privilege!(process, process::Privilege::Driver);
+                    if process.restricted_view {
+                        return SyscallResult::Continue(Err(ErrorCode::permission_denied.into()));
+                    }
+                }
+
+                let data = match sched.process_by_id(target) {
+                    Some(t) => pinecone::to_vec(&t.memory_map).unwrap(),
+                    None => {
+                        return SyscallResult::Continue(Err(ErrorCode::process_not_found.into()));
+                    },
+                };
+                if data.len() as u64 > buf_len {
+                    return SyscallResult::Continue(Err(ErrorCode::buffer_too_small.into()));
+                }
+
+                let process = sched.process_by_id_mut(pid).unwrap();
+                if let Some((area, slice)) =
+                    unsafe { m.process_slice_mut(process, data.len() as u64, buf_ptr) }
+                {
+                    slice.copy_from_slice(&data);
+                    unsafe { m.unmap_area(area) };
+                    m.free_virtual_area(area);
+                    SyscallResult::Continue(Ok(data.len() as u64))
+                } else {
+                    SyscallResult::Terminate(process::ProcessResult::Failed(
+                        process::Error::Pointer(buf_ptr),
+                    ))
+                }
+            },
+            SC::process_vm_read | SC::process_vm_write => {
+                let write = matches!(sc, SC::process_vm_write);
+                if write {
+                    require_privilege!(process, process::Privilege::Full);
+                } else {
+                    require_privilege!(process, process::Privilege::Driver);
+                }
+                if process.restricted_view {
+                    return SyscallResult::Continue(Err(ErrorCode::permission_denied.into()));
+                }
+                let privilege = process.privilege;
+                let (target, remote_addr, len, local_ptr) = rsc.args;
+                let local_ptr = VirtAddr::new(local_ptr);
+                if target == 0 || len > MAX_PROCESS_VM_BYTES {
+                    return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                }
+                if len == 0 {
+                    return SyscallResult::Continue(Ok(0));
+                }
+
+                let own_address_space = process.address_space_owner();
+                let owner = match sched.process_by_id(ProcessId::from_u64(target)) {
+                    Some(t) => t.address_space_owner(),
+                    None => {
+                        return SyscallResult::Continue(Err(ErrorCode::process_not_found.into()));
+                    },
+                };
+                // The same frames would be mapped twice, and the process can copy them itself
+                if owner == own_address_space {
+                    return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                }
+                let target = sched.process_by_id(owner).expect("Thread leader not found");
+                // Memory of more privileged processes is off limits
+                if target.privilege > privilege {
+                    return SyscallResult::Continue(Err(ErrorCode::permission_denied.into()));
+                }
+
+                // The whole range must be inside a single region. Physical mappings
+                // are excluded, as device memory can have side effects on access.
+                let remote_end = match remote_addr.checked_add(len) {
+                    Some(end) => end,
+                    None => return SyscallResult::Continue(Err(ErrorCode::bad_address.into())),
+                };
+                let valid = target.memory_map.iter().any(|r| {
+                    r.start.as_u64() <= remote_addr
+                        && remote_end <= r.start.as_u64() + r.size_bytes
+                        && r.kind != process::MemoryRegionKind::Physical
+                        && (r.writable || !write)
+                });
+                if !valid {
+                    return SyscallResult::Continue(Err(ErrorCode::bad_address.into()));
+                }
+                let remote_addr = VirtAddr::new(remote_addr);
+
+                let (remote_area, remote_slice) =
+                    match unsafe { m.process_slice_mut(target, len, remote_addr) } {
+                        Some(v) => v,
+                        None => {
+                            return SyscallResult::Continue(Err(ErrorCode::bad_address.into()));
+                        },
+                    };
+                let process = sched.process_by_id(pid).unwrap();
+                let result = if let Some((local_area, local_slice)) =
+                    unsafe { m.process_slice_mut(process, len, local_ptr) }
+                {
+                    if write {
+                        remote_slice.copy_from_slice(local_slice);
+                    } else {
+                        local_slice.copy_from_slice(remote_slice);
+                    }
+                    unsafe { m.unmap_area(local_area) };
+                    m.free_virtual_area(local_area);
+                    SyscallResult::Continue(Ok(len))
+                } else {
+                    SyscallResult::Terminate(process::ProcessResult::Failed(
+                        process::Error::Pointer(local_ptr),
+                    ))
+                };
+                unsafe { m.unmap_area(remote_area) };
+                m.free_virtual_area(remote_area);
+                result
+            },
+            SC::process_signal => {
+                let (target, signal, _, _) = rsc.args;
+                let signal = match process::Signal::try_from(signal) {
+                    Ok(s) => s,
+                    Err(_) => {
+                        return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                    },
+                };
+                if target == 0 {
+                    return SyscallResult::Continue(Err(ErrorCode::process_not_found.into()));
+                }
+                let target = ProcessId::from_u64(target);
+
+                // A process would be terminated while running its own system call
+                if signal == process::Signal::Kill
+                    && (target == pid || target == process.address_space_owner())
+                {
+                    return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                }
+
+                // Processes can signal themselves and their children,
+                // and the service daemon can signal anything
+                let is_child = match sched.process_by_id(target) {
+                    Some(t) => t.parent == Some(pid),
+                    None => {
+                        return SyscallResult::Continue(Err(ErrorCode::process_not_found.into()));
+                    },
+                };
+                let process = sched.process_by_id(pid).unwrap();
+                if target != pid && !is_child {
+                    require_privilege!(process, process::Privilege::Full);
+                }
+
+                log::debug!("[pid={:8}] signal {:?} to pid {}", pid, signal, target);
+                sched.send_signal(pid, target, signal);
+                SyscallResult::Continue(Ok(0))
+            },
+            SC::process_signal_mask => {
+                let (mask, _, _, _) = rsc.args;
+                if mask & process::Signal::Kill.bit() != 0 {
+                    return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                }
+                let previous = mem::replace(&mut process.signal_mask, mask);
+                sched.deliver_signals(pid);
+                SyscallResult::Continue(Ok(previous))
+            },
             SC::sched_yield => {
                 let (_, _, _, _) = rsc.args;
                 SyscallResult::Switch(Ok(0), WaitFor::None)
             },
+            SC::sched_yield_to => {
+                let (target, _, _, _) = rsc.args;
+                if target == 0 || sched.process_by_id(ProcessId::from_u64(target)).is_none() {
+                    return SyscallResult::Continue(Err(ErrorCode::process_not_found.into()));
+                }
+
+                // Switching doesn't reset the time slice,
+                // so the target gets the rest of the current one
+                if sched.make_next(ProcessId::from_u64(target)) {
+                    SyscallResult::Switch(Ok(1), WaitFor::None)
+                } else {
+                    SyscallResult::Continue(Ok(0))
+                }
+            },
+            SC::futex_wait => {
+                let (addr, expected, _, _) = rsc.args;
+                let addr = VirtAddr::new(addr);
+                if addr.as_u64() % 4 != 0 {
+                    return SyscallResult::Continue(Err(ErrorCode::ptr_unaligned.into()));
+                }
+                let value = if let Some((area, slice)) =
+                    unsafe { m.process_slice(process, 4, addr) }
+                {
+                    let value = u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]);
+                    unsafe { m.unmap_area(area) };
+                    m.free_virtual_area(area);
+                    value
+                } else {
+                    return SyscallResult::Terminate(process::ProcessResult::Failed(
+                        process::Error::Pointer(addr),
+                    ));
+                };
+
+                // The scheduler is locked, so a wake cannot happen
+                // between the check and the wait
+                if value != expected as u32 {
+                    return SyscallResult::Continue(Err(ErrorCode::would_block.into()));
+                }
+                let owner = process.address_space_owner();
+                let event_id = sched.futex_event(owner, addr);
+                SyscallResult::Switch(Ok(0), WaitFor::Event(event_id))
+            },
+            SC::futex_wake => {
+                let (addr, _, _, _) = rsc.args;
+                let owner = process.address_space_owner();
+                let woken = sched.futex_wake(owner, VirtAddr::new(addr));
+                SyscallResult::Continue(Ok(woken as u64))
+            },
             SC::sched_sleep_ns => {
                 let (time_ns, _, _, _) = rsc.args;
                 if crate::smp::is_bsp() {
+                    let time_ns = match real_timeout_ns(pid, time_ns) {
+                        Some(ns) => ns,
+                        None => {
+                            return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                        },
+                    };
                     SyscallResult::Switch(Ok(0), WaitFor::Time(BSPInstant::now().add_ns(time_ns)))
                 } else {
                     todo!(); // If core != BSP, push into a set-to-sleep queue
                 }
             },
+            SC::sched_timer => {
+                let (time_ns, token, _, _) = rsc.args;
+                if crate::smp::is_bsp() {
+                    let time_ns = match real_timeout_ns(pid, time_ns) {
+                        Some(ns) => ns,
+                        None => {
+                            return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                        },
+                    };
+                    sched.set_timer(pid, BSPInstant::now().add_ns(time_ns), token);
+                    SyscallResult::Continue(Ok(0))
+                } else {
+                    // Timers use the TSC of the BSP
+                    SyscallResult::Continue(Err(ErrorCode::not_supported.into()))
+                }
+            },
+            SC::sched_time_namespace => {
+                let (target, offset_ns, scale, _) = rsc.args;
+                // Changes the clock of other processes
+                require_privilege!(process, process::Privilege::Full);
+                if !crate::time::is_valid_clock(offset_ns, scale) {
+                    return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                }
+                let target = if target == 0 {
+                    pid
+                } else {
+                    ProcessId::from_u64(target)
+                };
+                if sched.process_by_id(target).is_none() {
+                    return SyscallResult::Continue(Err(ErrorCode::process_not_found.into()));
+                }
+                if crate::smp::is_bsp() {
+                    if sched.set_time_namespace(m, target, offset_ns, scale) {
+                        SyscallResult::Continue(Ok(0))
+                    } else {
+                        SyscallResult::Continue(Err(ErrorCode::quota_exceeded.into()))
+                    }
+                } else {
+                    // The namespace clock starts from the TSC of the BSP
+                    SyscallResult::Continue(Err(ErrorCode::not_supported.into()))
+                }
+            },
             SC::ipc_subscribe => {
                 let (filter_len, filter_ptr, exact, reliable) = rsc.args;
                 let exact = exact != 0;
@@ -162,6 +618,29 @@ fn syscall(
                     let filter_str = try_str!(slice);
                     let filter = try_ipc!(ipc::TopicFilter::try_new(filter_str, exact));
 
+                    // Hardware interrupts are only available for drivers
+                    if filter.covers_prefix("irq/")
+                        && process.privilege < process::Privilege::Driver
+                    {
+                        log::warn!("[pid={:8}] Not allowed to subscribe {:?}", pid, filter_str);
+                        unsafe { m.unmap_area(area) };
+                        m.free_virtual_area(area);
+                        return SyscallResult::Continue(Err(ErrorCode::permission_denied.into()));
+                    }
+
+                    // Kernel events are only available for the process itself,
+                    // and system-wide events are hidden from restricted processes
+                    if (filter.covers_prefix("process/events/")
+                        && !filter.is_exact(&process::events_topic(pid)))
+                        || (process.restricted_view
+                            && RESTRICTED_VIEW_HIDDEN.iter().any(|p| filter.covers_prefix(p)))
+                    {
+                        log::warn!("[pid={:8}] Not allowed to subscribe {:?}", pid, filter_str);
+                        unsafe { m.unmap_area(area) };
+                        m.free_virtual_area(area);
+                        return SyscallResult::Continue(Err(ErrorCode::permission_denied.into()));
+                    }
+
                     log::trace!("[pid={:8}] ipc_subscribe {:?}", pid, filter);
 
                     let mut ipc_manager = ipc::IPC.try_lock().expect("IPC LOCKED");
@@ -182,12 +661,14 @@ fn syscall(
 
                 log::trace!("[pid={:8}] ipc_unsubscribe {:?}", pid, sub_id);
 
-                let mut ipc_manager = ipc::IPC.try_lock().expect("IPC LOCKED");
-                try_ipc!(ipc_manager.unsubscribe(pid, sub_id).consume_events(sched));
+                try_ipc!(ipc::with_manager(sched, |ipc_manager| {
+                    ipc_manager.unsubscribe(pid, sub_id)
+                }));
 
                 SyscallResult::Continue(Ok(0))
             },
-            SC::ipc_publish => {
+            SC::ipc_publish | SC::ipc_publish_retained => {
+                let retain = matches!(sc, SC::ipc_publish_retained);
                 let (topic_len, topic_ptr, data_len, data_ptr) = rsc.args;
                 let topic_ptr = VirtAddr::new(topic_ptr);
                 let data_ptr = VirtAddr::new(data_ptr);
@@ -201,14 +682,31 @@ fn syscall(
                         let topic = try_ipc!(ipc::Topic::try_new(topic_str));
 
                         log::trace!(
-                            "[pid={:8}] ipc_publish topic={:?} len={:?}",
+                            "[pid={:8}] ipc_publish topic={:?} len={:?} retain={:?}",
                             pid,
                             topic,
-                            data_len
+                            data_len,
+                            retain
                         );
 
-                        let mut ipc_manager = ipc::IPC.try_lock().expect("IPC LOCKED");
-                        try_ipc!(ipc_manager.publish(topic, data_slice).consume_events(sched));
+                        if !retain && ipc::is_kernel_topic(&topic) {
+                            log::warn!("[pid={:8}] Not allowed to publish {:?}", pid, topic_str);
+                            unsafe { m.unmap_area(data_area) };
+                            m.free_virtual_area(data_area);
+                            unsafe { m.unmap_area(topic_area) };
+                            m.free_virtual_area(topic_area);
+                            return SyscallResult::Continue(Err(
+                                ErrorCode::ipc_permission_error.into()
+                            ));
+                        }
+
+                        try_ipc!(ipc::with_manager(sched, |ipc_manager| {
+                            if retain {
+                                ipc_manager.publish_retained(Some(pid), topic, data_slice)
+                            } else {
+                                ipc_manager.publish(topic, data_slice)
+                            }
+                        }));
 
                         unsafe { m.unmap_area(data_area) };
                         m.free_virtual_area(data_area);
@@ -232,16 +730,34 @@ fn syscall(
                 let topic_ptr = VirtAddr::new(topic_ptr);
                 let data_ptr = VirtAddr::new(data_ptr);
 
-                let mut ipc_manager = ipc::IPC.try_lock().expect("IPC LOCKED");
-
                 // Delivery complete
-                if ipc_manager.delivery_complete(pid) {
+                let complete = ipc::IPC
+                    .try_lock()
+                    .expect("IPC LOCKED")
+                    .delivery_complete(pid);
+                if complete {
                     log::trace!("[pid={:8}] ipc_deliver complete", pid);
+                    sched.end_inherited_boosts_of(pid);
 
-                    try_ipc!(ipc_manager.after_delivery(pid).consume_events(sched));
+                    try_ipc!(ipc::with_manager(sched, |ipc_manager| {
+                        ipc_manager.after_delivery(pid)
+                    }));
                     return SyscallResult::Continue(Ok(0));
                 }
 
+                // Delivery timed out, the receiver gets the message anyway
+                if let Some(deadline) = process.syscall_deadline {
+                    if BSPInstant::now() >= deadline {
+                        log::trace!("[pid={:8}] ipc_deliver timed out", pid);
+                        ipc::IPC
+                            .try_lock()
+                            .expect("IPC LOCKED")
+                            .cancel_delivery(pid);
+                        sched.end_inherited_boosts_of(pid);
+                        return SyscallResult::Continue(Err(ErrorCode::timed_out.into()));
+                    }
+                }
+
                 if let Some((topic_area, topic_slice)) =
                     unsafe { m.process_slice(process, topic_len, topic_ptr) }
                 {
@@ -250,6 +766,24 @@ fn syscall(
                     {
                         let topic_str = try_str!(topic_slice);
                         let topic = try_ipc!(ipc::Topic::try_new(topic_str));
+
+                        // Kernel debugging services are only available for drivers,
+                        // and system-wide information is hidden from restricted processes
+                        if (process.privilege < process::Privilege::Driver
+                            && DRIVER_ONLY.iter().any(|p| topic_str.starts_with(p)))
+                            || (process.restricted_view
+                                && RESTRICTED_VIEW_HIDDEN.iter().any(|p| topic_str.starts_with(p)))
+                        {
+                            log::warn!("[pid={:8}] Not allowed to deliver {:?}", pid, topic_str);
+                            unsafe { m.unmap_area(data_area) };
+                            m.free_virtual_area(data_area);
+                            unsafe { m.unmap_area(topic_area) };
+                            m.free_virtual_area(topic_area);
+                            return SyscallResult::Continue(Err(
+                                ErrorCode::permission_denied.into()
+                            ));
+                        }
+
                         log::trace!(
                             "[pid={:8}] ipc_deliver topic={:?} len={:?}",
                             pid,
@@ -257,18 +791,32 @@ fn syscall(
                             data_len
                         );
 
-                        let deliver = try_ipc!(
-                            ipc_manager
-                                .deliver(pid, topic, data_slice)
-                                .consume_events(sched)
-                        );
+                        let privilege = process.privilege;
+                        let deliver = try_ipc!(ipc::with_manager(sched, |ipc_manager| {
+                            ipc_manager.deliver(pid, privilege, topic, data_slice)
+                        }));
 
                         unsafe { m.unmap_area(data_area) };
                         m.free_virtual_area(data_area);
 
                         match deliver {
-                            ipc::Deliver::Process(event) => {
-                                SyscallResult::RepeatAfter(WaitFor::Event(event))
+                            ipc::Deliver::Process(event, receiver) => {
+                                sched.inherit_boost(pid, receiver);
+                                let process = sched.process_by_id_mut(pid).unwrap();
+                                if process.deliver_timeout_ns != 0 {
+                                    // The namespace may have changed since the timeout was set
+                                    let timeout_ns =
+                                        real_timeout_ns(pid, process.deliver_timeout_ns)
+                                            .unwrap_or(MAX_TIMEOUT_NS);
+                                    let deadline = BSPInstant::now().add_ns(timeout_ns);
+                                    process.syscall_deadline = Some(deadline);
+                                    SyscallResult::RepeatAfter(WaitFor::FirstOf(vec![
+                                        WaitFor::Event(event),
+                                        WaitFor::Time(deadline),
+                                    ]))
+                                } else {
+                                    SyscallResult::RepeatAfter(WaitFor::Event(event))
+                                }
                             },
                             ipc::Deliver::Kernel => SyscallResult::Continue(Ok(0)),
                         }
@@ -291,8 +839,6 @@ fn syscall(
                 let topic_ptr = VirtAddr::new(topic_ptr);
                 let data_ptr = VirtAddr::new(data_ptr);
 
-                let mut ipc_manager = ipc::IPC.try_lock().expect("IPC LOCKED");
-
                 if let Some((topic_area, topic_slice)) =
                     unsafe { m.process_slice(process, topic_len, topic_ptr) }
                 {
@@ -308,9 +854,9 @@ fn syscall(
                             data_len
                         );
 
-                        let result = ipc_manager
-                            .deliver_reply(pid, topic, data_slice)
-                            .consume_events(sched);
+                        let result = ipc::with_manager(sched, |ipc_manager| {
+                            ipc_manager.deliver_reply(pid, topic, data_slice)
+                        });
 
                         unsafe { m.unmap_area(data_area) };
                         m.free_virtual_area(data_area);
@@ -345,9 +891,9 @@ fn syscall(
                         buf_len
                     );
 
-                    let mut ipc_manager = ipc::IPC.try_lock().expect("IPC LOCKED");
-                    let message_or_event =
-                        try_ipc!(ipc_manager.receive(pid, sub_id).consume_events(sched));
+                    let message_or_event = try_ipc!(ipc::with_manager(sched, |ipc_manager| {
+                        ipc_manager.receive(pid, sub_id)
+                    }));
 
                     let msg = match message_or_event {
                         Ok(msg) => msg,
@@ -359,11 +905,19 @@ fn syscall(
                     let ser_msg = pinecone::to_vec(&msg).unwrap();
 
                     if ser_msg.len() > slice.len() {
-                        panic!(
-                            "Buffer too small msg_len={} buf_len={}",
+                        log::warn!(
+                            "[pid={:8}] ipc_receive buffer too small msg_len={} buf_len={}",
+                            pid,
                             ser_msg.len(),
                             slice.len()
-                        ); // TODO: client error
+                        );
+                        ipc::IPC
+                            .try_lock()
+                            .expect("IPC LOCKED")
+                            .unreceive(sub_id, msg);
+                        unsafe { m.unmap_area(area) };
+                        m.free_virtual_area(area);
+                        return SyscallResult::Continue(Err(ErrorCode::buffer_too_small.into()));
                     }
 
                     slice[..ser_msg.len()].copy_from_slice(&ser_msg);
@@ -391,16 +945,14 @@ fn syscall(
                     positive
                 );
 
-                let mut ipc_manager = ipc::IPC.try_lock().expect("IPC LOCKED");
-                try_ipc!(
-                    ipc_manager
-                        .acknowledge(sub_id, ack_id, positive)
-                        .consume_events(sched)
-                );
+                let sender = try_ipc!(ipc::with_manager(sched, |ipc_manager| {
+                    ipc_manager.acknowledge(pid, sub_id, ack_id, positive)
+                }));
+                sched.end_inherited_boost(pid, sender);
                 SyscallResult::Continue(Ok(0))
             },
             SC::ipc_select => {
-                let (subs_len, subs, nonblocking, _) = rsc.args;
+                let (subs_len, subs, nonblocking, timeout_ns) = rsc.args;
 
                 if subs_len == 0 {
                     return SyscallResult::Continue(Err(ErrorCode::empty_list_argument.into()));
@@ -413,19 +965,34 @@ fn syscall(
 
                 log::trace!("ipc_select n={} blocking={}", subs_len, blocking);
 
+                let subs_bytes = match subs_len.checked_mul(size) {
+                    Some(v) => v,
+                    None => {
+                        return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                    },
+                };
+
                 if let Some((area, subs_slice)) =
-                    unsafe { m.process_slice(process, subs_len * size, subs) }
+                    unsafe { m.process_slice(process, subs_bytes, subs) }
                 {
                     let mut conditions = Vec::new();
                     for sub_bytes in subs_slice.chunks_exact(8) {
                         let sub_id = ipc::SubscriptionId::from_u64(u64::from_le_bytes(
                             sub_bytes.try_into().unwrap(),
                         ));
-                        let condition = ipc_manager.waiting_for(sub_id);
+                        let condition = match ipc_manager.waiting_for(pid, sub_id) {
+                            Ok(condition) => condition,
+                            Err(error) => {
+                                unsafe { m.unmap_area(area) };
+                                m.free_virtual_area(area);
+                                return SyscallResult::Continue(Err(error.into()));
+                            },
+                        };
                         log::trace!("* {:?} condition = {:?}", sub_id, condition);
 
                         if condition == WaitFor::None {
                             unsafe { m.unmap_area(area) };
+                            m.free_virtual_area(area);
                             return SyscallResult::Continue(Ok(sub_id.as_u64()));
                         }
 
@@ -439,6 +1006,30 @@ fn syscall(
                         return SyscallResult::Continue(Err(ErrorCode::would_block.into()));
                     }
 
+                    if timeout_ns != 0 {
+                        // The deadline is set on the first call, and kept when repeating
+                        let process = sched.process_by_id_mut(pid).unwrap();
+                        let deadline = match process.syscall_deadline {
+                            Some(deadline) => deadline,
+                            None => match real_timeout_ns(pid, timeout_ns) {
+                                Some(ns) => {
+                                    let deadline = BSPInstant::now().add_ns(ns);
+                                    process.syscall_deadline = Some(deadline);
+                                    deadline
+                                },
+                                None => {
+                                    return SyscallResult::Continue(Err(
+                                        ErrorCode::invalid_argument.into(),
+                                    ));
+                                },
+                            },
+                        };
+                        if BSPInstant::now() >= deadline {
+                            return SyscallResult::Continue(Err(ErrorCode::timed_out.into()));
+                        }
+                        conditions.push(WaitFor::Time(deadline));
+                    }
+
                     SyscallResult::RepeatAfter(WaitFor::FirstOf(conditions))
                 } else {
                     SyscallResult::Terminate(process::ProcessResult::Failed(
@@ -446,7 +1037,61 @@ fn syscall(
                     ))
                 }
             },
+            SC::ipc_transfer => {
+                let (sub_id, target, _, _) = rsc.args;
+                let sub_id = ipc::SubscriptionId::from_u64(sub_id);
+
+                log::trace!(
+                    "[pid={:8}] ipc_transfer sub={:?} target={}",
+                    pid,
+                    sub_id,
+                    target
+                );
+
+                if target == 0 || sched.process_by_id(ProcessId::from_u64(target)).is_none() {
+                    return SyscallResult::Continue(Err(ErrorCode::process_not_found.into()));
+                }
+                let target = ProcessId::from_u64(target);
+
+                try_ipc!(ipc::with_manager(sched, |ipc_manager| {
+                    ipc_manager.transfer(pid, sub_id, target)
+                }));
+                SyscallResult::Continue(Ok(0))
+            },
+            SC::ipc_deliver_timeout => {
+                let (timeout_ns, _, _, _) = rsc.args;
+                log::trace!("[pid={:8}] ipc_deliver_timeout ns={}", pid, timeout_ns);
+                if real_timeout_ns(pid, timeout_ns).is_none() {
+                    return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                }
+                process.deliver_timeout_ns = timeout_ns;
+                SyscallResult::Continue(Ok(0))
+            },
+            SC::ipc_restrict => {
+                let (sub_id, privilege, _, _) = rsc.args;
+                let sub_id = ipc::SubscriptionId::from_u64(sub_id);
+
+                log::trace!(
+                    "[pid={:8}] ipc_restrict sub={:?} privilege={}",
+                    pid,
+                    sub_id,
+                    privilege
+                );
+
+                let privilege = match process::Privilege::try_from(privilege) {
+                    Ok(p) => p,
+                    Err(_) => {
+                        return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                    },
+                };
+
+                try_ipc!(ipc::with_manager(sched, |ipc_manager| {
+                    ipc_manager.restrict(pid, sub_id, privilege)
+                }));
+                SyscallResult::Continue(Ok(0))
+            },
             SC::kernel_log_read => {
+                require_privilege!(process, process::Privilege::Driver);
                 let (buf_len, buf_ptr, _, _) = rsc.args;
                 let buf_ptr = VirtAddr::new(buf_ptr);
                 if let Some((area, slice)) =
@@ -462,9 +1107,35 @@ fn syscall(
                     ))
                 }
             },
+            SC::kernel_panic_action => {
+                require_privilege!(process, process::Privilege::Full);
+                let (action, value, _, _) = rsc.args;
+                match d7abi::PanicAction::from_args(action, value) {
+                    Some(action) => {
+                        log::info!("[pid={:8}] Panic action set to {:?}", pid, action);
+                        crate::panic_action::set(action);
+                        SyscallResult::Continue(Ok(0))
+                    },
+                    None => SyscallResult::Continue(Err(ErrorCode::invalid_argument.into())),
+                }
+            },
+            SC::kernel_shutdown => {
+                require_privilege!(process, process::Privilege::Full);
+                let (action, _, _, _) = rsc.args;
+                match d7abi::ShutdownAction::from_arg(action) {
+                    Some(action) => {
+                        // A repeated request doesn't restart the grace period
+                        let started = sched.begin_shutdown(action, pid);
+                        SyscallResult::Continue(Ok(started as u64))
+                    },
+                    None => SyscallResult::Continue(Err(ErrorCode::invalid_argument.into())),
+                }
+            },
             SC::irq_set_handler => {
+                require_privilege!(process, process::Privilege::Driver);
                 let (ird, image_len, image_ptr, _) = rsc.args;
-                todo!();
+                // TODO: Implement, see the old implementation below
+                SyscallResult::Continue(Err(ErrorCode::not_supported.into()))
                 // let image_ptr = VirtAddr::new(image_ptr);
                 // if let Some((area, slice)) =
                 //     unsafe { m.process_slice(process, image_len, image_ptr) }
@@ -488,6 +1159,7 @@ fn syscall(
                 use d7abi::MemoryProtectionFlags as PFlags;
                 use x86_64::structures::paging::page_table::PageTableFlags;
 
+                require_privilege!(process, process::Privilege::Driver);
                 let (len, phys_addr, virt_addr, flags) = rsc.args;
                 let phys_addr = PhysAddr::new(phys_addr);
                 let virt_addr = VirtAddr::new(virt_addr);
@@ -554,20 +1226,105 @@ fn syscall(
                         }
                     });
                 }
+                process.set_memory_region(process::MemoryRegion {
+                    start: virt_addr,
+                    size_bytes: page_align_u64(len, true),
+                    kind: process::MemoryRegionKind::Physical,
+                    writable,
+                    executable: false,
+                });
 
                 SyscallResult::Continue(Ok(0))
             },
             SC::dma_allocate => {
+                require_privilege!(process, process::Privilege::Driver);
                 let (len, _, _, _) = rsc.args;
-                assert!(len != 0); // TODO: client error
+                if len == 0 {
+                    return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                }
                 log::debug!("[pid={:8}] dma_allocate len={}", pid, len);
-                let region = m.dma_allocator.allocate(len as usize);
-                SyscallResult::Continue(Ok(region.start.as_u64()))
+                match m.dma_allocator.allocate(len as usize) {
+                    Some(region) => SyscallResult::Continue(Ok(region.start.as_u64())),
+                    None => SyscallResult::Continue(Err(ErrorCode::out_of_memory.into())),
+                }
             },
             SC::dma_free => {
-                log::warn!("Ignoring syscall dma_free");
-                // TODO
-                SyscallResult::Continue(Ok(0))
+                require_privilege!(process, process::Privilege::Driver);
+                let (len, phys_addr, _, _) = rsc.args;
+                let region = memory::dma_allocator::DMARegion::from_raw(phys_addr, len as usize);
+                if region.map_or(false, |r| m.dma_allocator.free(r)) {
+                    SyscallResult::Continue(Ok(0))
+                } else {
+                    SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()))
+                }
+            },
+            SC::shm_create | SC::shm_map => {
+                use crate::memory::shared;
+
+                let create = matches!(sc, SC::shm_create);
+                if create {
+                    // Objects are never freed, so the quota is shared by the drivers only
+                    require_privilege!(process, process::Privilege::Driver);
+                }
+                let (name_len, name_ptr, size_bytes, privilege) = rsc.args;
+                let name_ptr = VirtAddr::new(name_ptr);
+                if size_bytes == 0 || name_len == 0 {
+                    return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                }
+                // Only used by `shm_create`
+                let privilege = match process::Privilege::try_from(privilege) {
+                    Ok(p) => p,
+                    Err(_) if create => {
+                        return SyscallResult::Continue(Err(ErrorCode::invalid_argument.into()));
+                    },
+                    Err(_) => process::Privilege::User,
+                };
+                if size_bytes > memory::SHARED_MEMORY_QUOTA {
+                    return SyscallResult::Continue(Err(ErrorCode::quota_exceeded.into()));
+                }
+
+                let name = if let Some((area, slice)) =
+                    unsafe { m.process_slice(process, name_len, name_ptr) }
+                {
+                    let name = try_str!(slice).to_owned();
+                    unsafe { m.unmap_area(area) };
+                    m.free_virtual_area(area);
+                    name
+                } else {
+                    return SyscallResult::Terminate(process::ProcessResult::Failed(
+                        process::Error::Pointer(name_ptr),
+                    ));
+                };
+
+                let frames = if create {
+                    shared::create(m, process, &name, size_bytes, privilege)
+                } else {
+                    shared::frames(process, &name, size_bytes)
+                };
+                let frames = match frames {
+                    Ok(frames) => frames,
+                    Err(error) => {
+                        let code = match error {
+                            shared::Error::Exists => ErrorCode::already_exists,
+                            shared::Error::NotFound => ErrorCode::not_found,
+                            shared::Error::TooLarge => ErrorCode::invalid_argument,
+                            shared::Error::Quota => ErrorCode::quota_exceeded,
+                            shared::Error::Permission => ErrorCode::permission_denied,
+                        };
+                        return SyscallResult::Continue(Err(code.into()));
+                    },
+                };
+
+                // Threads share the mappings of their process
+                let owner = process.address_space_owner();
+                let owner = sched.process_by_id_mut(owner).expect("Thread leader not found");
+                match owner.map_shared_memory(m, &frames) {
+                    Some(start) => {
+                        log::debug!("[pid={:8}] shm {:?} mapped at {:?}", pid, name, start);
+                        SyscallResult::Continue(Ok(start.as_u64()))
+                    },
+                    None => SyscallResult::Continue(Err(ErrorCode::quota_exceeded.into())),
+                }
             },
         }
     } else {
@@ -602,60 +1359,58 @@ pub fn handle_syscall(
             .try_lock()
             .expect("SCHEDULER LOCKED at start of handle_syscall");
 
-        let stack_area = mm.alloc_virtual_area(PROCESS_STACK_SIZE_PAGES);
+        // Retrieve required register values from the process stack. The registers
+        // are saved on the stack of the current thread, which isn't necessarily
+        // backed by `stack_frames`, so the page table of the process is used.
         let process = sched.process_by_id(pid).expect("Process not found");
-
-        // Map the process stack frames to the kernel page tables
-        for (page_index, frame) in process.stack_frames.iter().enumerate() {
-            let vaddr = stack_area.start + (page_index as u64) * PAGE_SIZE_BYTES;
-            unsafe {
-                mm.page_map
-                    .map_to(
-                        PT_VADDR,
-                        Page::from_start_address(vaddr).unwrap(),
-                        frame.clone(),
-                        Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE,
-                    )
-                    .flush();
-            }
+        let (stack_area, stack_slice) = unsafe {
+            mm.process_slice_mut(process, mem::size_of::<RegisterState>() as u64, process_stack)
         }
-
-        // Retrieve required register values from the process stack
-        let process_offset_from_end = PROCESS_STACK_END - process_stack;
-        let stack_addr = stack_area.end - process_offset_from_end;
-        let stack_ptr: *mut u64 = stack_addr.as_mut_ptr();
-        let reg_rax: u64 = unsafe { ptr::read(stack_ptr.add(0)) };
-        let reg_rdi: u64 = unsafe { ptr::read(stack_ptr.add(5)) };
-        let reg_rsi: u64 = unsafe { ptr::read(stack_ptr.add(4)) };
-        let reg_rdx: u64 = unsafe { ptr::read(stack_ptr.add(3)) };
-        let reg_rcx: u64 = unsafe { ptr::read(stack_ptr.add(2)) };
-
+        .expect("Saved registers not mapped");
+        let registers: &mut RegisterState =
+            unsafe { &mut *(stack_slice.as_mut_ptr() as *mut RegisterState) };
         let rsc = RawSyscall {
-            routine: reg_rax,
-            args: (reg_rdi, reg_rsi, reg_rdx, reg_rcx),
+            routine: registers.rax,
+            args: (registers.rdi, registers.rsi, registers.rdx, registers.rcx),
         };
         log::trace!(
             "[pid={:8}] <= {:?} ",
             pid,
             d7abi::SyscallNumber::try_from(rsc.routine).ok()
         );
-        let res = syscall(mm, &mut sched, pid, rsc);
+        let interrupted = {
+            let process = sched.process_by_id_mut(pid).expect("Process not found");
+            process.repeat_syscall && mem::replace(&mut process.syscall_interrupted, false)
+        };
+        let res = if interrupted {
+            // An interrupted delivery is abandoned like one that timed out,
+            // so that a late acknowledgement isn't taken as the result of the next one
+            let number = d7abi::SyscallNumber::try_from(rsc.routine);
+            if matches!(number, Ok(d7abi::SyscallNumber::ipc_deliver)) {
+                ipc::IPC
+                    .try_lock()
+                    .expect("IPC LOCKED")
+                    .cancel_delivery(pid);
+                sched.end_inherited_boosts_of(pid);
+            }
+            SyscallResult::Continue(Err(ErrorCode::interrupted.into()))
+        } else {
+            let start = tsc::read();
+            let routine = rsc.routine;
+            let res = syscall(mm, &mut sched, pid, rsc);
+            latency::record_syscall(routine, start);
+            res
+        };
         log::trace!("[pid={:8}] => {:?} ", pid, res);
 
         // Write result register values into the process stack
         if let SyscallResult::Continue(r) | SyscallResult::Switch(r, _) = res {
-            unsafe {
-                match r {
-                    Ok(v) => {
-                        ptr::write(stack_ptr.add(0), 1); // Success
-                        ptr::write(stack_ptr.add(5), v); // Value
-                    },
-                    Err(v) => {
-                        ptr::write(stack_ptr.add(0), 0); // Error
-                        ptr::write(stack_ptr.add(5), v); // Value
-                    },
-                }
-            }
+            let (success, value) = match r {
+                Ok(v) => (1, v),
+                Err(v) => (0, v),
+            };
+            registers.rax = success;
+            registers.rdi = value;
         }
 
         let process = sched.process_by_id_mut(pid).expect("Process not found");
@@ -669,18 +1424,12 @@ pub fn handle_syscall(
                 SyscallResultAction::Switch(s)
             },
         };
-
-        // Unmap from the kernel tables
-        for (page_index, frame) in process.stack_frames.iter().enumerate() {
-            let vaddr = stack_area.start + (page_index as u64) * PAGE_SIZE_BYTES;
-            unsafe {
-                mm.page_map
-                    .unmap(PT_VADDR, Page::from_start_address(vaddr).unwrap())
-                    .flush();
-            }
+        if !process.repeat_syscall {
+            process.syscall_deadline = None;
         }
 
-        // Free virtual memory
+        // Unmap from the kernel tables
+        unsafe { mm.unmap_area(stack_area) };
         mm.free_virtual_area(stack_area);
 
         action
@@ -1,10 +1,11 @@
-use alloc::collections::VecDeque;
 use alloc::prelude::v1::*;
 use core::fmt::Write;
 use core::sync::atomic::{spin_loop_hint, AtomicBool, Ordering};
 use log::{Level, Metadata, Record};
 use spin::Mutex;
 
+use crate::util::log_buffer::{LogBuffer, LogCursor};
+
 /// Disable logging directly to the built-in vga buffer.
 /// This MUST NOT BE done before memory map has been initialized,
 /// or it causes page faults. (Requires allocation)
@@ -113,18 +114,51 @@ macro_rules! uart_print {
 
 /**************************** BUFFER + SYSCALL *******************************/
 
+/// Kernel log size budget, the oldest lines are dropped after this
+const KERNEL_LOG_BUDGET: usize = 0x4_0000;
+
 lazy_static::lazy_static! {
-    /// Write ahead log for kernel log messages
-    static ref WRITE_AHEAD_LOG: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+    /// Kernel log messages, one record per line
+    static ref KERNEL_LOG: Mutex<LogBuffer> = Mutex::new(LogBuffer::new(KERNEL_LOG_BUDGET));
 }
 
+/// Position of the `kernel_log_read` system call reader
+static SYSCALL_CURSOR: Mutex<LogCursor> = Mutex::new(LogCursor::new());
+
 pub fn syscall_read(buffer: &mut [u8]) -> usize {
-    let mut wal = WRITE_AHEAD_LOG.try_lock().unwrap();
-    let count = wal.len().min(buffer.len());
-    for (i, b) in wal.drain(..count).enumerate() {
-        buffer[i] = b;
+    let log = KERNEL_LOG.try_lock().unwrap();
+    let mut cursor = SYSCALL_CURSOR.try_lock().unwrap();
+    log.read(&mut cursor, buffer)
+}
+
+/// Calls `f` with the log contents not read by `kernel_log_read`,
+/// without consuming them. Does nothing if the log is locked.
+/// Used for crash dumps.
+pub fn with_unread<F: FnMut(&[u8])>(mut f: F) {
+    if let (Some(log), Some(cursor)) = (KERNEL_LOG.try_lock(), SYSCALL_CURSOR.try_lock()) {
+        for bytes in log.unread(&cursor) {
+            f(bytes);
+        }
     }
-    count
+}
+
+/// Sequence number the next line will get
+pub fn next_seq() -> u64 {
+    KERNEL_LOG.lock().next_seq()
+}
+
+/// Up to `max` lines starting from `seq`, for independent log readers.
+/// Returns the sequence number of the first returned line, which is larger
+/// than `seq` if older lines have already been dropped.
+pub fn lines_from(seq: u64, max: usize) -> (u64, Vec<String>) {
+    let log = KERNEL_LOG.lock();
+    let first = seq.max(log.first_seq());
+    let lines = log
+        .records_from(seq)
+        .take(max)
+        .map(|(_, r)| String::from_utf8_lossy(r).into_owned())
+        .collect();
+    (first, lines)
 }
 
 /***************************** LOGGER ITSELF ********************************/
@@ -168,8 +202,7 @@ impl log::Log for SystemLogger {
                     record.target(),
                     record.args()
                 );
-                let mut wal = WRITE_AHEAD_LOG.lock();
-                wal.extend(message.bytes());
+                KERNEL_LOG.lock().push(message.into_bytes());
             }
 
             if !DISABLE_DIRECT_VGA.load(Ordering::Acquire) {
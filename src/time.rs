@@ -6,8 +6,20 @@
 //! Other cores should very rarely need access to this time.
 //! The BSP core is the only core that moves tasks out of the sleep queue,
 //! so schduler times are stored in (future) TSC timestamps of the BSP.
+//!
+//! For deterministic testing, a group of processes can be given a time
+//! namespace: a virtualized clock with its own time page, see `TimeNamespace`.
+
+use alloc::prelude::v1::*;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+use hashbrown::HashMap;
+use spin::Mutex;
+
+use d7abi::process::ProcessId;
+use d7abi::time_page::{TimePage, TIME_PAGE_VERSION, TIME_SCALE_SHIFT};
 
 use crate::driver::tsc;
+use crate::memory::{self, MemoryController, PhysAddr, PhysFrame};
 use crate::smp::is_bsp;
 
 /// Timestamp relative to the TSC of the BSP core.
@@ -48,4 +60,242 @@ impl BSPInstant {
     pub fn duration_since(self) -> d7time::Duration {
         Self::now().duration_from(self)
     }
+
+    /// Arms the TSC deadline timer to fire at this instant.
+    /// Fires immediately if the instant has already passed.
+    pub fn set_deadline(self) {
+        assert!(is_bsp(), "BSPInstant is only usable from the BSP core");
+        tsc::set_deadline(self.0);
+    }
+}
+
+/// Shift used for the TSC-to-nanoseconds multiplier in the time page
+const TIME_PAGE_TSC_SHIFT: u64 = 32;
+
+/// Physical address of the time page frame, zero before `init_time_page`
+static TIME_PAGE_PHYS: AtomicU64 = AtomicU64::new(0);
+
+/// Frame containing the time page, mapped read-only into every process
+pub fn time_page_frame() -> PhysFrame {
+    let addr = TIME_PAGE_PHYS.load(Ordering::SeqCst);
+    assert!(addr != 0, "Time page not initialized");
+    PhysFrame::from_start_address(PhysAddr::new(addr)).unwrap()
+}
+
+fn page_at(frame: PhysFrame) -> &'static TimePage {
+    let virt = memory::phys_to_virt(frame.start_address());
+    unsafe { &*virt.as_ptr::<TimePage>() }
+}
+
+fn time_page() -> &'static TimePage {
+    page_at(time_page_frame())
+}
+
+/// TSC ticks to nanoseconds multiplier of the real clock
+fn tsc_mult() -> u64 {
+    ((1_000_000_000u128 << TIME_PAGE_TSC_SHIFT) / (tsc::freq_hz() as u128)) as u64
+}
+
+/// Allocates the time page. Requires that memory and TSC are initialized.
+pub fn init_time_page() {
+    let frame = memory::configure(|mm| mm.alloc_frames_zeroed(1)[0]);
+    TIME_PAGE_PHYS.store(frame.start_address().as_u64(), Ordering::SeqCst);
+
+    let page = time_page();
+    page.tsc_shift.store(TIME_PAGE_TSC_SHIFT, Ordering::Relaxed);
+    for word in page.boot_id.iter() {
+        word.store(random_u64(), Ordering::Relaxed);
+    }
+    log::info!("Boot id {:032x}", page.boot_id());
+    update_time_page();
+    page.version.store(TIME_PAGE_VERSION, Ordering::Release);
+}
+
+/// Random value for the boot id. Uses `rdrand` when available, and falls
+/// back to mixing the TSC, which differs between boots but is predictable.
+fn random_u64() -> u64 {
+    if crate::cpuid::has_rdrand() {
+        // The instruction can fail temporarily, so it's retried a few times
+        for _ in 0..10 {
+            let value: u64;
+            let ok: u8;
+            unsafe {
+                asm!("rdrand {}; setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+            }
+            if ok != 0 {
+                return value;
+            }
+        }
+    }
+
+    // SplitMix64 finalizer
+    let mut z = tsc::read().wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Refreshes the base values of the time page and the namespace time pages.
+/// Only called on the BSP, so there is a single writer.
+pub fn update_time_page() {
+    let tsc_base = BSPInstant::now().0;
+    let now = d7time::Duration::from_nanos(tsc::ticks_to_ns(tsc_base));
+    write_time_page(time_page(), tsc_base, now, tsc_mult());
+
+    for ns in NAMESPACES.lock().list.iter() {
+        ns.update(tsc_base);
+    }
+}
+
+fn write_time_page(page: &TimePage, tsc_base: u64, now: d7time::Duration, mult: u64) {
+    // Seqlock write, paired with `TimePage::snapshot`. The fence keeps the
+    // field stores from becoming visible before the sequence is odd, and the
+    // final release store keeps them from becoming visible after it is even.
+    // Readers never block the writer, so the tick cannot deadlock on them.
+    page.sequence.fetch_add(1, Ordering::Relaxed);
+    fence(Ordering::Release);
+    page.tsc_base.store(tsc_base, Ordering::Relaxed);
+    page.base_sec.store(now.as_secs(), Ordering::Relaxed);
+    page.base_nsec.store(now.subsec_nanos() as u64, Ordering::Relaxed);
+    page.tsc_mult.store(mult, Ordering::Relaxed);
+    page.sequence.fetch_add(1, Ordering::Release);
+}
+
+/// Time namespaces are never removed, as frames cannot be freed,
+/// so the number of them is limited. Reconfiguring one doesn't count.
+const MAX_TIME_NAMESPACES: usize = 16;
+
+/// Fastest namespace clock, 65536 times the real speed, so that
+/// the multiplier of its time page cannot overflow
+const MAX_TIME_SCALE: u64 = 1 << (TIME_SCALE_SHIFT + 16);
+
+/// Latest time a namespace clock can be set to, about 100 years
+const MAX_TIME_OFFSET_NS: u64 = 100 * 365 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Whether a namespace clock can be set to read `offset_ns`
+/// and advance at `scale`, see `set_namespace`
+pub fn is_valid_clock(offset_ns: u64, scale: u64) -> bool {
+    offset_ns <= MAX_TIME_OFFSET_NS && (1..=MAX_TIME_SCALE).contains(&scale)
+}
+
+/// Virtualized clock of a group of processes, for testing time-dependent
+/// behavior deterministically and faster than real time. The clock reads
+/// `offset` at the TSC value `start`, and advances `scale` times as fast as
+/// the real clock. Members have the time page of the namespace mapped
+/// instead of the global one, and their sleeps and timers are converted
+/// to real time.
+#[derive(Debug)]
+struct TimeNamespace {
+    start: u64,
+    offset: d7time::Duration,
+    /// Fixed point, `1 << TIME_SCALE_SHIFT` is the real speed
+    scale: u64,
+    frame: PhysFrame,
+}
+impl TimeNamespace {
+    fn time_at(&self, tsc: u64) -> d7time::Duration {
+        let real_ns = tsc::ticks_to_ns(tsc.saturating_sub(self.start)) as u128;
+        let ns = (real_ns * (self.scale as u128)) >> TIME_SCALE_SHIFT;
+        self.offset + d7time::Duration::from_nanos(ns as u64)
+    }
+
+    fn to_real_ns(&self, ns: u64) -> u64 {
+        let real_ns = ((ns as u128) << TIME_SCALE_SHIFT) / (self.scale as u128);
+        real_ns.min(u64::MAX as u128) as u64
+    }
+
+    fn update(&self, tsc_base: u64) {
+        let mult = ((tsc_mult() as u128 * self.scale as u128) >> TIME_SCALE_SHIFT) as u64;
+        write_time_page(page_at(self.frame), tsc_base, self.time_at(tsc_base), mult);
+    }
+}
+
+struct Namespaces {
+    list: Vec<TimeNamespace>,
+    /// Namespace index of each member process
+    members: HashMap<ProcessId, usize>,
+}
+
+lazy_static::lazy_static! {
+    static ref NAMESPACES: Mutex<Namespaces> = Mutex::new(Namespaces {
+        list: Vec::new(),
+        members: HashMap::new(),
+    });
+}
+
+/// Sets the clock of the namespace of the process to read `offset_ns` now,
+/// and advance at `scale`. Creates a namespace if the process has none.
+/// Returns the frame of its time page, to be mapped into the process,
+/// or `None` if the namespace limit has been reached.
+pub fn set_namespace(
+    mm: &mut MemoryController, pid: ProcessId, offset_ns: u64, scale: u64,
+) -> Option<PhysFrame> {
+    assert!(is_valid_clock(offset_ns, scale), "Invalid namespace clock");
+    let mut namespaces = NAMESPACES.lock();
+    let index = match namespaces.members.get(&pid) {
+        Some(index) => *index,
+        None => {
+            if namespaces.list.len() >= MAX_TIME_NAMESPACES {
+                return None;
+            }
+            let frame = mm.alloc_frames_zeroed(1)[0];
+            let page = page_at(frame);
+            page.tsc_shift.store(TIME_PAGE_TSC_SHIFT, Ordering::Relaxed);
+            for (word, value) in page.boot_id.iter().zip(time_page().boot_id.iter()) {
+                word.store(value.load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+            page.version.store(TIME_PAGE_VERSION, Ordering::Release);
+            namespaces.list.push(TimeNamespace {
+                start: 0,
+                offset: d7time::Duration::from_nanos(0),
+                scale: 1 << TIME_SCALE_SHIFT,
+                frame,
+            });
+            let index = namespaces.list.len() - 1;
+            namespaces.members.insert(pid, index);
+            index
+        },
+    };
+
+    let start = BSPInstant::now().0;
+    let ns = &mut namespaces.list[index];
+    ns.start = start;
+    ns.offset = d7time::Duration::from_nanos(offset_ns);
+    ns.scale = scale;
+    ns.update(start);
+    Some(ns.frame)
+}
+
+/// Adds a new process to the namespace of its creator.
+/// Returns the frame of the time page if it has to be mapped.
+pub fn join_namespace(creator: ProcessId, pid: ProcessId) -> Option<PhysFrame> {
+    let mut namespaces = NAMESPACES.lock();
+    let index = *namespaces.members.get(&creator)?;
+    namespaces.members.insert(pid, index);
+    Some(namespaces.list[index].frame)
+}
+
+/// Called when a process terminates
+pub fn leave_namespace(pid: ProcessId) {
+    NAMESPACES.lock().members.remove(&pid);
+}
+
+/// Converts a duration requested by a process, e.g. for a sleep,
+/// from the clock of its namespace to real nanoseconds
+pub fn to_real_ns(pid: ProcessId, ns: u64) -> u64 {
+    let namespaces = NAMESPACES.lock();
+    match namespaces.members.get(&pid) {
+        Some(index) => namespaces.list[*index].to_real_ns(ns),
+        None => ns,
+    }
+}
+
+/// Monotonic time on the clock of the process
+pub fn monotonic(pid: ProcessId) -> d7time::Duration {
+    let tsc = tsc::read();
+    let namespaces = NAMESPACES.lock();
+    match namespaces.members.get(&pid) {
+        Some(index) => namespaces.list[*index].time_at(tsc),
+        None => d7time::Duration::from_nanos(tsc::ticks_to_ns(tsc)),
+    }
 }
@@ -1,5 +1,7 @@
 // Represents useful attributes from 64-bit elf file
 
+use crate::memory::constants::{PAGE_SIZE_BYTES, PROCESS_STACK_END, PROCESS_STACK_MAX_SIZE_BYTES};
+
 const KERNEL_ELF_IMAGE_POSITION: usize = 0x10_0000; // must match with plan.md
 const MAX_PH_ENTRY_COUNT: usize = 20;
 
@@ -9,11 +11,15 @@ const ELF_LITTLE_ENDIAN: u8 = 1;
 const CURRENT_ELF_VERSION: u8 = 1;
 const ELF_ARCH_X86_64: u16 = 0x3E;
 const ELF_PH_TABLE_ENTRY_SIZE: u16 = 56;
+/// Largest supported thread-local storage segment
+const MAX_TLS_SIZE: u64 = 0x1_0000;
 
 #[derive(Debug, Copy, Clone)]
 pub struct ELFData {
     pub header: ELFHeader,
     pub ph_table: [Option<ELFProgramHeader>; MAX_PH_ENTRY_COUNT],
+    /// Thread-local storage template (PT_TLS), if any
+    pub tls: Option<ELFProgramHeader>,
 }
 impl ELFData {
     pub fn last_addr(&self) -> u64 {
@@ -93,6 +99,21 @@ pub enum ELFParsingError {
     InvalidELF,
     FeatureSupportMissing,
     EmptyHeader,
+    /// Program header table is not within the image
+    HeaderOutOfRange,
+    /// Segment contents are not within the image, or larger than the segment
+    SegmentOutOfRange,
+    /// Segment would be loaded outside of the process code area
+    AddressOutOfRange,
+    /// Segment virtual address is not page-aligned
+    UnalignedSegment,
+    /// Two segments are loaded to the same page
+    OverlappingSegments,
+    /// Segment is not readable
+    NotReadable,
+    NoLoadableSegments,
+    /// Thread-local storage template is not within a segment, or is too large
+    InvalidTls,
 }
 
 pub unsafe fn parse_elf(ptr: usize) -> Result<ELFData, ELFParsingError> {
@@ -116,6 +137,7 @@ pub unsafe fn parse_elf(ptr: usize) -> Result<ELFData, ELFParsingError> {
         let mut elf_data = ELFData {
             header: elf_header,
             ph_table: [None; MAX_PH_ENTRY_COUNT],
+            tls: None,
         };
 
         // get program headers
@@ -129,9 +151,16 @@ pub unsafe fn parse_elf(ptr: usize) -> Result<ELFData, ELFParsingError> {
             match ph.header_type as usize {
                 1 => {
                     // load, (needed)
+                    if ph_table >= MAX_PH_ENTRY_COUNT {
+                        return Err(ELFParsingError::FeatureSupportMissing);
+                    }
                     elf_data.ph_table[ph_table] = Some(ph);
                     ph_table += 1;
                 },
+                7 => {
+                    // thread-local storage template
+                    elf_data.tls = Some(ph);
+                },
                 0x60000000 => {}, // OS Specific 0, decompression tables, (but unused here)
                 _ => {},          // unknown, not supported
             }
@@ -141,9 +170,224 @@ pub unsafe fn parse_elf(ptr: usize) -> Result<ELFData, ELFParsingError> {
     }
 }
 
+/// Like `parse_elf`, but for untrusted images: checks that all headers and
+/// loaded segments are within the `len` bytes starting from `ptr`.
+pub unsafe fn parse_elf_checked(ptr: usize, len: usize) -> Result<ELFData, ELFParsingError> {
+    if len < core::mem::size_of::<ELFHeader>() {
+        return Err(ELFParsingError::EmptyHeader);
+    }
+
+    let elf_header: ELFHeader = *(ptr as *const _);
+    let ph_table_end = (elf_header.ph_table_entry_count as u64)
+        .checked_mul(elf_header.ph_table_entry_size as u64)
+        .and_then(|size| size.checked_add(elf_header.ph_table_position));
+    if ph_table_end.map_or(true, |end| end > len as u64) {
+        return Err(ELFParsingError::HeaderOutOfRange);
+    }
+
+    let elf_data = parse_elf(ptr)?;
+
+    // Segments must leave room for the process stack to grow
+    let stack_end = PROCESS_STACK_END.as_u64();
+    let stack_limit = stack_end - PROCESS_STACK_MAX_SIZE_BYTES;
+
+    let mut loaded = 0;
+    for ph in elf_data.ph_table.iter().copied().flatten() {
+        // Copy fields out of the packed struct before comparing them
+        let (offset, size_in_file, size_in_memory) =
+            (ph.offset, ph.size_in_file, ph.size_in_memory);
+        let segment_end = offset.checked_add(size_in_file);
+        let virtual_address = ph.virtual_address;
+        if segment_end.map_or(true, |end| end > len as u64) || size_in_file > size_in_memory {
+            return Err(ELFParsingError::SegmentOutOfRange);
+        }
+        // Process loader requirements
+        if virtual_address.checked_add(size_in_memory).is_none()
+            || virtual_address < 0x400_000
+            || (virtual_address < stack_end && virtual_address + size_in_memory > stack_limit)
+        {
+            return Err(ELFParsingError::AddressOutOfRange);
+        }
+        if virtual_address % PAGE_SIZE_BYTES != 0 {
+            return Err(ELFParsingError::UnalignedSegment);
+        }
+        if !ph.has_flag(ELFPermissionFlags::READABLE) {
+            return Err(ELFParsingError::NotReadable);
+        }
+        loaded += 1;
+    }
+    if loaded == 0 {
+        return Err(ELFParsingError::NoLoadableSegments);
+    }
+
+    // Each page can be mapped by one segment only. Segments start on page
+    // boundaries, so it's enough to compare the page-rounded ranges.
+    let segments = &elf_data.ph_table[..loaded];
+    for (i, a) in segments.iter().copied().flatten().enumerate() {
+        for b in segments[i + 1..].iter().copied().flatten() {
+            let (a_start, a_size) = (a.virtual_address, a.size_in_memory);
+            let (b_start, b_size) = (b.virtual_address, b.size_in_memory);
+            let a_end = a_start + a_size.max(1);
+            let b_end = b_start + b_size.max(1);
+            if a_start < b_end && b_start < a_end {
+                return Err(ELFParsingError::OverlappingSegments);
+            }
+        }
+    }
+
+    if let Some(tls) = elf_data.tls {
+        let (virtual_address, size_in_file, size_in_memory, alignment) =
+            (tls.virtual_address, tls.size_in_file, tls.size_in_memory, tls.alignment);
+        // The template must be within a loaded segment, as it's copied from there
+        let in_segment = elf_data.ph_table.iter().copied().flatten().any(|ph| {
+            let (start, size) = (ph.virtual_address, ph.size_in_memory);
+            let end = virtual_address.checked_add(size_in_file);
+            start <= virtual_address && end.map_or(false, |end| end <= start + size)
+        });
+        if !in_segment
+            || size_in_file > size_in_memory
+            || size_in_memory > MAX_TLS_SIZE
+            || alignment > PAGE_SIZE_BYTES
+            || (alignment != 0 && !alignment.is_power_of_two())
+        {
+            return Err(ELFParsingError::InvalidTls);
+        }
+    }
+
+    Ok(elf_data)
+}
+
 pub unsafe fn parse_kernel_elf() -> ELFData {
     match parse_elf(KERNEL_ELF_IMAGE_POSITION) {
         Ok(header) => header,
         Err(error) => panic!("Could not receive kernel image data: {:?}", error),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::prelude::v1::*;
+
+    const HEADER_SIZE: usize = 64;
+    const CODE_START: u64 = 0x40_0000;
+
+    /// Loadable segment: file offset, virtual address, file size and memory size
+    type Segment = (u64, u64, u64, u64);
+
+    fn put(image: &mut [u8], offset: usize, bytes: &[u8]) {
+        image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Image with a valid header, the program header table after it,
+    /// and some room for segment contents
+    fn image(segments: &[Segment]) -> Vec<u8> {
+        let ph_size = ELF_PH_TABLE_ENTRY_SIZE as usize;
+        let mut image = vec![0u8; HEADER_SIZE + segments.len() * ph_size + 0x100];
+        put(&mut image, 0, &ELF_MAGIC.to_le_bytes());
+        put(&mut image, 4, &[ELF_BITNESS_64, ELF_LITTLE_ENDIAN, CURRENT_ELF_VERSION]);
+        put(&mut image, 18, &ELF_ARCH_X86_64.to_le_bytes());
+        put(&mut image, 20, &(CURRENT_ELF_VERSION as u32).to_le_bytes());
+        put(&mut image, 32, &(HEADER_SIZE as u64).to_le_bytes());
+        put(&mut image, 54, &ELF_PH_TABLE_ENTRY_SIZE.to_le_bytes());
+        put(&mut image, 56, &(segments.len() as u16).to_le_bytes());
+        for (i, &(offset, virtual_address, size_in_file, size_in_memory)) in
+            segments.iter().enumerate()
+        {
+            let ph = HEADER_SIZE + i * ph_size;
+            put(&mut image, ph, &1u32.to_le_bytes());
+            put(&mut image, ph + 4, &ELFPermissionFlags::READABLE.bits().to_le_bytes());
+            put(&mut image, ph + 8, &offset.to_le_bytes());
+            put(&mut image, ph + 16, &virtual_address.to_le_bytes());
+            put(&mut image, ph + 32, &size_in_file.to_le_bytes());
+            put(&mut image, ph + 40, &size_in_memory.to_le_bytes());
+        }
+        image
+    }
+
+    fn parse(image: &[u8], len: usize) -> Result<ELFData, ELFParsingError> {
+        assert!(len <= image.len());
+        unsafe { parse_elf_checked(image.as_ptr() as usize, len) }
+    }
+
+    #[test]
+    fn test_valid() {
+        let image = image(&[(0, CODE_START, 0x100, 0x1000), (0, CODE_START + 0x1000, 0, 0x10)]);
+        let elf = parse(&image, image.len()).unwrap();
+        assert_eq!(elf.ph_table.iter().flatten().count(), 2);
+        assert_eq!(elf.last_addr(), CODE_START + 0x1010);
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        let image = image(&[(0, CODE_START, 0x100, 0x1000)]);
+        assert!(matches!(parse(&image, 0), Err(ELFParsingError::EmptyHeader)));
+        assert!(matches!(parse(&image, HEADER_SIZE - 1), Err(ELFParsingError::EmptyHeader)));
+    }
+
+    #[test]
+    fn test_program_headers_out_of_bounds() {
+        let image = image(&[(0, CODE_START, 0x10, 0x1000)]);
+        let table_end = HEADER_SIZE + ELF_PH_TABLE_ENTRY_SIZE as usize;
+        assert!(matches!(
+            parse(&image, table_end - 1),
+            Err(ELFParsingError::HeaderOutOfRange)
+        ));
+        assert!(parse(&image, table_end).is_ok());
+
+        let mut image = image.clone();
+        put(&mut image, 56, &u16::MAX.to_le_bytes());
+        assert!(matches!(
+            parse(&image, image.len()),
+            Err(ELFParsingError::HeaderOutOfRange)
+        ));
+
+        let mut image = image.clone();
+        put(&mut image, 32, &(u64::MAX - 8).to_le_bytes());
+        assert!(matches!(
+            parse(&image, image.len()),
+            Err(ELFParsingError::HeaderOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn test_segment_out_of_bounds() {
+        let mut image = image(&[(0x10, CODE_START, 0, 0x1000)]);
+        let len = image.len() as u64;
+        put(&mut image, HEADER_SIZE + 32, &(len - 0x10).to_le_bytes());
+        assert!(parse(&image, image.len()).is_ok());
+        put(&mut image, HEADER_SIZE + 32, &(len - 0xf).to_le_bytes());
+        assert!(matches!(
+            parse(&image, image.len()),
+            Err(ELFParsingError::SegmentOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn test_segment_end_overflow() {
+        let image = image(&[(u64::MAX, CODE_START, 2, 0x1000)]);
+        assert!(matches!(
+            parse(&image, image.len()),
+            Err(ELFParsingError::SegmentOutOfRange)
+        ));
+        let image = self::image(&[(1, CODE_START, u64::MAX, u64::MAX)]);
+        assert!(matches!(
+            parse(&image, image.len()),
+            Err(ELFParsingError::SegmentOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn test_overlapping_segments() {
+        let image = image(&[(0, CODE_START, 0x10, 0x2000), (0, CODE_START + 0x1000, 0x10, 0x10)]);
+        assert!(matches!(
+            parse(&image, image.len()),
+            Err(ELFParsingError::OverlappingSegments)
+        ));
+        let image = self::image(&[(0, CODE_START, 0x10, 0x10), (0, CODE_START, 0, 0)]);
+        assert!(matches!(
+            parse(&image, image.len()),
+            Err(ELFParsingError::OverlappingSegments)
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+//! Append-only record log with a size budget and independent readers.
+//!
+//! Records are numbered with a sequence number. Readers keep their own
+//! position, so any number of them can read the same log. When the total size
+//! of the records exceeds the budget, the oldest records are dropped, and
+//! readers that hadn't read them yet skip over them.
+
+use alloc::collections::VecDeque;
+use alloc::prelude::v1::*;
+
+#[derive(Debug)]
+pub struct LogBuffer {
+    records: VecDeque<Vec<u8>>,
+    /// Sequence number of the first record in `records`
+    first_seq: u64,
+    /// Total size of `records`
+    bytes: usize,
+    budget: usize,
+}
+impl LogBuffer {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            first_seq: 0,
+            bytes: 0,
+            budget,
+        }
+    }
+
+    /// Sequence number of the oldest record still in the log
+    pub fn first_seq(&self) -> u64 {
+        self.first_seq
+    }
+
+    /// Sequence number the next record will get
+    pub fn next_seq(&self) -> u64 {
+        self.first_seq + self.records.len() as u64
+    }
+
+    /// Append a record, dropping the oldest ones if over the budget.
+    /// A record larger than the budget is kept alone.
+    pub fn push(&mut self, record: Vec<u8>) {
+        self.bytes += record.len();
+        self.records.push_back(record);
+        while self.bytes > self.budget && self.records.len() > 1 {
+            let dropped = self.records.pop_front().unwrap();
+            self.bytes -= dropped.len();
+            self.first_seq += 1;
+        }
+    }
+
+    /// Records starting from `seq`, with their sequence numbers.
+    /// Starts from the oldest available record if `seq` was already dropped.
+    pub fn records_from(&self, seq: u64) -> impl Iterator<Item = (u64, &[u8])> {
+        let skip = seq.saturating_sub(self.first_seq) as usize;
+        let first_seq = self.first_seq;
+        self.records
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .map(move |(i, r)| (first_seq + i as u64, r.as_slice()))
+    }
+
+    /// Bytes not read yet by the cursor, without advancing it
+    pub fn unread<'a>(&'a self, cursor: &LogCursor) -> impl Iterator<Item = &'a [u8]> {
+        let offset = if cursor.seq < self.first_seq {
+            0
+        } else {
+            cursor.offset
+        };
+        self.records_from(cursor.seq)
+            .enumerate()
+            .map(move |(i, (_, r))| if i == 0 { &r[offset..] } else { r })
+    }
+
+    /// Copy records to `buffer` as a byte stream, advancing the cursor.
+    /// Returns the number of bytes written.
+    pub fn read(&self, cursor: &mut LogCursor, buffer: &mut [u8]) -> usize {
+        if cursor.seq < self.first_seq {
+            cursor.dropped += self.first_seq - cursor.seq;
+            cursor.seq = self.first_seq;
+            cursor.offset = 0;
+        }
+
+        let mut count = 0;
+        for (seq, record) in self.records_from(cursor.seq) {
+            let part = &record[cursor.offset..];
+            let n = part.len().min(buffer.len() - count);
+            buffer[count..count + n].copy_from_slice(&part[..n]);
+            count += n;
+            if n < part.len() {
+                cursor.offset += n;
+                break;
+            }
+            cursor.seq = seq + 1;
+            cursor.offset = 0;
+        }
+        count
+    }
+}
+
+/// Position of a byte stream reader, see `LogBuffer::read`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogCursor {
+    /// Next record to read
+    seq: u64,
+    /// Bytes of that record already read
+    offset: usize,
+    /// Number of records dropped before this reader got them
+    pub dropped: u64,
+}
+impl LogCursor {
+    pub const fn new() -> Self {
+        Self {
+            seq: 0,
+            offset: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn log_of(records: &[&str], budget: usize) -> LogBuffer {
+        let mut log = LogBuffer::new(budget);
+        for r in records {
+            log.push(r.as_bytes().to_vec());
+        }
+        log
+    }
+
+    #[test]
+    fn test_budget() {
+        let log = log_of(&["aaaa", "bbbb", "cccc"], 8);
+        assert_eq!(log.first_seq(), 1);
+        assert_eq!(log.next_seq(), 3);
+        let records: Vec<_> = log.records_from(0).collect();
+        assert_eq!(records, vec![(1, &b"bbbb"[..]), (2, &b"cccc"[..])]);
+
+        // Oversized records are kept alone
+        let log = log_of(&["aa", "bbbbbbbbbbbb"], 8);
+        assert_eq!(log.first_seq(), 1);
+        assert_eq!(log.records_from(0).count(), 1);
+    }
+
+    #[test]
+    fn test_independent_readers() {
+        let mut log = log_of(&["abc", "de"], 100);
+        let mut a = LogCursor::new();
+        let mut b = LogCursor::new();
+        let mut buffer = [0u8; 4];
+
+        assert_eq!(log.read(&mut a, &mut buffer), 4);
+        assert_eq!(&buffer, b"abcd");
+        assert_eq!(log.read(&mut a, &mut buffer), 1);
+        assert_eq!(&buffer[..1], b"e");
+        assert_eq!(log.read(&mut a, &mut buffer), 0);
+
+        log.push(b"f".to_vec());
+        assert_eq!(log.read(&mut a, &mut buffer), 1);
+        assert_eq!(&buffer[..1], b"f");
+
+        let unread: Vec<u8> = log.unread(&b).flatten().copied().collect();
+        assert_eq!(unread, b"abcdef");
+        b.offset = 1;
+        let unread: Vec<u8> = log.unread(&b).flatten().copied().collect();
+        assert_eq!(unread, b"bcdef");
+        b.offset = 0;
+
+        assert_eq!(log.read(&mut b, &mut [0u8; 100]), 6);
+        assert_eq!(b.seq(), 3);
+    }
+
+    #[test]
+    fn test_reader_skips_dropped() {
+        let mut log = log_of(&["abc"], 4);
+        let mut cursor = LogCursor::new();
+        let mut buffer = [0u8; 2];
+        assert_eq!(log.read(&mut cursor, &mut buffer), 2);
+
+        log.push(b"de".to_vec());
+        log.push(b"fg".to_vec());
+        assert_eq!(log.read(&mut cursor, &mut buffer), 2);
+        assert_eq!(&buffer, b"de");
+        assert_eq!(cursor.dropped, 1);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod elf_parser;
+pub mod log_buffer;
 
 use cpuio::Port;
 